@@ -12,7 +12,7 @@ use lading::{
     blackhole,
     captures::CaptureManager,
     config::{Config, Telemetry},
-    generator::{self, process_tree},
+    generator::{self, process_tree, unix_sender},
     inspector, observer,
     target::{self, Behavior, Output},
     target_metrics,
@@ -127,6 +127,8 @@ impl FromStr for CliKeyValues {
 
 #[derive(Parser)]
 #[clap(version, about, long_about = None)]
+// Extra sub-commands are run by lading itself and do not measure a target.
+#[clap(subcommand_negates_reqs = true)]
 #[clap(group(
     ArgGroup::new("target")
         .required(true)
@@ -217,6 +219,7 @@ struct Opts {
 #[clap(hide = true)]
 enum ExtraCommands {
     ProcessTreeGen(ProcessTreeGen),
+    UnixSender(UnixSender),
 }
 
 #[derive(Parser, Debug)]
@@ -234,6 +237,22 @@ struct ProcessTreeGen {
     config_content: Option<String>,
 }
 
+#[derive(Parser, Debug)]
+struct UnixSender {
+    /// path of the socket to write to
+    #[clap(long)]
+    path: PathBuf,
+    /// the kind of socket at `path`
+    #[clap(long, value_enum)]
+    kind: unix_sender::Kind,
+    /// attach `SCM_CREDENTIALS` to every write
+    #[clap(long)]
+    attach_credentials: bool,
+    /// the requested `SO_SNDBUF` of the socket
+    #[clap(long)]
+    send_buffer_size: Option<usize>,
+}
+
 fn get_config(ops: &Opts, config: Option<String>) -> Result<Config, Error> {
     let contents = if let Some(config) = config {
         config
@@ -623,6 +642,12 @@ fn run_extra_cmds(cmds: ExtraCommands) -> Result<(), Error> {
     match cmds {
         // This command will call fork and the process must be kept fork-safe up to this point.
         ExtraCommands::ProcessTreeGen(opts) => run_process_tree(opts),
+        ExtraCommands::UnixSender(opts) => Ok(unix_sender::run(
+            &opts.path,
+            opts.kind,
+            opts.attach_credentials,
+            opts.send_buffer_size,
+        )?),
     }
 }
 
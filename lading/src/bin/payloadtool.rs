@@ -1,16 +1,21 @@
-use std::io::Read;
-use std::time::Instant;
-use std::{io, num::NonZeroU32};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use std::{fs, io, num::NonZeroU32};
 
-use clap::Parser;
-use lading::generator::http::Method;
-use lading_payload::block;
+use clap::{Parser, Subcommand};
+use flate2::{write::GzEncoder, Compression};
+use lading::generator::{fixed_block_cache, http::Method};
+use lading_payload::{
+    adversarial,
+    block::{self, Block},
+    conformance,
+};
 use rand::{rngs::StdRng, SeedableRng};
 use tracing::{debug, error, info, warn};
 use tracing_subscriber::{fmt::format::FmtSpan, util::SubscriberInitExt};
 
-const UDP_PACKET_LIMIT_BYTES: u32 = 65_507;
-
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
@@ -20,6 +25,37 @@ struct Args {
     /// Optionally only run a single generator's payload
     #[clap(short, long)]
     generator_id: Option<String>,
+
+    /// What to do with the generated payloads, `check` if not given
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Check that block caches generate to their configured size
+    Check,
+    /// Write generated blocks to stdout or, one file per block, to a directory
+    Dump {
+        /// Directory to write blocks into rather than stdout
+        #[clap(long)]
+        output_dir: Option<PathBuf>,
+    },
+    /// Print statistics of each generated block and a summary per generator
+    Inspect {
+        /// Only print the summary per generator
+        #[clap(long)]
+        summary_only: bool,
+    },
+    /// Decode generated blocks with a reference parser for their format,
+    /// reporting those that do not conform
+    Validate,
+    /// Measure payload generation throughput
+    Bench {
+        /// Number of times to generate each block cache
+        #[clap(long, default_value_t = 3)]
+        iterations: u32,
+    },
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -34,114 +70,399 @@ pub enum Error {
     Byte(#[from] byte_unit::ByteError),
     #[error(transparent)]
     Deserialize(#[from] serde_yaml::Error),
+    #[error("{0} generated blocks do not conform to their format")]
+    Nonconforming(usize),
 }
 
-fn generate_and_check(
-    config: &lading_payload::Config,
+/// The block caches a generator builds at startup.
+struct Spec<'a> {
+    /// The generator's id or, absent one, its position in the configuration
+    label: String,
+    variant: &'a lading_payload::Config,
     seed: [u8; 32],
     total_bytes: NonZeroU32,
     max_block_size: byte_unit::Byte,
-) -> Result<(), Error> {
-    let mut rng = StdRng::from_seed(seed);
-    let start = Instant::now();
-    let blocks =
-        match block::Cache::fixed(&mut rng, total_bytes, max_block_size.get_bytes(), config)? {
-            block::Cache::Fixed { blocks, .. } => blocks,
+    /// Number of caches the generator builds in turn from its seed
+    caches: u16,
+    directory: Option<&'a Path>,
+}
+
+impl<'a> Spec<'a> {
+    fn new(index: usize, config: &'a lading::generator::Config) -> Self {
+        let (variant, seed, total_bytes, max_block_size, caches) = match &config.inner {
+            lading::generator::Inner::FileGen(_) => unimplemented!("FileGen not supported"),
+            lading::generator::Inner::UnixDatagram(g) => (
+                &g.variant,
+                g.seed,
+                g.maximum_prebuild_cache_size_bytes,
+                g.maximum_block_size,
+                g.parallel_connections,
+            ),
+            lading::generator::Inner::Tcp(g) => (
+                &g.variant,
+                g.seed,
+                g.maximum_prebuild_cache_size_bytes,
+                g.maximum_block_size,
+                1,
+            ),
+            lading::generator::Inner::Udp(g) => (
+                &g.variant,
+                g.seed,
+                g.maximum_prebuild_cache_size_bytes,
+                g.maximum_block_size,
+                1,
+            ),
+            lading::generator::Inner::Http(g) => {
+                let (variant, max_prebuild_cache_size_bytes) = match &g.method {
+                    Method::Post {
+                        variant,
+                        maximum_prebuild_cache_size_bytes,
+                        block_cache_method: _,
+                    } => (variant, maximum_prebuild_cache_size_bytes),
+                };
+                (
+                    variant,
+                    g.seed,
+                    *max_prebuild_cache_size_bytes,
+                    g.maximum_block_size,
+                    1,
+                )
+            }
+            lading::generator::Inner::SplunkHec(_) => unimplemented!("SplunkHec not supported"),
+            lading::generator::Inner::FileTree(_) => unimplemented!("FileTree not supported"),
+            lading::generator::Inner::Grpc(g) => (
+                &g.variant,
+                g.seed,
+                g.maximum_prebuild_cache_size_bytes,
+                g.maximum_block_size,
+                1,
+            ),
+            lading::generator::Inner::UnixStream(g) => (
+                &g.variant,
+                g.seed,
+                g.maximum_prebuild_cache_size_bytes,
+                g.maximum_block_size,
+                1,
+            ),
+            lading::generator::Inner::PassthruFile(g) => (
+                &g.variant,
+                g.seed,
+                g.maximum_prebuild_cache_size_bytes,
+                g.maximum_block_size,
+                1,
+            ),
+            lading::generator::Inner::ProcessTree(_) => unimplemented!("ProcessTree not supported"),
+            lading::generator::Inner::ProcFs(_) => unimplemented!("ProcFs not supported"),
         };
-    info!("Payload generation took {:?}", start.elapsed());
-    debug!("Payload: {:#?}", blocks);
 
-    let mut total_generated_bytes: u32 = 0;
-    for block in blocks.iter() {
-        total_generated_bytes += block.total_bytes.get();
+        Self {
+            label: config
+                .general
+                .id
+                .clone()
+                .unwrap_or_else(|| format!("generator-{index}")),
+            variant,
+            seed,
+            total_bytes: NonZeroU32::new(total_bytes.get_bytes() as u32)
+                .expect("Non-zero max prebuild cache size"),
+            max_block_size,
+            caches,
+            directory: config.general.block_cache_directory.as_deref(),
+        }
     }
-    if total_bytes.get() != total_generated_bytes {
-        let total_requested_bytes = byte_unit::Byte::from_bytes(total_bytes.get().into());
-        let total_requested_bytes_str = total_requested_bytes
-            .get_appropriate_unit(false)
-            .to_string();
-        let total_generated_bytes = byte_unit::Byte::from_bytes(total_generated_bytes.into());
-        let total_generated_bytes_str = total_generated_bytes
-            .get_appropriate_unit(false)
-            .to_string();
-        warn!("Generator failed to generate {total_requested_bytes_str}, instead only found {total_generated_bytes_str} of data")
+
+    /// Generate the caches in turn from the seed, as the generator would,
+    /// passing each to `f` with its index.
+    fn for_each_cache<F>(&self, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(u16, Vec<Block>) -> Result<(), Error>,
+    {
+        let mut rng = StdRng::from_seed(self.seed);
+        for cache in 0..self.caches {
+            let start = Instant::now();
+            let blocks = match fixed_block_cache(
+                &mut rng,
+                self.total_bytes,
+                self.max_block_size.get_bytes(),
+                self.variant,
+                self.directory,
+            )? {
+                block::Cache::Fixed { blocks, .. } => blocks,
+                block::Cache::Streaming(_) => unreachable!("fixed cache constructed"),
+            };
+            info!("Payload generation took {:?}", start.elapsed());
+            debug!("Payload: {:#?}", blocks);
+            f(cache, blocks)?;
+        }
+        Ok(())
+    }
+}
+
+/// The name of a payload variant as written in configuration.
+fn variant_name(variant: &lading_payload::Config) -> String {
+    match serde_json::to_value(variant) {
+        Ok(serde_json::Value::String(name)) => name,
+        Ok(serde_json::Value::Object(map)) => map.keys().next().cloned().unwrap_or_default(),
+        _ => String::from("unknown"),
     }
+}
+
+fn check(spec: &Spec) -> Result<(), Error> {
+    spec.for_each_cache(|_, blocks| {
+        let mut total_generated_bytes: u32 = 0;
+        for block in &blocks {
+            total_generated_bytes += block.total_bytes.get();
+        }
+        if spec.total_bytes.get() != total_generated_bytes {
+            let total_requested_bytes =
+                byte_unit::Byte::from_bytes(spec.total_bytes.get().into());
+            let total_requested_bytes_str = total_requested_bytes
+                .get_appropriate_unit(false)
+                .to_string();
+            let total_generated_bytes = byte_unit::Byte::from_bytes(total_generated_bytes.into());
+            let total_generated_bytes_str = total_generated_bytes
+                .get_appropriate_unit(false)
+                .to_string();
+            warn!("Generator failed to generate {total_requested_bytes_str}, instead only found {total_generated_bytes_str} of data")
+        }
+        Ok(())
+    })
+}
 
+fn dump(spec: &Spec, output_dir: Option<&Path>) -> Result<(), Error> {
+    if let Some(output_dir) = output_dir {
+        fs::create_dir_all(output_dir)?;
+    }
+    let mut stdout = io::stdout().lock();
+    spec.for_each_cache(|cache, blocks| {
+        for (index, block) in blocks.iter().enumerate() {
+            match output_dir {
+                Some(output_dir) => fs::write(
+                    output_dir.join(format!("{}-{cache}-{index:06}.block", spec.label)),
+                    &block.bytes,
+                )?,
+                None => stdout.write_all(&block.bytes)?,
+            }
+        }
+        Ok(())
+    })?;
+    stdout.flush()?;
     Ok(())
 }
 
-fn check_generator(config: &lading::generator::Config) -> Result<(), Error> {
-    match &config.inner {
-        lading::generator::Inner::FileGen(_) => unimplemented!("FileGen not supported"),
-        lading::generator::Inner::UnixDatagram(g) => {
-            let max_block_size =
-                byte_unit::Byte::from_unit(UDP_PACKET_LIMIT_BYTES.into(), byte_unit::ByteUnit::B)
-                    .expect("valid bytes");
-            let total_bytes =
-                NonZeroU32::new(g.maximum_prebuild_cache_size_bytes.get_bytes() as u32)
-                    .expect("Non-zero max prebuild cache size");
-            generate_and_check(&g.variant, g.seed, total_bytes, max_block_size)?;
+/// Statistics of a run of bytes.
+///
+/// Lines are newline terminated, for line-oriented payloads these are the
+/// events. For binary payloads the line statistics are not meaningful.
+#[derive(Default)]
+struct Stats {
+    blocks: usize,
+    bytes: usize,
+    byte_counts: Vec<u64>,
+    gzip_bytes: usize,
+    zstd_bytes: usize,
+    line_lengths: Vec<usize>,
+}
+
+impl Stats {
+    fn of(bytes: &[u8]) -> Result<Self, Error> {
+        let mut byte_counts = vec![0; 256];
+        for byte in bytes {
+            byte_counts[usize::from(*byte)] += 1;
         }
-        lading::generator::Inner::Tcp(g) => {
-            let total_bytes =
-                NonZeroU32::new(g.maximum_prebuild_cache_size_bytes.get_bytes() as u32)
-                    .expect("Non-zero max prebuild cache size");
-            generate_and_check(&g.variant, g.seed, total_bytes, g.maximum_block_size)?;
+
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(bytes)?;
+
+        Ok(Self {
+            blocks: 1,
+            bytes: bytes.len(),
+            byte_counts,
+            gzip_bytes: gzip.finish()?.len(),
+            zstd_bytes: zstd::bulk::compress(bytes, zstd::DEFAULT_COMPRESSION_LEVEL)?.len(),
+            line_lengths: bytes
+                .split_inclusive(|byte| *byte == b'\n')
+                .filter(|line| line.ends_with(b"\n"))
+                .map(|line| line.len() - 1)
+                .collect(),
+        })
+    }
+
+    fn add(&mut self, other: Self) {
+        if self.byte_counts.is_empty() {
+            self.byte_counts = vec![0; 256];
         }
-        lading::generator::Inner::Udp(g) => {
-            let total_bytes =
-                NonZeroU32::new(g.maximum_prebuild_cache_size_bytes.get_bytes() as u32)
-                    .expect("Non-zero max prebuild cache size");
-            let max_block_size =
-                byte_unit::Byte::from_unit(UDP_PACKET_LIMIT_BYTES.into(), byte_unit::ByteUnit::B)
-                    .expect("valid bytes");
-            generate_and_check(&g.variant, g.seed, total_bytes, max_block_size)?;
+        self.blocks += other.blocks;
+        self.bytes += other.bytes;
+        for (count, other) in self.byte_counts.iter_mut().zip(other.byte_counts) {
+            *count += other;
         }
-        lading::generator::Inner::Http(g) => {
-            let (variant, max_prebuild_cache_size_bytes) = match &g.method {
-                Method::Post {
-                    variant,
-                    maximum_prebuild_cache_size_bytes,
-                    block_cache_method: _,
-                } => (variant, maximum_prebuild_cache_size_bytes),
-            };
-            let total_bytes = NonZeroU32::new(max_prebuild_cache_size_bytes.get_bytes() as u32)
-                .expect("Non-zero max prebuild cache size");
-            generate_and_check(variant, g.seed, total_bytes, g.maximum_block_size)?;
+        self.gzip_bytes += other.gzip_bytes;
+        self.zstd_bytes += other.zstd_bytes;
+        self.line_lengths.extend(other.line_lengths);
+    }
+
+    /// Shannon entropy in bits per byte.
+    #[allow(clippy::cast_precision_loss)]
+    fn entropy(&self) -> f64 {
+        let total = self.bytes as f64;
+        self.byte_counts
+            .iter()
+            .filter(|count| **count > 0)
+            .map(|count| {
+                let p = *count as f64 / total;
+                -p * p.log2()
+            })
+            .sum()
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn ratio(&self, compressed_bytes: usize) -> f64 {
+        self.bytes as f64 / compressed_bytes.max(1) as f64
+    }
+
+    /// The line length at percentile `p`, zero if there are no lines. Line
+    /// lengths must be sorted.
+    fn line_length(&self, p: usize) -> usize {
+        self.line_lengths
+            .get(self.line_lengths.len().saturating_sub(1) * p / 100)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    fn print(&mut self, label: &str) {
+        self.line_lengths.sort_unstable();
+        println!(
+            "{label:<24} {:>10} {:>8} {:>7.3} {:>6.2} {:>6.2} {:>6} {:>6} {:>6} {:>6}",
+            self.bytes,
+            self.line_lengths.len(),
+            self.entropy(),
+            self.ratio(self.gzip_bytes),
+            self.ratio(self.zstd_bytes),
+            self.line_length(0),
+            self.line_length(50),
+            self.line_length(99),
+            self.line_length(100),
+        );
+    }
+}
+
+fn inspect(spec: &Spec, summary_only: bool) -> Result<(), Error> {
+    println!(
+        "{:<24} {:>10} {:>8} {:>7} {:>6} {:>6} {:>6} {:>6} {:>6} {:>6}",
+        "block", "bytes", "lines", "entropy", "gzip", "zstd", "min", "p50", "p99", "max"
+    );
+
+    let mut summary = Stats::default();
+    // Block sizes bucketed by the next power of two.
+    let mut sizes: BTreeMap<usize, usize> = BTreeMap::new();
+    let mut defects: Option<adversarial::Counts> = None;
+    spec.for_each_cache(|cache, blocks| {
+        for (index, block) in blocks.iter().enumerate() {
+            if let Some(counts) = &block.defects {
+                defects.get_or_insert_with(Default::default).add(counts);
+            }
+            let mut stats = Stats::of(&block.bytes)?;
+            if !summary_only {
+                stats.print(&format!("{}-{cache}-{index:06}", spec.label));
+            }
+            *sizes.entry(stats.bytes.next_power_of_two()).or_default() += 1;
+            summary.add(stats);
         }
-        lading::generator::Inner::SplunkHec(_) => unimplemented!("SplunkHec not supported"),
-        lading::generator::Inner::FileTree(_) => unimplemented!("FileTree not supported"),
-        lading::generator::Inner::Grpc(g) => {
-            let total_bytes =
-                NonZeroU32::new(g.maximum_prebuild_cache_size_bytes.get_bytes() as u32)
-                    .expect("Non-zero max prebuild cache size");
-            generate_and_check(&g.variant, g.seed, total_bytes, g.maximum_block_size)?;
+        Ok(())
+    })?;
+    summary.print(&spec.label);
+
+    println!(
+        "{} blocks of {}, block sizes:",
+        summary.blocks,
+        variant_name(spec.variant)
+    );
+    for (size, count) in sizes {
+        let size = byte_unit::Byte::from_bytes(size as u128)
+            .get_appropriate_unit(true)
+            .to_string();
+        println!("  <= {size:>12}: {count}");
+    }
+    if let Some(defects) = defects {
+        println!("defects:");
+        for (defect, count) in defects.iter() {
+            println!("  {:>18}: {count}", defect.name());
         }
-        lading::generator::Inner::UnixStream(g) => {
-            let total_bytes =
-                NonZeroU32::new(g.maximum_prebuild_cache_size_bytes.get_bytes() as u32)
-                    .expect("Non-zero max prebuild cache size");
-            generate_and_check(&g.variant, g.seed, total_bytes, g.maximum_block_size)?;
+    }
+    Ok(())
+}
+
+/// Validate every generated block, returning the number that do not conform.
+fn validate(spec: &Spec) -> Result<usize, Error> {
+    let mut blocks = 0;
+    let mut messages = 0;
+    let mut invalid = 0;
+    spec.for_each_cache(|cache, cache_blocks| {
+        for (index, block) in cache_blocks.iter().enumerate() {
+            blocks += 1;
+            match conformance::validate(spec.variant, &block.bytes) {
+                Ok(count) => messages += count,
+                Err(err) => {
+                    invalid += 1;
+                    error!("{}-{cache}-{index:06} does not conform: {err}", spec.label);
+                }
+            }
         }
-        lading::generator::Inner::PassthruFile(g) => {
-            let total_bytes =
-                NonZeroU32::new(g.maximum_prebuild_cache_size_bytes.get_bytes() as u32)
-                    .expect("Non-zero max prebuild cache size");
-            generate_and_check(&g.variant, g.seed, total_bytes, g.maximum_block_size)?;
+        Ok(())
+    })?;
+    println!(
+        "{} ({}): {blocks} blocks, {messages} messages, {invalid} invalid",
+        spec.label,
+        variant_name(spec.variant),
+    );
+    Ok(invalid)
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn bench(spec: &Spec, iterations: u32) -> Result<(), Error> {
+    let mut elapsed = Vec::new();
+    let mut bytes = 0;
+    for _ in 0..iterations {
+        let mut rng = StdRng::from_seed(spec.seed);
+        let start = Instant::now();
+        let cache = block::Cache::fixed(
+            &mut rng,
+            spec.total_bytes,
+            spec.max_block_size.get_bytes(),
+            spec.variant,
+        )?;
+        elapsed.push(start.elapsed());
+        if let block::Cache::Fixed { blocks, .. } = cache {
+            bytes = blocks.iter().map(|block| block.bytes.len()).sum::<usize>();
         }
-        lading::generator::Inner::ProcessTree(_) => unimplemented!("ProcessTree not supported"),
-        lading::generator::Inner::ProcFs(_) => unimplemented!("ProcFs not supported"),
-    };
+    }
 
+    let mib_per_second =
+        |elapsed: &Duration| bytes as f64 / elapsed.as_secs_f64() / (1024.0 * 1024.0);
+    let (Some(fastest), Some(slowest)) = (elapsed.iter().min(), elapsed.iter().max()) else {
+        return Ok(());
+    };
+    let total: Duration = elapsed.iter().sum();
+    println!(
+        "{} ({}): {bytes} bytes, {:.1} MiB/s (min {:.1}, max {:.1})",
+        spec.label,
+        variant_name(spec.variant),
+        mib_per_second(&(total / iterations)),
+        mib_per_second(slowest),
+        mib_per_second(fastest),
+    );
     Ok(())
 }
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<(), Error> {
+    // Logs go to stderr, stdout is reserved for payloads and statistics.
     tracing_subscriber::fmt()
         .with_span_events(FmtSpan::CLOSE)
         .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
         .with_ansi(false)
+        .with_writer(io::stderr)
         .finish()
         .init();
 
@@ -167,11 +488,12 @@ async fn main() -> Result<(), Error> {
         config.generator.len()
     );
 
-    if let Some(generator_id) = args.generator_id {
-        let generator = config
+    let specs: Vec<Spec> = if let Some(generator_id) = args.generator_id {
+        let (index, generator) = config
             .generator
             .iter()
-            .find(|g| {
+            .enumerate()
+            .find(|(_, g)| {
                 let Some(ref id) = g.general.id else {
                     return false;
                 };
@@ -181,12 +503,29 @@ async fn main() -> Result<(), Error> {
                 error!("No generator found with id: {}", generator_id);
                 Error::InvalidArgs
             })?;
-        check_generator(generator)?;
+        vec![Spec::new(index, generator)]
     } else {
-        for generator in config.generator {
-            check_generator(&generator)?;
+        config
+            .generator
+            .iter()
+            .enumerate()
+            .map(|(index, generator)| Spec::new(index, generator))
+            .collect()
+    };
+
+    let mut nonconforming = 0;
+    for spec in &specs {
+        match args.command {
+            None | Some(Command::Check) => check(spec)?,
+            Some(Command::Dump { ref output_dir }) => dump(spec, output_dir.as_deref())?,
+            Some(Command::Inspect { summary_only }) => inspect(spec, summary_only)?,
+            Some(Command::Validate) => nonconforming += validate(spec)?,
+            Some(Command::Bench { iterations }) => bench(spec, iterations)?,
         }
     }
+    if nonconforming > 0 {
+        return Err(Error::Nonconforming(nonconforming));
+    }
 
     Ok(())
 }
@@ -8,9 +8,12 @@
 use serde::{Deserialize, Serialize};
 
 mod common;
+pub mod fluent;
 pub mod http;
+pub mod slow_consumer;
 pub mod splunk_hec;
 pub mod sqs;
+pub mod syslog;
 pub mod tcp;
 pub mod udp;
 pub mod unix_datagram;
@@ -40,9 +43,15 @@ pub enum Error {
     /// See [`crate::blackhole::sqs::Error`] for details.
     #[error(transparent)]
     Sqs(sqs::Error),
+    /// See [`crate::blackhole::syslog::Error`] for details.
+    #[error(transparent)]
+    Syslog(syslog::Error),
+    /// See [`crate::blackhole::fluent::Error`] for details.
+    #[error(transparent)]
+    Fluent(fluent::Error),
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 #[serde(rename_all = "snake_case")]
 #[serde(deny_unknown_fields)]
 /// Configuration for [`Server`]
@@ -64,7 +73,7 @@ pub struct General {
     pub id: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 #[serde(rename_all = "snake_case")]
 #[serde(deny_unknown_fields)]
 /// Configuration for [`Server`]
@@ -83,6 +92,10 @@ pub enum Inner {
     UnixDatagram(unix_datagram::Config),
     /// See [`crate::blackhole::sqs::Config`] for details.
     Sqs(sqs::Config),
+    /// See [`crate::blackhole::syslog::Config`] for details.
+    Syslog(syslog::Config),
+    /// See [`crate::blackhole::fluent::Config`] for details.
+    Fluent(fluent::Config),
 }
 
 #[derive(Debug)]
@@ -105,6 +118,10 @@ pub enum Server {
     UnixDatagram(unix_datagram::UnixDatagram),
     /// See [`crate::blackhole::sqs::Sqs`] for details.
     Sqs(sqs::Sqs),
+    /// See [`crate::blackhole::syslog::Syslog`] for details.
+    Syslog(syslog::Syslog),
+    /// See [`crate::blackhole::fluent::Fluent`] for details.
+    Fluent(fluent::Fluent),
 }
 
 impl Server {
@@ -119,23 +136,35 @@ impl Server {
     /// signals error.
     pub fn new(config: Config, shutdown: lading_signal::Watcher) -> Result<Self, Error> {
         let server = match config.inner {
-            Inner::Tcp(conf) => Self::Tcp(tcp::Tcp::new(config.general, &conf, shutdown)),
+            Inner::Tcp(conf) => {
+                Self::Tcp(tcp::Tcp::new(config.general, &conf, shutdown).map_err(Error::Tcp)?)
+            }
             Inner::Http(conf) => {
                 Self::Http(http::Http::new(config.general, &conf, shutdown).map_err(Error::Http)?)
             }
-            Inner::Udp(conf) => Self::Udp(udp::Udp::new(config.general, &conf, shutdown)),
-            Inner::UnixStream(conf) => {
-                Self::UnixStream(unix_stream::UnixStream::new(config.general, conf, shutdown))
+            Inner::Udp(conf) => {
+                Self::Udp(udp::Udp::new(config.general, &conf, shutdown).map_err(Error::Udp)?)
             }
+            Inner::UnixStream(conf) => Self::UnixStream(
+                unix_stream::UnixStream::new(config.general, conf, shutdown)
+                    .map_err(Error::UnixStream)?,
+            ),
             Inner::UnixDatagram(conf) => Self::UnixDatagram(unix_datagram::UnixDatagram::new(
                 config.general,
                 conf,
                 shutdown,
             )),
             Inner::Sqs(conf) => Self::Sqs(sqs::Sqs::new(config.general, &conf, shutdown)),
-            Inner::SplunkHec(conf) => {
-                Self::SplunkHec(splunk_hec::SplunkHec::new(config.general, &conf, shutdown))
+            Inner::Syslog(conf) => {
+                Self::Syslog(syslog::Syslog::new(config.general, &conf, shutdown))
+            }
+            Inner::Fluent(conf) => {
+                Self::Fluent(fluent::Fluent::new(config.general, &conf, shutdown))
             }
+            Inner::SplunkHec(conf) => Self::SplunkHec(
+                splunk_hec::SplunkHec::new(config.general, &conf, shutdown)
+                    .map_err(Error::SplunkHec)?,
+            ),
         };
         Ok(server)
     }
@@ -157,6 +186,8 @@ impl Server {
             Server::UnixStream(inner) => inner.run().await.map_err(Error::UnixStream),
             Server::UnixDatagram(inner) => Box::pin(inner.run()).await.map_err(Error::UnixDatagram),
             Server::Sqs(inner) => inner.run().await.map_err(Error::Sqs),
+            Server::Syslog(inner) => inner.run().await.map_err(Error::Syslog),
+            Server::Fluent(inner) => inner.run().await.map_err(Error::Fluent),
             Server::SplunkHec(inner) => inner.run().await.map_err(Error::SplunkHec),
         }
     }
@@ -6,14 +6,15 @@ use hyper_util::{
     server::conn::auto,
 };
 use lading_signal::Watcher;
-use metrics::gauge;
+use metrics::{counter, gauge};
 use std::{net::SocketAddr, sync::Arc};
 use tokio::{
-    net::TcpListener,
+    net::{TcpListener, TcpStream},
     pin,
     sync::{Semaphore, TryAcquireError},
     task::JoinSet,
 };
+use tokio_rustls::TlsAcceptor;
 use tracing::{debug, error, info, warn};
 
 #[derive(thiserror::Error, Debug)]
@@ -28,11 +29,12 @@ pub(crate) async fn run_httpd<SF, S>(
     concurrency_limit: usize,
     shutdown: Watcher,
     labels: Vec<(String, String)>,
+    tls: Option<Arc<rustls::ServerConfig>>,
     make_service: SF,
 ) -> Result<(), Error>
 where
-    // "service factory"
-    SF: Send + Sync + 'static + Clone + Fn() -> S,
+    // "service factory", called once per accepted connection
+    SF: Send + Sync + 'static + Clone + Fn(&TcpStream) -> S,
     // The bounds on `S` per
     // https://docs.rs/hyper/latest/hyper/service/trait.Service.html and then
     // made concrete per
@@ -40,9 +42,9 @@ where
     S: Service<
             hyper::Request<hyper::body::Incoming>,
             Response = hyper::Response<BoxBody<Bytes, hyper::Error>>,
-            Error = hyper::Error,
         > + Send
         + 'static,
+    S::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
 
     S::Future: Send + 'static,
 {
@@ -76,6 +78,8 @@ where
 
                 let sem = Arc::clone(&sem);
                 let service_factory = make_service.clone();
+                let tls = tls.clone().map(TlsAcceptor::from);
+                let labels = labels.clone();
 
                 join_set.spawn(async move {
                     // NOTE we are paying the cost for allocating a socket et al
@@ -98,13 +102,29 @@ where
                         }
                     };
 
+                    let service = service_factory(&stream);
                     let builder = auto::Builder::new(TokioExecutor::new());
-                    let serve_future = builder.serve_connection_with_upgrades(
-                        TokioIo::new(stream),
-                        service_factory(),
-                    );
+                    let res = match tls {
+                        None => {
+                            builder
+                                .serve_connection_with_upgrades(TokioIo::new(stream), service)
+                                .await
+                        }
+                        Some(acceptor) => match acceptor.accept(stream).await {
+                            Ok(stream) => {
+                                builder
+                                    .serve_connection_with_upgrades(TokioIo::new(stream), service)
+                                    .await
+                            }
+                            Err(e) => {
+                                counter!("tls_handshake_failure", &labels).increment(1);
+                                warn!("TLS handshake with {addr} failed: {e}");
+                                return;
+                            }
+                        },
+                    };
 
-                    if let Err(e) = serve_future.await {
+                    if let Err(e) = res {
                         error!("Error serving {addr}: {e}");
                     }
                     drop(permit);
@@ -0,0 +1,563 @@
+//! The Fluent Forward protocol speaking blackhole.
+//!
+//! Implements the receiving side of [this
+//! protocol](https://github.com/fluent/fluentd/wiki/Forward-Protocol-Specification-v1)
+//! over TCP or a unix stream socket, accepting the `Message`, `Forward`,
+//! `PackedForward` and `CompressedPackedForward` modes. Messages carrying a
+//! `chunk` option are acknowledged.
+//!
+//! ## Metrics
+//!
+//! `connection_accepted`: Incoming connections received
+//! `bytes_received`: Total bytes received
+//! `message_received`: Total Forward protocol messages received, labeled by
+//! `mode`
+//! `entries_received`: Total event entries received, labeled by `tag`. Tags
+//! beyond `maximum_tags` are counted under the tag `_other`.
+//! `ack_sent`: Acknowledgements sent in response to `chunk` options
+//! `decode_failure`: Messages that could not be decoded or that exceed
+//! `maximum_message_size`, compressed or inflated; the connection is closed
+//!
+
+use std::{
+    io::{self, Cursor, Read},
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use bytes::{Buf, BytesMut};
+use flate2::read::MultiGzDecoder;
+use metrics::counter;
+use rmpv::Value;
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, UnixListener},
+};
+use tracing::{info, warn};
+
+use super::General;
+
+const READ_SIZE: usize = 64 * 1024;
+const OTHER_TAG: &str = "_other";
+
+fn default_maximum_tags() -> u16 {
+    64
+}
+
+fn default_maximum_message_size() -> byte_unit::Byte {
+    byte_unit::Byte::from_unit(64f64, byte_unit::ByteUnit::MiB)
+        .expect("catastrophic programming bug")
+}
+
+#[derive(thiserror::Error, Debug)]
+/// Errors produced by [`Fluent`].
+pub enum Error {
+    /// Wrapper for [`std::io::Error`].
+    #[error(transparent)]
+    Io(io::Error),
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "snake_case")]
+/// Where [`Fluent`] listens.
+pub enum Listen {
+    /// address -- IP plus port -- to bind to
+    Tcp(SocketAddr),
+    /// path of the unix stream socket to bind to
+    Unix(PathBuf),
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+/// Configuration for [`Fluent`].
+pub struct Config {
+    /// Where to listen for connections
+    pub listen: Listen,
+    /// The number of distinct tags given their own `entries_received` label
+    #[serde(default = "default_maximum_tags")]
+    pub maximum_tags: u16,
+    /// The largest message buffered per connection. A connection sending a
+    /// larger message is closed.
+    #[serde(default = "default_maximum_message_size")]
+    pub maximum_message_size: byte_unit::Byte,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Message,
+    Forward,
+    PackedForward,
+    CompressedPackedForward,
+}
+
+impl Mode {
+    fn as_str(self) -> &'static str {
+        match self {
+            Mode::Message => "message",
+            Mode::Forward => "forward",
+            Mode::PackedForward => "packed_forward",
+            Mode::CompressedPackedForward => "compressed_packed_forward",
+        }
+    }
+}
+
+/// A single decoded Forward protocol message.
+#[derive(Debug, PartialEq)]
+struct Decoded {
+    mode: Mode,
+    tag: String,
+    entries: u64,
+    chunk: Option<String>,
+}
+
+/// Interpret a msgpack value as a Forward protocol message. Compressed
+/// entries may inflate to at most `maximum_message_size` bytes.
+fn decode(value: &Value, maximum_message_size: usize) -> Result<Decoded, &'static str> {
+    let items = value.as_array().ok_or("message is not an array")?;
+    let tag = items
+        .first()
+        .and_then(Value::as_str)
+        .ok_or("message has no tag")?
+        .to_string();
+    let (mode, entries, option) = match items.get(1) {
+        Some(Value::Array(entries)) => (Mode::Forward, entries.len() as u64, items.get(2)),
+        Some(Value::Binary(raw)) => {
+            let (mode, entries) = decode_packed(raw, items.get(2), maximum_message_size)?;
+            (mode, entries, items.get(2))
+        }
+        Some(Value::String(raw)) => {
+            let (mode, entries) =
+                decode_packed(raw.as_bytes(), items.get(2), maximum_message_size)?;
+            (mode, entries, items.get(2))
+        }
+        // Message mode: [tag, time, record, option?], time being an integer
+        // or EventTime extension.
+        Some(Value::Integer(_) | Value::F64(_) | Value::F32(_) | Value::Ext(..)) => {
+            if !items.get(2).is_some_and(Value::is_map) {
+                return Err("message record is not a map");
+            }
+            (Mode::Message, 1, items.get(3))
+        }
+        _ => return Err("unrecognized message mode"),
+    };
+    let chunk = option
+        .and_then(|option| lookup(option, "chunk"))
+        .and_then(Value::as_str)
+        .map(ToString::to_string);
+    Ok(Decoded {
+        mode,
+        tag,
+        entries,
+        chunk,
+    })
+}
+
+/// Interpret the entry stream of a `PackedForward` or, if `option` says so,
+/// `CompressedPackedForward` message, returning its mode and entry count.
+fn decode_packed(
+    raw: &[u8],
+    option: Option<&Value>,
+    maximum_message_size: usize,
+) -> Result<(Mode, u64), &'static str> {
+    let compressed = option
+        .and_then(|option| lookup(option, "compressed"))
+        .and_then(Value::as_str);
+    match compressed {
+        Some("gzip") => {
+            // Inflate no more than the limit, and a byte to tell that the
+            // entries exceed it.
+            let mut inflated = Vec::new();
+            MultiGzDecoder::new(raw)
+                .take(maximum_message_size as u64 + 1)
+                .read_to_end(&mut inflated)
+                .map_err(|_| "compressed entries are not gzip")?;
+            if inflated.len() > maximum_message_size {
+                return Err("compressed entries exceed the maximum message size");
+            }
+            Ok((Mode::CompressedPackedForward, count_entries(&inflated)?))
+        }
+        Some(_) => Err("unknown compression"),
+        None => Ok((Mode::PackedForward, count_entries(raw)?)),
+    }
+}
+
+fn lookup<'a>(map: &'a Value, key: &str) -> Option<&'a Value> {
+    map.as_map()?
+        .iter()
+        .find(|(k, _)| k.as_str() == Some(key))
+        .map(|(_, v)| v)
+}
+
+/// Count the `[time, record]` entries in a packed entry stream.
+fn count_entries(mut raw: &[u8]) -> Result<u64, &'static str> {
+    let mut entries = 0;
+    while !raw.is_empty() {
+        rmpv::decode::read_value(&mut raw).map_err(|_| "packed entries are not msgpack")?;
+        entries += 1;
+    }
+    Ok(entries)
+}
+
+fn is_incomplete(err: &rmpv::decode::Error) -> bool {
+    match err {
+        rmpv::decode::Error::InvalidMarkerRead(e) | rmpv::decode::Error::InvalidDataRead(e) => {
+            e.kind() == io::ErrorKind::UnexpectedEof
+        }
+        rmpv::decode::Error::DepthLimitExceeded => false,
+    }
+}
+
+type MetricLabels = Arc<Vec<(String, String)>>;
+
+/// Metric labels, with `entries_received` labels created per tag up to a
+/// maximum.
+#[derive(Debug)]
+struct Labels {
+    base: Vec<(String, String)>,
+    modes: Vec<Vec<(String, String)>>,
+    maximum_tags: usize,
+    tags: Mutex<FxHashMap<String, MetricLabels>>,
+    other: MetricLabels,
+}
+
+impl Labels {
+    fn new(base: Vec<(String, String)>, maximum_tags: usize) -> Self {
+        let modes = [
+            Mode::Message,
+            Mode::Forward,
+            Mode::PackedForward,
+            Mode::CompressedPackedForward,
+        ]
+        .into_iter()
+        .map(|mode| {
+            let mut labels = base.clone();
+            labels.push(("mode".to_string(), mode.as_str().to_string()));
+            labels
+        })
+        .collect();
+        let mut other = base.clone();
+        other.push(("tag".to_string(), OTHER_TAG.to_string()));
+        Self {
+            base,
+            modes,
+            maximum_tags,
+            tags: Mutex::new(FxHashMap::default()),
+            other: Arc::new(other),
+        }
+    }
+
+    fn mode(&self, mode: Mode) -> &[(String, String)] {
+        &self.modes[mode as usize]
+    }
+
+    fn tag(&self, tag: &str) -> MetricLabels {
+        let mut tags = self.tags.lock().expect("tag labels poisoned");
+        if let Some(labels) = tags.get(tag) {
+            return Arc::clone(labels);
+        }
+        if tags.len() >= self.maximum_tags {
+            return Arc::clone(&self.other);
+        }
+        let mut labels = self.base.clone();
+        labels.push(("tag".to_string(), tag.to_string()));
+        let labels = Arc::new(labels);
+        tags.insert(tag.to_string(), Arc::clone(&labels));
+        labels
+    }
+}
+
+#[derive(Debug)]
+/// The Fluent Forward blackhole.
+pub struct Fluent {
+    listen: Listen,
+    maximum_tags: usize,
+    maximum_message_size: usize,
+    shutdown: lading_signal::Watcher,
+    metric_labels: Vec<(String, String)>,
+}
+
+impl Fluent {
+    /// Create a new [`Fluent`] server instance
+    #[must_use]
+    pub fn new(general: General, config: &Config, shutdown: lading_signal::Watcher) -> Self {
+        let mut metric_labels = vec![
+            ("component".to_string(), "blackhole".to_string()),
+            ("component_name".to_string(), "fluent".to_string()),
+        ];
+        if let Some(id) = general.id {
+            metric_labels.push(("id".to_string(), id));
+        }
+
+        Self {
+            listen: config.listen.clone(),
+            maximum_tags: usize::from(config.maximum_tags),
+            maximum_message_size: usize::try_from(config.maximum_message_size.get_bytes())
+                .unwrap_or(usize::MAX),
+            shutdown,
+            metric_labels,
+        }
+    }
+
+    async fn handle_connection<S>(mut socket: S, labels: Arc<Labels>, maximum_message_size: usize)
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut buf = BytesMut::with_capacity(READ_SIZE);
+        loop {
+            // Decode every complete message buffered so far.
+            loop {
+                let mut cursor = Cursor::new(&buf[..]);
+                let value = match rmpv::decode::read_value(&mut cursor) {
+                    Ok(value) => value,
+                    Err(err) if is_incomplete(&err) => break,
+                    Err(err) => {
+                        counter!("decode_failure", &labels.base).increment(1);
+                        warn!("closing fluent connection, invalid msgpack: {err}");
+                        return;
+                    }
+                };
+                let used = usize::try_from(cursor.position()).expect("cursor beyond buffer");
+                buf.advance(used);
+
+                let decoded = match decode(&value, maximum_message_size) {
+                    Ok(decoded) => decoded,
+                    Err(err) => {
+                        counter!("decode_failure", &labels.base).increment(1);
+                        warn!("closing fluent connection: {err}");
+                        return;
+                    }
+                };
+                counter!("message_received", labels.mode(decoded.mode)).increment(1);
+                counter!("entries_received", labels.tag(&decoded.tag).as_slice())
+                    .increment(decoded.entries);
+                if let Some(chunk) = decoded.chunk {
+                    let ack = Value::Map(vec![(Value::from("ack"), Value::from(chunk))]);
+                    let mut encoded = Vec::new();
+                    rmpv::encode::write_value(&mut encoded, &ack).expect("failed to encode ack");
+                    if let Err(err) = socket.write_all(&encoded).await {
+                        warn!("failed to send fluent ack: {err}");
+                        return;
+                    }
+                    counter!("ack_sent", &labels.base).increment(1);
+                }
+            }
+
+            // The buffer holds one incomplete message, which is not allowed
+            // to grow without bound.
+            let Some(room) = maximum_message_size
+                .checked_sub(buf.len())
+                .filter(|&n| n > 0)
+            else {
+                counter!("decode_failure", &labels.base).increment(1);
+                warn!("closing fluent connection, message exceeds {maximum_message_size} bytes");
+                return;
+            };
+            // Grow reads with the buffer so that a large message is not
+            // re-parsed once per small read.
+            let read_size = READ_SIZE.max(buf.len()).min(room);
+            buf.reserve(read_size);
+            match (&mut socket)
+                .take(read_size as u64)
+                .read_buf(&mut buf)
+                .await
+            {
+                Ok(0) => return,
+                Ok(bytes) => {
+                    counter!("bytes_received", &labels.base).increment(bytes as u64);
+                }
+                Err(err) => {
+                    warn!("fluent connection read failed: {err}");
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Run [`Fluent`] to completion
+    ///
+    /// This function runs the Fluent server forever, unless a shutdown signal
+    /// is received or an unrecoverable error is encountered.
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if binding to the assigned address fails.
+    ///
+    /// # Panics
+    ///
+    /// None known.
+    pub async fn run(self) -> Result<(), Error> {
+        let labels = Arc::new(Labels::new(self.metric_labels.clone(), self.maximum_tags));
+
+        let shutdown_wait = self.shutdown.recv();
+        tokio::pin!(shutdown_wait);
+        match self.listen {
+            Listen::Tcp(addr) => {
+                let listener = TcpListener::bind(addr).await.map_err(Error::Io)?;
+                loop {
+                    tokio::select! {
+                        conn = listener.accept() => {
+                            let (socket, _) = conn.map_err(Error::Io)?;
+                            counter!("connection_accepted", &self.metric_labels).increment(1);
+                            tokio::spawn(Self::handle_connection(socket, Arc::clone(&labels), self.maximum_message_size));
+                        }
+                        () = &mut shutdown_wait => {
+                            info!("shutdown signal received");
+                            return Ok(())
+                        }
+                    }
+                }
+            }
+            Listen::Unix(path) => {
+                // Sockets cannot be rebound if they existed previously. Delete
+                // the socket, ignore any errors.
+                let _res = std::fs::remove_file(&path);
+                let listener = UnixListener::bind(&path).map_err(Error::Io)?;
+                loop {
+                    tokio::select! {
+                        conn = listener.accept() => {
+                            let (socket, _) = conn.map_err(Error::Io)?;
+                            counter!("connection_accepted", &self.metric_labels).increment(1);
+                            tokio::spawn(Self::handle_connection(socket, Arc::clone(&labels), self.maximum_message_size));
+                        }
+                        () = &mut shutdown_wait => {
+                            info!("shutdown signal received");
+                            return Ok(())
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, sync::Arc, time::Duration};
+
+    use flate2::{write::GzEncoder, Compression};
+    use rmpv::Value;
+    use tokio::io::AsyncWriteExt;
+
+    use super::{decode, Decoded, Fluent, Labels, Mode};
+
+    const MAXIMUM: usize = 64 * 1024;
+
+    fn entry() -> Value {
+        Value::Array(vec![
+            Value::from(1_700_000_000),
+            Value::Map(vec![(Value::from("message"), Value::from("hello"))]),
+        ])
+    }
+
+    fn packed(count: usize) -> Vec<u8> {
+        let mut raw = Vec::new();
+        for _ in 0..count {
+            rmpv::encode::write_value(&mut raw, &entry()).expect("failed to encode");
+        }
+        raw
+    }
+
+    fn chunk_option() -> Value {
+        Value::Map(vec![(Value::from("chunk"), Value::from("abc"))])
+    }
+
+    #[test]
+    fn decodes_all_modes() {
+        let message = Value::Array(vec![
+            Value::from("app.log"),
+            Value::from(1_700_000_000),
+            Value::Map(vec![]),
+            chunk_option(),
+        ]);
+        assert_eq!(
+            decode(&message, MAXIMUM),
+            Ok(Decoded {
+                mode: Mode::Message,
+                tag: "app.log".to_string(),
+                entries: 1,
+                chunk: Some("abc".to_string()),
+            })
+        );
+
+        let forward = Value::Array(vec![
+            Value::from("app.log"),
+            Value::Array(vec![entry(), entry(), entry()]),
+        ]);
+        assert_eq!(
+            decode(&forward, MAXIMUM).map(|d| (d.mode, d.entries, d.chunk)),
+            Ok((Mode::Forward, 3, None))
+        );
+
+        let packed_forward = Value::Array(vec![
+            Value::from("app.log"),
+            Value::Binary(packed(5)),
+            chunk_option(),
+        ]);
+        assert_eq!(
+            decode(&packed_forward, MAXIMUM).map(|d| (d.mode, d.entries, d.chunk)),
+            Ok((Mode::PackedForward, 5, Some("abc".to_string())))
+        );
+
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(&packed(7)).expect("failed to compress");
+        let compressed = Value::Array(vec![
+            Value::from("app.log"),
+            Value::Binary(gz.finish().expect("failed to compress")),
+            Value::Map(vec![(Value::from("compressed"), Value::from("gzip"))]),
+        ]);
+        assert_eq!(
+            decode(&compressed, MAXIMUM).map(|d| (d.mode, d.entries)),
+            Ok((Mode::CompressedPackedForward, 7))
+        );
+
+        assert!(decode(&Value::from("nope"), MAXIMUM).is_err());
+        assert!(decode(&Value::Array(vec![Value::from("tag"), Value::Nil]), MAXIMUM).is_err());
+    }
+
+    // Compressed entries that inflate beyond the maximum message size are
+    // rejected without inflating them in full.
+    #[test]
+    fn compressed_entries_are_bounded() {
+        let mut gz = GzEncoder::new(Vec::new(), Compression::best());
+        // Each zero byte is a msgpack entry, 16 MiB of them compress to some
+        // KiB.
+        gz.write_all(&vec![0; 16 << 20])
+            .expect("failed to compress");
+        let bomb = gz.finish().expect("failed to compress");
+        assert!(bomb.len() < MAXIMUM);
+        let message = Value::Array(vec![
+            Value::from("app.log"),
+            Value::Binary(bomb),
+            Value::Map(vec![(Value::from("compressed"), Value::from("gzip"))]),
+        ]);
+        assert_eq!(
+            decode(&message, MAXIMUM).map(|d| d.entries),
+            Err("compressed entries exceed the maximum message size")
+        );
+        assert_eq!(decode(&message, 16 << 20).map(|d| d.entries), Ok(16 << 20));
+    }
+
+    // A connection whose message outgrows the buffer cap is closed rather than
+    // buffered without bound.
+    #[tokio::test]
+    async fn oversized_message_closes_connection() {
+        let (mut client, server) = tokio::io::duplex(4096);
+        let labels = Arc::new(Labels::new(Vec::new(), 1));
+        let handler = tokio::spawn(Fluent::handle_connection(server, labels, 64 * 1024));
+
+        // A message whose entries, bin32 of 1 MiB, never finish arriving.
+        let mut message = vec![0x92, 0xa3, b't', b'a', b'g', 0xc6];
+        message.extend_from_slice(&(1_u32 << 20).to_be_bytes());
+        let _ = client.write_all(&message).await;
+        let _ = client.write_all(&[0; 128 * 1024]).await;
+
+        tokio::time::timeout(Duration::from_secs(5), handler)
+            .await
+            .expect("connection was not closed")
+            .expect("handler panicked");
+    }
+}
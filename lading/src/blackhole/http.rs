@@ -4,19 +4,24 @@
 //!
 //! `bytes_received`: Total bytes received
 //! `requests_received`: Total requests received
+//! `fault_injected`: Requests answered with a fault, labeled by `fault`
+//! `tls_handshake_failure`: TLS handshakes that did not complete
 //!
 
 use bytes::Bytes;
+use futures::{stream, StreamExt};
 use http::{header::InvalidHeaderValue, status::InvalidStatusCode, HeaderMap};
-use http_body_util::{combinators::BoxBody, BodyExt};
-use hyper::{header, Request, Response, StatusCode};
+use http_body_util::{combinators::BoxBody, BodyExt, StreamBody};
+use hyper::{body::Frame, header, Request, Response, StatusCode};
 use metrics::counter;
 use serde::{Deserialize, Serialize};
-use std::{net::SocketAddr, time::Duration};
-use tracing::error;
+use std::{net::SocketAddr, os::fd::AsFd, sync::Arc, time::Duration};
+use tracing::{error, warn};
 
 use super::General;
 
+pub mod fault;
+
 fn default_concurrent_requests_max() -> usize {
     100
 }
@@ -39,6 +44,21 @@ pub enum Error {
     /// Wrapper for [`crate::blackhole::common::Error`].
     #[error(transparent)]
     Common(#[from] crate::blackhole::common::Error),
+    /// Wrapper for [`crate::tls::Error`].
+    #[error(transparent)]
+    Tls(#[from] crate::tls::Error),
+    /// The fault injection configuration was not valid.
+    #[error("The fault injection configuration was not valid: {0}")]
+    InvalidFaults(&'static str),
+}
+
+/// Errors returned from the service, causing the connection to be dropped.
+#[derive(thiserror::Error, Debug)]
+enum ServiceError {
+    #[error(transparent)]
+    Hyper(#[from] hyper::Error),
+    #[error("connection reset by fault injection")]
+    Reset,
 }
 
 /// Body variant supported by this blackhole.
@@ -79,7 +99,7 @@ fn default_headers() -> HeaderMap {
     map
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 #[serde(deny_unknown_fields)]
 /// Configuration for [`Http`]
 pub struct Config {
@@ -103,6 +123,12 @@ pub struct Config {
     /// delay to add before making a response
     #[serde(default = "default_response_delay_millis")]
     pub response_delay_millis: u64,
+    /// fault injection configuration, default none
+    #[serde(default)]
+    pub faults: Option<fault::Config>,
+    /// TLS configuration, default plaintext
+    #[serde(default)]
+    pub tls: Option<crate::tls::ServerConfig>,
 }
 
 #[derive(Serialize)]
@@ -122,6 +148,7 @@ struct KinesisPutRecordBatchResponse {
 }
 
 #[allow(clippy::borrow_interior_mutable_const)]
+#[allow(clippy::too_many_arguments)]
 async fn srv(
     status: StatusCode,
     metric_labels: Vec<(String, String)>,
@@ -129,7 +156,9 @@ async fn srv(
     req: Request<hyper::body::Incoming>,
     headers: HeaderMap,
     response_delay: Duration,
-) -> Result<hyper::Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    faults: Option<Arc<fault::Injector>>,
+    socket: Option<Arc<socket2::Socket>>,
+) -> Result<hyper::Response<BoxBody<Bytes, hyper::Error>>, ServiceError> {
     counter!("requests_received", &metric_labels).increment(1);
 
     // Split into parts
@@ -145,12 +174,66 @@ async fn srv(
         Ok(body) => {
             counter!("decoded_bytes_received", &metric_labels).increment(body.len() as u64);
 
+            let (fault, response_delay) = match faults {
+                Some(ref injector) => injector.decide(response_delay),
+                None => (fault::Fault::None, response_delay),
+            };
+            if fault != fault::Fault::None {
+                let mut fault_labels = metric_labels.clone();
+                fault_labels.push(("fault".to_string(), fault.name().to_string()));
+                counter!("fault_injected", &fault_labels).increment(1);
+            }
+
             tokio::time::sleep(response_delay).await;
 
             let mut okay = Response::default();
             *okay.status_mut() = status;
             *okay.headers_mut() = headers;
-            *okay.body_mut() = crate::full(body_bytes);
+            match fault {
+                fault::Fault::None => {
+                    *okay.body_mut() = crate::full(body_bytes);
+                }
+                fault::Fault::ServerError(code) => {
+                    // `Config::valid` rejects statuses outside 500..=599, all of which
+                    // are valid status codes.
+                    *okay.status_mut() =
+                        StatusCode::from_u16(code).expect("invalid server error status");
+                }
+                fault::Fault::TooManyRequests(retry_after) => {
+                    *okay.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+                    if let Some(secs) = retry_after {
+                        okay.headers_mut()
+                            .insert(header::RETRY_AFTER, header::HeaderValue::from(secs));
+                    }
+                }
+                fault::Fault::ConnectionReset => {
+                    // With a zero linger the kernel sends RST rather than FIN
+                    // once hyper drops the connection in response to our error.
+                    if let Some(socket) = socket {
+                        if let Err(e) = socket.set_linger(Some(Duration::ZERO)) {
+                            warn!("Unable to set linger for connection reset: {e}");
+                        }
+                    }
+                    return Err(ServiceError::Reset);
+                }
+                fault::Fault::TruncatedResponse => {
+                    // Advertise one byte more than is sent. The body is
+                    // streamed so hyper cannot know its length up front and
+                    // will abort the connection when it ends short of the
+                    // advertised Content-Length. Yielding before the end of
+                    // the stream lets hyper flush what has been written.
+                    let advertised = body_bytes.len() + 1;
+                    let sent = Bytes::from(body_bytes[..body_bytes.len() / 2].to_vec());
+                    okay.headers_mut().insert(
+                        header::CONTENT_LENGTH,
+                        header::HeaderValue::from(advertised),
+                    );
+                    let frames = stream::iter([Ok(Frame::data(sent))]).chain(
+                        stream::once(tokio::task::yield_now()).filter_map(|()| async { None }),
+                    );
+                    *okay.body_mut() = BodyExt::boxed(StreamBody::new(frames));
+                }
+            }
             Ok(okay)
         }
     }
@@ -167,6 +250,8 @@ pub struct Http {
     status: StatusCode,
     metric_labels: Vec<(String, String)>,
     response_delay: Duration,
+    faults: Option<Arc<fault::Injector>>,
+    tls: Option<Arc<rustls::ServerConfig>>,
 }
 
 impl Http {
@@ -185,6 +270,18 @@ impl Http {
         shutdown: lading_signal::Watcher,
     ) -> Result<Self, Error> {
         let status = StatusCode::from_u16(config.status).map_err(Error::InvalidStatusCode)?;
+        let faults = match config.faults {
+            Some(faults) => {
+                faults.valid().map_err(Error::InvalidFaults)?;
+                Some(Arc::new(fault::Injector::new(faults)))
+            }
+            None => None,
+        };
+        let tls = config
+            .tls
+            .as_ref()
+            .map(|tls| crate::tls::server_config(tls, crate::tls::ALPN_HTTP))
+            .transpose()?;
 
         let mut metric_labels = vec![
             ("component".to_string(), "blackhole".to_string()),
@@ -221,6 +318,8 @@ impl Http {
             shutdown,
             metric_labels,
             response_delay: Duration::from_millis(config.response_delay_millis),
+            faults,
+            tls,
         })
     }
 
@@ -239,12 +338,28 @@ impl Http {
             self.concurrency_limit,
             self.shutdown,
             self.metric_labels.clone(),
-            move || {
+            self.tls.clone(),
+            move |stream| {
                 let metric_labels = self.metric_labels.clone();
                 let body_bytes = self.body_bytes.clone();
                 let headers = self.headers.clone();
                 let status = self.status;
                 let response_delay = self.response_delay;
+                let faults = self.faults.clone();
+
+                // Resetting a connection requires a handle to the underlying
+                // socket. We only pay for duplicating the descriptor when
+                // resets might be injected.
+                let socket = faults
+                    .as_ref()
+                    .filter(|f| f.resets_connections())
+                    .and_then(|_| match stream.as_fd().try_clone_to_owned() {
+                        Ok(fd) => Some(Arc::new(socket2::Socket::from(fd))),
+                        Err(e) => {
+                            warn!("Unable to duplicate connection descriptor: {e}");
+                            None
+                        }
+                    });
 
                 hyper::service::service_fn(move |req| {
                     srv(
@@ -254,6 +369,8 @@ impl Http {
                         req,
                         headers.clone(),
                         response_delay,
+                        faults.clone(),
+                        socket.clone(),
                     )
                 })
             },
@@ -289,6 +406,8 @@ body_variant: "nothing"
                 headers: default_headers(),
                 status: default_status_code(),
                 raw_bytes: vec![],
+                faults: None,
+                tls: None,
             },
         );
     }
@@ -313,7 +432,53 @@ raw_bytes: [0x01, 0x02, 0x10]
                 headers: default_headers(),
                 status: default_status_code(),
                 raw_bytes: vec![0x01, 0x02, 0x10],
+                faults: None,
+                tls: None,
             },
         );
     }
+
+    #[test]
+    fn config_deserializes_faults() {
+        let contents = r#"
+binding_addr: "127.0.0.1:1000"
+faults:
+  seed: [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131]
+  server_error:
+    probability: 0.05
+  too_many_requests:
+    probability: 0.01
+    retry_after_seconds: 30
+  connection_reset_probability: 0.001
+  delay:
+    exponential:
+      mean_millis: 50
+      max_millis: 2000
+  schedule:
+    period_millis: 60000
+    active_millis: 10000
+"#;
+        // Enums are expressed as singleton maps, as in the top-level config.
+        let config: Config = serde_yaml::with::singleton_map_recursive::deserialize(
+            serde_yaml::Deserializer::from_str(contents),
+        )
+        .expect("Contents do not match the structure expected");
+        let faults = config.faults.expect("faults must be present");
+        assert!(faults.valid().is_ok());
+        assert_eq!(
+            faults.server_error,
+            Some(fault::ServerError {
+                probability: 0.05,
+                status: 503,
+            })
+        );
+        assert!((faults.truncated_response_probability - 0.0).abs() < f32::EPSILON);
+        assert_eq!(
+            faults.delay,
+            Some(fault::Delay::Exponential {
+                mean_millis: 50,
+                max_millis: 2000,
+            })
+        );
+    }
 }
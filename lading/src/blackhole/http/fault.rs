@@ -0,0 +1,359 @@
+//! Fault injection for the HTTP blackhole.
+//!
+//! Targets under test frequently implement retry and backoff logic that is
+//! never exercised against a blackhole that always responds identically. The
+//! types in this module allow the HTTP blackhole to probabilistically respond
+//! with server errors, rate limits, reset connections, truncated bodies and
+//! latency drawn from a distribution. Faults may be restricted to a recurring
+//! window of time, allowing the user to simulate a periodic outage.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+fn default_server_error_status() -> u16 {
+    503
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields)]
+/// Configuration for 5xx responses injected by [`Injector`].
+pub struct ServerError {
+    /// Probability between 0 and 1 that a request is answered with
+    /// `status`.
+    pub probability: f32,
+    /// The 5xx status code to respond with, default 503.
+    #[serde(default = "default_server_error_status")]
+    pub status: u16,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields)]
+/// Configuration for 429 responses injected by [`Injector`].
+pub struct TooManyRequests {
+    /// Probability between 0 and 1 that a request is answered with a 429.
+    pub probability: f32,
+    /// The value of the `Retry-After` header, in seconds. If not set no
+    /// header is included in the response.
+    pub retry_after_seconds: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+/// The distribution from which response delays are drawn.
+pub enum Delay {
+    /// Every response is delayed by exactly `millis`.
+    Constant {
+        /// The delay in milliseconds.
+        millis: u64,
+    },
+    /// Responses are delayed uniformly between `min_millis` and `max_millis`,
+    /// inclusive.
+    Uniform {
+        /// The minimum delay in milliseconds.
+        min_millis: u64,
+        /// The maximum delay in milliseconds.
+        max_millis: u64,
+    },
+    /// Responses are delayed according to an exponential distribution with
+    /// mean `mean_millis`, clamped to `max_millis`. Mimics a long-tailed
+    /// service.
+    Exponential {
+        /// The mean delay in milliseconds.
+        mean_millis: u64,
+        /// The maximum delay in milliseconds.
+        max_millis: u64,
+    },
+}
+
+impl Delay {
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_sign_loss)]
+    fn sample<R>(&self, rng: &mut R) -> Duration
+    where
+        R: Rng + ?Sized,
+    {
+        let millis = match *self {
+            Delay::Constant { millis } => millis,
+            Delay::Uniform {
+                min_millis,
+                max_millis,
+            } => rng.gen_range(min_millis..=max_millis),
+            Delay::Exponential {
+                mean_millis,
+                max_millis,
+            } => {
+                // Inverse transform sampling. `gen` produces a value in [0, 1)
+                // so `1.0 - u` is never zero.
+                let u: f64 = rng.gen();
+                let sample = -(mean_millis as f64) * (1.0 - u).ln();
+                (sample as u64).min(max_millis)
+            }
+        };
+        Duration::from_millis(millis)
+    }
+
+    fn valid(&self) -> Result<(), &'static str> {
+        match *self {
+            Delay::Constant { .. } => Ok(()),
+            Delay::Uniform {
+                min_millis,
+                max_millis,
+            } => {
+                if min_millis > max_millis {
+                    Err("delay min_millis must not be greater than max_millis")
+                } else {
+                    Ok(())
+                }
+            }
+            Delay::Exponential {
+                mean_millis,
+                max_millis,
+            } => {
+                if mean_millis > max_millis {
+                    Err("delay mean_millis must not be greater than max_millis")
+                } else {
+                    Ok(())
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+/// A recurring window in which faults are injected.
+///
+/// Time is measured from the start of the blackhole. Each period begins with
+/// `active_millis` of fault injection, followed by `period_millis -
+/// active_millis` of normal operation.
+pub struct Schedule {
+    /// The total length of each period in milliseconds.
+    pub period_millis: u64,
+    /// The length of the fault window at the start of each period in
+    /// milliseconds.
+    pub active_millis: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields)]
+/// Configuration for [`Injector`].
+///
+/// Probabilities are evaluated per request and are mutually exclusive: their
+/// sum must not exceed 1.
+pub struct Config {
+    /// The seed for random operations in this blackhole
+    pub seed: [u8; 32],
+    /// Respond with a 5xx status code
+    pub server_error: Option<ServerError>,
+    /// Respond with a 429 status code, optionally including `Retry-After`
+    pub too_many_requests: Option<TooManyRequests>,
+    /// Probability between 0 and 1 that the connection is reset rather than a
+    /// response being sent
+    #[serde(default)]
+    pub connection_reset_probability: f32,
+    /// Probability between 0 and 1 that the response body is cut short of
+    /// its advertised `Content-Length` and the connection closed
+    #[serde(default)]
+    pub truncated_response_probability: f32,
+    /// The distribution of response delays. If set this replaces the
+    /// blackhole's `response_delay_millis` while faults are active.
+    pub delay: Option<Delay>,
+    /// Restrict fault injection to a recurring window. If not set faults are
+    /// always active.
+    pub schedule: Option<Schedule>,
+}
+
+impl Config {
+    /// Determine whether the passed configuration obeys validation criteria
+    ///
+    /// # Errors
+    ///
+    /// Function will error if the configuration is invalid
+    pub fn valid(&self) -> Result<(), &'static str> {
+        let probabilities = [
+            self.server_error.map_or(0.0, |se| se.probability),
+            self.too_many_requests.map_or(0.0, |tmr| tmr.probability),
+            self.connection_reset_probability,
+            self.truncated_response_probability,
+        ];
+        if probabilities.iter().any(|p| !(0.0..=1.0).contains(p)) {
+            return Err("fault probabilities must be between 0 and 1");
+        }
+        if probabilities.iter().sum::<f32>() > 1.0 {
+            return Err("the sum of fault probabilities must not exceed 1");
+        }
+        if let Some(se) = self.server_error {
+            if !(500..=599).contains(&se.status) {
+                return Err("server_error status must be a 5xx status code");
+            }
+        }
+        if let Some(delay) = self.delay {
+            delay.valid()?;
+        }
+        if let Some(schedule) = self.schedule {
+            if schedule.period_millis == 0 {
+                return Err("schedule period_millis must not be zero");
+            }
+            if schedule.active_millis > schedule.period_millis {
+                return Err("schedule active_millis must not exceed period_millis");
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A fault to be applied to a single request.
+pub(crate) enum Fault {
+    /// Respond normally.
+    None,
+    /// Respond with the given 5xx status.
+    ServerError(u16),
+    /// Respond with 429, optionally with a `Retry-After` value in seconds.
+    TooManyRequests(Option<u32>),
+    /// Reset the connection without responding.
+    ConnectionReset,
+    /// Advertise the full body but send only part of it.
+    TruncatedResponse,
+}
+
+impl Fault {
+    /// The label value used when counting this fault.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Fault::None => "none",
+            Fault::ServerError(_) => "server_error",
+            Fault::TooManyRequests(_) => "too_many_requests",
+            Fault::ConnectionReset => "connection_reset",
+            Fault::TruncatedResponse => "truncated_response",
+        }
+    }
+}
+
+#[derive(Debug)]
+/// Decides, per request, which fault to inject.
+pub(crate) struct Injector {
+    config: Config,
+    rng: Mutex<StdRng>,
+    start: Instant,
+}
+
+impl Injector {
+    pub(crate) fn new(config: Config) -> Self {
+        Self {
+            rng: Mutex::new(StdRng::from_seed(config.seed)),
+            config,
+            start: Instant::now(),
+        }
+    }
+
+    /// Whether the injector will ever reset connections. Callers must prepare
+    /// connections for reset ahead of time if so.
+    pub(crate) fn resets_connections(&self) -> bool {
+        self.config.connection_reset_probability > 0.0
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn active(&self) -> bool {
+        match self.config.schedule {
+            None => true,
+            Some(schedule) => {
+                let elapsed = self.start.elapsed().as_millis() as u64;
+                elapsed % schedule.period_millis < schedule.active_millis
+            }
+        }
+    }
+
+    /// Choose the fault and response delay for a single request. If faults are
+    /// not currently active the result is `(Fault::None, default_delay)`.
+    pub(crate) fn decide(&self, default_delay: Duration) -> (Fault, Duration) {
+        if !self.active() {
+            return (Fault::None, default_delay);
+        }
+        let mut rng = self.rng.lock().expect("fault injector lock poisoned");
+        let delay = self
+            .config
+            .delay
+            .map_or(default_delay, |d| d.sample(&mut *rng));
+
+        let mut roll: f32 = rng.gen();
+        if let Some(se) = self.config.server_error {
+            if roll < se.probability {
+                return (Fault::ServerError(se.status), delay);
+            }
+            roll -= se.probability;
+        }
+        if let Some(tmr) = self.config.too_many_requests {
+            if roll < tmr.probability {
+                return (Fault::TooManyRequests(tmr.retry_after_seconds), delay);
+            }
+            roll -= tmr.probability;
+        }
+        if roll < self.config.connection_reset_probability {
+            return (Fault::ConnectionReset, delay);
+        }
+        roll -= self.config.connection_reset_probability;
+        if roll < self.config.truncated_response_probability {
+            return (Fault::TruncatedResponse, delay);
+        }
+        (Fault::None, delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use proptest::prelude::*;
+
+    use super::{Config, Delay, Fault, Injector, ServerError, TooManyRequests};
+
+    fn config(seed: [u8; 32]) -> Config {
+        Config {
+            seed,
+            server_error: Some(ServerError {
+                probability: 0.25,
+                status: 503,
+            }),
+            too_many_requests: Some(TooManyRequests {
+                probability: 0.25,
+                retry_after_seconds: Some(5),
+            }),
+            connection_reset_probability: 0.25,
+            truncated_response_probability: 0.25,
+            delay: Some(Delay::Uniform {
+                min_millis: 10,
+                max_millis: 20,
+            }),
+            schedule: None,
+        }
+    }
+
+    #[test]
+    fn probabilities_over_one_are_invalid() {
+        let mut conf = config([0; 32]);
+        conf.truncated_response_probability = 0.5;
+        assert!(conf.valid().is_err());
+    }
+
+    // When the fault probabilities sum to one every request must be faulted
+    // and every sampled delay must lie within the configured bounds.
+    proptest! {
+        #[test]
+        fn saturated_probabilities_always_fault(seed: [u8; 32]) {
+            let injector = Injector::new(config(seed));
+            for _ in 0..128 {
+                let (fault, delay) = injector.decide(Duration::ZERO);
+                prop_assert_ne!(fault, Fault::None);
+                prop_assert!(delay >= Duration::from_millis(10));
+                prop_assert!(delay <= Duration::from_millis(20));
+            }
+        }
+    }
+}
@@ -0,0 +1,294 @@
+//! Slow consumer support for the stream blackholes.
+//!
+//! By default the stream blackholes read as fast as the target is able to
+//! write. The types in this module allow a blackhole to read at a limited rate
+//! per connection and in aggregate across all connections, and to stop reading
+//! entirely for recurring windows of time. This lets the user observe how a
+//! target behaves when its downstream is slow: buffer growth, backpressure and
+//! the like.
+//!
+//! ## Metrics
+//!
+//! `read_paused`: Number of times a connection was paused by a pause window
+//!
+//! Additional metrics may be emitted by this blackhole's [throttle](lading_throttle).
+
+use std::{num::NonZeroU32, sync::Arc};
+
+use byte_unit::Byte;
+use lading_throttle::Throttle;
+use metrics::counter;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    sync::Mutex,
+    time::{self, Duration, Instant},
+};
+use tracing::warn;
+
+/// The largest single read, matching the default capacity of
+/// [`tokio_util::io::ReaderStream`].
+const MAX_READ_SIZE: usize = 4096;
+
+#[derive(thiserror::Error, Debug, Clone, Copy)]
+/// Errors produced when constructing the slow consumer limiter.
+pub enum Error {
+    /// A configured rate was zero or did not fit in a u32.
+    #[error("Read rate must be between 1 and {} bytes per second", u32::MAX)]
+    Rate,
+    /// The pause window configuration was not valid.
+    #[error("The pause configuration was not valid: {0}")]
+    Pause(&'static str),
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+/// A recurring window in which the blackhole stops reading.
+///
+/// Time is measured from the start of the blackhole. Each period ends with
+/// `pause_millis` in which no connection is read from.
+pub struct Pause {
+    /// The total length of each period in milliseconds.
+    pub period_millis: u64,
+    /// The length of the pause at the end of each period in milliseconds.
+    pub pause_millis: u64,
+}
+
+impl Pause {
+    /// Time remaining in the current pause, zero if not paused.
+    fn remaining(self, elapsed: Duration) -> Duration {
+        let period = u128::from(self.period_millis);
+        let offset = elapsed.as_millis() % period;
+        if offset < period - u128::from(self.pause_millis) {
+            Duration::ZERO
+        } else {
+            // `period - offset` is bounded by `period_millis` and so fits in
+            // a u64.
+            Duration::from_millis(u64::try_from(period - offset).unwrap_or(self.period_millis))
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields)]
+/// Configuration for the slow consumer limiter.
+pub struct Config {
+    /// The bytes per second to read from each connection, unlimited if not
+    /// set
+    pub connection_bytes_per_second: Option<Byte>,
+    /// The bytes per second to read across all connections, unlimited if not
+    /// set. Connections take turns waiting on this limit, so while one waits
+    /// no other reads.
+    pub aggregate_bytes_per_second: Option<Byte>,
+    /// The load throttle configuration
+    #[serde(default)]
+    pub throttle: lading_throttle::Config,
+    /// Stop reading for a recurring window of time. If not set reads are
+    /// never paused.
+    pub pause: Option<Pause>,
+}
+
+fn rate(bytes: Option<Byte>) -> Result<Option<NonZeroU32>, Error> {
+    bytes
+        .map(|b| {
+            u32::try_from(b.get_bytes())
+                .ok()
+                .and_then(NonZeroU32::new)
+                .ok_or(Error::Rate)
+        })
+        .transpose()
+}
+
+#[derive(Debug)]
+/// Shared state for all connections accepted by a single blackhole.
+pub(crate) struct Limiter {
+    connection_rate: Option<NonZeroU32>,
+    throttle: lading_throttle::Config,
+    aggregate: Option<Arc<Mutex<Throttle>>>,
+    pause: Option<Pause>,
+    read_size: usize,
+    start: Instant,
+}
+
+impl Limiter {
+    /// Create a new [`Limiter`]
+    ///
+    /// # Errors
+    ///
+    /// Function will error if a configured rate is zero or exceeds u32, or if
+    /// the pause window is not valid.
+    pub(crate) fn new(config: Config) -> Result<Self, Error> {
+        let connection_rate = rate(config.connection_bytes_per_second)?;
+        let aggregate_rate = rate(config.aggregate_bytes_per_second)?;
+        if let Some(pause) = config.pause {
+            if pause.period_millis == 0 {
+                return Err(Error::Pause("period_millis must not be zero"));
+            }
+            if pause.pause_millis > pause.period_millis {
+                return Err(Error::Pause("pause_millis must not exceed period_millis"));
+            }
+        }
+
+        // No single read may exceed the capacity of either throttle.
+        let read_size = [connection_rate, aggregate_rate]
+            .into_iter()
+            .flatten()
+            .map(|r| r.get() as usize)
+            .fold(MAX_READ_SIZE, usize::min);
+
+        Ok(Self {
+            connection_rate,
+            throttle: config.throttle,
+            aggregate: aggregate_rate
+                .map(|r| Arc::new(Mutex::new(Throttle::new_with_config(config.throttle, r)))),
+            pause: config.pause,
+            read_size,
+            start: Instant::now(),
+        })
+    }
+
+    /// Read `reader` to completion, obeying the configured limits.
+    pub(crate) async fn consume<R>(&self, mut reader: R, labels: &'static [(String, String)])
+    where
+        R: AsyncRead + Unpin,
+    {
+        let mut throttle = self
+            .connection_rate
+            .map(|r| Throttle::new_with_config(self.throttle, r));
+        let mut buf = vec![0; self.read_size];
+
+        loop {
+            if let Some(pause) = self.pause {
+                let remaining = pause.remaining(self.start.elapsed());
+                if !remaining.is_zero() {
+                    counter!("read_paused", labels).increment(1);
+                    time::sleep(remaining).await;
+                }
+            }
+
+            let n = match reader.read(&mut buf).await {
+                Ok(0) => return,
+                Ok(n) => n,
+                Err(e) => {
+                    warn!("Read error: {e}");
+                    return;
+                }
+            };
+            counter!("message_received", labels).increment(1);
+            counter!("bytes_received", labels).increment(n as u64);
+
+            // `n` is bounded by `read_size` which is in turn bounded by each
+            // throttle's capacity.
+            #[allow(clippy::cast_possible_truncation)]
+            let n = n as u32;
+            if let Some(throttle) = throttle.as_mut() {
+                if let Err(e) = throttle
+                    .wait_for(NonZeroU32::new(n).expect("read of zero"))
+                    .await
+                {
+                    warn!("Connection throttle error: {e}");
+                }
+            }
+            // The lock is held while waiting, serializing every connection on
+            // the aggregate throttle: a connection that has read waits its
+            // turn, and no other connection reads until it is through.
+            if let Some(aggregate) = self.aggregate.as_ref() {
+                let mut throttle = aggregate.lock().await;
+                if let Err(e) = throttle
+                    .wait_for(NonZeroU32::new(n).expect("read of zero"))
+                    .await
+                {
+                    warn!("Aggregate throttle error: {e}");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use byte_unit::Byte;
+    use tokio::{
+        io::{duplex, AsyncWriteExt},
+        time::{self, Duration, Instant},
+    };
+
+    use super::{Config, Limiter, Pause, MAX_READ_SIZE};
+
+    fn limiter(connection: Option<u128>, aggregate: Option<u128>) -> Limiter {
+        Limiter::new(Config {
+            connection_bytes_per_second: connection.map(Byte::from_bytes),
+            aggregate_bytes_per_second: aggregate.map(Byte::from_bytes),
+            throttle: lading_throttle::Config::Stable,
+            pause: None,
+        })
+        .expect("invalid limiter configuration")
+    }
+
+    /// Consume `bytes` written up front to a fresh connection, returning once
+    /// the writer is drained.
+    async fn consume(limiter: &Limiter, bytes: usize) {
+        let (mut writer, reader) = duplex(bytes);
+        writer
+            .write_all(&vec![0; bytes])
+            .await
+            .expect("failed to write");
+        drop(writer);
+        limiter.consume(reader, &[]).await;
+    }
+
+    #[test]
+    fn read_size_is_bounded_by_throttles() {
+        assert_eq!(limiter(None, None).read_size, MAX_READ_SIZE);
+        assert_eq!(limiter(Some(10_000), Some(1_000)).read_size, 1_000);
+        assert_eq!(limiter(Some(100), None).read_size, 100);
+    }
+
+    // At 1,000 bytes per second 5,000 bytes are read over four intervals
+    // after the first.
+    #[tokio::test]
+    async fn connection_rate_limits_reads() {
+        time::pause();
+        let limiter = limiter(Some(1_000), None);
+        let start = Instant::now();
+        consume(&limiter, 5_000).await;
+        let elapsed = start.elapsed();
+        assert!(
+            (Duration::from_secs(4)..Duration::from_secs(5)).contains(&elapsed),
+            "read in {elapsed:?}"
+        );
+    }
+
+    // Connections share the aggregate rate: two connections of 2,000 bytes
+    // take as long as one of 4,000.
+    #[tokio::test]
+    async fn aggregate_rate_limits_all_connections() {
+        time::pause();
+        let limiter = limiter(None, Some(1_000));
+        let start = Instant::now();
+        tokio::join!(consume(&limiter, 2_000), consume(&limiter, 2_000));
+        let elapsed = start.elapsed();
+        assert!(
+            (Duration::from_secs(3)..Duration::from_secs(4)).contains(&elapsed),
+            "read in {elapsed:?}"
+        );
+    }
+
+    #[test]
+    fn pause_is_at_end_of_period() {
+        let pause = Pause {
+            period_millis: 1_000,
+            pause_millis: 250,
+        };
+        assert_eq!(pause.remaining(Duration::from_millis(0)), Duration::ZERO);
+        assert_eq!(pause.remaining(Duration::from_millis(749)), Duration::ZERO);
+        assert_eq!(
+            pause.remaining(Duration::from_millis(750)),
+            Duration::from_millis(250)
+        );
+        assert_eq!(
+            pause.remaining(Duration::from_millis(2_900)),
+            Duration::from_millis(100)
+        );
+    }
+}
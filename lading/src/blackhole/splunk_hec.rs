@@ -4,11 +4,15 @@
 //!
 //! `bytes_received`: Total bytes received
 //! `requests_received`: Total requests received
+//! `tls_handshake_failure`: TLS handshakes that did not complete
 //!
 
 use std::{
     net::SocketAddr,
-    sync::atomic::{AtomicU64, Ordering},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
 };
 
 use bytes::Bytes;
@@ -41,9 +45,12 @@ pub enum Error {
     /// Wrapper for [`crate::blackhole::common::Error`].
     #[error(transparent)]
     Common(#[from] crate::blackhole::common::Error),
+    /// Wrapper for [`crate::tls::Error`].
+    #[error(transparent)]
+    Tls(#[from] crate::tls::Error),
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
 #[serde(deny_unknown_fields)]
 /// Configuration for [`SplunkHec`].
 pub struct Config {
@@ -52,6 +59,9 @@ pub struct Config {
     pub concurrent_requests_max: usize,
     /// address -- IP plus port -- to bind to
     pub binding_addr: SocketAddr,
+    /// TLS configuration, default plaintext
+    #[serde(default)]
+    pub tls: Option<crate::tls::ServerConfig>,
 }
 
 #[derive(Deserialize)]
@@ -164,12 +174,20 @@ pub struct SplunkHec {
     httpd_addr: SocketAddr,
     shutdown: lading_signal::Watcher,
     metric_labels: Vec<(String, String)>,
+    tls: Option<Arc<rustls::ServerConfig>>,
 }
 
 impl SplunkHec {
     /// Create a new [`SplunkHec`] server instance
-    #[must_use]
-    pub fn new(general: General, config: &Config, shutdown: lading_signal::Watcher) -> Self {
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if the TLS configuration is not valid.
+    pub fn new(
+        general: General,
+        config: &Config,
+        shutdown: lading_signal::Watcher,
+    ) -> Result<Self, Error> {
         let mut metric_labels = vec![
             ("component".to_string(), "blackhole".to_string()),
             ("component_name".to_string(), "splunk_hec".to_string()),
@@ -178,12 +196,19 @@ impl SplunkHec {
             metric_labels.push(("id".to_string(), id));
         }
 
-        Self {
+        let tls = config
+            .tls
+            .as_ref()
+            .map(|tls| crate::tls::server_config(tls, crate::tls::ALPN_HTTP))
+            .transpose()?;
+
+        Ok(Self {
             httpd_addr: config.binding_addr,
             concurrency_limit: config.concurrent_requests_max,
             shutdown,
             metric_labels,
-        }
+            tls,
+        })
     }
 
     /// Run [`SplunkHec`] to completion
@@ -204,7 +229,8 @@ impl SplunkHec {
             self.concurrency_limit,
             self.shutdown,
             self.metric_labels.clone(),
-            move || {
+            self.tls.clone(),
+            move |_| {
                 let metric_labels = self.metric_labels.clone();
                 hyper::service::service_fn(move |req| srv(req, metric_labels.clone()))
             },
@@ -102,7 +102,8 @@ impl Sqs {
             self.concurrency_limit,
             self.shutdown,
             self.metric_labels.clone(),
-            move || {
+            None,
+            move |_| {
                 let metric_labels = self.metric_labels.clone();
                 hyper::service::service_fn(move |req| srv(req, metric_labels.clone()))
             },
@@ -0,0 +1,480 @@
+//! The syslog protocol speaking blackhole.
+//!
+//! Messages are received over TCP, framed per RFC 6587 with either
+//! octet-counting or non-transparent framing detected per message, or over UDP
+//! with one message per datagram. Both RFC 5424 and RFC 3164 (BSD) messages
+//! are understood.
+//!
+//! ## Metrics
+//!
+//! `connection_accepted`: Incoming TCP connections received
+//! `bytes_received`: Total bytes received
+//! `message_received`: Total messages parsed, labeled by `format` and
+//! `severity`
+//! `parse_failure`: Messages that could not be parsed, labeled by `reason`.
+//! A `framing` failure closes the TCP connection as the stream cannot be
+//! resynchronized.
+//!
+
+use std::{io, net::SocketAddr};
+
+use byte_unit::{Byte, ByteUnit};
+use bytes::{Buf, BytesMut};
+use futures::stream::StreamExt;
+use metrics::counter;
+use serde::{Deserialize, Serialize};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio_util::codec::{Decoder, FramedRead};
+use tracing::{info, warn};
+
+use super::General;
+
+const SEVERITIES: [&str; 8] = [
+    "emerg", "alert", "crit", "err", "warning", "notice", "info", "debug",
+];
+const MONTHS: [&[u8]; 12] = [
+    b"Jan", b"Feb", b"Mar", b"Apr", b"May", b"Jun", b"Jul", b"Aug", b"Sep", b"Oct", b"Nov", b"Dec",
+];
+/// The longest octet-counting length prefix we will wait on, enough for any
+/// `usize`.
+const MAXIMUM_PREFIX_DIGITS: usize = 20;
+
+fn default_maximum_message_size() -> Byte {
+    Byte::from_unit(64_f64, ByteUnit::KiB).expect("catastrophic programming bug")
+}
+
+#[derive(thiserror::Error, Debug)]
+/// Errors produced by [`Syslog`].
+pub enum Error {
+    /// Wrapper for [`std::io::Error`].
+    #[error(transparent)]
+    Io(io::Error),
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "snake_case")]
+/// The transport syslog messages arrive over.
+pub enum Protocol {
+    /// TCP with RFC 6587 framing
+    #[default]
+    Tcp,
+    /// UDP, one message per datagram
+    Udp,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+/// Configuration for [`Syslog`].
+pub struct Config {
+    /// address -- IP plus port -- to bind to
+    pub binding_addr: SocketAddr,
+    /// The transport to listen on, default TCP
+    #[serde(default)]
+    pub protocol: Protocol,
+    /// The largest message accepted over TCP, larger frames are a framing
+    /// failure
+    #[serde(default = "default_maximum_message_size")]
+    pub maximum_message_size: Byte,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Rfc5424,
+    Rfc3164,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ParseFailure {
+    Priority,
+    Header,
+}
+
+impl ParseFailure {
+    fn as_str(self) -> &'static str {
+        match self {
+            ParseFailure::Priority => "priority",
+            ParseFailure::Header => "header",
+        }
+    }
+}
+
+/// Parse the format and severity of a single syslog message.
+fn parse(msg: &[u8]) -> Result<(Format, u8), ParseFailure> {
+    let rest = msg.strip_prefix(b"<").ok_or(ParseFailure::Priority)?;
+    let end = rest
+        .iter()
+        .take(4)
+        .position(|b| *b == b'>')
+        .ok_or(ParseFailure::Priority)?;
+    let priority: u8 = std::str::from_utf8(&rest[..end])
+        .ok()
+        .filter(|p| !p.is_empty() && p.bytes().all(|b| b.is_ascii_digit()))
+        .and_then(|p| p.parse().ok())
+        .filter(|p| *p <= 191)
+        .ok_or(ParseFailure::Priority)?;
+    let severity = priority % 8;
+    let rest = &rest[end + 1..];
+
+    // RFC 5424 headers begin with a numeric version, RFC 3164 headers with a
+    // month name.
+    if rest.first().is_some_and(u8::is_ascii_digit) {
+        // VERSION TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA
+        let fields = rest.splitn(7, |b| *b == b' ').collect::<Vec<_>>();
+        if fields.len() < 7
+            || !fields[0].iter().all(u8::is_ascii_digit)
+            || fields[1..6].iter().any(|f| f.is_empty())
+        {
+            return Err(ParseFailure::Header);
+        }
+        Ok((Format::Rfc5424, severity))
+    } else {
+        // Mmm dd hh:mm:ss HOSTNAME
+        let valid = rest.len() > 16
+            && MONTHS.contains(&&rest[..3])
+            && rest[3] == b' '
+            && rest[4..6].iter().all(|b| *b == b' ' || b.is_ascii_digit())
+            && rest[6] == b' '
+            && rest[9] == b':'
+            && rest[12] == b':'
+            && rest[15] == b' ';
+        if !valid {
+            return Err(ParseFailure::Header);
+        }
+        Ok((Format::Rfc3164, severity))
+    }
+}
+
+/// Splits a TCP stream into messages per RFC 6587. Frames beginning with a
+/// digit are octet-counted, all others are terminated by LF or NUL.
+#[derive(Debug, Clone, Copy)]
+struct FrameDecoder {
+    maximum_message_size: usize,
+}
+
+impl Decoder for FrameDecoder {
+    type Item = BytesMut;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        loop {
+            let Some(first) = src.first() else {
+                return Ok(None);
+            };
+            if first.is_ascii_digit() {
+                let Some(space) = src.iter().position(|b| *b == b' ') else {
+                    if src.len() > MAXIMUM_PREFIX_DIGITS {
+                        return Err(framing_error("octet count without length"));
+                    }
+                    return Ok(None);
+                };
+                let len: usize = std::str::from_utf8(&src[..space])
+                    .ok()
+                    .and_then(|len| len.parse().ok())
+                    .ok_or_else(|| framing_error("invalid octet count"))?;
+                if len > self.maximum_message_size {
+                    return Err(framing_error("octet count exceeds maximum message size"));
+                }
+                if src.len() < space + 1 + len {
+                    src.reserve(space + 1 + len - src.len());
+                    return Ok(None);
+                }
+                src.advance(space + 1);
+                return Ok(Some(src.split_to(len)));
+            }
+
+            let Some(trailer) = src.iter().position(|b| *b == b'\n' || *b == 0) else {
+                if src.len() > self.maximum_message_size {
+                    return Err(framing_error("message exceeds maximum message size"));
+                }
+                return Ok(None);
+            };
+            let mut frame = src.split_to(trailer);
+            src.advance(1);
+            if frame.last() == Some(&b'\r') {
+                frame.truncate(frame.len() - 1);
+            }
+            // Stray trailers between messages are not messages.
+            if !frame.is_empty() {
+                return Ok(Some(frame));
+            }
+        }
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self.decode(src)? {
+            Some(frame) => Ok(Some(frame)),
+            None if src.is_empty() => Ok(None),
+            // A final octet-counted message is short of its count.
+            None if src[0].is_ascii_digit() => {
+                Err(framing_error("truncated octet-counted message"))
+            }
+            // A final non-transparent message may lack its trailer.
+            None => Ok(Some(src.split())),
+        }
+    }
+}
+
+fn framing_error(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Pre-built metric labels, one set per format and severity.
+#[derive(Debug)]
+struct Labels {
+    base: Vec<(String, String)>,
+    messages: Vec<Vec<(String, String)>>,
+    failures: Vec<Vec<(String, String)>>,
+}
+
+impl Labels {
+    fn new(base: Vec<(String, String)>) -> Self {
+        let mut messages = Vec::with_capacity(2 * SEVERITIES.len());
+        for format in ["rfc5424", "rfc3164"] {
+            for severity in SEVERITIES {
+                let mut labels = base.clone();
+                labels.push(("format".to_string(), format.to_string()));
+                labels.push(("severity".to_string(), severity.to_string()));
+                messages.push(labels);
+            }
+        }
+        let failures = ["priority", "header", "framing"]
+            .into_iter()
+            .map(|reason| {
+                let mut labels = base.clone();
+                labels.push(("reason".to_string(), reason.to_string()));
+                labels
+            })
+            .collect();
+        Self {
+            base,
+            messages,
+            failures,
+        }
+    }
+
+    fn record(&self, msg: &[u8]) {
+        counter!("bytes_received", &self.base).increment(msg.len() as u64);
+        match parse(msg) {
+            Ok((format, severity)) => {
+                let offset = match format {
+                    Format::Rfc5424 => 0,
+                    Format::Rfc3164 => SEVERITIES.len(),
+                };
+                counter!(
+                    "message_received",
+                    &self.messages[offset + usize::from(severity)]
+                )
+                .increment(1);
+            }
+            Err(failure) => {
+                counter!("parse_failure", self.failure(failure.as_str())).increment(1);
+            }
+        }
+    }
+
+    fn failure(&self, reason: &str) -> &[(String, String)] {
+        self.failures
+            .iter()
+            .find(|labels| labels.last().is_some_and(|(_, r)| r == reason))
+            .expect("unknown failure reason")
+    }
+}
+
+#[derive(Debug)]
+/// The syslog blackhole.
+pub struct Syslog {
+    binding_addr: SocketAddr,
+    protocol: Protocol,
+    maximum_message_size: usize,
+    shutdown: lading_signal::Watcher,
+    metric_labels: Vec<(String, String)>,
+}
+
+impl Syslog {
+    /// Create a new [`Syslog`] server instance
+    #[must_use]
+    pub fn new(general: General, config: &Config, shutdown: lading_signal::Watcher) -> Self {
+        let mut metric_labels = vec![
+            ("component".to_string(), "blackhole".to_string()),
+            ("component_name".to_string(), "syslog".to_string()),
+        ];
+        if let Some(id) = general.id {
+            metric_labels.push(("id".to_string(), id));
+        }
+
+        Self {
+            binding_addr: config.binding_addr,
+            protocol: config.protocol,
+            maximum_message_size: usize::try_from(config.maximum_message_size.get_bytes())
+                .unwrap_or(usize::MAX),
+            shutdown,
+            metric_labels,
+        }
+    }
+
+    async fn handle_connection(socket: TcpStream, decoder: FrameDecoder, labels: &'static Labels) {
+        let mut frames = FramedRead::new(socket, decoder);
+        while let Some(frame) = frames.next().await {
+            match frame {
+                Ok(msg) => labels.record(&msg),
+                Err(err) => {
+                    if err.kind() == io::ErrorKind::InvalidData {
+                        counter!("parse_failure", labels.failure("framing")).increment(1);
+                    }
+                    warn!("closing syslog connection: {err}");
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Run [`Syslog`] to completion
+    ///
+    /// This function runs the syslog server forever, unless a shutdown signal
+    /// is received or an unrecoverable error is encountered.
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if binding to the assigned address fails
+    /// or receiving fails.
+    ///
+    /// # Panics
+    ///
+    /// None known.
+    pub async fn run(self) -> Result<(), Error> {
+        let labels: &'static Labels = Box::leak(Box::new(Labels::new(self.metric_labels.clone())));
+        let shutdown_wait = self.shutdown.recv();
+        tokio::pin!(shutdown_wait);
+
+        match self.protocol {
+            Protocol::Tcp => {
+                let listener = TcpListener::bind(self.binding_addr)
+                    .await
+                    .map_err(Error::Io)?;
+                let decoder = FrameDecoder {
+                    maximum_message_size: self.maximum_message_size,
+                };
+                loop {
+                    tokio::select! {
+                        conn = listener.accept() => {
+                            let (socket, _) = conn.map_err(Error::Io)?;
+                            counter!("connection_accepted", &self.metric_labels).increment(1);
+                            tokio::spawn(Self::handle_connection(socket, decoder, labels));
+                        }
+                        () = &mut shutdown_wait => {
+                            info!("shutdown signal received");
+                            return Ok(())
+                        }
+                    }
+                }
+            }
+            Protocol::Udp => {
+                let socket = UdpSocket::bind(self.binding_addr)
+                    .await
+                    .map_err(Error::Io)?;
+                let mut buf = vec![0; 65_536];
+                loop {
+                    tokio::select! {
+                        packet = socket.recv_from(&mut buf) => {
+                            let (bytes, _) = packet.map_err(Error::Io)?;
+                            let msg = &buf[..bytes];
+                            let msg = msg.strip_suffix(b"\n").unwrap_or(msg);
+                            labels.record(msg);
+                        }
+                        () = &mut shutdown_wait => {
+                            info!("shutdown signal received");
+                            return Ok(())
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use bytes::BytesMut;
+    use tokio_util::codec::Decoder;
+
+    use super::{parse, Format, FrameDecoder, ParseFailure};
+
+    #[test]
+    fn parses_formats_and_severity() {
+        assert_eq!(
+            parse(b"<134>1 2024-01-01T00:00:00Z host app 123 ID1 - hello"),
+            Ok((Format::Rfc5424, 6))
+        );
+        assert_eq!(
+            parse(b"<11>Jan  5 10:00:00 host sshd[99]: hello"),
+            Ok((Format::Rfc3164, 3))
+        );
+        assert_eq!(
+            parse(b"<192>Jan  5 10:00:00 host x"),
+            Err(ParseFailure::Priority)
+        );
+        assert_eq!(parse(b"hello"), Err(ParseFailure::Priority));
+        assert_eq!(
+            parse(b"<13>1 2024-01-01T00:00:00Z host"),
+            Err(ParseFailure::Header)
+        );
+        assert_eq!(
+            parse(b"<13>hello world, not a header"),
+            Err(ParseFailure::Header)
+        );
+    }
+
+    #[test]
+    fn decodes_mixed_framing() {
+        let mut decoder = FrameDecoder {
+            maximum_message_size: 1024,
+        };
+        let mut src = BytesMut::from(&b"4 <1>a<2>b\r\n\n<3>c\x009 <4>d"[..]);
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.decode(&mut src).expect("failed to decode") {
+            frames.push(frame);
+        }
+        assert_eq!(frames, vec![&b"<1>a"[..], &b"<2>b"[..], &b"<3>c"[..]]);
+        // The final octet-counted frame is incomplete.
+        assert_eq!(&src[..], b"9 <4>d");
+        src.extend_from_slice(b"efghi");
+        assert_eq!(
+            decoder.decode(&mut src).expect("failed to decode"),
+            Some(BytesMut::from(&b"<4>defghi"[..]))
+        );
+
+        let mut src = BytesMut::from(&b"2048 <1>"[..]);
+        assert!(decoder.decode(&mut src).is_err());
+    }
+
+    // At the end of the stream a non-transparent message may lack its
+    // trailer, an octet-counted one may not lack any of its octets.
+    #[test]
+    fn decodes_eof() {
+        let mut decoder = FrameDecoder {
+            maximum_message_size: 1024,
+        };
+        let mut src = BytesMut::from(&b"<1>a\n<2>b"[..]);
+        assert_eq!(
+            decoder.decode_eof(&mut src).expect("failed to decode"),
+            Some(BytesMut::from(&b"<1>a"[..]))
+        );
+        assert_eq!(
+            decoder.decode_eof(&mut src).expect("failed to decode"),
+            Some(BytesMut::from(&b"<2>b"[..]))
+        );
+        assert_eq!(
+            decoder.decode_eof(&mut src).expect("failed to decode"),
+            None
+        );
+
+        let mut src = BytesMut::from(&b"120 <13>1 2024-01-01T00:00:00Z host app"[..]);
+        let err = decoder
+            .decode_eof(&mut src)
+            .expect_err("truncated frame decoded");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -6,17 +6,26 @@
 //! `bytes_received`: Total bytes received
 //! `message_received`: Total messages received
 //!
+//! `tls_handshake_failure`: TLS handshakes that did not complete
+//!
+//! Additional metrics may be emitted when reads are limited, see
+//! [`super::slow_consumer`].
+//!
 
-use std::{io, net::SocketAddr};
+use std::{io, net::SocketAddr, sync::Arc};
 
 use futures::stream::StreamExt;
 use metrics::counter;
 use serde::{Deserialize, Serialize};
-use tokio::net::{TcpListener, TcpStream};
+use tokio::{
+    io::AsyncRead,
+    net::{TcpListener, TcpStream},
+};
+use tokio_rustls::TlsAcceptor;
 use tokio_util::io::ReaderStream;
-use tracing::info;
+use tracing::{info, warn};
 
-use super::General;
+use super::{slow_consumer, General};
 
 #[derive(thiserror::Error, Debug)]
 /// Errors emitted by [`Tcp`]
@@ -24,14 +33,26 @@ pub enum Error {
     /// Wrapper for [`std::io::Error`].
     #[error(transparent)]
     Io(io::Error),
+    /// Wrapper for [`slow_consumer::Error`].
+    #[error(transparent)]
+    SlowConsumer(#[from] slow_consumer::Error),
+    /// Wrapper for [`crate::tls::Error`].
+    #[error(transparent)]
+    Tls(#[from] crate::tls::Error),
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 #[serde(deny_unknown_fields)]
 /// Configuration for [`Tcp`]
 pub struct Config {
     /// address -- IP plus port -- to bind to
     pub binding_addr: SocketAddr,
+    /// limit the rate at which connections are read, default unlimited
+    #[serde(default)]
+    pub slow_consumer: Option<slow_consumer::Config>,
+    /// TLS configuration, default plaintext
+    #[serde(default)]
+    pub tls: Option<crate::tls::ServerConfig>,
 }
 
 #[derive(Debug)]
@@ -40,12 +61,22 @@ pub struct Tcp {
     binding_addr: SocketAddr,
     shutdown: lading_signal::Watcher,
     metric_labels: Vec<(String, String)>,
+    limiter: Option<Arc<slow_consumer::Limiter>>,
+    tls: Option<Arc<rustls::ServerConfig>>,
 }
 
 impl Tcp {
     /// Create a new [`Tcp`] server instance
-    #[must_use]
-    pub fn new(general: General, config: &Config, shutdown: lading_signal::Watcher) -> Self {
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if the slow consumer or TLS
+    /// configuration is not valid.
+    pub fn new(
+        general: General,
+        config: &Config,
+        shutdown: lading_signal::Watcher,
+    ) -> Result<Self, Error> {
         let mut metric_labels = vec![
             ("component".to_string(), "blackhole".to_string()),
             ("component_name".to_string(), "tcp".to_string()),
@@ -54,14 +85,56 @@ impl Tcp {
             metric_labels.push(("id".to_string(), id));
         }
 
-        Self {
+        let limiter = config
+            .slow_consumer
+            .map(slow_consumer::Limiter::new)
+            .transpose()?
+            .map(Arc::new);
+        let tls = config
+            .tls
+            .as_ref()
+            .map(|tls| crate::tls::server_config(tls, &[]))
+            .transpose()?;
+
+        Ok(Self {
             binding_addr: config.binding_addr,
             shutdown,
             metric_labels,
+            limiter,
+            tls,
+        })
+    }
+
+    async fn handle_connection(
+        socket: TcpStream,
+        tls: Option<Arc<rustls::ServerConfig>>,
+        limiter: Option<Arc<slow_consumer::Limiter>>,
+        labels: &'static [(String, String)],
+    ) {
+        match tls {
+            None => Self::consume(socket, limiter, labels).await,
+            Some(config) => match TlsAcceptor::from(config).accept(socket).await {
+                Ok(stream) => Self::consume(stream, limiter, labels).await,
+                Err(e) => {
+                    counter!("tls_handshake_failure", labels).increment(1);
+                    warn!("TLS handshake failed: {e}");
+                }
+            },
         }
     }
 
-    async fn handle_connection(socket: TcpStream, labels: &'static [(String, String)]) {
+    async fn consume<S>(
+        socket: S,
+        limiter: Option<Arc<slow_consumer::Limiter>>,
+        labels: &'static [(String, String)],
+    ) where
+        S: AsyncRead + Unpin,
+    {
+        if let Some(limiter) = limiter {
+            limiter.consume(socket, labels).await;
+            return;
+        }
+
         let mut stream = ReaderStream::new(socket);
 
         while let Some(msg) = stream.next().await {
@@ -99,7 +172,7 @@ impl Tcp {
                     let (socket, _) = conn.map_err(Error::Io)?;
                     counter!("connection_accepted", &self.metric_labels).increment(1);
                     tokio::spawn(
-                        Self::handle_connection(socket, labels)
+                        Self::handle_connection(socket, self.tls.clone(), self.limiter.clone(), labels)
                     );
                 }
                 () = &mut shutdown_wait => {
@@ -4,23 +4,64 @@
 //!
 //! `bytes_received`: Total bytes received
 //! `packet_received`: Total packets received
+//! `receive_buffer_bytes`: Size of each socket's receive buffer as granted by
+//! the kernel, labeled by `receiver`
+//! `kernel_packets_dropped`: Packets the kernel dropped on a receiving socket,
+//! labeled by `receiver`. Linux only.
+//! `receive_queue_bytes`: Bytes queued in the kernel waiting to be read from a
+//! receiving socket, labeled by `receiver`. Linux only.
 //!
 
-use std::{io, net::SocketAddr};
+use std::{io, net::SocketAddr, time::Duration};
 
-use metrics::counter;
+use byte_unit::{Byte, ByteUnit};
+use metrics::{counter, gauge};
 use serde::{Deserialize, Serialize};
-use tokio::net::UdpSocket;
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+use tokio::{net::UdpSocket, task::JoinSet};
 use tracing::info;
 
 use super::General;
 
+/// The largest possible UDP datagram.
+const MAXIMUM_DATAGRAM_SIZE: u128 = 65_536;
+/// The largest batch a single `recvmmsg` call accepts, `UIO_MAXIOV`.
+const MAXIMUM_BATCH_SIZE: u16 = 1024;
+
+fn default_receivers() -> u16 {
+    1
+}
+
+fn default_batch_size() -> u16 {
+    1
+}
+
+fn default_drop_accounting_interval_millis() -> u64 {
+    1_000
+}
+
+fn default_maximum_datagram_size() -> Byte {
+    Byte::from_unit(64_f64, ByteUnit::KiB).expect("catastrophic programming bug")
+}
+
 #[derive(thiserror::Error, Debug)]
 /// Errors produced by [`Udp`].
 pub enum Error {
     /// Wrapper for [`std::io::Error`].
     #[error(transparent)]
     Io(io::Error),
+    /// A receiver task failed to complete.
+    #[error(transparent)]
+    Join(tokio::task::JoinError),
+    /// Receivers or batch size was zero or too large.
+    #[error("Receivers must be non-zero and batch size within [1, {MAXIMUM_BATCH_SIZE}]")]
+    InvalidConfig,
+    /// The drop accounting interval was zero.
+    #[error("Drop accounting interval must be non-zero")]
+    DropAccountingInterval,
+    /// The maximum datagram size was zero or too large.
+    #[error("Maximum datagram size must be within [1, {MAXIMUM_DATAGRAM_SIZE}] bytes")]
+    MaximumDatagramSize,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
@@ -29,6 +70,26 @@ pub enum Error {
 pub struct Config {
     /// address -- IP plus port -- to bind to
     pub binding_addr: SocketAddr,
+    /// The number of sockets bound to `binding_addr` with `SO_REUSEPORT`, each
+    /// read by its own task. The kernel spreads datagrams across them by
+    /// source address and port.
+    #[serde(default = "default_receivers")]
+    pub receivers: u16,
+    /// The maximum number of datagrams read per system call. Batches are read
+    /// with `recvmmsg` on Linux and one datagram at a time elsewhere.
+    #[serde(default = "default_batch_size")]
+    pub batch_size: u16,
+    /// The requested `SO_RCVBUF` of each socket, default chosen by the OS
+    pub receive_buffer_size: Option<Byte>,
+    /// How often to poll the kernel's socket drop counters, must be non-zero
+    #[serde(default = "default_drop_accounting_interval_millis")]
+    pub drop_accounting_interval_millis: u64,
+    /// The largest datagram read whole, at most 64 KiB. Each receiver holds
+    /// `batch_size` buffers of this size. Longer datagrams are truncated and
+    /// only their first `maximum_datagram_size` bytes count toward
+    /// `bytes_received`.
+    #[serde(default = "default_maximum_datagram_size")]
+    pub maximum_datagram_size: Byte,
 }
 
 #[derive(Debug)]
@@ -36,14 +97,41 @@ pub struct Config {
 /// The UDP blackhole.
 pub struct Udp {
     binding_addr: SocketAddr,
+    receivers: u16,
+    batch_size: u16,
+    receive_buffer_size: Option<Byte>,
+    drop_accounting_interval: Duration,
+    maximum_datagram_size: usize,
     shutdown: lading_signal::Watcher,
     metric_labels: Vec<(String, String)>,
 }
 
 impl Udp {
     /// Create a new [`Udp`] server instance
-    #[must_use]
-    pub fn new(general: General, config: &Config, shutdown: lading_signal::Watcher) -> Self {
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if `receivers` is zero, `batch_size` is
+    /// zero or larger than a single system call accepts, the drop accounting
+    /// interval is zero or the maximum datagram size is zero or larger than a
+    /// UDP datagram.
+    pub fn new(
+        general: General,
+        config: &Config,
+        shutdown: lading_signal::Watcher,
+    ) -> Result<Self, Error> {
+        if config.receivers == 0 || config.batch_size == 0 || config.batch_size > MAXIMUM_BATCH_SIZE
+        {
+            return Err(Error::InvalidConfig);
+        }
+        if config.drop_accounting_interval_millis == 0 {
+            return Err(Error::DropAccountingInterval);
+        }
+        let maximum_datagram_size = config.maximum_datagram_size.get_bytes();
+        if maximum_datagram_size == 0 || maximum_datagram_size > MAXIMUM_DATAGRAM_SIZE {
+            return Err(Error::MaximumDatagramSize);
+        }
+
         let mut metric_labels = vec![
             ("component".to_string(), "blackhole".to_string()),
             ("component_name".to_string(), "udp".to_string()),
@@ -52,11 +140,35 @@ impl Udp {
             metric_labels.push(("id".to_string(), id));
         }
 
-        Self {
+        Ok(Self {
             binding_addr: config.binding_addr,
+            receivers: config.receivers,
+            batch_size: config.batch_size,
+            receive_buffer_size: config.receive_buffer_size,
+            drop_accounting_interval: Duration::from_millis(config.drop_accounting_interval_millis),
+            // Bounded by `MAXIMUM_DATAGRAM_SIZE` above.
+            maximum_datagram_size: maximum_datagram_size as usize,
             shutdown,
             metric_labels,
+        })
+    }
+
+    fn bind(&self) -> Result<UdpSocket, io::Error> {
+        let socket = Socket::new(
+            Domain::for_address(self.binding_addr),
+            Type::DGRAM,
+            Some(Protocol::UDP),
+        )?;
+        if self.receivers > 1 {
+            socket.set_reuse_port(true)?;
         }
+        if let Some(size) = self.receive_buffer_size {
+            let size = usize::try_from(size.get_bytes()).unwrap_or(usize::MAX);
+            socket.set_recv_buffer_size(size)?;
+        }
+        socket.set_nonblocking(true)?;
+        socket.bind(&self.binding_addr.into())?;
+        UdpSocket::from_std(socket.into())
     }
 
     /// Run [`Udp`] to completion
@@ -71,20 +183,60 @@ impl Udp {
     /// # Panics
     ///
     /// None known.
+    #[allow(clippy::cast_precision_loss)]
     pub async fn run(self) -> Result<(), Error> {
-        let socket = UdpSocket::bind(&self.binding_addr)
-            .await
-            .map_err(Error::Io)?;
-        let mut buf = [0; 65536];
+        let receiver_labels: Vec<Vec<(String, String)>> = (0..self.receivers)
+            .map(|idx| {
+                let mut labels = self.metric_labels.clone();
+                labels.push(("receiver".to_string(), idx.to_string()));
+                labels
+            })
+            .collect();
+
+        let mut sockets = Vec::with_capacity(usize::from(self.receivers));
+        for labels in &receiver_labels {
+            let socket = self.bind().map_err(Error::Io)?;
+            let buffer_size = SockRef::from(&socket)
+                .recv_buffer_size()
+                .map_err(Error::Io)?;
+            gauge!("receive_buffer_bytes", labels).set(buffer_size as f64);
+            sockets.push(socket);
+        }
+
+        let mut tasks = JoinSet::new();
+        #[cfg(target_os = "linux")]
+        {
+            let inodes = sockets
+                .iter()
+                .map(linux::inode)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(Error::Io)?;
+            tasks.spawn(linux::account_drops(
+                inodes,
+                self.drop_accounting_interval,
+                receiver_labels,
+            ));
+        }
+        for socket in sockets {
+            tasks.spawn(receive(
+                socket,
+                usize::from(self.batch_size),
+                self.maximum_datagram_size,
+                self.metric_labels.clone(),
+            ));
+        }
 
         let shutdown_wait = self.shutdown.recv();
         tokio::pin!(shutdown_wait);
         loop {
             tokio::select! {
-                packet = socket.recv_from(&mut buf) => {
-                    let (bytes, _) = packet.map_err(Error::Io)?;
-                    counter!("packet_received", &self.metric_labels).increment(1);
-                    counter!("bytes_received", &self.metric_labels).increment(bytes as u64);
+                res = tasks.join_next() => {
+                    match res {
+                        Some(Ok(Ok(()))) => continue,
+                        Some(Ok(Err(err))) => return Err(err),
+                        Some(Err(err)) => return Err(Error::Join(err)),
+                        None => return Ok(()),
+                    }
                 }
                 () = &mut shutdown_wait => {
                     info!("shutdown signal received");
@@ -94,3 +246,217 @@ impl Udp {
         }
     }
 }
+
+async fn receive(
+    socket: UdpSocket,
+    batch_size: usize,
+    maximum_datagram_size: usize,
+    metric_labels: Vec<(String, String)>,
+) -> Result<(), Error> {
+    let mut buf = vec![0; batch_size * maximum_datagram_size];
+    loop {
+        let (packets, bytes) = recv_batch(&socket, &mut buf, maximum_datagram_size)
+            .await
+            .map_err(Error::Io)?;
+        counter!("packet_received", &metric_labels).increment(packets);
+        counter!("bytes_received", &metric_labels).increment(bytes);
+    }
+}
+
+/// Read a datagram into each `maximum_datagram_size` chunk of `buf`,
+/// returning the number of datagrams and total bytes read.
+#[cfg(target_os = "linux")]
+async fn recv_batch(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+    maximum_datagram_size: usize,
+) -> io::Result<(u64, u64)> {
+    use nix::libc;
+    use std::os::fd::AsRawFd;
+    use tokio::io::Interest;
+
+    if buf.len() == maximum_datagram_size {
+        let (bytes, _) = socket.recv_from(buf).await?;
+        return Ok((1, bytes as u64));
+    }
+    socket
+        .async_io(Interest::READABLE, || {
+            let mut iovecs: Vec<libc::iovec> = buf
+                .chunks_exact_mut(maximum_datagram_size)
+                .map(|chunk| libc::iovec {
+                    iov_base: chunk.as_mut_ptr().cast::<libc::c_void>(),
+                    iov_len: chunk.len(),
+                })
+                .collect();
+            let mut msgs: Vec<libc::mmsghdr> = iovecs
+                .iter_mut()
+                .map(|iov| {
+                    // SAFETY: msghdr is a plain C struct for which all zeroes
+                    // is a valid, empty value.
+                    let mut hdr: libc::msghdr = unsafe { std::mem::zeroed() };
+                    hdr.msg_iov = std::ptr::from_mut(iov);
+                    hdr.msg_iovlen = 1;
+                    libc::mmsghdr {
+                        msg_hdr: hdr,
+                        msg_len: 0,
+                    }
+                })
+                .collect();
+            // SAFETY: every header points at a live iovec over a distinct
+            // chunk of `buf`, all of which outlive this call, and the batch
+            // length is bounded by `MAXIMUM_BATCH_SIZE`.
+            #[allow(clippy::cast_possible_truncation)]
+            let received = unsafe {
+                libc::recvmmsg(
+                    socket.as_raw_fd(),
+                    msgs.as_mut_ptr(),
+                    msgs.len() as libc::c_uint,
+                    0,
+                    std::ptr::null_mut(),
+                )
+            };
+            let received = usize::try_from(received).map_err(|_| io::Error::last_os_error())?;
+            let bytes = msgs[..received]
+                .iter()
+                .map(|msg| u64::from(msg.msg_len))
+                .sum();
+            Ok((received as u64, bytes))
+        })
+        .await
+}
+
+/// Read a single datagram, returning the number of datagrams and total bytes
+/// read.
+#[cfg(not(target_os = "linux"))]
+async fn recv_batch(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+    maximum_datagram_size: usize,
+) -> io::Result<(u64, u64)> {
+    let (bytes, _) = socket.recv_from(&mut buf[..maximum_datagram_size]).await?;
+    Ok((1, bytes as u64))
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::{io, os::fd::AsRawFd, os::unix::fs::MetadataExt, time::Duration};
+
+    use metrics::{counter, gauge};
+    use tokio::net::UdpSocket;
+
+    use super::Error;
+
+    /// Kernel accounting for a single socket, one row of `/proc/net/udp`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub(super) struct Entry {
+        pub(super) inode: u64,
+        pub(super) receive_queue: u64,
+        pub(super) drops: u64,
+    }
+
+    /// The inode of `socket`, by which it is identified in `/proc/net/udp`.
+    pub(super) fn inode(socket: &UdpSocket) -> Result<u64, io::Error> {
+        let metadata = std::fs::metadata(format!("/proc/self/fd/{}", socket.as_raw_fd()))?;
+        Ok(metadata.ino())
+    }
+
+    /// Parse the contents of `/proc/net/udp` or `/proc/net/udp6`, skipping
+    /// malformed rows.
+    pub(super) fn parse(contents: &str) -> impl Iterator<Item = Entry> + '_ {
+        contents.lines().skip(1).filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (_, receive_queue) = fields.get(4)?.split_once(':')?;
+            Some(Entry {
+                receive_queue: u64::from_str_radix(receive_queue, 16).ok()?,
+                inode: fields.get(9)?.parse().ok()?,
+                drops: fields.get(12)?.parse().ok()?,
+            })
+        })
+    }
+
+    /// Export kernel drop and queue accounting for the sockets with `inodes`,
+    /// each labeled by its entry in `receiver_labels`, every `interval`.
+    #[allow(clippy::cast_precision_loss)]
+    pub(super) async fn account_drops(
+        inodes: Vec<u64>,
+        interval: Duration,
+        receiver_labels: Vec<Vec<(String, String)>>,
+    ) -> Result<(), Error> {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            for table in ["/proc/net/udp", "/proc/net/udp6"] {
+                let contents = match tokio::fs::read_to_string(table).await {
+                    Ok(contents) => contents,
+                    // IPv6 may be disabled
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                    Err(err) => return Err(Error::Io(err)),
+                };
+                for entry in parse(&contents) {
+                    if let Some(idx) = inodes.iter().position(|inode| *inode == entry.inode) {
+                        let labels = &receiver_labels[idx];
+                        counter!("kernel_packets_dropped", labels).absolute(entry.drops);
+                        gauge!("receive_queue_bytes", labels).set(entry.receive_queue as f64);
+                    }
+                }
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::{parse, Entry};
+
+        #[test]
+        fn parses_proc_net_udp() {
+            let contents = "   sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode ref pointer drops
+  163: 0100007F:4704 00000000:0000 07 00000000:00000A00 00:00000000 00000000     0        0 5151351 2 0000000000000000 17
+  990: 00000000:0044 00000000:0000 07 00000000:00000000 00:00000000 00000000     0        0 20931 2 0000000000000000 0
+";
+            let entries: Vec<Entry> = parse(contents).collect();
+            assert_eq!(
+                entries,
+                vec![
+                    Entry {
+                        inode: 5_151_351,
+                        receive_queue: 0xA00,
+                        drops: 17,
+                    },
+                    Entry {
+                        inode: 20931,
+                        receive_queue: 0,
+                        drops: 0,
+                    },
+                ]
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, Error, General, Udp};
+
+    fn new(config: &str) -> Result<Udp, Error> {
+        let config: Config = serde_yaml::from_str(config).expect("failed to parse config");
+        let (shutdown, _) = lading_signal::signal();
+        Udp::new(General { id: None }, &config, shutdown)
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        assert!(new("binding_addr: 127.0.0.1:0").is_ok());
+        assert!(matches!(
+            new("binding_addr: 127.0.0.1:0\ndrop_accounting_interval_millis: 0"),
+            Err(Error::DropAccountingInterval)
+        ));
+        assert!(matches!(
+            new("binding_addr: 127.0.0.1:0\nmaximum_datagram_size: 0"),
+            Err(Error::MaximumDatagramSize)
+        ));
+        assert!(matches!(
+            new("binding_addr: 127.0.0.1:0\nmaximum_datagram_size: 65537"),
+            Err(Error::MaximumDatagramSize)
+        ));
+    }
+}
@@ -4,16 +4,21 @@
 //!
 //! `bytes_received`: Total bytes received
 //!
+//! On Linux a `path` beginning with `@` names a socket in the abstract
+//! namespace.
+//!
 
 use std::{io, path::PathBuf};
 
-use futures::TryFutureExt;
+use byte_unit::Byte;
 use metrics::counter;
 use serde::{Deserialize, Serialize};
+use socket2::SockRef;
 use tokio::net;
 use tracing::info;
 
 use super::General;
+use crate::common::unix_socket_addr;
 
 #[derive(thiserror::Error, Debug)]
 /// Errors produced by [`UnixDatagram`].
@@ -29,12 +34,16 @@ pub enum Error {
 pub struct Config {
     /// The path of the socket to read from.
     pub path: PathBuf,
+    /// The requested `SO_RCVBUF` of the socket, default chosen by the OS
+    #[serde(default)]
+    pub receive_buffer_size: Option<Byte>,
 }
 
 #[derive(Debug)]
 /// The `UnixDatagram` blackhole.
 pub struct UnixDatagram {
     path: PathBuf,
+    receive_buffer_size: Option<Byte>,
     shutdown: lading_signal::Watcher,
     metric_labels: Vec<(String, String)>,
 }
@@ -53,11 +62,28 @@ impl UnixDatagram {
 
         Self {
             path: config.path,
+            receive_buffer_size: config.receive_buffer_size,
             shutdown,
             metric_labels,
         }
     }
 
+    fn bind(&self) -> Result<net::UnixDatagram, io::Error> {
+        let addr = unix_socket_addr(&self.path)?;
+        if let Some(path) = addr.as_pathname() {
+            // Sockets cannot be rebound if they existed previously. Delete the
+            // socket, ignore any errors.
+            let _res = std::fs::remove_file(path);
+        }
+        let socket = std::os::unix::net::UnixDatagram::bind_addr(&addr)?;
+        if let Some(size) = self.receive_buffer_size {
+            let size = usize::try_from(size.get_bytes()).unwrap_or(usize::MAX);
+            SockRef::from(&socket).set_recv_buffer_size(size)?;
+        }
+        socket.set_nonblocking(true)?;
+        net::UnixDatagram::from_std(socket)
+    }
+
     /// Run [`UnixDatagram`] to completion
     ///
     /// This function runs the UDS server forever, unless a shutdown signal is
@@ -71,10 +97,7 @@ impl UnixDatagram {
     ///
     /// None known.
     pub async fn run(self) -> Result<(), Error> {
-        // Sockets cannot be rebound if they existed previously. Delete the
-        // socket, ignore any errors.
-        let _res = tokio::fs::remove_file(&self.path).map_err(Error::Io);
-        let socket = net::UnixDatagram::bind(&self.path).map_err(Error::Io)?;
+        let socket = self.bind().map_err(Error::Io)?;
         let mut buf = [0; 65536];
 
         let shutdown_wait = self.shutdown.recv();
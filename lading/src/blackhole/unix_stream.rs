@@ -6,17 +6,26 @@
 //! `bytes_received`: Total bytes received
 //! `requests_received`: Total requests received
 //!
+//! Additional metrics may be emitted when reads are limited, see
+//! [`super::slow_consumer`].
+//!
+//! On Linux a `path` beginning with `@` names a socket in the abstract
+//! namespace.
+//!
 
-use std::{io, path::PathBuf};
+use std::{io, path::PathBuf, sync::Arc};
 
+use byte_unit::Byte;
 use futures::StreamExt;
 use metrics::counter;
 use serde::{Deserialize, Serialize};
+use socket2::SockRef;
 use tokio::net;
 use tokio_util::io::ReaderStream;
 use tracing::info;
 
-use super::General;
+use super::{slow_consumer, General};
+use crate::common::unix_socket_addr;
 
 #[derive(thiserror::Error, Debug)]
 /// Errors produced by [`UnixStream`].
@@ -24,28 +33,48 @@ pub enum Error {
     /// Wrapper for [`std::io::Error`].
     #[error(transparent)]
     Io(io::Error),
+    /// Wrapper for [`slow_consumer::Error`].
+    #[error(transparent)]
+    SlowConsumer(#[from] slow_consumer::Error),
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 #[serde(deny_unknown_fields)]
 /// Configuration for [`UnixStream`].
 pub struct Config {
     /// The path of the socket to read from.
     pub path: PathBuf,
+    /// Limit the rate at which connections are read, default unlimited.
+    #[serde(default)]
+    pub slow_consumer: Option<slow_consumer::Config>,
+    /// The requested `SO_RCVBUF` of accepted connections, default chosen by
+    /// the OS
+    #[serde(default)]
+    pub receive_buffer_size: Option<Byte>,
 }
 
 #[derive(Debug)]
 /// The `UnixStream` blackhole.
 pub struct UnixStream {
     path: PathBuf,
+    receive_buffer_size: Option<Byte>,
     shutdown: lading_signal::Watcher,
     metric_labels: Vec<(String, String)>,
+    limiter: Option<Arc<slow_consumer::Limiter>>,
 }
 
 impl UnixStream {
     /// Create a new [`UnixStream`] server instance
-    #[must_use]
-    pub fn new(general: General, config: Config, shutdown: lading_signal::Watcher) -> Self {
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if the slow consumer configuration is
+    /// not valid.
+    pub fn new(
+        general: General,
+        config: Config,
+        shutdown: lading_signal::Watcher,
+    ) -> Result<Self, Error> {
         let mut metric_labels = vec![
             ("component".to_string(), "blackhole".to_string()),
             ("component_name".to_string(), "unix_stream".to_string()),
@@ -54,11 +83,30 @@ impl UnixStream {
             metric_labels.push(("id".to_string(), id));
         }
 
-        Self {
+        let limiter = config
+            .slow_consumer
+            .map(slow_consumer::Limiter::new)
+            .transpose()?
+            .map(Arc::new);
+
+        Ok(Self {
             path: config.path,
+            receive_buffer_size: config.receive_buffer_size,
             shutdown,
             metric_labels,
+            limiter,
+        })
+    }
+
+    fn bind(&self) -> Result<net::UnixListener, io::Error> {
+        let listener = std::os::unix::net::UnixListener::bind_addr(&unix_socket_addr(&self.path)?)?;
+        // Accepted connections inherit the listener's buffer size.
+        if let Some(size) = self.receive_buffer_size {
+            let size = usize::try_from(size.get_bytes()).unwrap_or(usize::MAX);
+            SockRef::from(&listener).set_recv_buffer_size(size)?;
         }
+        listener.set_nonblocking(true)?;
+        net::UnixListener::from_std(listener)
     }
 
     /// Run [`UnixStream`] to completion
@@ -74,7 +122,7 @@ impl UnixStream {
     ///
     /// None known.
     pub async fn run(self) -> Result<(), Error> {
-        let listener = net::UnixListener::bind(&self.path).map_err(Error::Io)?;
+        let listener = self.bind().map_err(Error::Io)?;
 
         let labels: &'static _ = Box::new(self.metric_labels.clone()).leak();
 
@@ -86,7 +134,7 @@ impl UnixStream {
                     let (socket, _) = conn.map_err(Error::Io)?;
                     counter!("connection_accepted", &self.metric_labels).increment(1);
                     tokio::spawn(
-                        Self::handle_connection(socket, labels)
+                        Self::handle_connection(socket, self.limiter.clone(), labels)
                     );
                 }
                 () = &mut shutdown_wait => {
@@ -97,7 +145,16 @@ impl UnixStream {
         }
     }
 
-    async fn handle_connection(socket: net::UnixStream, labels: &'static [(String, String)]) {
+    async fn handle_connection(
+        socket: net::UnixStream,
+        limiter: Option<Arc<slow_consumer::Limiter>>,
+        labels: &'static [(String, String)],
+    ) {
+        if let Some(limiter) = limiter {
+            limiter.consume(socket, labels).await;
+            return;
+        }
+
         let mut stream = ReaderStream::new(socket);
 
         while let Some(msg) = stream.next().await {
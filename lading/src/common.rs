@@ -1,4 +1,10 @@
-use std::{fmt, fs, path::PathBuf, process::Stdio, str};
+use std::{
+    fmt, fs, io,
+    os::unix::net::SocketAddr,
+    path::{Path, PathBuf},
+    process::Stdio,
+    str,
+};
 
 use serde::Deserialize;
 use tokio::sync::mpsc;
@@ -67,6 +73,23 @@ pub(crate) fn stdio(behavior: &Behavior) -> Stdio {
     }
 }
 
+/// Resolve `path` to the address of a Unix socket.
+///
+/// On Linux a path beginning with `@`, as in `@lading`, names a socket in the
+/// abstract namespace. Abstract sockets have no presence in the filesystem and
+/// so are not subject to its permissions.
+pub(crate) fn unix_socket_addr(path: &Path) -> io::Result<SocketAddr> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::{linux::net::SocketAddrExt, unix::ffi::OsStrExt};
+
+        if let Some(name) = path.as_os_str().as_bytes().strip_prefix(b"@") {
+            return SocketAddr::from_abstract_name(name);
+        }
+    }
+    SocketAddr::from_pathname(path)
+}
+
 #[derive(Debug)]
 pub(crate) struct PeekableReceiver<T> {
     receiver: mpsc::Receiver<T>,
@@ -96,3 +119,29 @@ impl<T> PeekableReceiver<T> {
         self.buffer.as_ref()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::unix_socket_addr;
+
+    #[test]
+    fn unix_socket_addr_names() {
+        let addr = unix_socket_addr(Path::new("/tmp/lading.sock")).expect("pathname");
+        assert_eq!(addr.as_pathname(), Some(Path::new("/tmp/lading.sock")));
+
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::linux::net::SocketAddrExt;
+
+            let addr = unix_socket_addr(Path::new("@lading")).expect("abstract name");
+            assert_eq!(addr.as_pathname(), None);
+            assert_eq!(addr.as_abstract_name(), Some(&b"lading"[..]));
+
+            // Only a leading `@` names an abstract socket.
+            let addr = unix_socket_addr(Path::new("lading@1")).expect("pathname");
+            assert_eq!(addr.as_pathname(), Some(Path::new("lading@1")));
+        }
+    }
+}
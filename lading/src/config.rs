@@ -141,7 +141,8 @@ blackhole:
             Config {
                 generator: vec![generator::Config {
                     general: generator::General {
-                        id: Some(String::from("Data out"))
+                        id: Some(String::from("Data out")),
+                        block_cache_directory: None,
                     },
                     inner: generator::Inner::Http(generator::http::Config {
                         seed: Default::default(),
@@ -164,6 +165,7 @@ blackhole:
                         maximum_block_size: lading_payload::block::default_maximum_block_size(),
                         parallel_connections: 5,
                         throttle: lading_throttle::Config::default(),
+                        tls: None,
                     }),
                 }],
                 blackhole: Some(vec![
@@ -173,12 +175,16 @@ blackhole:
                         },
                         inner: blackhole::Inner::Tcp(blackhole::tcp::Config {
                             binding_addr: SocketAddr::from_str("127.0.0.1:1000")?,
+                            slow_consumer: None,
+                            tls: None,
                         })
                     },
                     blackhole::Config {
                         general: blackhole::General { id: None },
                         inner: blackhole::Inner::Tcp(blackhole::tcp::Config {
                             binding_addr: SocketAddr::from_str("127.0.0.1:1001")?,
+                            slow_consumer: None,
+                            tls: None,
                         })
                     },
                 ]),
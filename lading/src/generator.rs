@@ -10,6 +10,10 @@
 //! indefinitely, paying higher memory and longer startup for better
 //! experimental control.
 
+use std::{num::NonZeroU32, path::Path, path::PathBuf};
+
+use lading_payload::block;
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 use tracing::{error, warn};
 
@@ -26,6 +30,7 @@ pub mod splunk_hec;
 pub mod tcp;
 pub mod udp;
 pub mod unix_datagram;
+pub mod unix_sender;
 pub mod unix_stream;
 
 #[derive(thiserror::Error, Debug)]
@@ -89,6 +94,34 @@ pub struct Config {
 pub struct General {
     /// The ID assigned to this generator
     pub id: Option<String>,
+    /// A directory in which to persist this generator's fixed block caches.
+    /// Later runs with the same configuration reload the caches rather than
+    /// generating them again, see [`block::Cache::fixed_persisted`].
+    #[serde(default)]
+    pub block_cache_directory: Option<PathBuf>,
+}
+
+/// Construct a fixed block cache, persisted in `directory` if one is given.
+///
+/// # Errors
+///
+/// See [`block::Cache::fixed`] and [`block::Cache::fixed_persisted`].
+pub fn fixed_block_cache<R>(
+    rng: &mut R,
+    total_bytes: NonZeroU32,
+    maximum_block_bytes: u128,
+    payload: &lading_payload::Config,
+    directory: Option<&Path>,
+) -> Result<block::Cache, block::Error>
+where
+    R: Rng + ?Sized,
+{
+    match directory {
+        Some(directory) => {
+            block::Cache::fixed_persisted(rng, total_bytes, maximum_block_bytes, payload, directory)
+        }
+        None => block::Cache::fixed(rng, total_bytes, maximum_block_bytes, payload),
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, PartialEq)]
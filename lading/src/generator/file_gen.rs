@@ -12,6 +12,7 @@
 //! Additional metrics may be emitted by this generator's [throttle].
 //!
 
+pub mod kubernetes;
 pub mod logrotate;
 #[cfg(feature = "logrotate_fs")]
 pub mod logrotate_fs;
@@ -32,6 +33,9 @@ pub enum Error {
     /// Wrapper around [`logrotate::Error`].
     #[error(transparent)]
     Logrotate(#[from] logrotate::Error),
+    /// Wrapper around [`kubernetes::Error`].
+    #[error(transparent)]
+    Kubernetes(#[from] kubernetes::Error),
     #[cfg(feature = "logrotate_fs")]
     /// Wrapper around [`logrotate_fs::Error`].
     #[error(transparent)]
@@ -47,6 +51,8 @@ pub enum Config {
     Traditional(traditional::Config),
     /// See [`logrotate::Config`].
     Logrotate(logrotate::Config),
+    /// See [`kubernetes::Config`].
+    Kubernetes(kubernetes::Config),
     #[cfg(feature = "logrotate_fs")]
     /// See [`logrotate_fs::Config`].
     LogrotateFs(logrotate_fs::Config),
@@ -62,6 +68,8 @@ pub enum FileGen {
     Traditional(traditional::Server),
     /// See [`logrotate::Server`] for details.
     Logrotate(logrotate::Server),
+    /// See [`kubernetes::Server`] for details.
+    Kubernetes(kubernetes::Server),
     #[cfg(feature = "logrotate_fs")]
     /// See [`logrotate_fs::Server`] for details.
     LogrotateFs(logrotate_fs::Server),
@@ -89,6 +97,9 @@ impl FileGen {
                 Self::Traditional(traditional::Server::new(general, c, shutdown)?)
             }
             Config::Logrotate(c) => Self::Logrotate(logrotate::Server::new(general, c, shutdown)?),
+            Config::Kubernetes(c) => {
+                Self::Kubernetes(kubernetes::Server::new(general, c, shutdown)?)
+            }
             #[cfg(feature = "logrotate_fs")]
             Config::LogrotateFs(c) => {
                 Self::LogrotateFs(logrotate_fs::Server::new(general, c, shutdown)?)
@@ -113,6 +124,7 @@ impl FileGen {
         match self {
             Self::Traditional(inner) => inner.spin().await?,
             Self::Logrotate(inner) => inner.spin().await?,
+            Self::Kubernetes(inner) => inner.spin().await?,
             #[cfg(feature = "logrotate_fs")]
             Self::LogrotateFs(inner) => inner.spin().await?,
         };
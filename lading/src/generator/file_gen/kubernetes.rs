@@ -0,0 +1,828 @@
+//! The lading 'kubernetes' file generator.
+//!
+//! This generator lays out a kubelet-style pod log directory tree and writes
+//! container logs into it, mimicking what a node agent tailing
+//! `/var/log/pods` sees. Each pod lives at
+//! `<root>/<namespace>_<pod>_<uid>/<container>/0.log` and every line of the
+//! configured payload is wrapped in either the CRI log format or the docker
+//! json-file format. Lines longer than `maximum_line_size` are split into
+//! partial records, as the container runtime does. Docker records are JSON
+//! strings, so their lines are made valid UTF-8 and split between characters.
+//! Each block's records are attributed to `stderr` with probability
+//! `stderr_ratio`, otherwise to `stdout`.
+//!
+//! As in the [`super::logrotate`] generator, log files are written up to a
+//! soft maximum size and then rotated. Rotation follows the kubelet: the live
+//! file is renamed to `0.log.<YYYYMMDD-hhmmss>` and the oldest rotated files
+//! are removed once there are more than `total_rotations` of them. Rotated
+//! files are not compressed.
+//!
+//! Pods may optionally be churned. When churn is configured each pod lives
+//! for a random time between the configured bounds, after which its directory
+//! is removed and a new pod with a fresh name and uid takes its place.
+//!
+//! ## Metrics
+//!
+//! `bytes_written`: Total bytes written, including record framing
+//! `bytes_per_second`: Configured rate to send data
+//! `partial_records_written`: Total partial (`P`) records written
+//! `pods_created`: Total pod directories created
+//! `pods_deleted`: Total pod directories removed
+//! `log_rotated`: Total container log rotations
+//!
+//! Additional metrics may be emitted by this generator's [throttle].
+//!
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    io::Write,
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use byte_unit::{Byte, ByteError};
+use futures::future::join_all;
+use lading_throttle::Throttle;
+use metrics::{counter, gauge};
+use rand::{distributions::Alphanumeric, prelude::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tokio::{
+    fs,
+    io::{AsyncWriteExt, BufWriter},
+    sync::mpsc,
+    task::{JoinError, JoinHandle},
+};
+use tracing::{error, info};
+
+use crate::common::PeekableReceiver;
+use lading_payload::block::{self, Block};
+
+use super::{logrotate::IoOp, General};
+
+#[derive(thiserror::Error, Debug)]
+/// Errors produced by [`Server`].
+pub enum Error {
+    /// Wrapper around [`std::io::Error`].
+    #[error("IO error [{path}]: {err}")]
+    Io {
+        /// The path being operated on
+        path: PathBuf,
+        /// The operation
+        operation: IoOp,
+        /// The error
+        err: std::io::Error,
+    },
+    /// Error for `fs::rename` operation
+    #[error("Rename error [{from} -> {to}]: {err}")]
+    IoRename {
+        /// The path being moved from
+        from: PathBuf,
+        /// The path being moved to
+        to: PathBuf,
+        /// The actual error
+        err: std::io::Error,
+    },
+    /// Error for `fs::remove_dir_all` operation
+    #[error("Remove directory error [{path}]: {err}")]
+    IoRemoveDir {
+        /// The directory being removed
+        path: PathBuf,
+        /// The actual error
+        err: std::io::Error,
+    },
+    /// Error for `fp.write_all` operation
+    #[error("Write all bytes error: {err}")]
+    IoWriteAll {
+        /// The actual error
+        err: std::io::Error,
+    },
+    /// Error for thread spawning
+    #[error("Unable to spawn thread: {err}")]
+    IoThreadSpawn {
+        /// The actual error
+        err: std::io::Error,
+    },
+    /// Error for `fs::flush` operation
+    #[error("Flush error: {err}")]
+    IoFlush {
+        /// The error, actual
+        err: std::io::Error,
+    },
+    /// Creation of payload blocks failed.
+    #[error("Block creation error: {0}")]
+    Block(#[from] block::Error),
+    /// Child sub-task error.
+    #[error("Child join error: {0}")]
+    Child(#[from] JoinError),
+    /// Byte error
+    #[error("Bytes must not be negative: {0}")]
+    Byte(#[from] ByteError),
+    /// Failed to convert, value is 0
+    #[error("Value provided must not be zero")]
+    Zero,
+    /// No namespaces were configured
+    #[error("At least one namespace must be configured")]
+    NoNamespaces,
+    /// The `stderr` ratio is not a probability
+    #[error("stderr ratio must be within [0, 1], got {0}")]
+    StderrRatio(f32),
+    /// Pod lifetime bounds are inverted
+    #[error("Minimum pod lifetime {min}s exceeds maximum {max}s")]
+    Lifetime {
+        /// The configured minimum lifetime
+        min: u32,
+        /// The configured maximum lifetime
+        max: u32,
+    },
+}
+
+/// The on-disk format of container log records.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    /// The CRI log format, `<timestamp> <stream> <P|F> <content>`, as written
+    /// by containerd and CRI-O.
+    #[default]
+    Cri,
+    /// The docker json-file format, one JSON object per record with `log`,
+    /// `stream` and `time` fields.
+    Docker,
+}
+
+/// Configuration of pod churn.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct Churn {
+    /// The minimum number of seconds a pod lives before being deleted.
+    pub minimum_pod_lifetime_seconds: u32,
+    /// The maximum number of seconds a pod lives before being deleted.
+    pub maximum_pod_lifetime_seconds: u32,
+}
+
+fn default_namespaces() -> Vec<String> {
+    vec!["default".to_string()]
+}
+
+fn default_containers_per_pod() -> u8 {
+    1
+}
+
+fn default_maximum_line_size() -> Byte {
+    // The kubelet and container runtimes split lines at 16 KiB.
+    Byte::from_unit(16.0, byte_unit::ByteUnit::KiB).expect("valid bytes")
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+/// Configuration of [`Server`]
+pub struct Config {
+    /// The seed for random operations against this target
+    pub seed: [u8; 32],
+    /// The root path for writing pod logs, conventionally `/var/log/pods`.
+    pub root: PathBuf,
+    /// The namespaces pods are placed in, chosen uniformly.
+    #[serde(default = "default_namespaces")]
+    pub namespaces: Vec<String>,
+    /// Total number of concurrent pods.
+    pub concurrent_pods: u16,
+    /// The number of containers in each pod.
+    #[serde(default = "default_containers_per_pod")]
+    pub containers_per_pod: u8,
+    /// The format of records in container logs.
+    #[serde(default)]
+    pub format: Format,
+    /// The **soft** maximum byte size of each container log.
+    pub maximum_bytes_per_log: Byte,
+    /// The number of rotated files kept per container log.
+    pub total_rotations: u8,
+    /// Lines longer than this are split into partial records.
+    #[serde(default = "default_maximum_line_size")]
+    pub maximum_line_size: Byte,
+    /// The probability in `[0, 1]` that a block's records are attributed to
+    /// the `stderr` stream rather than `stdout`, default 0.
+    #[serde(default)]
+    pub stderr_ratio: f32,
+    /// Pod churn configuration. If absent pods live for the whole experiment.
+    #[serde(default)]
+    pub churn: Option<Churn>,
+    /// Sets the [`crate::payload::Config`] of this template.
+    pub variant: lading_payload::Config,
+    /// Defines the number of bytes written per second to each pod.
+    bytes_per_second: Byte,
+    /// Defines the maximum internal cache of this log target. `file_gen` will
+    /// pre-build its outputs up to the byte capacity specified here.
+    maximum_prebuild_cache_size_bytes: Byte,
+    /// The maximum size in bytes of the largest block in the prebuild cache.
+    #[serde(default = "lading_payload::block::default_maximum_block_size")]
+    maximum_block_size: byte_unit::Byte,
+    /// Whether to use a fixed or streaming block cache
+    #[serde(default = "lading_payload::block::default_cache_method")]
+    block_cache_method: block::CacheMethod,
+    /// The load throttle configuration
+    #[serde(default)]
+    pub throttle: lading_throttle::Config,
+}
+
+#[derive(Debug)]
+/// The kubernetes pod log generator.
+///
+/// This generator writes pod logs to disk in the kubelet's layout, rotating
+/// and churning them as appropriate. It does this without coordination to the
+/// target.
+pub struct Server {
+    handles: Vec<JoinHandle<Result<(), Error>>>,
+    shutdown: lading_signal::Watcher,
+}
+
+impl Server {
+    /// Create a new [`Server`]
+    ///
+    /// # Errors
+    ///
+    /// Creation will fail if the configuration is invalid or the block cache
+    /// cannot be constructed.
+    ///
+    /// # Panics
+    ///
+    /// Function will panic if the configured bytes per second is zero.
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::needless_pass_by_value)]
+    pub fn new(
+        general: General,
+        config: Config,
+        shutdown: lading_signal::Watcher,
+    ) -> Result<Self, Error> {
+        let mut rng = StdRng::from_seed(config.seed);
+        let mut labels = vec![
+            ("component".to_string(), "generator".to_string()),
+            ("component_name".to_string(), "kubernetes".to_string()),
+        ];
+        if let Some(id) = general.id {
+            labels.push(("id".to_string(), id));
+        }
+
+        if config.namespaces.is_empty() {
+            return Err(Error::NoNamespaces);
+        }
+        if config.concurrent_pods == 0 || config.containers_per_pod == 0 {
+            return Err(Error::Zero);
+        }
+        if !(0.0..=1.0).contains(&config.stderr_ratio) {
+            return Err(Error::StderrRatio(config.stderr_ratio));
+        }
+        if let Some(churn) = config.churn {
+            if churn.maximum_pod_lifetime_seconds == 0 {
+                return Err(Error::Zero);
+            }
+            if churn.minimum_pod_lifetime_seconds > churn.maximum_pod_lifetime_seconds {
+                return Err(Error::Lifetime {
+                    min: churn.minimum_pod_lifetime_seconds,
+                    max: churn.maximum_pod_lifetime_seconds,
+                });
+            }
+        }
+
+        let bytes_per_second = NonZeroU32::new(config.bytes_per_second.get_bytes() as u32)
+            .expect("Expect: config bytes per second must be non-zero");
+        gauge!("bytes_per_second", &labels).set(f64::from(bytes_per_second.get()));
+
+        let maximum_bytes_per_log =
+            NonZeroU32::new(config.maximum_bytes_per_log.get_bytes() as u32).ok_or(Error::Zero)?;
+        let maximum_line_size = usize::try_from(config.maximum_line_size.get_bytes())
+            .ok()
+            .filter(|sz| *sz > 0)
+            .ok_or(Error::Zero)?;
+
+        let mut handles = Vec::new();
+
+        for idx in 0..config.concurrent_pods {
+            let throttle = Throttle::new_with_config(config.throttle, bytes_per_second);
+
+            let total_bytes =
+                NonZeroU32::new(config.maximum_prebuild_cache_size_bytes.get_bytes() as u32)
+                    .ok_or(Error::Zero)?;
+            let block_cache = match config.block_cache_method {
+                block::CacheMethod::Fixed => crate::generator::fixed_block_cache(
+                    &mut rng,
+                    total_bytes,
+                    config.maximum_block_size.get_bytes(),
+                    &config.variant,
+                    general.block_cache_directory.as_deref(),
+                )?,
+                block::CacheMethod::Streaming => block::Cache::streaming(
+                    &mut rng,
+                    config.maximum_block_size.get_bytes(),
+                    &config.variant,
+                )?,
+            };
+
+            let mut child_labels = labels.clone();
+            child_labels.push(("child_idx".to_string(), idx.to_string()));
+
+            let child = Child {
+                rng: StdRng::from_seed(rng.gen()),
+                root: config.root.clone(),
+                namespaces: config.namespaces.clone(),
+                containers_per_pod: config.containers_per_pod,
+                writer: Writer {
+                    format: config.format,
+                    maximum_line_size,
+                },
+                stderr_ratio: f64::from(config.stderr_ratio),
+                total_rotations: config.total_rotations,
+                maximum_bytes_per_log: u64::from(maximum_bytes_per_log.get()),
+                churn: config.churn,
+                bytes_per_second,
+                block_cache,
+                throttle,
+                shutdown: shutdown.clone(),
+                labels: child_labels,
+            };
+
+            handles.push(tokio::spawn(child.spin()));
+        }
+
+        Ok(Self { handles, shutdown })
+    }
+
+    /// Run [`Server`] to completion or until a shutdown signal is received.
+    ///
+    /// # Errors
+    ///
+    /// This function will terminate with an error if file permissions are not
+    /// correct, if the file cannot be written to etc. Any error from
+    /// `std::io::Error` is possible.
+    pub async fn spin(mut self) -> Result<(), Error> {
+        self.shutdown.recv().await;
+        info!("shutdown signal received");
+        for res in join_all(self.handles.drain(..)).await {
+            match res {
+                Ok(Ok(())) => continue,
+                Ok(Err(err)) => {
+                    error!("join_all error: {err}");
+                    return Err(err);
+                }
+                Err(err) => {
+                    error!("kubernetes child error: {err}");
+                    return Err(Error::Child(err));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Wraps payload lines into container log records.
+#[derive(Debug, Clone, Copy)]
+struct Writer {
+    format: Format,
+    maximum_line_size: usize,
+}
+
+impl Writer {
+    /// Wrap every line of `bytes` into `buf`, returning the number of partial
+    /// records written. A trailing fragment without a newline is treated as a
+    /// complete line.
+    fn wrap(&self, bytes: &[u8], stream: &str, timestamp: &str, buf: &mut Vec<u8>) -> u64 {
+        let mut partials = 0;
+        let bytes = bytes.strip_suffix(b"\n").unwrap_or(bytes);
+        for line in bytes.split(|b| *b == b'\n') {
+            // Docker records hold their content as a JSON string.
+            let line = match self.format {
+                Format::Cri => Cow::Borrowed(line),
+                Format::Docker => match String::from_utf8_lossy(line) {
+                    Cow::Borrowed(line) => Cow::Borrowed(line.as_bytes()),
+                    Cow::Owned(line) => Cow::Owned(line.into_bytes()),
+                },
+            };
+            let mut pieces = Pieces {
+                rest: &line,
+                maximum: self.maximum_line_size,
+                chars: self.format == Format::Docker,
+            }
+            .peekable();
+            if pieces.peek().is_none() {
+                self.record(b"", stream, timestamp, false, buf);
+                continue;
+            }
+            while let Some(piece) = pieces.next() {
+                let partial = pieces.peek().is_some();
+                if partial {
+                    partials += 1;
+                }
+                self.record(piece, stream, timestamp, partial, buf);
+            }
+        }
+        partials
+    }
+
+    fn record(
+        &self,
+        content: &[u8],
+        stream: &str,
+        timestamp: &str,
+        partial: bool,
+        buf: &mut Vec<u8>,
+    ) {
+        match self.format {
+            Format::Cri => {
+                let tag = if partial { 'P' } else { 'F' };
+                write!(buf, "{timestamp} {stream} {tag} ").expect("write to vec cannot fail");
+                buf.extend_from_slice(content);
+                buf.push(b'\n');
+            }
+            Format::Docker => {
+                // Partial docker records are those whose `log` lacks the
+                // trailing newline.
+                let mut log = std::str::from_utf8(content)
+                    .expect("docker lines are split between characters")
+                    .to_owned();
+                if !partial {
+                    log.push('\n');
+                }
+                buf.extend_from_slice(b"{\"log\":");
+                serde_json::to_writer(&mut *buf, &log).expect("write to vec cannot fail");
+                writeln!(buf, ",\"stream\":\"{stream}\",\"time\":\"{timestamp}\"}}")
+                    .expect("write to vec cannot fail");
+            }
+        }
+    }
+}
+
+/// The pieces of a line, each at most `maximum` bytes. If `chars` the line is
+/// valid UTF-8 and is split between characters, a piece holding at least one
+/// character.
+struct Pieces<'a> {
+    rest: &'a [u8],
+    maximum: usize,
+    chars: bool,
+}
+
+impl<'a> Iterator for Pieces<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        if self.rest.is_empty() {
+            return None;
+        }
+        let is_continuation = |b: u8| b & 0xc0 == 0x80;
+        let mut end = self.maximum.min(self.rest.len());
+        if self.chars {
+            while end > 0 && end < self.rest.len() && is_continuation(self.rest[end]) {
+                end -= 1;
+            }
+            if end == 0 {
+                end = 1;
+                while end < self.rest.len() && is_continuation(self.rest[end]) {
+                    end += 1;
+                }
+            }
+        }
+        let (piece, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        Some(piece)
+    }
+}
+
+/// A single container's live log file.
+struct Container {
+    path: PathBuf,
+    fp: BufWriter<fs::File>,
+    bytes_written: u64,
+    rotated: VecDeque<PathBuf>,
+}
+
+/// A pod directory and the logs of its containers.
+struct Pod {
+    dir: PathBuf,
+    containers: Vec<Container>,
+}
+
+struct Child {
+    rng: StdRng,
+    root: PathBuf,
+    namespaces: Vec<String>,
+    containers_per_pod: u8,
+    writer: Writer,
+    stderr_ratio: f64,
+    total_rotations: u8,
+    // The soft limit bytes per file that will trigger a rotation.
+    maximum_bytes_per_log: u64,
+    churn: Option<Churn>,
+    bytes_per_second: NonZeroU32,
+    block_cache: block::Cache,
+    throttle: Throttle,
+    shutdown: lading_signal::Watcher,
+    labels: Vec<(String, String)>,
+}
+
+impl Child {
+    async fn spin(mut self) -> Result<(), Error> {
+        // Move the block_cache into an OS thread, exposing a channel between it
+        // and this async context.
+        let block_cache = self.block_cache;
+        let (snd, rcv) = mpsc::channel(1024);
+        let mut rcv: PeekableReceiver<Block> = PeekableReceiver::new(rcv);
+        thread::Builder::new()
+            .spawn(|| block_cache.spin(snd))
+            .map_err(|err| Error::IoThreadSpawn { err })?;
+
+        let bytes_per_second = self.bytes_per_second.get() as usize;
+        let mut buf = Vec::with_capacity(bytes_per_second);
+        let mut next_container = 0;
+
+        let shutdown_wait = self.shutdown.recv();
+        tokio::pin!(shutdown_wait);
+        loop {
+            let mut pod = create_pod(
+                &mut self.rng,
+                &self.root,
+                &self.namespaces,
+                self.containers_per_pod,
+                bytes_per_second,
+            )
+            .await?;
+            counter!("pods_created", &self.labels).increment(1);
+
+            let lifetime = match self.churn {
+                Some(churn) => Duration::from_secs(u64::from(self.rng.gen_range(
+                    churn.minimum_pod_lifetime_seconds..=churn.maximum_pod_lifetime_seconds,
+                ))),
+                // Effectively forever, without overflowing the deadline.
+                None => Duration::from_secs(u64::from(u32::MAX)),
+            };
+            let expired = tokio::time::sleep(lifetime);
+            tokio::pin!(expired);
+
+            loop {
+                // SAFETY: By construction the block cache will never be empty
+                // except in the event of a catastrophic failure.
+                let blk = rcv.peek().await.expect("block cache should never be empty");
+
+                tokio::select! {
+                    _ = self.throttle.wait_for(blk.total_bytes) => {
+                        let blk = rcv.next().await.expect("failed to advance through the blocks");
+                        next_container = (next_container + 1) % pod.containers.len();
+                        let container = &mut pod.containers[next_container];
+
+                        let timestamp = OffsetDateTime::now_utc()
+                            .format(&Rfc3339)
+                            .expect("RFC 3339 formatting of the current time cannot fail");
+                        let stream = if self.rng.gen_bool(self.stderr_ratio) {
+                            "stderr"
+                        } else {
+                            "stdout"
+                        };
+                        buf.clear();
+                        let partials = self.writer.wrap(&blk.bytes, stream, &timestamp, &mut buf);
+
+                        container
+                            .fp
+                            .write_all(&buf)
+                            .await
+                            .map_err(|err| Error::IoWriteAll { err })?;
+                        counter!("bytes_written", &self.labels).increment(buf.len() as u64);
+                        counter!("partial_records_written", &self.labels).increment(partials);
+                        blk.record_defects(&self.labels);
+                        container.bytes_written += buf.len() as u64;
+
+                        if container.bytes_written > self.maximum_bytes_per_log {
+                            rotate(container, self.total_rotations, bytes_per_second).await?;
+                            counter!("log_rotated", &self.labels).increment(1);
+                        }
+                    }
+                    () = &mut expired => {
+                        delete_pod(pod).await?;
+                        counter!("pods_deleted", &self.labels).increment(1);
+                        break;
+                    }
+                    () = &mut shutdown_wait => {
+                        for container in &mut pod.containers {
+                            container.fp.flush().await.map_err(|err| Error::IoFlush { err })?;
+                        }
+                        info!("shutdown signal received");
+                        return Ok(());
+                    },
+                }
+            }
+        }
+    }
+}
+
+/// Generate a pod name in the style of a deployment's replica set,
+/// `<app>-<hash>-<suffix>`.
+fn pod_name<R: Rng + ?Sized>(rng: &mut R) -> String {
+    const LOWER: &[u8] = b"bcdfghjklmnpqrstvwxz2456789";
+    let suffix: String = (0..5)
+        .map(|_| LOWER[rng.gen_range(0..LOWER.len())] as char)
+        .collect();
+    let app: String = rng
+        .sample_iter(&Alphanumeric)
+        .take(8)
+        .map(|c| char::from(c).to_ascii_lowercase())
+        .collect();
+    format!(
+        "{app}-{hash:010x}-{suffix}",
+        hash = rng.gen::<u64>() & 0xff_ffff_ffff
+    )
+}
+
+async fn create_pod<R: Rng + ?Sized>(
+    rng: &mut R,
+    root: &Path,
+    namespaces: &[String],
+    containers_per_pod: u8,
+    bytes_per_second: usize,
+) -> Result<Pod, Error> {
+    let namespace = &namespaces[rng.gen_range(0..namespaces.len())];
+    let uid = uuid::Builder::from_random_bytes(rng.gen()).into_uuid();
+    let dir = root.join(format!("{namespace}_{}_{uid}", pod_name(rng)));
+
+    let mut containers = Vec::with_capacity(containers_per_pod.into());
+    for idx in 0..containers_per_pod {
+        let container_dir = dir.join(format!("container-{idx}"));
+        fs::create_dir_all(&container_dir)
+            .await
+            .map_err(|err| Error::Io {
+                path: container_dir.clone(),
+                operation: IoOp::CreateDirAll,
+                err,
+            })?;
+        let path = container_dir.join("0.log");
+        let fp = open(&path, bytes_per_second).await?;
+        containers.push(Container {
+            path,
+            fp,
+            bytes_written: 0,
+            rotated: VecDeque::new(),
+        });
+    }
+
+    Ok(Pod { dir, containers })
+}
+
+async fn delete_pod(pod: Pod) -> Result<(), Error> {
+    for mut container in pod.containers {
+        container
+            .fp
+            .flush()
+            .await
+            .map_err(|err| Error::IoFlush { err })?;
+    }
+    fs::remove_dir_all(&pod.dir)
+        .await
+        .map_err(|err| Error::IoRemoveDir {
+            path: pod.dir.clone(),
+            err,
+        })
+}
+
+async fn open(path: &Path, bytes_per_second: usize) -> Result<BufWriter<fs::File>, Error> {
+    let fp = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+        .map_err(|err| Error::Io {
+            path: PathBuf::from(path),
+            operation: IoOp::Open,
+            err,
+        })?;
+    Ok(BufWriter::with_capacity(bytes_per_second, fp))
+}
+
+/// Rotate a container log the way the kubelet does: rename the live file with
+/// a timestamp suffix, drop the oldest rotations beyond `total_rotations` and
+/// reopen the live file.
+async fn rotate(
+    container: &mut Container,
+    total_rotations: u8,
+    bytes_per_second: usize,
+) -> Result<(), Error> {
+    container
+        .fp
+        .flush()
+        .await
+        .map_err(|err| Error::IoFlush { err })?;
+
+    if total_rotations == 0 {
+        // Nothing is kept, the live file is simply unlinked.
+        fs::remove_file(&container.path)
+            .await
+            .map_err(|err| Error::Io {
+                path: container.path.clone(),
+                operation: IoOp::RemoveFile,
+                err,
+            })?;
+    } else {
+        let now = OffsetDateTime::now_utc();
+        let mut rotated = container.path.clone().into_os_string();
+        rotated.push(format!(
+            ".{:04}{:02}{:02}-{:02}{:02}{:02}",
+            now.year(),
+            u8::from(now.month()),
+            now.day(),
+            now.hour(),
+            now.minute(),
+            now.second()
+        ));
+        let rotated = PathBuf::from(rotated);
+        fs::rename(&container.path, &rotated)
+            .await
+            .map_err(|err| Error::IoRename {
+                from: container.path.clone(),
+                to: rotated.clone(),
+                err,
+            })?;
+        // Rotating twice within the same second overwrites the previous
+        // rotation, as the kubelet would.
+        if container.rotated.back() != Some(&rotated) {
+            container.rotated.push_back(rotated);
+        }
+        while container.rotated.len() > usize::from(total_rotations) {
+            let oldest = container
+                .rotated
+                .pop_front()
+                .expect("rotated cannot be empty");
+            fs::remove_file(&oldest).await.map_err(|err| Error::Io {
+                path: oldest.clone(),
+                operation: IoOp::RemoveFile,
+                err,
+            })?;
+        }
+    }
+
+    // Any holders of the old file pointer still have it but the file no
+    // longer has the live name.
+    container.fp = open(&container.path, bytes_per_second).await?;
+    container.bytes_written = 0;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Format, Writer};
+
+    #[test]
+    fn wraps_lines_with_partials() {
+        let ts = "2024-01-01T00:00:00.5Z";
+        let bytes = b"abcdefgh\nxy\n\"q\"\n";
+
+        let cri = Writer {
+            format: Format::Cri,
+            maximum_line_size: 3,
+        };
+        let mut buf = Vec::new();
+        assert_eq!(cri.wrap(bytes, "stdout", ts, &mut buf), 2);
+        assert_eq!(
+            String::from_utf8(buf).expect("records are valid UTF-8"),
+            "2024-01-01T00:00:00.5Z stdout P abc\n\
+             2024-01-01T00:00:00.5Z stdout P def\n\
+             2024-01-01T00:00:00.5Z stdout F gh\n\
+             2024-01-01T00:00:00.5Z stdout F xy\n\
+             2024-01-01T00:00:00.5Z stdout F \"q\"\n"
+        );
+
+        let docker = Writer {
+            format: Format::Docker,
+            maximum_line_size: 6,
+        };
+        let mut buf = Vec::new();
+        assert_eq!(docker.wrap(bytes, "stderr", ts, &mut buf), 1);
+        assert_eq!(
+            String::from_utf8(buf).expect("records are valid UTF-8"),
+            "{\"log\":\"abcdef\",\"stream\":\"stderr\",\"time\":\"2024-01-01T00:00:00.5Z\"}\n\
+             {\"log\":\"gh\\n\",\"stream\":\"stderr\",\"time\":\"2024-01-01T00:00:00.5Z\"}\n\
+             {\"log\":\"xy\\n\",\"stream\":\"stderr\",\"time\":\"2024-01-01T00:00:00.5Z\"}\n\
+             {\"log\":\"\\\"q\\\"\\n\",\"stream\":\"stderr\",\"time\":\"2024-01-01T00:00:00.5Z\"}\n"
+        );
+    }
+
+    // Docker records are split between characters, never through one, and
+    // invalid UTF-8 is replaced rather than split further.
+    #[test]
+    fn docker_splits_between_characters() {
+        let ts = "2024-01-01T00:00:00.5Z";
+        let docker = Writer {
+            format: Format::Docker,
+            maximum_line_size: 4,
+        };
+        let mut buf = Vec::new();
+        assert_eq!(docker.wrap("aé€😀\n".as_bytes(), "stdout", ts, &mut buf), 2);
+        assert_eq!(docker.wrap(b"\xffab\n", "stdout", ts, &mut buf), 1);
+
+        let logs: Vec<String> = std::str::from_utf8(&buf)
+            .expect("records are valid UTF-8")
+            .lines()
+            .map(|record| {
+                let record: serde_json::Value =
+                    serde_json::from_str(record).expect("record is not JSON");
+                record["log"].as_str().expect("log is a string").to_string()
+            })
+            .collect();
+        assert_eq!(logs, ["aé", "€", "😀\n", "\u{fffd}a", "b\n"]);
+    }
+}
@@ -199,11 +199,17 @@ impl Server {
                 NonZeroU32::new(config.maximum_prebuild_cache_size_bytes.get_bytes() as u32)
                     .ok_or(Error::Zero)?;
             let block_cache = match config.block_cache_method {
-                block::CacheMethod::Fixed => block::Cache::fixed(
+                block::CacheMethod::Fixed => crate::generator::fixed_block_cache(
                     &mut rng,
                     total_bytes,
                     config.maximum_block_size.get_bytes(),
                     &config.variant,
+                    general.block_cache_directory.as_deref(),
+                )?,
+                block::CacheMethod::Streaming => block::Cache::streaming(
+                    &mut rng,
+                    config.maximum_block_size.get_bytes(),
+                    &config.variant,
                 )?,
             };
 
@@ -412,6 +418,7 @@ async fn write_bytes(
             .await
             .map_err(|err| Error::IoWriteAll { err })?;
         counter!("bytes_written", labels).increment(total_bytes);
+        blk.record_defects(labels);
         *total_bytes_written += total_bytes;
     }
 
@@ -53,6 +53,10 @@ pub struct Config {
     /// The maximum size in bytes of the largest block in the prebuild cache.
     #[serde(default = "lading_payload::block::default_maximum_block_size")]
     maximum_block_size: byte_unit::Byte,
+    /// The block cache method. Files are read at arbitrary offsets, which only
+    /// the fixed method supports.
+    #[serde(default = "lading_payload::block::default_cache_method")]
+    block_cache_method: block::CacheMethod,
     /// The mount-point for this filesystem
     mount_point: PathBuf,
     /// The load profile, controlling bytes per second as a function of time.
@@ -105,6 +109,9 @@ pub enum Error {
     /// Could not join on task
     #[error("Could not join on task: {0}")]
     Join(#[from] JoinError),
+    /// The block cache method does not support reads at an offset
+    #[error("logrotate_fs requires the fixed block cache method")]
+    CacheMethod,
 }
 
 #[derive(Debug)]
@@ -124,26 +131,31 @@ impl Server {
     ///
     /// # Errors
     ///
-    /// Function will error if block cache cannot be built.
+    /// Function will error if block cache cannot be built or if the block
+    /// cache method is not fixed.
     ///
     /// # Panics
     ///
     /// Function will panic if the filesystem cannot be started.
     pub fn new(
-        _: generator::General,
+        general: generator::General,
         config: Config,
         shutdown: lading_signal::Watcher,
     ) -> Result<Self, Error> {
+        if config.block_cache_method != block::CacheMethod::Fixed {
+            return Err(Error::CacheMethod);
+        }
         let mut rng = SmallRng::from_seed(config.seed);
 
         let total_bytes =
             NonZeroU32::new(config.maximum_prebuild_cache_size_bytes.get_bytes() as u32)
                 .ok_or(Error::Zero)?;
-        let block_cache = block::Cache::fixed(
+        let block_cache = generator::fixed_block_cache(
             &mut rng,
             total_bytes,
             config.maximum_block_size.get_bytes(),
             &config.variant,
+            general.block_cache_directory.as_deref(),
         )?;
 
         let start_time = std::time::Instant::now();
@@ -467,3 +479,37 @@ impl Filesystem for LogrotateFS {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, Error, Server};
+    use crate::generator::General;
+
+    // Files are read at offsets, which a streaming cache cannot serve.
+    #[test]
+    fn rejects_streaming_cache() {
+        let config: Config = serde_yaml::from_str(&format!(
+            "seed: {seed:?}
+concurrent_logs: 1
+maximum_bytes_per_log: 1 MiB
+total_rotations: 1
+max_depth: 0
+variant: ascii
+maximum_prebuild_cache_size_bytes: 1 MiB
+block_cache_method: streaming
+mount_point: /tmp/lading-logrotate-fs
+load_profile:
+  constant: 1 KiB",
+            seed = [0_u8; 32],
+        ))
+        .expect("failed to parse config");
+        let general = General {
+            id: None,
+            block_cache_directory: None,
+        };
+        assert!(matches!(
+            Server::new(general, config, lading_signal::signal().0),
+            Err(Error::CacheMethod)
+        ));
+    }
+}
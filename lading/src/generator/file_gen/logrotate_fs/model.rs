@@ -850,6 +850,9 @@ impl State {
 
                 // Get data from block_cache without worrying about blocks
                 let data = self.block_cache.read_at(offset as u64, to_read);
+                if let Some(defects) = self.block_cache.defects_at(offset as u64, to_read) {
+                    defects.record(&[]);
+                }
                 assert!(data.len() == to_read, "Data returned from block_cache is distinct from the read size: {l} != {to_read}", l = data.len());
 
                 file.read(to_read as u64, now);
@@ -1025,13 +1028,16 @@ mod test {
                         load_profile,
                     )| {
                         let mut rng = StdRng::seed_from_u64(seed);
-                        let block_cache = block::Cache::fixed(
-                            &mut rng,
-                            NonZeroU32::new(1_000_000).expect("zero value"),
-                            10_000,
-                            &lading_payload::Config::Ascii,
-                        )
-                        .expect("block construction");
+                        let block_cache =
+                            block::Cache::fixed(
+                                &mut rng,
+                                NonZeroU32::new(1_000_000).expect("zero value"),
+                                10_000,
+                                &lading_payload::Config::Ascii(
+                                    lading_payload::ascii::Config::default(),
+                                ),
+                            )
+                            .expect("block construction");
 
                         State::new(
                             &mut rng,
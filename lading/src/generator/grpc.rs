@@ -17,6 +17,8 @@ use std::{convert::TryFrom, num::NonZeroU32, thread, time::Duration};
 use byte_unit::ByteError;
 use bytes::{Buf, BufMut, Bytes};
 use http::{uri::PathAndQuery, Uri};
+use hyper_rustls::HttpsConnector;
+use hyper_util::client::legacy::connect::HttpConnector;
 use lading_throttle::Throttle;
 use metrics::{counter, gauge};
 use rand::rngs::StdRng;
@@ -55,6 +57,9 @@ pub enum Error {
     /// Zero value
     #[error("Value provided must not be zero")]
     Zero,
+    /// Wrapper around [`crate::tls::Error`].
+    #[error(transparent)]
+    Tls(#[from] crate::tls::Error),
 }
 
 /// Config for [`Grpc`]
@@ -83,6 +88,10 @@ pub struct Config {
     /// The load throttle configuration
     #[serde(default)]
     pub throttle: lading_throttle::Config,
+    /// TLS configuration, default plaintext. Required for `https` target
+    /// URIs.
+    #[serde(default)]
+    pub tls: Option<crate::tls::ClientConfig>,
 }
 
 /// No-op tonic codec. Sends raw bytes and returns the number of bytes received.
@@ -145,6 +154,7 @@ pub struct Grpc {
     throttle: Throttle,
     block_cache: block::Cache,
     metric_labels: Vec<(String, String)>,
+    connector: Option<HttpsConnector<HttpConnector>>,
 }
 
 impl Grpc {
@@ -181,11 +191,17 @@ impl Grpc {
             NonZeroU32::new(config.maximum_prebuild_cache_size_bytes.get_bytes() as u32)
                 .ok_or(Error::Zero)?;
         let block_cache = match config.block_cache_method {
-            block::CacheMethod::Fixed => block::Cache::fixed(
+            block::CacheMethod::Fixed => super::fixed_block_cache(
                 &mut rng,
                 total_bytes,
                 config.maximum_block_size.get_bytes(),
                 &config.variant,
+                general.block_cache_directory.as_deref(),
+            )?,
+            block::CacheMethod::Streaming => block::Cache::streaming(
+                &mut rng,
+                config.maximum_block_size.get_bytes(),
+                &config.variant,
             )?,
         };
 
@@ -196,8 +212,15 @@ impl Grpc {
             .cloned()
             .expect("target_uri should have an RPC path");
 
+        let connector = config
+            .tls
+            .as_ref()
+            .map(|tls| crate::tls::https_connector(Some(tls), true))
+            .transpose()?;
+
         let throttle = Throttle::new_with_config(config.throttle, bytes_per_second);
         Ok(Self {
+            connector,
             target_uri,
             rpc_path,
             config,
@@ -217,7 +240,10 @@ impl Grpc {
         let endpoint = tonic::transport::Endpoint::new(uri)?;
         let endpoint = endpoint.concurrency_limit(self.config.parallel_connections as usize);
         let endpoint = endpoint.connect_timeout(Duration::from_secs(1));
-        let conn = endpoint.connect().await?;
+        let conn = match self.connector {
+            Some(ref connector) => endpoint.connect_with_connector(connector.clone()).await?,
+            None => endpoint.connect().await?,
+        };
         let conn = tonic::client::Grpc::new(conn);
 
         debug!("gRPC generator connected");
@@ -291,6 +317,7 @@ impl Grpc {
                         Ok(res) => {
                             counter!("bytes_written", &self.metric_labels).increment(block_length as u64);
                             counter!("request_ok", &self.metric_labels).increment(1);
+                            blk.record_defects(&self.metric_labels);
                             counter!("response_bytes", &self.metric_labels).increment(res.into_inner() as u64);
                         }
                         Err(err) => {
@@ -15,7 +15,11 @@ use std::{num::NonZeroU32, thread};
 
 use byte_unit::ByteError;
 use hyper::{header::CONTENT_LENGTH, HeaderMap, Request, Uri};
-use hyper_util::{client::legacy::Client, rt::TokioExecutor};
+use hyper_rustls::HttpsConnector;
+use hyper_util::{
+    client::legacy::{connect::HttpConnector, Client},
+    rt::TokioExecutor,
+};
 use lading_throttle::Throttle;
 use metrics::{counter, gauge};
 use once_cell::sync::OnceCell;
@@ -72,6 +76,10 @@ pub struct Config {
     /// The load throttle configuration
     #[serde(default)]
     pub throttle: lading_throttle::Config,
+    /// TLS configuration, default plaintext. Required for `https` target
+    /// URIs.
+    #[serde(default)]
+    pub tls: Option<crate::tls::ClientConfig>,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -95,6 +103,9 @@ pub enum Error {
     /// Failed to convert, value is 0
     #[error("Value provided must not be zero")]
     Zero,
+    /// Wrapper around [`crate::tls::Error`].
+    #[error(transparent)]
+    Tls(#[from] crate::tls::Error),
 }
 
 /// The HTTP generator.
@@ -111,6 +122,7 @@ pub struct Http {
     block_cache: block::Cache,
     metric_labels: Vec<(String, String)>,
     shutdown: lading_signal::Watcher,
+    connector: HttpsConnector<HttpConnector>,
 }
 
 impl Http {
@@ -142,6 +154,7 @@ impl Http {
         let bytes_per_second = NonZeroU32::new(config.bytes_per_second.get_bytes() as u32)
             .expect("config bytes per second must be non-zero");
         gauge!("bytes_per_second", &labels).set(f64::from(bytes_per_second.get()));
+        let connector = crate::tls::https_connector(config.tls.as_ref(), false)?;
 
         match config.method {
             Method::Post {
@@ -153,11 +166,17 @@ impl Http {
                     NonZeroU32::new(maximum_prebuild_cache_size_bytes.get_bytes() as u32)
                         .ok_or(Error::Zero)?;
                 let block_cache = match block_cache_method {
-                    block::CacheMethod::Fixed => block::Cache::fixed(
+                    block::CacheMethod::Fixed => super::fixed_block_cache(
                         &mut rng,
                         total_bytes,
                         config.maximum_block_size.get_bytes(),
                         &variant,
+                        general.block_cache_directory.as_deref(),
+                    )?,
+                    block::CacheMethod::Streaming => block::Cache::streaming(
+                        &mut rng,
+                        config.maximum_block_size.get_bytes(),
+                        &variant,
                     )?,
                 };
 
@@ -174,6 +193,7 @@ impl Http {
                     throttle: Throttle::new_with_config(config.throttle, bytes_per_second),
                     metric_labels: labels,
                     shutdown,
+                    connector,
                 })
             }
         }
@@ -193,7 +213,7 @@ impl Http {
         let client = Client::builder(TokioExecutor::new())
             .pool_max_idle_per_host(self.parallel_connections as usize)
             .retry_canceled_requests(false)
-            .build_http();
+            .build(self.connector);
         let method = self.method;
         let uri = self.uri;
 
@@ -213,6 +233,7 @@ impl Http {
 
             let body = crate::full(blk.bytes.clone());
             let block_length = blk.bytes.len();
+            let defects = blk.defects;
 
             let mut request = Request::builder()
                 .method(method.clone())
@@ -237,6 +258,9 @@ impl Http {
                         match client.request(request).await {
                             Ok(response) => {
                                 counter!("bytes_written", &labels).increment(block_length as u64);
+                                if let Some(defects) = defects {
+                                    defects.record(&labels);
+                                }
                                 let status = response.status();
                                 let mut status_labels = labels.clone();
                                 status_labels
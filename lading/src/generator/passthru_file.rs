@@ -107,12 +107,13 @@ impl PassthruFile {
             NonZeroU32::new(config.bytes_per_second.get_bytes() as u32).ok_or(Error::Zero)?;
         gauge!("bytes_per_second", &labels).set(f64::from(bytes_per_second.get()));
 
-        let block_cache = block::Cache::fixed(
+        let block_cache = super::fixed_block_cache(
             &mut rng,
             NonZeroU32::new(config.maximum_prebuild_cache_size_bytes.get_bytes() as u32)
                 .ok_or(Error::Zero)?,
             config.maximum_block_size.get_bytes(),
             &config.variant,
+            general.block_cache_directory.as_deref(),
         )?;
 
         let path = PathBuf::from(&config.path);
@@ -179,6 +180,7 @@ impl PassthruFile {
                     match current_file.write_all(&blk.bytes).await {
                         Ok(()) => {
                             counter!("bytes_written", &self.metric_labels).increment(u64::from(blk.total_bytes.get()));
+                            blk.record_defects(&self.metric_labels);
                         }
                         Err(err) => {
                             warn!("write failed: {}", err);
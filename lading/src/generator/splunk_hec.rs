@@ -26,6 +26,7 @@ use http::{
 };
 use http_body_util::BodyExt;
 use hyper::body::Body;
+use hyper_rustls::HttpsConnector;
 use hyper_util::{
     client::legacy::{connect::HttpConnector, Client},
     rt::TokioExecutor,
@@ -93,6 +94,10 @@ pub struct Config {
     /// The load throttle configuration
     #[serde(default)]
     pub throttle: lading_throttle::Config,
+    /// TLS configuration, default plaintext. If set requests are made over
+    /// HTTPS.
+    #[serde(default)]
+    pub tls: Option<crate::tls::ClientConfig>,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -128,6 +133,9 @@ pub enum Error {
     /// Wrapper around [`hyper::Error`].
     #[error("HTTP error: {0}")]
     Hyper(#[from] hyper::Error),
+    /// Wrapper around [`crate::tls::Error`].
+    #[error(transparent)]
+    Tls(#[from] crate::tls::Error),
 }
 
 /// Defines a task that emits variant lines to a Splunk HEC server controlling
@@ -142,12 +150,14 @@ pub struct SplunkHec {
     metric_labels: Vec<(String, String)>,
     channels: Channels,
     shutdown: lading_signal::Watcher,
+    connector: HttpsConnector<HttpConnector>,
 }
 
 /// Derive the intended path from the format configuration
 // https://docs.splunk.com/Documentation/Splunk/latest/Data/FormateventsforHTTPEventCollector#Event_data
 fn get_uri_by_format(
     base_uri: &Uri,
+    scheme: &str,
     format: lading_payload::splunk_hec::Encoding,
 ) -> Result<Uri, Error> {
     let path = match format {
@@ -162,7 +172,7 @@ fn get_uri_by_format(
                 .ok_or(Error::EmptyAuthorityURI)?
                 .to_string(),
         )
-        .scheme("http")
+        .scheme(scheme)
         .path_and_query(path)
         .build()?;
     Ok(uri)
@@ -198,7 +208,13 @@ impl SplunkHec {
             NonZeroU32::new(config.bytes_per_second.get_bytes() as u32).ok_or(Error::Zero)?;
         gauge!("bytes_per_second", &labels).set(f64::from(bytes_per_second.get()));
 
-        let uri = get_uri_by_format(&config.target_uri, config.format)?;
+        let scheme = if config.tls.is_some() {
+            "https"
+        } else {
+            "http"
+        };
+        let uri = get_uri_by_format(&config.target_uri, scheme, config.format)?;
+        let connector = crate::tls::https_connector(config.tls.as_ref(), false)?;
 
         let payload_config = lading_payload::Config::SplunkHec {
             encoding: config.format,
@@ -207,11 +223,17 @@ impl SplunkHec {
             NonZeroU32::new(config.maximum_prebuild_cache_size_bytes.get_bytes() as u32)
                 .ok_or(Error::Zero)?;
         let block_cache = match config.block_cache_method {
-            block::CacheMethod::Fixed => block::Cache::fixed(
+            block::CacheMethod::Fixed => super::fixed_block_cache(
                 &mut rng,
                 total_bytes,
                 config.maximum_block_size.get_bytes(),
                 &payload_config,
+                general.block_cache_directory.as_deref(),
+            )?,
+            block::CacheMethod::Streaming => block::Cache::streaming(
+                &mut rng,
+                config.maximum_block_size.get_bytes(),
+                &payload_config,
             )?,
         };
 
@@ -219,10 +241,15 @@ impl SplunkHec {
         if let Some(ack_settings) = config.acknowledgements {
             let ack_uri = Uri::builder()
                 .authority(uri.authority().ok_or(Error::EmptyAuthorityURI)?.to_string())
-                .scheme("http")
+                .scheme(scheme)
                 .path_and_query(SPLUNK_HEC_ACKNOWLEDGEMENTS_PATH)
                 .build()?;
-            channels.enable_acknowledgements(ack_uri, config.token.clone(), ack_settings);
+            channels.enable_acknowledgements(
+                ack_uri,
+                config.token.clone(),
+                ack_settings,
+                connector.clone(),
+            );
         }
 
         CONNECTION_SEMAPHORE
@@ -238,6 +265,7 @@ impl SplunkHec {
             throttle: Throttle::new_with_config(config.throttle, bytes_per_second),
             metric_labels: labels,
             shutdown,
+            connector,
         })
     }
 
@@ -256,7 +284,7 @@ impl SplunkHec {
         let client = Client::builder(TokioExecutor::new())
             .pool_max_idle_per_host(self.parallel_connections as usize)
             .retry_canceled_requests(false)
-            .build_http();
+            .build(self.connector);
 
         let uri = self.uri;
         let labels = self.metric_labels;
@@ -305,7 +333,7 @@ impl SplunkHec {
                     // the AckID, meaning we could just keep the channel logic
                     // in this main loop here and avoid the AckService entirely.
                     let permit = CONNECTION_SEMAPHORE.get().expect("Connecton Semaphore is empty or being initialized").acquire().await.expect("Semaphore has already been closed");
-                    tokio::spawn(send_hec_request(permit, block_length, labels, channel, client, request, request_shutdown.clone()));
+                    tokio::spawn(send_hec_request(permit, blk, labels, channel, client, request, request_shutdown.clone()));
                 }
                 () = &mut shutdown_wait => {
                     info!("shutdown signal received");
@@ -324,10 +352,10 @@ impl SplunkHec {
 
 async fn send_hec_request<B>(
     permit: SemaphorePermit<'_>,
-    block_length: usize,
+    blk: Block,
     labels: Vec<(String, String)>,
     channel: Channel,
-    client: Client<HttpConnector, B>,
+    client: Client<HttpsConnector<HttpConnector>, B>,
     request: Request<B>,
     shutdown: lading_signal::Watcher,
 ) -> Result<(), Error>
@@ -344,7 +372,8 @@ where
             match tm {
                 Ok(tm) => match tm {
                     Ok(response) => {
-                        counter!("bytes_written", &labels).increment(block_length as u64);
+                        counter!("bytes_written", &labels).increment(blk.bytes.len() as u64);
+                        blk.record_defects(&labels);
                         let (parts, body) = response.into_parts();
                         let status = parts.status;
                         let mut status_labels = labels.clone();
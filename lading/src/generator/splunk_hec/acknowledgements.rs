@@ -5,6 +5,7 @@ use bytes::Bytes;
 use futures::Future;
 use http::{header::AUTHORIZATION, Method, Request, StatusCode, Uri};
 use http_body_util::{combinators::BoxBody, BodyExt};
+use hyper_rustls::HttpsConnector;
 use hyper_util::{
     client::legacy::{connect::HttpConnector, Client},
     rt::TokioExecutor,
@@ -91,10 +92,11 @@ impl Channels {
         ack_uri: Uri,
         token: String,
         ack_settings: AckSettings,
+        connector: HttpsConnector<HttpConnector>,
     ) {
         let client = Client::builder(TokioExecutor::new())
             .retry_canceled_requests(false)
-            .build_http();
+            .build(connector);
 
         let ack_service = AckService {
             ack_uri,
@@ -135,7 +137,7 @@ impl<'a, V> Iterator for Iter<'a, V> {
 struct AckService {
     pub(crate) ack_uri: Uri,
     pub(crate) token: String,
-    pub(crate) client: Client<HttpConnector, BoxBody<Bytes, hyper::Error>>,
+    pub(crate) client: Client<HttpsConnector<HttpConnector>, BoxBody<Bytes, hyper::Error>>,
     pub(crate) ack_settings: AckSettings,
 }
 
@@ -196,7 +198,7 @@ impl AckService {
 }
 
 async fn ack_request(
-    client: Client<HttpConnector, BoxBody<Bytes, hyper::Error>>,
+    client: Client<HttpsConnector<HttpConnector>, BoxBody<Bytes, hyper::Error>>,
     request: Request<BoxBody<Bytes, hyper::Error>>,
     channel_id: String,
     ack_ids: &mut FxHashMap<AckId, u64>,
@@ -5,6 +5,8 @@
 //! `bytes_written`: Bytes sent successfully
 //! `packets_sent`: Packets sent successfully
 //! `request_failure`: Number of failed writes; each occurrence causes a reconnect
+//! `connection_opened`: Number of connections established
+//! `connection_closed`: Number of connections closed, labeled by `reason`
 //! `connection_failure`: Number of connection failures
 //! `bytes_per_second`: Configured rate to send data
 //!
@@ -12,17 +14,28 @@
 //!
 
 use std::{
+    io,
     net::{SocketAddr, ToSocketAddrs},
     num::NonZeroU32,
+    sync::Arc,
     thread,
+    time::Duration,
 };
 
 use byte_unit::ByteError;
 use lading_throttle::Throttle;
 use metrics::{counter, gauge};
-use rand::{rngs::StdRng, SeedableRng};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rustls::pki_types::ServerName;
 use serde::{Deserialize, Serialize};
-use tokio::{io::AsyncWriteExt, net::TcpStream, sync::mpsc};
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+    sync::{mpsc, Mutex},
+    task::{JoinError, JoinSet},
+    time::Instant,
+};
+use tokio_rustls::TlsConnector;
 use tracing::{info, trace};
 
 use crate::common::PeekableReceiver;
@@ -50,6 +63,37 @@ pub struct Config {
     /// The load throttle configuration
     #[serde(default)]
     pub throttle: lading_throttle::Config,
+    /// TLS configuration, default plaintext
+    #[serde(default)]
+    pub tls: Option<crate::tls::ClientConfig>,
+    /// The number of connections to hold open to the target in parallel
+    #[serde(default = "default_parallel_connections")]
+    pub parallel_connections: u16,
+    /// Connection churn configuration, default long-lived connections
+    #[serde(default)]
+    pub churn: Option<Churn>,
+}
+
+fn default_parallel_connections() -> u16 {
+    1
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+/// Configuration for connection churn. Each connection is closed and a new one
+/// opened in its place once either of its limits is reached.
+pub struct Churn {
+    /// Reconnect once this many bytes have been written to a connection
+    pub max_bytes_per_connection: Option<byte_unit::Byte>,
+    /// Reconnect once a connection has been open this many milliseconds
+    pub max_lifetime_millis: Option<u64>,
+    /// Fraction in `[0, 1]` by which each connection's limits are randomly
+    /// shortened, spreading reconnects out over time
+    #[serde(default)]
+    pub jitter: f32,
+    /// The maximum rate at which new connections are opened, across all
+    /// parallel connections
+    pub connections_per_second: Option<NonZeroU32>,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -67,6 +111,18 @@ pub enum Error {
     /// Zero value error
     #[error("Value cannot be zero")]
     Zero,
+    /// Wrapper around [`crate::tls::Error`].
+    #[error(transparent)]
+    Tls(#[from] crate::tls::Error),
+    /// Churn jitter outside of `[0, 1]`
+    #[error("Churn jitter must be within [0, 1], got {0}")]
+    Jitter(f32),
+    /// Connection throttle error
+    #[error("Connection throttle error: {0}")]
+    Throttle(#[from] lading_throttle::Error),
+    /// Connection task error
+    #[error("Connection task failure: {0}")]
+    Connection(#[from] JoinError),
 }
 
 #[derive(Debug)]
@@ -79,6 +135,21 @@ pub struct Tcp {
     block_cache: block::Cache,
     metric_labels: Vec<(String, String)>,
     shutdown: lading_signal::Watcher,
+    tls: Option<(Arc<rustls::ClientConfig>, ServerName<'static>)>,
+    parallel_connections: u16,
+    churn: Option<Churn>,
+    rng: StdRng,
+}
+
+#[derive(Debug)]
+/// A single connection to the target, reopened on failure or churn.
+struct Connection {
+    addr: SocketAddr,
+    tls: Option<(Arc<rustls::ClientConfig>, ServerName<'static>)>,
+    churn: Option<Churn>,
+    connect_throttle: Option<Arc<Mutex<Throttle>>>,
+    rng: StdRng,
+    metric_labels: Vec<(String, String)>,
 }
 
 impl Tcp {
@@ -111,12 +182,13 @@ impl Tcp {
             NonZeroU32::new(config.bytes_per_second.get_bytes() as u32).ok_or(Error::Zero)?;
         gauge!("bytes_per_second", &labels).set(f64::from(bytes_per_second.get()));
 
-        let block_cache = block::Cache::fixed(
+        let block_cache = super::fixed_block_cache(
             &mut rng,
             NonZeroU32::new(config.maximum_prebuild_cache_size_bytes.get_bytes() as u32)
                 .ok_or(Error::Zero)?,
             config.maximum_block_size.get_bytes(),
             &config.variant,
+            general.block_cache_directory.as_deref(),
         )?;
 
         let addr = config
@@ -125,20 +197,61 @@ impl Tcp {
             .expect("could not convert to socket")
             .next()
             .expect("could not convert to socket addr");
+        let tls = match config.tls {
+            Some(ref tls) => {
+                // The host portion of the address, without any IPv6 brackets.
+                let host = config
+                    .addr
+                    .rsplit_once(':')
+                    .map_or(config.addr.as_str(), |(host, _)| host)
+                    .trim_start_matches('[')
+                    .trim_end_matches(']');
+                Some(crate::tls::client(tls, host)?)
+            }
+            None => None,
+        };
+        if let Some(churn) = config.churn {
+            if !(0.0..=1.0).contains(&churn.jitter) {
+                return Err(Error::Jitter(churn.jitter));
+            }
+        }
+        if config.parallel_connections == 0 {
+            return Err(Error::Zero);
+        }
         Ok(Self {
             addr,
+            tls,
             block_cache,
             throttle: Throttle::new_with_config(config.throttle, bytes_per_second),
             metric_labels: labels,
             shutdown,
+            parallel_connections: config.parallel_connections,
+            churn: config.churn,
+            rng,
         })
     }
 
+    async fn connect(
+        addr: SocketAddr,
+        tls: Option<&(Arc<rustls::ClientConfig>, ServerName<'static>)>,
+    ) -> Result<Box<dyn AsyncWrite + Unpin + Send>, io::Error> {
+        let stream = TcpStream::connect(addr).await?;
+        match tls {
+            None => Ok(Box::new(stream)),
+            Some((config, server_name)) => {
+                let stream = TlsConnector::from(Arc::clone(config))
+                    .connect(server_name.clone(), stream)
+                    .await?;
+                Ok(Box::new(stream))
+            }
+        }
+    }
+
     /// Run [`Tcp`] to completion or until a shutdown signal is received.
     ///
     /// # Errors
     ///
-    /// Function will return an error when the TCP socket cannot be written to.
+    /// Function will return an error if a connection's throttle fails.
     ///
     /// # Panics
     ///
@@ -151,54 +264,305 @@ impl Tcp {
         let mut rcv: PeekableReceiver<Block> = PeekableReceiver::new(rcv);
         thread::Builder::new().spawn(|| block_cache.spin(snd))?;
 
-        let mut current_connection = None;
+        // Blocks are released at the configured rate and handed to whichever
+        // connection is free to write them.
+        let (blk_snd, blk_rcv) = mpsc::channel(usize::from(self.parallel_connections));
+        let blk_rcv = Arc::new(Mutex::new(blk_rcv));
+        let connect_throttle = self
+            .churn
+            .and_then(|churn| churn.connections_per_second)
+            .map(|cps| {
+                Arc::new(Mutex::new(Throttle::new_with_config(
+                    lading_throttle::Config::default(),
+                    cps,
+                )))
+            });
+        let mut connections = JoinSet::new();
+        for _ in 0..self.parallel_connections {
+            let connection = Connection {
+                addr: self.addr,
+                tls: self.tls.clone(),
+                churn: self.churn,
+                connect_throttle: connect_throttle.clone(),
+                rng: StdRng::from_rng(&mut self.rng).expect("failed to seed connection rng"),
+                metric_labels: self.metric_labels.clone(),
+            };
+            connections.spawn(connection.spin(Arc::clone(&blk_rcv)));
+        }
 
         let shutdown_wait = self.shutdown.recv();
         tokio::pin!(shutdown_wait);
         loop {
-            let Some(ref mut connection) = current_connection else {
-                match TcpStream::connect(self.addr).await {
-                    Ok(client) => {
-                        current_connection = Some(client);
+            let blk = rcv.peek().await.expect("block cache should never be empty");
+            let total_bytes = blk.total_bytes;
+
+            tokio::select! {
+                _ = self.throttle.wait_for(total_bytes) => {
+                    let blk = rcv.next().await.expect("failed to advance through the blocks"); // actually advance through the blocks
+                    tokio::select! {
+                        res = blk_snd.send(blk) => if res.is_err() {
+                            // Every connection has exited, with an error
+                            // joined below.
+                            break;
+                        },
+                        () = &mut shutdown_wait => break,
+                    }
+                }
+                Some(res) = connections.join_next() => res??,
+                () = &mut shutdown_wait => break,
+            }
+        }
+        info!("shutdown signal received");
+        connections.abort_all();
+        while let Some(res) = connections.join_next().await {
+            match res {
+                Ok(res) => res?,
+                Err(err) if err.is_cancelled() => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Connection {
+    /// Connection limits, shortened by a random fraction of the configured
+    /// jitter.
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    fn limits(&mut self) -> (Option<u64>, Option<Instant>) {
+        let Some(churn) = self.churn else {
+            return (None, None);
+        };
+        let scale = 1.0 - f64::from(churn.jitter) * self.rng.gen::<f64>();
+        let max_bytes = churn
+            .max_bytes_per_connection
+            .map(|b| ((b.get_bytes() as f64 * scale) as u64).max(1));
+        let deadline = churn
+            .max_lifetime_millis
+            .map(|millis| Instant::now() + Duration::from_millis((millis as f64 * scale) as u64));
+        (max_bytes, deadline)
+    }
+
+    /// Write blocks from `blocks` until it closes, reconnecting as needed.
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if the connection throttle fails.
+    async fn spin(mut self, blocks: Arc<Mutex<mpsc::Receiver<Block>>>) -> Result<(), Error> {
+        loop {
+            if let Some(ref throttle) = self.connect_throttle {
+                throttle.lock().await.wait().await?;
+            }
+            let mut connection = match Tcp::connect(self.addr, self.tls.as_ref()).await {
+                Ok(client) => {
+                    counter!("connection_opened", &self.metric_labels).increment(1);
+                    client
+                }
+                Err(err) => {
+                    trace!("connection to {} failed: {}", self.addr, err);
+
+                    let mut error_labels = self.metric_labels.clone();
+                    error_labels.push(("error".to_string(), err.to_string()));
+                    counter!("connection_failure", &error_labels).increment(1);
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+            };
+
+            let (max_bytes, deadline) = self.limits();
+            let expired = async move {
+                match deadline {
+                    Some(deadline) => tokio::time::sleep_until(deadline).await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::pin!(expired);
+            let mut bytes_written: u64 = 0;
+            let reason = loop {
+                let blk = tokio::select! {
+                    blk = async { blocks.lock().await.recv().await } => match blk {
+                        Some(blk) => blk,
+                        None => return Ok(()),
+                    },
+                    () = &mut expired => break "lifetime",
+                };
+                match connection.write_all(&blk.bytes).await {
+                    Ok(()) => {
+                        let total_bytes = u64::from(blk.total_bytes.get());
+                        counter!("bytes_written", &self.metric_labels).increment(total_bytes);
+                        counter!("packets_sent", &self.metric_labels).increment(1);
+                        blk.record_defects(&self.metric_labels);
+                        bytes_written += total_bytes;
+                        if max_bytes.is_some_and(|max| bytes_written >= max) {
+                            break "bytes";
+                        }
                     }
                     Err(err) => {
-                        trace!("connection to {} failed: {}", self.addr, err);
+                        trace!("write failed: {}", err);
 
                         let mut error_labels = self.metric_labels.clone();
                         error_labels.push(("error".to_string(), err.to_string()));
-                        counter!("connection_failure", &error_labels).increment(1);
-                        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+                        counter!("request_failure", &error_labels).increment(1);
+                        break "error";
                     }
                 }
-                continue;
             };
+            if reason != "error" {
+                let _ = connection.shutdown().await;
+            }
+            let mut closed_labels = self.metric_labels.clone();
+            closed_labels.push(("reason".to_string(), reason.to_string()));
+            counter!("connection_closed", &closed_labels).increment(1);
+        }
+    }
+}
 
-            let blk = rcv.peek().await.expect("block cache should never be empty");
-            let total_bytes = blk.total_bytes;
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
 
-            tokio::select! {
-                _ = self.throttle.wait_for(total_bytes) => {
-                    let blk = rcv.next().await.expect("failed to advance through the blocks"); // actually advance through the blocks
-                    match connection.write_all(&blk.bytes).await {
-                        Ok(()) => {
-                            counter!("bytes_written", &self.metric_labels).increment(u64::from(blk.total_bytes.get()));
-                            counter!("packets_sent", &self.metric_labels).increment(1);
-                        }
-                        Err(err) => {
-                            trace!("write failed: {}", err);
+    use tokio::{io::AsyncReadExt, net::TcpListener};
 
-                            let mut error_labels = self.metric_labels.clone();
-                            error_labels.push(("error".to_string(), err.to_string()));
-                            counter!("request_failure", &error_labels).increment(1);
-                            current_connection = None;
-                        }
+    use super::{Config, General, Tcp};
+
+    /// What a listener has seen of the generator's connections.
+    #[derive(Debug, Default)]
+    struct Seen {
+        accepted: AtomicUsize,
+        open: AtomicUsize,
+        max_open: AtomicUsize,
+        /// The bytes read so far from each connection, in order of accept.
+        received: std::sync::Mutex<Vec<usize>>,
+        /// The bytes read from each closed connection.
+        closed: std::sync::Mutex<Vec<usize>>,
+    }
+
+    /// Accept connections on a local listener, reading each to its end.
+    async fn listen() -> (SocketAddr, Arc<Seen>) {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("failed to bind");
+        let addr = listener.local_addr().expect("no local address");
+        let seen = Arc::new(Seen::default());
+        let server = Arc::clone(&seen);
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.expect("failed to accept");
+                let index = server.accepted.fetch_add(1, Ordering::SeqCst);
+                server.received.lock().expect("poisoned").push(0);
+                let open = server.open.fetch_add(1, Ordering::SeqCst) + 1;
+                server.max_open.fetch_max(open, Ordering::SeqCst);
+                let server = Arc::clone(&server);
+                tokio::spawn(async move {
+                    let mut buf = vec![0; 4096];
+                    let mut total = 0;
+                    while let Ok(n @ 1..) = stream.read(&mut buf).await {
+                        total += n;
+                        server.received.lock().expect("poisoned")[index] = total;
                     }
-                }
-                () = &mut shutdown_wait => {
-                    info!("shutdown signal received");
-                    return Ok(());
-                },
+                    server.closed.lock().expect("poisoned").push(total);
+                    server.open.fetch_sub(1, Ordering::SeqCst);
+                });
             }
-        }
+        });
+        (addr, seen)
+    }
+
+    /// Wait for `seen` to satisfy `done`. The timeout only bounds a test that
+    /// would otherwise hang, nothing is asserted of elapsed time.
+    async fn until(seen: &Seen, done: impl Fn(&Seen) -> bool) {
+        let wait = async {
+            while !done(seen) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(60), wait)
+            .await
+            .expect("listener never saw the expected connections");
+    }
+
+    /// Run a generator against `addr` until the listener's `seen` satisfies
+    /// `done`, then until the listener has read every connection to its end.
+    async fn run(addr: SocketAddr, extra: &str, seen: &Seen, done: impl Fn(&Seen) -> bool) {
+        let config: Config = serde_yaml::from_str(&format!(
+            "seed: {seed:?}
+addr: \"{addr}\"
+variant: ascii
+bytes_per_second: \"1 MiB\"
+maximum_block_size: \"1 KiB\"
+maximum_prebuild_cache_size_bytes: \"64 KiB\"
+parallel_connections: 3
+{extra}",
+            seed = [0_u8; 32],
+        ))
+        .expect("failed to parse config");
+        let (shutdown, broadcast) = lading_signal::signal();
+        let tcp = Tcp::new(
+            General {
+                id: None,
+                block_cache_directory: None,
+            },
+            &config,
+            shutdown,
+        )
+        .expect("failed to create generator");
+        let spin = tokio::spawn(tcp.spin());
+        until(seen, done).await;
+        broadcast.signal();
+        spin.await
+            .expect("generator panicked")
+            .expect("generator failed");
+        until(seen, |seen| seen.open.load(Ordering::SeqCst) == 0).await;
+    }
+
+    // Long-lived connections are opened once each, all at once, and carry
+    // many blocks without reconnecting.
+    #[tokio::test]
+    async fn holds_parallel_connections() {
+        let (addr, seen) = listen().await;
+        run(addr, "", &seen, |seen| {
+            let received = seen.received.lock().expect("poisoned");
+            received.len() == 3 && received.iter().all(|&n| n >= 32 * 1024)
+        })
+        .await;
+
+        assert_eq!(seen.accepted.load(Ordering::SeqCst), 3);
+        assert_eq!(seen.max_open.load(Ordering::SeqCst), 3);
+    }
+
+    // Churned connections reconnect once their jittered byte limit is reached.
+    #[tokio::test]
+    async fn churn_reconnects() {
+        let (addr, seen) = listen().await;
+        run(
+            addr,
+            "churn:
+  max_bytes_per_connection: \"8 KiB\"
+  jitter: 0.5",
+            &seen,
+            |seen| seen.closed.lock().expect("poisoned").len() > 3 * 4,
+        )
+        .await;
+
+        assert!(seen.accepted.load(Ordering::SeqCst) > 3 * 4);
+        let closed = seen.closed.lock().expect("poisoned");
+        // A connection is closed by the first block to reach its limit, which
+        // jitter shortens by up to half. The last connections are closed by
+        // shutdown instead.
+        let churned: Vec<usize> = closed.iter().copied().filter(|&n| n >= 4 * 1024).collect();
+        assert!(churned.len() >= closed.len() - 3, "{closed:?}");
+        assert!(closed.iter().all(|&n| n < 9 * 1024), "{closed:?}");
+        assert!(churned.iter().any(|&n| n < 7 * 1024), "{closed:?}");
     }
 }
@@ -4,16 +4,22 @@
 //!
 //! `bytes_written`: Bytes written successfully
 //! `packets_sent`: Packets written successfully
-//! `request_failure`: Number of failed writes; each occurrence causes a socket re-bind
-//! `connection_failure`: Number of socket bind failures
+//! `request_failure`: Number of failed writes, labeled by `socket`; each
+//! occurrence other than `ENOBUFS` causes a socket re-bind
+//! `packets_dropped`: Packets of a batch not written after a failed write
+//! `bytes_dropped`: Bytes of a batch not written after a failed write
+//! `connection_failure`: Number of socket bind failures, labeled by `socket`
 //! `bytes_per_second`: Configured rate to send data
 //!
 //! Additional metrics may be emitted by this generator's [throttle].
 //!
 
 use std::{
-    net::{SocketAddr, ToSocketAddrs},
+    future::Future,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs},
     num::NonZeroU32,
+    sync::Arc,
     thread,
     time::Duration,
 };
@@ -21,9 +27,15 @@ use std::{
 use byte_unit::{Byte, ByteError, ByteUnit};
 use lading_throttle::Throttle;
 use metrics::{counter, gauge};
+use nix::libc;
 use rand::{rngs::StdRng, SeedableRng};
 use serde::{Deserialize, Serialize};
-use tokio::{net::UdpSocket, sync::mpsc};
+use socket2::{SockAddr, SockRef};
+use tokio::{
+    net::UdpSocket,
+    sync::{mpsc, Mutex},
+    task::JoinSet,
+};
 use tracing::{debug, info, trace};
 
 use crate::common::PeekableReceiver;
@@ -31,18 +43,38 @@ use lading_payload::block::{self, Block};
 
 use super::General;
 
+/// The largest batch a single `sendmmsg` call accepts, `UIO_MAXIOV`.
+const MAXIMUM_BATCH_SIZE: u16 = 1024;
+
 // https://stackoverflow.com/a/42610200
 fn maximum_block_size() -> Byte {
     Byte::from_unit(65_507f64, ByteUnit::B).expect("catastrophic programming bug")
 }
 
+fn default_sockets() -> u16 {
+    1
+}
+
+fn default_batch_size() -> u16 {
+    1
+}
+
+fn default_multicast_ttl() -> u32 {
+    1
+}
+
+fn default_multicast_loopback() -> bool {
+    true
+}
+
 #[derive(Debug, Deserialize, Serialize, PartialEq)]
 #[serde(deny_unknown_fields)]
 /// Configuration of this generator.
 pub struct Config {
     /// The seed for random operations against this target
     pub seed: [u8; 32],
-    /// The address for the target, must be a valid `SocketAddr`
+    /// The address for the target, must be a valid `SocketAddr`. May be an
+    /// IPv4 or IPv6 address, unicast or multicast.
     pub addr: String,
     /// The payload variant
     pub variant: lading_payload::Config,
@@ -51,11 +83,41 @@ pub struct Config {
     /// The maximum size in bytes of the largest block in the prebuild cache.
     #[serde(default = "maximum_block_size")]
     pub maximum_block_size: byte_unit::Byte,
+    /// Whether to use a fixed or streaming block cache
+    #[serde(default = "lading_payload::block::default_cache_method")]
+    pub block_cache_method: block::CacheMethod,
     /// The maximum size in bytes of the cache of prebuilt messages
     pub maximum_prebuild_cache_size_bytes: byte_unit::Byte,
     /// The load throttle configuration
     #[serde(default)]
     pub throttle: lading_throttle::Config,
+    /// The number of sockets, each with its own source port, to send from
+    #[serde(default = "default_sockets")]
+    pub sockets: u16,
+    /// The maximum number of datagrams sent per system call. Batches are
+    /// sent with `sendmmsg` on Linux and one datagram at a time elsewhere.
+    #[serde(default = "default_batch_size")]
+    pub batch_size: u16,
+    /// Options applied when `addr` is a multicast address
+    #[serde(default)]
+    pub multicast: Option<Multicast>,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+/// Multicast options for the sending sockets.
+pub struct Multicast {
+    /// The multicast TTL, or hop limit for IPv6
+    #[serde(default = "default_multicast_ttl")]
+    pub ttl: u32,
+    /// Whether sent datagrams are looped back to the local host
+    #[serde(default = "default_multicast_loopback")]
+    pub loopback: bool,
+    /// The local IPv4 interface address to send from, default chosen by the
+    /// OS
+    pub interface_addr: Option<Ipv4Addr>,
+    /// The IPv6 interface index to send from, default chosen by the OS
+    pub interface_index: Option<u32>,
 }
 
 /// Errors produced by [`Udp`].
@@ -73,6 +135,9 @@ pub enum Error {
     /// Failed to convert, value is 0
     #[error("Value provided is zero")]
     Zero,
+    /// Batch size exceeds what a single system call accepts
+    #[error("Batch size must not exceed {MAXIMUM_BATCH_SIZE}, got {0}")]
+    BatchSize(u16),
 }
 
 #[derive(Debug)]
@@ -85,6 +150,19 @@ pub struct Udp {
     block_cache: block::Cache,
     metric_labels: Vec<(String, String)>,
     shutdown: lading_signal::Watcher,
+    sockets: u16,
+    batch_size: u16,
+    multicast: Option<Multicast>,
+}
+
+#[derive(Debug)]
+/// A single sending socket, re-bound on failure.
+struct Sender {
+    addr: SocketAddr,
+    batch_size: usize,
+    multicast: Option<Multicast>,
+    metric_labels: Vec<(String, String)>,
+    socket_labels: Vec<(String, String)>,
 }
 
 impl Udp {
@@ -117,13 +195,30 @@ impl Udp {
             NonZeroU32::new(config.bytes_per_second.get_bytes() as u32).ok_or(Error::Zero)?;
         gauge!("bytes_per_second", &labels).set(f64::from(bytes_per_second.get()));
 
-        let block_cache = block::Cache::fixed(
-            &mut rng,
+        if config.sockets == 0 || config.batch_size == 0 {
+            return Err(Error::Zero);
+        }
+        if config.batch_size > MAXIMUM_BATCH_SIZE {
+            return Err(Error::BatchSize(config.batch_size));
+        }
+
+        let total_bytes =
             NonZeroU32::new(config.maximum_prebuild_cache_size_bytes.get_bytes() as u32)
-                .ok_or(Error::Zero)?,
-            config.maximum_block_size.get_bytes(),
-            &config.variant,
-        )?;
+                .ok_or(Error::Zero)?;
+        let block_cache = match config.block_cache_method {
+            block::CacheMethod::Fixed => super::fixed_block_cache(
+                &mut rng,
+                total_bytes,
+                config.maximum_block_size.get_bytes(),
+                &config.variant,
+                general.block_cache_directory.as_deref(),
+            )?,
+            block::CacheMethod::Streaming => block::Cache::streaming(
+                &mut rng,
+                config.maximum_block_size.get_bytes(),
+                &config.variant,
+            )?,
+        };
 
         let addr = config
             .addr
@@ -138,6 +233,9 @@ impl Udp {
             throttle: Throttle::new_with_config(config.throttle, bytes_per_second),
             metric_labels: labels,
             shutdown,
+            sockets: config.sockets,
+            batch_size: config.batch_size,
+            multicast: config.multicast,
         })
     }
 
@@ -152,7 +250,6 @@ impl Udp {
     /// Function will panic if underlying byte capacity is not available.
     pub async fn spin(mut self) -> Result<(), Error> {
         debug!("UDP generator running");
-        let mut connection = Option::<UdpSocket>::None;
 
         // Move the block_cache into an OS thread, exposing a channel between it
         // and this async context.
@@ -161,6 +258,25 @@ impl Udp {
         let mut rcv: PeekableReceiver<Block> = PeekableReceiver::new(rcv);
         thread::Builder::new().spawn(|| block_cache.spin(snd))?;
 
+        // Blocks are released at the configured rate and picked up in batches
+        // by whichever socket is free to send them.
+        let (blk_snd, blk_rcv) =
+            mpsc::channel(usize::from(self.sockets) * usize::from(self.batch_size));
+        let blk_rcv = Arc::new(Mutex::new(blk_rcv));
+        let mut senders = JoinSet::new();
+        for index in 0..self.sockets {
+            let mut socket_labels = self.metric_labels.clone();
+            socket_labels.push(("socket".to_string(), index.to_string()));
+            let sender = Sender {
+                addr: self.addr,
+                batch_size: usize::from(self.batch_size),
+                multicast: self.multicast,
+                metric_labels: self.metric_labels.clone(),
+                socket_labels,
+            };
+            senders.spawn(sender.spin(Arc::clone(&blk_rcv)));
+        }
+
         let shutdown_wait = self.shutdown.recv();
         tokio::pin!(shutdown_wait);
         loop {
@@ -168,46 +284,360 @@ impl Udp {
             let total_bytes = blk.total_bytes;
 
             tokio::select! {
-                conn = UdpSocket::bind("127.0.0.1:0"), if connection.is_none() => {
-                    match conn {
-                        Ok(sock) => {
-                            debug!("UDP port bound");
-                            connection = Some(sock);
-                        }
-                        Err(err) => {
-                            trace!("binding UDP port failed: {}", err);
-
-                            let mut error_labels = self.metric_labels.clone();
-                            error_labels.push(("error".to_string(), err.to_string()));
-                            counter!("connection_failure", &error_labels).increment(1);
-                            tokio::time::sleep(Duration::from_secs(1)).await;
-                        }
+                _ = self.throttle.wait_for(total_bytes) => {
+                    let blk = rcv.next().await.expect("failed to advance through the blocks"); // actually advance through the blocks
+                    tokio::select! {
+                        res = blk_snd.send(blk) => res.expect("senders exited unexpectedly"),
+                        () = &mut shutdown_wait => break,
                     }
                 }
-                _ = self.throttle.wait_for(total_bytes), if connection.is_some() => {
-                    let sock = connection.expect("connection failed");
-                    let blk = rcv.next().await.expect("failed to advance through the blocks"); // actually advance through the blocks
-                    match sock.send_to(&blk.bytes, self.addr).await {
-                        Ok(bytes) => {
-                            counter!("bytes_written", &self.metric_labels).increment(bytes as u64);
-                            counter!("packets_sent", &self.metric_labels).increment(1);
-                            connection = Some(sock);
-                        }
-                        Err(err) => {
-                            debug!("write failed: {}", err);
-
-                            let mut error_labels = self.metric_labels.clone();
-                            error_labels.push(("error".to_string(), err.to_string()));
-                            counter!("request_failure", &error_labels).increment(1);
-                            connection = None;
-                        }
+                () = &mut shutdown_wait => break,
+            }
+        }
+        info!("shutdown signal received");
+        senders.shutdown().await;
+        Ok(())
+    }
+}
+
+impl Sender {
+    async fn bind(&self) -> Result<UdpSocket, io::Error> {
+        let ip = match (self.addr.ip(), self.addr.ip().is_loopback()) {
+            (IpAddr::V4(_), true) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            (IpAddr::V4(_), false) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            (IpAddr::V6(_), true) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            (IpAddr::V6(_), false) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        let sock = UdpSocket::bind(SocketAddr::new(ip, 0)).await?;
+        if let Some(multicast) = self.multicast {
+            if self.addr.ip().is_multicast() {
+                let sock_ref = SockRef::from(&sock);
+                if self.addr.is_ipv4() {
+                    sock.set_multicast_ttl_v4(multicast.ttl)?;
+                    sock.set_multicast_loop_v4(multicast.loopback)?;
+                    if let Some(interface) = multicast.interface_addr {
+                        sock_ref.set_multicast_if_v4(&interface)?;
+                    }
+                } else {
+                    sock_ref.set_multicast_hops_v6(multicast.ttl)?;
+                    sock.set_multicast_loop_v6(multicast.loopback)?;
+                    if let Some(interface) = multicast.interface_index {
+                        sock_ref.set_multicast_if_v6(interface)?;
                     }
                 }
-                () = &mut shutdown_wait => {
-                    info!("shutdown signal received");
-                    return Ok(());
-                },
             }
         }
+        Ok(sock)
+    }
+
+    async fn spin(self, blocks: Arc<Mutex<mpsc::Receiver<Block>>>) {
+        let target = SockAddr::from(self.addr);
+        let mut batch: Vec<Block> = Vec::with_capacity(self.batch_size);
+        let mut connection = Option::<UdpSocket>::None;
+        loop {
+            let Some(sock) = connection.take() else {
+                match self.bind().await {
+                    Ok(sock) => {
+                        debug!("UDP port bound");
+                        connection = Some(sock);
+                    }
+                    Err(err) => {
+                        trace!("binding UDP port failed: {}", err);
+
+                        let mut error_labels = self.socket_labels.clone();
+                        error_labels.push(("error".to_string(), err.to_string()));
+                        counter!("connection_failure", &error_labels).increment(1);
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                    }
+                }
+                continue;
+            };
+
+            batch.clear();
+            {
+                let mut blocks = blocks.lock().await;
+                let Some(blk) = blocks.recv().await else {
+                    return;
+                };
+                batch.push(blk);
+                while batch.len() < self.batch_size {
+                    match blocks.try_recv() {
+                        Ok(blk) => batch.push(blk),
+                        Err(_) => break,
+                    }
+                }
+            }
+
+            let outcome = send_all(&batch, |rest| send_batch(&sock, &target, rest)).await;
+            counter!("bytes_written", &self.metric_labels).increment(outcome.bytes_written);
+            counter!("packets_sent", &self.metric_labels).increment(outcome.sent as u64);
+            for blk in &batch[..outcome.sent] {
+                blk.record_defects(&self.metric_labels);
+            }
+            let mut rebind = false;
+            if let Some(err) = outcome.error {
+                debug!("write failed: {}", err);
+
+                let mut error_labels = self.socket_labels.clone();
+                error_labels.push(("error".to_string(), err.to_string()));
+                counter!("request_failure", &error_labels).increment(1);
+                counter!("packets_dropped", &self.metric_labels).increment(outcome.dropped as u64);
+                counter!("bytes_dropped", &self.metric_labels).increment(outcome.bytes_dropped);
+                rebind = err.raw_os_error() != Some(libc::ENOBUFS);
+            }
+            // A full send buffer is transient, any other failure re-binds the
+            // socket.
+            if !rebind {
+                connection = Some(sock);
+            }
+        }
+    }
+}
+
+/// The fate of the datagrams of one batch.
+#[derive(Debug, Default)]
+struct Outcome {
+    /// Datagrams sent, a prefix of the batch
+    sent: usize,
+    bytes_written: u64,
+    /// Datagrams dropped after `error`, the rest of the batch
+    dropped: usize,
+    bytes_dropped: u64,
+    error: Option<io::Error>,
+}
+
+/// Send `batch` with as many calls to `send` as it takes, each sending a
+/// prefix of what remains. If a call fails the rest of the batch is dropped.
+async fn send_all<'a, F, Fut>(batch: &'a [Block], mut send: F) -> Outcome
+where
+    F: FnMut(&'a [Block]) -> Fut,
+    Fut: Future<Output = io::Result<usize>>,
+{
+    let bytes = |blocks: &[Block]| blocks.iter().map(|blk| blk.bytes.len() as u64).sum();
+    let mut outcome = Outcome::default();
+    while outcome.sent < batch.len() {
+        match send(&batch[outcome.sent..]).await {
+            Ok(total) => {
+                outcome.bytes_written += bytes(&batch[outcome.sent..outcome.sent + total]);
+                outcome.sent += total;
+            }
+            Err(err) => {
+                let rest = &batch[outcome.sent..];
+                outcome.dropped = rest.len();
+                outcome.bytes_dropped = bytes(rest);
+                outcome.error = Some(err);
+                break;
+            }
+        }
+    }
+    outcome
+}
+
+/// Send as much of `batch` to `target` as a single system call allows,
+/// returning the number of datagrams sent.
+#[cfg(target_os = "linux")]
+async fn send_batch(sock: &UdpSocket, target: &SockAddr, batch: &[Block]) -> io::Result<usize> {
+    use std::os::fd::AsRawFd;
+    use tokio::io::Interest;
+
+    if batch.len() == 1 {
+        sock.send_to(
+            &batch[0].bytes,
+            target.as_socket().expect("not an IP address"),
+        )
+        .await?;
+        return Ok(1);
+    }
+    sock.async_io(Interest::WRITABLE, || {
+        let mut iovecs: Vec<libc::iovec> = batch
+            .iter()
+            .map(|blk| libc::iovec {
+                iov_base: blk.bytes.as_ptr() as *mut libc::c_void,
+                iov_len: blk.bytes.len(),
+            })
+            .collect();
+        let mut msgs: Vec<libc::mmsghdr> = iovecs
+            .iter_mut()
+            .map(|iov| {
+                // SAFETY: msghdr is a plain C struct for which all zeroes is a
+                // valid, empty value.
+                let mut hdr: libc::msghdr = unsafe { std::mem::zeroed() };
+                hdr.msg_name = target.as_ptr() as *mut libc::c_void;
+                hdr.msg_namelen = target.len();
+                hdr.msg_iov = std::ptr::from_mut(iov);
+                hdr.msg_iovlen = 1;
+                libc::mmsghdr {
+                    msg_hdr: hdr,
+                    msg_len: 0,
+                }
+            })
+            .collect();
+        // SAFETY: every header points at a live iovec and the target address,
+        // both of which outlive this call, and the batch length is bounded by
+        // `MAXIMUM_BATCH_SIZE`.
+        #[allow(clippy::cast_possible_truncation)]
+        let sent = unsafe {
+            libc::sendmmsg(
+                sock.as_raw_fd(),
+                msgs.as_mut_ptr(),
+                msgs.len() as libc::c_uint,
+                0,
+            )
+        };
+        usize::try_from(sent).map_err(|_| io::Error::last_os_error())
+    })
+    .await
+}
+
+/// Send the first datagram of `batch` to `target`, returning the number of
+/// datagrams sent.
+#[cfg(not(target_os = "linux"))]
+async fn send_batch(sock: &UdpSocket, target: &SockAddr, batch: &[Block]) -> io::Result<usize> {
+    sock.send_to(
+        &batch[0].bytes,
+        target.as_socket().expect("not an IP address"),
+    )
+    .await?;
+    Ok(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+        num::NonZeroU32,
+    };
+
+    use bytes::Bytes;
+    use lading_payload::block::Block;
+    use nix::libc;
+    use socket2::{SockAddr, SockRef};
+    use tokio::net::UdpSocket;
+
+    use super::{send_all, send_batch, Multicast, Sender};
+
+    fn block(byte: u8, len: u32) -> Block {
+        Block {
+            total_bytes: NonZeroU32::new(len).expect("non-zero"),
+            bytes: Bytes::from(vec![byte; len as usize]),
+            defects: None,
+        }
+    }
+
+    fn sender(addr: SocketAddr, multicast: Option<Multicast>) -> Sender {
+        Sender {
+            addr,
+            batch_size: 1,
+            multicast,
+            metric_labels: Vec::new(),
+            socket_labels: Vec::new(),
+        }
+    }
+
+    // Each block of a batch arrives as its own datagram, in order.
+    #[tokio::test]
+    async fn batches_keep_datagram_boundaries() {
+        let receiver = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .expect("failed to bind receiver");
+        let target = receiver.local_addr().expect("no local address");
+        let sock = sender(target, None)
+            .bind()
+            .await
+            .expect("failed to bind sender");
+
+        let batch = vec![block(b'a', 1), block(b'b', 1500), block(b'c', 9000)];
+        let target = SockAddr::from(target);
+        let mut sent = send_batch(&sock, &target, &batch)
+            .await
+            .expect("failed to send");
+        #[cfg(target_os = "linux")]
+        assert_eq!(sent, batch.len(), "sendmmsg sent a partial batch");
+        while sent < batch.len() {
+            sent += send_batch(&sock, &target, &batch[sent..])
+                .await
+                .expect("failed to send");
+        }
+
+        let mut buf = vec![0; 65_536];
+        for blk in &batch {
+            let (len, _) = receiver
+                .recv_from(&mut buf)
+                .await
+                .expect("failed to receive");
+            assert_eq!(&buf[..len], &blk.bytes[..]);
+        }
+    }
+
+    // A failed send drops the rest of its batch, which is accounted for
+    // separately from what the earlier sends wrote.
+    #[tokio::test]
+    async fn failed_send_drops_rest_of_batch() {
+        let batch: Vec<Block> = (1..=5).map(|len| block(b'a', len * 100)).collect();
+
+        let mut calls = 0;
+        let outcome = send_all(&batch, |_| {
+            calls += 1;
+            std::future::ready(match calls {
+                1 => Ok(2),
+                _ => Err(io::Error::from_raw_os_error(libc::ENOBUFS)),
+            })
+        })
+        .await;
+        assert_eq!((outcome.sent, outcome.bytes_written), (2, 300));
+        assert_eq!((outcome.dropped, outcome.bytes_dropped), (3, 1200));
+        assert_eq!(
+            outcome.error.and_then(|err| err.raw_os_error()),
+            Some(libc::ENOBUFS)
+        );
+
+        // Partial sends continue with the rest of the batch.
+        let outcome = send_all(&batch, |_| std::future::ready(Ok(1))).await;
+        assert_eq!((outcome.sent, outcome.bytes_written), (5, 1500));
+        assert_eq!(outcome.dropped, 0);
+        assert!(outcome.error.is_none());
+    }
+
+    // Multicast options apply to sockets sending to a multicast group.
+    #[tokio::test]
+    async fn multicast_options() {
+        let multicast = Multicast {
+            ttl: 4,
+            loopback: false,
+            interface_addr: None,
+            interface_index: None,
+        };
+
+        let sock = sender((Ipv4Addr::new(239, 0, 0, 1), 8125).into(), Some(multicast))
+            .bind()
+            .await
+            .expect("failed to bind");
+        assert_eq!(sock.multicast_ttl_v4().expect("ttl"), 4);
+        assert!(!sock.multicast_loop_v4().expect("loopback"));
+
+        // The defaults are a TTL of one with loopback.
+        let sock = sender(
+            (Ipv4Addr::new(239, 0, 0, 1), 8125).into(),
+            Some(serde_yaml::from_str("{}").expect("failed to parse")),
+        )
+        .bind()
+        .await
+        .expect("failed to bind");
+        assert_eq!(sock.multicast_ttl_v4().expect("ttl"), 1);
+        assert!(sock.multicast_loop_v4().expect("loopback"));
+
+        // A host without IPv6 cannot bind the socket, skipping the hop limit.
+        if let Ok(sock) = sender(
+            (Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1), 8125).into(),
+            Some(multicast),
+        )
+        .bind()
+        .await
+        {
+            let sock_ref = SockRef::from(&sock);
+            assert_eq!(sock_ref.multicast_hops_v6().expect("hops"), 4);
+            assert!(!sock_ref.multicast_loop_v6().expect("loopback"));
+        }
     }
 }
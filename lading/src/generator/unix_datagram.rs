@@ -10,8 +10,12 @@
 //!
 //! Additional metrics may be emitted by this generator's [throttle].
 //!
+//! Credentials may be attached to every datagram and datagrams may be sent
+//! from a pool of child processes, see [`super::unix_sender`]. On Linux a
+//! `path` beginning with `@` names a socket in the abstract namespace.
+//!
 
-use crate::common::PeekableReceiver;
+use crate::common::{unix_socket_addr, PeekableReceiver};
 use byte_unit::{Byte, ByteError, ByteUnit};
 use futures::future::join_all;
 use lading_payload::block::{self, Block};
@@ -19,15 +23,20 @@ use lading_throttle::Throttle;
 use metrics::{counter, gauge};
 use rand::{rngs::StdRng, SeedableRng};
 use serde::{Deserialize, Serialize};
-use std::{num::NonZeroU32, path::PathBuf, thread};
+use socket2::SockRef;
+use std::{num::NonZeroU32, path::PathBuf, sync::Arc, thread};
 use tokio::{
+    io::Interest,
     net,
     sync::{broadcast::Receiver, mpsc},
     task::{JoinError, JoinHandle},
 };
 use tracing::{debug, error, info};
 
-use super::General;
+use super::{
+    unix_sender::{self, SenderPool},
+    General,
+};
 
 fn default_parallel_connections() -> u16 {
     1
@@ -45,7 +54,7 @@ fn maximum_block_size() -> Byte {
 pub struct Config {
     /// The seed for random operations against this target
     pub seed: [u8; 32],
-    /// The path of the socket to write to.
+    /// The path of the socket to write to, `@name` for an abstract socket.
     pub path: PathBuf,
     /// The payload variant
     pub variant: lading_payload::Config,
@@ -67,6 +76,18 @@ pub struct Config {
     /// The load throttle configuration
     #[serde(default)]
     pub throttle: lading_throttle::Config,
+    /// Whether to attach `SCM_CREDENTIALS` ancillary data naming the sending
+    /// process to every datagram. Linux only.
+    #[serde(default)]
+    pub attach_credentials: bool,
+    /// Send datagrams from a pool of child processes, each with a distinct
+    /// PID, rather than from lading itself. Parallel connections share the
+    /// pool
+    #[serde(default)]
+    pub sender_pool: Option<SenderPool>,
+    /// The requested `SO_SNDBUF` of each socket, default chosen by the OS
+    #[serde(default)]
+    pub send_buffer_size: Option<Byte>,
 }
 
 /// Errors produced by [`UnixDatagram`].
@@ -105,6 +126,7 @@ pub enum Error {
 /// datagrams.
 pub struct UnixDatagram {
     handles: Vec<JoinHandle<Result<(), Error>>>,
+    pool: Option<Arc<unix_sender::Pool>>,
     shutdown: lading_signal::Watcher,
     startup: tokio::sync::broadcast::Sender<()>,
 }
@@ -141,17 +163,40 @@ impl UnixDatagram {
 
         let (startup, _startup_rx) = tokio::sync::broadcast::channel(1);
 
+        let send_buffer_size = config
+            .send_buffer_size
+            .map(|size| usize::try_from(size.get_bytes()).unwrap_or(usize::MAX));
+
+        // The parallel connections share one pool rather than each spawning
+        // its own processes.
+        let pool = match config.sender_pool {
+            Some(ref pool) => Some(Arc::new(unix_sender::Pool::spawn(
+                pool,
+                &config.path,
+                unix_sender::Kind::Datagram,
+                config.attach_credentials,
+                send_buffer_size,
+            )?)),
+            None => None,
+        };
+
         let mut handles = Vec::new();
         for _ in 0..config.parallel_connections {
             let total_bytes =
                 NonZeroU32::new(config.maximum_prebuild_cache_size_bytes.get_bytes() as u32)
                     .ok_or(Error::Zero)?;
             let block_cache = match config.block_cache_method {
-                block::CacheMethod::Fixed => block::Cache::fixed(
+                block::CacheMethod::Fixed => super::fixed_block_cache(
                     &mut rng,
                     total_bytes,
                     config.maximum_block_size.get_bytes(),
                     &config.variant,
+                    general.block_cache_directory.as_deref(),
+                )?,
+                block::CacheMethod::Streaming => block::Cache::streaming(
+                    &mut rng,
+                    config.maximum_block_size.get_bytes(),
+                    &config.variant,
                 )?,
             };
 
@@ -161,6 +206,9 @@ impl UnixDatagram {
                 throttle: Throttle::new_with_config(config.throttle, bytes_per_second),
                 metric_labels: labels.clone(),
                 shutdown: shutdown.clone(),
+                attach_credentials: config.attach_credentials,
+                pool: pool.clone(),
+                send_buffer_size,
             };
 
             handles.push(tokio::spawn(child.spin(startup.subscribe())));
@@ -168,6 +216,7 @@ impl UnixDatagram {
 
         Ok(Self {
             handles,
+            pool,
             shutdown,
             startup,
         })
@@ -186,7 +235,12 @@ impl UnixDatagram {
         self.startup.send(())?;
         self.shutdown.recv().await;
         info!("shutdown signal received");
-        for res in join_all(self.handles.drain(..)).await {
+        let results = join_all(self.handles.drain(..)).await;
+        // Every connection has exited, releasing its share of the pool.
+        if let Some(pool) = self.pool.take().and_then(Arc::into_inner) {
+            pool.shutdown().await;
+        }
+        for res in results {
             match res {
                 Ok(Ok(())) => continue,
                 Ok(Err(err)) => return Err(err),
@@ -204,15 +258,22 @@ struct Child {
     block_cache: block::Cache,
     metric_labels: Vec<(String, String)>,
     shutdown: lading_signal::Watcher,
+    attach_credentials: bool,
+    pool: Option<Arc<unix_sender::Pool>>,
+    send_buffer_size: Option<usize>,
 }
 
 impl Child {
     async fn spin(mut self, mut startup_receiver: Receiver<()>) -> Result<(), Error> {
         startup_receiver.recv().await?;
         debug!("UnixDatagram generator running");
-        let socket = net::UnixDatagram::unbound().map_err(Error::Io)?;
+        let addr = unix_socket_addr(&self.path)?;
+        let socket = std::os::unix::net::UnixDatagram::unbound()?;
+        if let Some(size) = self.send_buffer_size {
+            SockRef::from(&socket).set_send_buffer_size(size)?;
+        }
         loop {
-            match socket.connect(&self.path).map_err(Error::Io) {
+            match socket.connect_addr(&addr).map_err(Error::Io) {
                 Ok(()) => {
                     info!("Connected socket to {path}", path = &self.path.display(),);
                     break;
@@ -230,6 +291,8 @@ impl Child {
                 }
             }
         }
+        socket.set_nonblocking(true)?;
+        let socket = net::UnixDatagram::from_std(socket)?;
 
         // Move the block_cache into an OS thread, exposing a channel between it
         // and this async context.
@@ -254,10 +317,22 @@ impl Child {
                     // without cooperation of the client, which we are not
                     // guaranteed.
                     let blk = rcv.next().await.expect("failed to advance through blocks"); // actually advance through the blocks
-                    match socket.send(&blk.bytes).await {
+                    let sent = if let Some(ref pool) = self.pool {
+                        pool.send(&blk.bytes).await
+                    } else if self.attach_credentials {
+                        socket
+                            .async_io(Interest::WRITABLE, || {
+                                unix_sender::send_with_credentials(&socket, &blk.bytes)
+                            })
+                            .await
+                    } else {
+                        socket.send(&blk.bytes).await
+                    };
+                    match sent {
                         Ok(bytes) => {
                             counter!("bytes_written", &self.metric_labels).increment(bytes as u64);
                             counter!("packets_sent", &self.metric_labels).increment(1);
+                            blk.record_defects(&self.metric_labels);
                         }
                         Err(err) => {
                             debug!("write failed: {}", err);
@@ -0,0 +1,449 @@
+//! Credential passing and child sender processes for the Unix Domain Socket
+//! generators.
+//!
+//! Targets that perform origin detection, the Datadog Agent's `DogStatsD`
+//! socket for instance, read the `SCM_CREDENTIALS` ancillary data of each
+//! message and resolve the sending PID to a container. The Unix generators may
+//! attach credentials to every write and may rotate their writes across a pool
+//! of child processes, each with a distinct PID and optionally its own cgroup,
+//! so that the cost of origin tagging is part of the experiment.
+//!
+//! Children are the `lading` binary run with the hidden `unix-sender`
+//! sub-command. Each child's stdin is one end of a socket pair: the parent
+//! writes a block as a little-endian `u32` length followed by the block bytes,
+//! the child writes the block to the target and replies with a little-endian
+//! `i64`, the number of bytes written or a negated OS error code.
+//!
+//! A generator's connections share one pool, each child serving one write at a
+//! time.
+
+use std::{
+    env, fs,
+    io::{self, IoSlice, Read, Write},
+    num::NonZeroU16,
+    os::{
+        fd::{AsFd, AsRawFd},
+        unix::net,
+    },
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use nix::libc;
+use serde::{Deserialize, Serialize};
+use socket2::SockRef;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    process::{Child, Command},
+    sync::Mutex,
+};
+use tracing::{info, warn};
+
+use crate::common::unix_socket_addr;
+
+fn default_processes() -> NonZeroU16 {
+    NonZeroU16::new(4).expect("catastrophic programming bug")
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[serde(deny_unknown_fields)]
+/// Configuration of a pool of child sender processes.
+pub struct SenderPool {
+    /// The number of child processes. Writes rotate across the children in
+    /// turn.
+    #[serde(default = "default_processes")]
+    pub processes: NonZeroU16,
+    /// A cgroup v2 directory under which each child is placed in a cgroup of
+    /// its own. Lading must be able to write to this hierarchy.
+    #[serde(default)]
+    pub cgroup_root: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+/// The kind of socket a child sender writes to.
+pub enum Kind {
+    /// A datagram socket, each block is one datagram.
+    Datagram,
+    /// A stream socket, each child maintains its own connection.
+    Stream,
+}
+
+impl Kind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Kind::Datagram => "datagram",
+            Kind::Stream => "stream",
+        }
+    }
+}
+
+/// Write `bytes` to `fd` with `SCM_CREDENTIALS` ancillary data naming this
+/// process.
+#[cfg(target_os = "linux")]
+pub(crate) fn send_with_credentials<F>(fd: &F, bytes: &[u8]) -> io::Result<usize>
+where
+    F: AsFd,
+{
+    use nix::sys::socket::{sendmsg, ControlMessage, MsgFlags, UnixAddr, UnixCredentials};
+
+    let credentials = UnixCredentials::new();
+    sendmsg::<UnixAddr>(
+        fd.as_fd().as_raw_fd(),
+        &[IoSlice::new(bytes)],
+        &[ControlMessage::ScmCredentials(&credentials)],
+        MsgFlags::MSG_NOSIGNAL,
+        None,
+    )
+    .map_err(io::Error::from)
+}
+
+/// `SCM_CREDENTIALS` is specific to Linux.
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn send_with_credentials<F>(_fd: &F, _bytes: &[u8]) -> io::Result<usize>
+where
+    F: AsFd,
+{
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "SCM_CREDENTIALS is only supported on Linux",
+    ))
+}
+
+#[derive(Debug)]
+struct Sender {
+    child: Child,
+    channel: tokio::net::UnixStream,
+    cgroup: Option<PathBuf>,
+}
+
+/// A pool of child sender processes, see the module documentation.
+#[derive(Debug)]
+pub(crate) struct Pool {
+    senders: Vec<Mutex<Sender>>,
+    next: AtomicUsize,
+}
+
+impl Pool {
+    /// Spawn the children of `config`, each writing to the socket at `path`.
+    pub(crate) fn spawn(
+        config: &SenderPool,
+        path: &Path,
+        kind: Kind,
+        attach_credentials: bool,
+        send_buffer_size: Option<usize>,
+    ) -> io::Result<Self> {
+        let lading_path = env::current_exe()?;
+
+        let mut senders = Vec::with_capacity(usize::from(config.processes.get()));
+        for _ in 0..config.processes.get() {
+            let (parent, child) = net::UnixStream::pair()?;
+            let mut command = Command::new(&lading_path);
+            command
+                .arg("unix-sender")
+                .arg("--path")
+                .arg(path)
+                .arg("--kind")
+                .arg(kind.as_str());
+            if attach_credentials {
+                command.arg("--attach-credentials");
+            }
+            if let Some(size) = send_buffer_size {
+                command.arg("--send-buffer-size").arg(size.to_string());
+            }
+            let child = command
+                .stdin(Stdio::from(std::os::fd::OwnedFd::from(child)))
+                .stdout(Stdio::null())
+                .kill_on_drop(true)
+                .spawn()?;
+
+            let cgroup = match (&config.cgroup_root, child.id()) {
+                (Some(root), Some(pid)) => {
+                    let cgroup = root.join(format!("lading-sender-{pid}"));
+                    fs::create_dir_all(&cgroup)?;
+                    fs::write(cgroup.join("cgroup.procs"), pid.to_string())?;
+                    Some(cgroup)
+                }
+                _ => None,
+            };
+            info!(pid = child.id(), "Spawned unix sender");
+
+            parent.set_nonblocking(true)?;
+            senders.push(Mutex::new(Sender {
+                child,
+                channel: tokio::net::UnixStream::from_std(parent)?,
+                cgroup,
+            }));
+        }
+
+        Ok(Self {
+            senders,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    /// Write `bytes` from the next child in turn, returning the number of bytes
+    /// written. A child busy with another write is waited on.
+    pub(crate) async fn send(&self, bytes: &[u8]) -> io::Result<usize> {
+        let next = self.next.fetch_add(1, Ordering::Relaxed) % self.senders.len();
+        let mut sender = self.senders[next].lock().await;
+
+        let len = u32::try_from(bytes.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "block too large"))?;
+        sender.channel.write_all(&len.to_le_bytes()).await?;
+        sender.channel.write_all(bytes).await?;
+
+        let mut reply = [0; 8];
+        sender.channel.read_exact(&mut reply).await?;
+        let reply = i64::from_le_bytes(reply);
+        usize::try_from(reply)
+            .map_err(|_| io::Error::from_raw_os_error(i32::try_from(-reply).unwrap_or(libc::EIO)))
+    }
+
+    /// Stop every child and remove their cgroups.
+    pub(crate) async fn shutdown(self) {
+        for sender in self.senders {
+            let mut sender = sender.into_inner();
+            if let Err(err) = sender.child.kill().await {
+                warn!("Failed to stop unix sender: {err}");
+            }
+            if let Some(cgroup) = sender.cgroup {
+                if let Err(err) = fs::remove_dir(&cgroup) {
+                    warn!("Failed to remove cgroup {}: {err}", cgroup.display());
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Socket {
+    Datagram(net::UnixDatagram),
+    Stream(net::UnixStream),
+}
+
+impl Socket {
+    fn connect(path: &Path, kind: Kind, send_buffer_size: Option<usize>) -> io::Result<Self> {
+        let addr = unix_socket_addr(path)?;
+        let socket = match kind {
+            Kind::Datagram => {
+                let socket = net::UnixDatagram::unbound()?;
+                socket.connect_addr(&addr)?;
+                Self::Datagram(socket)
+            }
+            Kind::Stream => Self::Stream(net::UnixStream::connect_addr(&addr)?),
+        };
+        if let Some(size) = send_buffer_size {
+            let sock_ref = match socket {
+                Self::Datagram(ref socket) => SockRef::from(socket),
+                Self::Stream(ref socket) => SockRef::from(socket),
+            };
+            sock_ref.set_send_buffer_size(size)?;
+        }
+        Ok(socket)
+    }
+
+    fn send(&mut self, bytes: &[u8], attach_credentials: bool) -> io::Result<usize> {
+        match self {
+            Self::Datagram(socket) if attach_credentials => send_with_credentials(socket, bytes),
+            Self::Datagram(socket) => socket.send(bytes),
+            // As in the stream generator a block is written in full, possibly
+            // across several writes.
+            Self::Stream(socket) => {
+                let mut offset = 0;
+                while offset < bytes.len() {
+                    offset += if attach_credentials {
+                        send_with_credentials(socket, &bytes[offset..])?
+                    } else {
+                        socket.write(&bytes[offset..])?
+                    };
+                }
+                Ok(offset)
+            }
+        }
+    }
+}
+
+/// Run a child sender, writing the blocks received on stdin to the socket at
+/// `path` until stdin closes.
+///
+/// A failed write is reported to the parent and the socket is reconnected
+/// before the next block.
+///
+/// # Errors
+///
+/// Function will return an error if stdin is not a socket or if communication
+/// with the parent fails.
+pub fn run(
+    path: &Path,
+    kind: Kind,
+    attach_credentials: bool,
+    send_buffer_size: Option<usize>,
+) -> io::Result<()> {
+    let channel = net::UnixStream::from(io::stdin().as_fd().try_clone_to_owned()?);
+    serve(channel, path, kind, attach_credentials, send_buffer_size)
+}
+
+/// Serve the parent's requests on `channel`, as [`run`] does for stdin.
+fn serve(
+    mut channel: net::UnixStream,
+    path: &Path,
+    kind: Kind,
+    attach_credentials: bool,
+    send_buffer_size: Option<usize>,
+) -> io::Result<()> {
+    let mut socket: Option<Socket> = None;
+    let mut block = Vec::new();
+    loop {
+        let mut len = [0; 4];
+        match channel.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(err) => return Err(err),
+        }
+        block.resize(u32::from_le_bytes(len) as usize, 0);
+        channel.read_exact(&mut block)?;
+
+        let sent = match socket {
+            Some(ref mut socket) => socket.send(&block, attach_credentials),
+            None => Socket::connect(path, kind, send_buffer_size)
+                .and_then(|connected| socket.insert(connected).send(&block, attach_credentials)),
+        };
+        let reply = match sent {
+            Ok(bytes) => i64::try_from(bytes).unwrap_or(i64::MAX),
+            Err(err) => {
+                socket = None;
+                -i64::from(err.raw_os_error().unwrap_or(libc::EIO))
+            }
+        };
+        channel.write_all(&reply.to_le_bytes())?;
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use std::{
+        io::{self, IoSliceMut, Read, Write},
+        os::{
+            fd::AsRawFd,
+            unix::net::{UnixDatagram, UnixStream},
+        },
+        path::Path,
+        thread,
+    };
+
+    use nix::{
+        cmsg_space, libc,
+        sys::socket::{
+            recvmsg, setsockopt, sockopt::PassCred, ControlMessageOwned, MsgFlags, UnixAddr,
+            UnixCredentials,
+        },
+        unistd::getpid,
+    };
+
+    use super::{send_with_credentials, serve, Kind};
+
+    #[test]
+    fn credentials_name_this_process() {
+        let (sender, receiver) = UnixDatagram::pair().expect("failed to create socket pair");
+        setsockopt(&receiver, PassCred, &true).expect("failed to set SO_PASSCRED");
+        let sent = send_with_credentials(&sender, b"lading").expect("failed to send");
+        assert_eq!(sent, 6);
+
+        let mut buf = [0; 6];
+        let mut iov = [IoSliceMut::new(&mut buf)];
+        let mut cmsg = cmsg_space!(UnixCredentials);
+        let msg = recvmsg::<UnixAddr>(
+            receiver.as_raw_fd(),
+            &mut iov,
+            Some(&mut cmsg),
+            MsgFlags::empty(),
+        )
+        .expect("failed to receive");
+        let credentials = msg
+            .cmsgs()
+            .expect("failed to read control messages")
+            .find_map(|cmsg| match cmsg {
+                ControlMessageOwned::ScmCredentials(credentials) => Some(credentials),
+                _ => None,
+            })
+            .expect("no credentials received");
+        assert_eq!(credentials.pid(), getpid().as_raw());
+    }
+
+    /// Serve requests on one end of a socket pair, returning the other.
+    fn child(path: &Path) -> (UnixStream, thread::JoinHandle<io::Result<()>>) {
+        let (parent, child) = UnixStream::pair().expect("failed to create socket pair");
+        let path = path.to_path_buf();
+        let handle = thread::spawn(move || serve(child, &path, Kind::Datagram, false, None));
+        (parent, handle)
+    }
+
+    fn request(parent: &mut UnixStream, bytes: &[u8]) -> i64 {
+        let len = u32::try_from(bytes.len()).expect("block too large");
+        parent
+            .write_all(&len.to_le_bytes())
+            .expect("failed to write");
+        parent.write_all(bytes).expect("failed to write");
+        let mut reply = [0; 8];
+        parent.read_exact(&mut reply).expect("failed to read reply");
+        i64::from_le_bytes(reply)
+    }
+
+    // Each block is written to the target and its length replied, a failed
+    // write replying the negated OS error. The child exits cleanly when the
+    // parent closes between blocks.
+    #[test]
+    fn serves_requests() {
+        let dir = tempfile::tempdir().expect("failed to create tempdir");
+        let path = dir.path().join("target.sock");
+        let target = UnixDatagram::bind(&path).expect("failed to bind target");
+        let (mut parent, handle) = child(&path);
+
+        let mut buf = [0; 16];
+        for bytes in [&b"lading"[..], b"", b"unix sender"] {
+            assert_eq!(
+                usize::try_from(request(&mut parent, bytes)),
+                Ok(bytes.len())
+            );
+            let len = target.recv(&mut buf).expect("failed to receive");
+            assert_eq!(&buf[..len], bytes);
+        }
+
+        // The target going away fails the write and the next block's
+        // reconnect.
+        drop(target);
+        std::fs::remove_file(&path).expect("failed to remove target");
+        assert_eq!(
+            request(&mut parent, b"lost"),
+            -i64::from(libc::ECONNREFUSED)
+        );
+        assert_eq!(request(&mut parent, b"lost"), -i64::from(libc::ENOENT));
+
+        drop(parent);
+        handle
+            .join()
+            .expect("child panicked")
+            .expect("child failed");
+    }
+
+    // A block cut short by the parent closing is an error.
+    #[test]
+    fn short_block_is_an_error() {
+        let dir = tempfile::tempdir().expect("failed to create tempdir");
+        let path = dir.path().join("target.sock");
+        let _target = UnixDatagram::bind(&path).expect("failed to bind target");
+        let (mut parent, handle) = child(&path);
+
+        parent
+            .write_all(&16_u32.to_le_bytes())
+            .expect("failed to write");
+        parent.write_all(b"short").expect("failed to write");
+        drop(parent);
+        let err = handle
+            .join()
+            .expect("child panicked")
+            .expect_err("short block served");
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -10,19 +10,38 @@
 //!
 //! Additional metrics may be emitted by this generator's [throttle].
 //!
+//! Credentials may be attached to every write and writes may be made from a
+//! pool of child processes, each with its own connection, see
+//! [`super::unix_sender`]. On Linux a `path` beginning with `@` names a socket
+//! in the abstract namespace.
+//!
 
-use crate::common::PeekableReceiver;
-use byte_unit::ByteError;
+use crate::common::{unix_socket_addr, PeekableReceiver};
+use byte_unit::{Byte, ByteError};
 use lading_payload::block::{self, Block};
 use lading_throttle::Throttle;
 use metrics::{counter, gauge};
 use rand::{rngs::StdRng, SeedableRng};
 use serde::{Deserialize, Serialize};
-use std::{num::NonZeroU32, path::PathBuf, thread};
-use tokio::{net, sync::mpsc, task::JoinError};
+use socket2::SockRef;
+use std::{
+    io,
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    thread,
+};
+use tokio::{
+    io::Interest,
+    net,
+    sync::mpsc,
+    task::{self, JoinError},
+};
 use tracing::{debug, error, info, warn};
 
-use super::General;
+use super::{
+    unix_sender::{self, SenderPool},
+    General,
+};
 
 #[derive(Debug, Deserialize, Serialize, PartialEq)]
 #[serde(deny_unknown_fields)]
@@ -30,7 +49,7 @@ use super::General;
 pub struct Config {
     /// The seed for random operations against this target
     pub seed: [u8; 32],
-    /// The path of the socket to write to.
+    /// The path of the socket to write to, `@name` for an abstract socket.
     pub path: PathBuf,
     /// The payload variant
     pub variant: lading_payload::Config,
@@ -47,6 +66,17 @@ pub struct Config {
     /// The load throttle configuration
     #[serde(default)]
     pub throttle: lading_throttle::Config,
+    /// Whether to attach `SCM_CREDENTIALS` ancillary data naming the sending
+    /// process to every write. Linux only.
+    #[serde(default)]
+    pub attach_credentials: bool,
+    /// Write from a pool of child processes, each with a distinct PID and its
+    /// own connection, rather than from lading itself
+    #[serde(default)]
+    pub sender_pool: Option<SenderPool>,
+    /// The requested `SO_SNDBUF` of each connection, default chosen by the OS
+    #[serde(default)]
+    pub send_buffer_size: Option<Byte>,
 }
 
 /// Errors produced by [`UnixStream`].
@@ -80,6 +110,9 @@ pub struct UnixStream {
     block_cache: block::Cache,
     metric_labels: Vec<(String, String)>,
     shutdown: lading_signal::Watcher,
+    attach_credentials: bool,
+    sender_pool: Option<SenderPool>,
+    send_buffer_size: Option<usize>,
 }
 
 impl UnixStream {
@@ -116,11 +149,17 @@ impl UnixStream {
             NonZeroU32::new(config.maximum_prebuild_cache_size_bytes.get_bytes() as u32)
                 .ok_or(Error::Zero)?;
         let block_cache = match config.block_cache_method {
-            block::CacheMethod::Fixed => block::Cache::fixed(
+            block::CacheMethod::Fixed => super::fixed_block_cache(
                 &mut rng,
                 total_bytes,
                 config.maximum_block_size.get_bytes(),
                 &config.variant,
+                general.block_cache_directory.as_deref(),
+            )?,
+            block::CacheMethod::Streaming => block::Cache::streaming(
+                &mut rng,
+                config.maximum_block_size.get_bytes(),
+                &config.variant,
             )?,
         };
 
@@ -130,6 +169,11 @@ impl UnixStream {
             throttle: Throttle::new_with_config(config.throttle, bytes_per_second),
             metric_labels: labels,
             shutdown,
+            attach_credentials: config.attach_credentials,
+            sender_pool: config.sender_pool,
+            send_buffer_size: config
+                .send_buffer_size
+                .map(|size| usize::try_from(size.get_bytes()).unwrap_or(usize::MAX)),
         })
     }
 
@@ -142,6 +186,7 @@ impl UnixStream {
     /// # Panics
     ///
     /// Function will panic if underlying byte capacity is not available.
+    #[allow(clippy::too_many_lines)]
     pub async fn spin(mut self) -> Result<(), Error> {
         debug!("UnixStream generator running");
 
@@ -152,13 +197,53 @@ impl UnixStream {
         let mut rcv: PeekableReceiver<Block> = PeekableReceiver::new(rcv);
         thread::Builder::new().spawn(|| block_cache.spin(snd))?;
 
-        let mut current_connection = None;
-
         let shutdown_wait = self.shutdown.recv();
         tokio::pin!(shutdown_wait);
+
+        if let Some(ref config) = self.sender_pool {
+            let pool = unix_sender::Pool::spawn(
+                config,
+                &self.path,
+                unix_sender::Kind::Stream,
+                self.attach_credentials,
+                self.send_buffer_size,
+            )?;
+            loop {
+                let blk = rcv.peek().await.expect("block cache should never be empty");
+                let total_bytes = blk.total_bytes;
+
+                tokio::select! {
+                    _ = self.throttle.wait_for(total_bytes) => {
+                        // Children write each block in full.
+                        let blk = rcv.next().await.expect("failed to advance to the next block");
+                        match pool.send(&blk.bytes).await {
+                            Ok(bytes) => {
+                                counter!("bytes_written", &self.metric_labels).increment(bytes as u64);
+                                counter!("packets_sent", &self.metric_labels).increment(1);
+                                blk.record_defects(&self.metric_labels);
+                            }
+                            Err(err) => {
+                                warn!("write failed: {}", err);
+
+                                let mut error_labels = self.metric_labels.clone();
+                                error_labels.push(("error".to_string(), err.to_string()));
+                                counter!("request_failure", &error_labels).increment(1);
+                            }
+                        }
+                    }
+                    () = &mut shutdown_wait => {
+                        info!("shutdown signal received");
+                        pool.shutdown().await;
+                        return Ok(());
+                    },
+                }
+            }
+        }
+
+        let mut current_connection = None;
         loop {
             let Some(ref socket) = current_connection else {
-                match net::UnixStream::connect(&self.path).await {
+                match connect(&self.path, self.send_buffer_size).await {
                     Ok(socket) => {
                         info!(
                             "Connected socket to path {path}",
@@ -201,11 +286,21 @@ impl UnixStream {
                         if ready.is_writable() {
                             // Try to write data, this may still fail with `WouldBlock`
                             // if the readiness event is a false positive.
-                            match stream.try_write(&blk.bytes[blk_offset..]) {
+                            let written = if self.attach_credentials {
+                                stream.try_io(Interest::WRITABLE, || {
+                                    unix_sender::send_with_credentials(stream, &blk.bytes[blk_offset..])
+                                })
+                            } else {
+                                stream.try_write(&blk.bytes[blk_offset..])
+                            };
+                            match written {
                                 Ok(bytes) => {
                                     counter!("bytes_written", &self.metric_labels).increment(bytes as u64);
                                     counter!("packets_sent", &self.metric_labels).increment(1);
                                     blk_offset += bytes;
+                                    if blk_offset == blk.bytes.len() {
+                                        blk.record_defects(&self.metric_labels);
+                                    }
                                 }
                                 Err(ref e) if e.kind() == tokio::io::ErrorKind::WouldBlock => {
                                     // If the read side has hung up we will never
@@ -238,3 +333,24 @@ impl UnixStream {
         }
     }
 }
+
+/// Connect to the socket at `path`. A connection blocks while the peer's
+/// accept backlog is full. Tokio connects only to pathnames, so an abstract
+/// socket is connected on the blocking pool.
+async fn connect(path: &Path, send_buffer_size: Option<usize>) -> io::Result<net::UnixStream> {
+    let addr = unix_socket_addr(path)?;
+    let socket = if addr.as_pathname().is_some() {
+        net::UnixStream::connect(path).await?
+    } else {
+        let socket =
+            task::spawn_blocking(move || std::os::unix::net::UnixStream::connect_addr(&addr))
+                .await
+                .map_err(|err| io::Error::new(io::ErrorKind::Other, err))??;
+        socket.set_nonblocking(true)?;
+        net::UnixStream::from_std(socket)?
+    };
+    if let Some(size) = send_buffer_size {
+        SockRef::from(&socket).set_send_buffer_size(size)?;
+    }
+    Ok(socket)
+}
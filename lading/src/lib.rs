@@ -35,6 +35,7 @@ pub mod inspector;
 pub mod observer;
 pub mod target;
 pub mod target_metrics;
+pub mod tls;
 
 #[inline]
 pub(crate) fn full<T: Into<bytes::Bytes>>(
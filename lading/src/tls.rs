@@ -0,0 +1,518 @@
+//! TLS support for generators and blackholes.
+//!
+//! TLS termination is a significant CPU cost in many targets. The types in
+//! this module allow network generators and blackholes to optionally speak
+//! TLS, backed by [rustls](https://docs.rs/rustls). Identities may be loaded
+//! from PEM files on disk or generated at startup, signed by a throwaway CA.
+//! The generated CA certificate may be written to disk so that the target can
+//! be configured to trust it.
+
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use hyper_rustls::{FixedServerNameResolver, HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::client::legacy::connect::HttpConnector;
+use rustls::{
+    client::{
+        danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        Resumption,
+    },
+    crypto::{ring, CryptoProvider},
+    pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, UnixTime},
+    server::{NoServerSessionStorage, ServerSessionMemoryCache, WebPkiClientVerifier},
+    DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+/// ALPN protocols offered by HTTP servers.
+pub(crate) const ALPN_HTTP: &[&[u8]] = &[b"h2", b"http/1.1"];
+
+#[derive(thiserror::Error, Debug)]
+/// Errors produced when constructing TLS configuration.
+pub enum Error {
+    /// Unable to read a PEM file.
+    #[error("Unable to read {path}: {error}")]
+    Io {
+        /// The path that could not be read
+        path: PathBuf,
+        /// The underlying error
+        error: io::Error,
+    },
+    /// A PEM file contained no certificates.
+    #[error("No certificates found in {0}")]
+    NoCertificates(PathBuf),
+    /// A PEM file contained no private key.
+    #[error("No private key found in {0}")]
+    NoPrivateKey(PathBuf),
+    /// A client was configured without any way to verify the server.
+    #[error("Either ca_path or insecure_skip_verify must be set")]
+    NoTrustRoots,
+    /// The server name is not a valid DNS name or IP address.
+    #[error("Invalid server name: {0}")]
+    InvalidServerName(String),
+    /// Wrapper around [`rustls::Error`].
+    #[error(transparent)]
+    Rustls(#[from] rustls::Error),
+    /// Wrapper around [`rustls::server::VerifierBuilderError`].
+    #[error(transparent)]
+    Verifier(#[from] rustls::server::VerifierBuilderError),
+    /// Wrapper around [`rcgen::Error`].
+    #[error(transparent)]
+    Rcgen(#[from] rcgen::Error),
+}
+
+fn default_subject_alt_names() -> Vec<String> {
+    vec!["localhost".to_string(), "127.0.0.1".to_string()]
+}
+
+fn default_session_resumption_enabled() -> bool {
+    true
+}
+
+fn default_session_cache_size() -> usize {
+    256
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+/// The source of a certificate chain and private key.
+pub enum Identity {
+    /// Load the identity from PEM files on disk.
+    Files {
+        /// Path to the PEM encoded certificate chain, leaf first
+        certificate_path: PathBuf,
+        /// Path to the PEM encoded private key
+        key_path: PathBuf,
+    },
+    /// Generate a certificate at startup, signed by a freshly generated CA.
+    SelfSigned {
+        /// The names the certificate is valid for, default `localhost` and
+        /// `127.0.0.1`
+        #[serde(default = "default_subject_alt_names")]
+        subject_alt_names: Vec<String>,
+        /// If set, the PEM encoded CA certificate is written to this path so
+        /// that peers may be configured to trust it
+        ca_certificate_path: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+/// Configuration of TLS session resumption.
+pub struct SessionResumption {
+    /// Whether sessions may be resumed, default true
+    #[serde(default = "default_session_resumption_enabled")]
+    pub enabled: bool,
+    /// The number of sessions to cache, default 256
+    #[serde(default = "default_session_cache_size")]
+    pub cache_size: usize,
+}
+
+impl Default for SessionResumption {
+    fn default() -> Self {
+        Self {
+            enabled: default_session_resumption_enabled(),
+            cache_size: default_session_cache_size(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+/// TLS configuration for servers, that is, blackholes.
+pub struct ServerConfig {
+    /// The identity presented to clients
+    pub identity: Identity,
+    /// Path to a PEM encoded CA bundle. If set clients must present a
+    /// certificate signed by one of these CAs.
+    pub client_ca_path: Option<PathBuf>,
+    /// Session resumption configuration, enabled by default
+    #[serde(default)]
+    pub session_resumption: SessionResumption,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+/// TLS configuration for clients, that is, generators.
+pub struct ClientConfig {
+    /// Path to a PEM encoded CA bundle used to verify the target's
+    /// certificate. The system bundle, for instance
+    /// `/etc/ssl/certs/ca-certificates.crt`, may be used here.
+    pub ca_path: Option<PathBuf>,
+    /// Do not verify the target's certificate. Useful when the target uses a
+    /// self-signed certificate.
+    #[serde(default)]
+    pub insecure_skip_verify: bool,
+    /// The name to verify the target's certificate against, default the host
+    /// of the target address
+    pub server_name: Option<String>,
+    /// The identity presented to the target, if the target requires client
+    /// certificates
+    pub identity: Option<Identity>,
+    /// Session resumption configuration, enabled by default
+    #[serde(default)]
+    pub session_resumption: SessionResumption,
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+fn open(path: &Path) -> Result<BufReader<File>, Error> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|error| Error::Io {
+            path: path.to_path_buf(),
+            error,
+        })
+}
+
+fn load_certificates(path: &Path) -> Result<Vec<CertificateDer<'static>>, Error> {
+    let certs = rustls_pemfile::certs(&mut open(path)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|error| Error::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+    if certs.is_empty() {
+        return Err(Error::NoCertificates(path.to_path_buf()));
+    }
+    Ok(certs)
+}
+
+fn load_roots(path: &Path) -> Result<RootCertStore, Error> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certificates(path)? {
+        roots.add(cert)?;
+    }
+    Ok(roots)
+}
+
+fn load_identity(
+    identity: &Identity,
+) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>), Error> {
+    match identity {
+        Identity::Files {
+            certificate_path,
+            key_path,
+        } => {
+            let certs = load_certificates(certificate_path)?;
+            let key = rustls_pemfile::private_key(&mut open(key_path)?)
+                .map_err(|error| Error::Io {
+                    path: key_path.clone(),
+                    error,
+                })?
+                .ok_or_else(|| Error::NoPrivateKey(key_path.clone()))?;
+            Ok((certs, key))
+        }
+        Identity::SelfSigned {
+            subject_alt_names,
+            ca_certificate_path,
+        } => self_signed(subject_alt_names, ca_certificate_path.as_deref()),
+    }
+}
+
+/// Generate a CA and a leaf certificate signed by it, valid for
+/// `subject_alt_names`.
+fn self_signed(
+    subject_alt_names: &[String],
+    ca_certificate_path: Option<&Path>,
+) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>), Error> {
+    use rcgen::{
+        BasicConstraints, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair,
+        KeyUsagePurpose,
+    };
+
+    let mut ca_params = CertificateParams::new(Vec::<String>::new())?;
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    ca_params
+        .distinguished_name
+        .push(DnType::CommonName, "lading self-signed CA");
+    ca_params.key_usages = vec![
+        KeyUsagePurpose::KeyCertSign,
+        KeyUsagePurpose::CrlSign,
+        KeyUsagePurpose::DigitalSignature,
+    ];
+    let ca_key = KeyPair::generate()?;
+    let ca = ca_params.self_signed(&ca_key)?;
+
+    let mut leaf_params = CertificateParams::new(subject_alt_names.to_vec())?;
+    if let Some(name) = subject_alt_names.first() {
+        leaf_params
+            .distinguished_name
+            .push(DnType::CommonName, name.as_str());
+    }
+    leaf_params.extended_key_usages = vec![
+        ExtendedKeyUsagePurpose::ServerAuth,
+        ExtendedKeyUsagePurpose::ClientAuth,
+    ];
+    let leaf_key = KeyPair::generate()?;
+    let leaf = leaf_params.signed_by(&leaf_key, &ca, &ca_key)?;
+
+    if let Some(path) = ca_certificate_path {
+        std::fs::write(path, ca.pem()).map_err(|error| Error::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        info!("wrote self-signed CA certificate to {}", path.display());
+    }
+
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(leaf_key.serialize_der()));
+    Ok((vec![leaf.der().clone(), ca.der().clone()], key))
+}
+
+/// Build a rustls server configuration from the user's configuration,
+/// offering `alpn` protocols to clients. Wrap the result in a
+/// [`tokio_rustls::TlsAcceptor`] to accept connections.
+pub(crate) fn server_config(
+    config: &ServerConfig,
+    alpn: &[&[u8]],
+) -> Result<Arc<rustls::ServerConfig>, Error> {
+    let provider = provider();
+    let builder = rustls::ServerConfig::builder_with_provider(Arc::clone(&provider))
+        .with_safe_default_protocol_versions()?;
+    let builder = match config.client_ca_path {
+        Some(ref path) => {
+            let roots = Arc::new(load_roots(path)?);
+            builder.with_client_cert_verifier(
+                WebPkiClientVerifier::builder_with_provider(roots, provider).build()?,
+            )
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let (certs, key) = load_identity(&config.identity)?;
+    let mut server_config = builder.with_single_cert(certs, key)?;
+
+    let resumption = config.session_resumption;
+    if resumption.enabled {
+        server_config.session_storage = ServerSessionMemoryCache::new(resumption.cache_size);
+        server_config.ticketer = ring::Ticketer::new()?;
+    } else {
+        server_config.session_storage = Arc::new(NoServerSessionStorage {});
+        server_config.send_tls13_tickets = 0;
+    }
+    server_config.alpn_protocols = alpn.iter().map(|p| p.to_vec()).collect();
+
+    Ok(Arc::new(server_config))
+}
+
+fn client_config(config: &ClientConfig) -> Result<rustls::ClientConfig, Error> {
+    let provider = provider();
+    let builder = rustls::ClientConfig::builder_with_provider(Arc::clone(&provider))
+        .with_safe_default_protocol_versions()?;
+    let builder = if config.insecure_skip_verify {
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(NoVerification(provider)))
+    } else {
+        let roots = config
+            .ca_path
+            .as_deref()
+            .ok_or(Error::NoTrustRoots)
+            .and_then(load_roots)?;
+        builder.with_root_certificates(roots)
+    };
+    let mut client_config = match config.identity {
+        Some(ref identity) => {
+            let (certs, key) = load_identity(identity)?;
+            builder.with_client_auth_cert(certs, key)?
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let resumption = config.session_resumption;
+    client_config.resumption = if resumption.enabled {
+        Resumption::in_memory_sessions(resumption.cache_size)
+    } else {
+        Resumption::disabled()
+    };
+
+    Ok(client_config)
+}
+
+/// Build a rustls client configuration from the user's configuration, along
+/// with the name to verify the server against. `host` is used when no name is
+/// configured. Wrap the result in a [`tokio_rustls::TlsConnector`] to make
+/// connections.
+pub(crate) fn client(
+    config: &ClientConfig,
+    host: &str,
+) -> Result<(Arc<rustls::ClientConfig>, ServerName<'static>), Error> {
+    let name = config.server_name.as_deref().unwrap_or(host);
+    let server_name = ServerName::try_from(name.to_string())
+        .map_err(|_| Error::InvalidServerName(name.to_string()))?;
+    Ok((Arc::new(client_config(config)?), server_name))
+}
+
+/// Build a connector for hyper clients. If `config` is `None` only plain HTTP
+/// targets may be reached.
+pub(crate) fn https_connector(
+    config: Option<&ClientConfig>,
+    http2: bool,
+) -> Result<HttpsConnector<HttpConnector>, Error> {
+    let builder = match config {
+        Some(config) => {
+            let builder = HttpsConnectorBuilder::new()
+                .with_tls_config(client_config(config)?)
+                .https_or_http();
+            match config.server_name {
+                Some(ref name) => {
+                    let name = ServerName::try_from(name.clone())
+                        .map_err(|_| Error::InvalidServerName(name.clone()))?;
+                    builder.with_server_name_resolver(FixedServerNameResolver::new(name))
+                }
+                None => builder,
+            }
+        }
+        // Without trust roots no HTTPS target will verify.
+        None => HttpsConnectorBuilder::new()
+            .with_tls_config(
+                rustls::ClientConfig::builder_with_provider(provider())
+                    .with_safe_default_protocol_versions()?
+                    .with_root_certificates(RootCertStore::empty())
+                    .with_no_client_auth(),
+            )
+            .https_or_http(),
+    };
+    let connector = if http2 {
+        builder.enable_http2().build()
+    } else {
+        builder.enable_http1().build()
+    };
+    Ok(connector)
+}
+
+/// A certificate verifier that accepts any certificate, checking only that
+/// handshake signatures are valid.
+#[derive(Debug)]
+struct NoVerification(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+    use super::{client, server_config, ClientConfig, Identity, ServerConfig, SessionResumption};
+
+    fn client_config(
+        ca_path: Option<std::path::PathBuf>,
+        identity: Option<Identity>,
+    ) -> ClientConfig {
+        ClientConfig {
+            insecure_skip_verify: ca_path.is_none(),
+            ca_path,
+            server_name: None,
+            identity,
+            session_resumption: SessionResumption::default(),
+        }
+    }
+
+    // A server requiring client certificates accepts a client presenting a
+    // self-signed identity whose CA was written to disk.
+    #[tokio::test]
+    async fn self_signed_mutual_handshake() {
+        let dir = tempfile::tempdir().expect("unable to create tempdir");
+        let client_ca = dir.path().join("client-ca.pem");
+
+        let (client_config, server_name) = client(
+            &client_config(
+                None,
+                Some(Identity::SelfSigned {
+                    subject_alt_names: vec!["client".to_string()],
+                    ca_certificate_path: Some(client_ca.clone()),
+                }),
+            ),
+            "localhost",
+        )
+        .expect("unable to build client");
+        let server = server_config(
+            &ServerConfig {
+                identity: Identity::SelfSigned {
+                    subject_alt_names: vec!["localhost".to_string()],
+                    ca_certificate_path: None,
+                },
+                client_ca_path: Some(client_ca),
+                session_resumption: SessionResumption::default(),
+            },
+            &[],
+        )
+        .expect("unable to build server");
+        let server = TlsAcceptor::from(server);
+        let client = TlsConnector::from(client_config);
+
+        let (a, b) = tokio::io::duplex(4096);
+        let server_task = tokio::spawn(async move {
+            let mut stream = server.accept(a).await.expect("server handshake failed");
+            let mut buf = [0; 5];
+            stream.read_exact(&mut buf).await.expect("read failed");
+            buf
+        });
+        let mut stream = client
+            .connect(server_name, b)
+            .await
+            .expect("client handshake failed");
+        stream.write_all(b"hello").await.expect("write failed");
+        stream.flush().await.expect("flush failed");
+
+        assert_eq!(&server_task.await.expect("server task failed"), b"hello");
+    }
+
+    #[test]
+    fn client_requires_trust_roots() {
+        let mut config = client_config(None, None);
+        config.insecure_skip_verify = false;
+        assert!(client(&config, "localhost").is_err());
+    }
+}
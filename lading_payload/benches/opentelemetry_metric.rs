@@ -8,7 +8,7 @@ fn opentelemetry_metric_setup(c: &mut Criterion) {
     c.bench_function("opentelemetry_metric_setup", |b| {
         b.iter(|| {
             let mut rng = SmallRng::seed_from_u64(19690716);
-            let _ot = OpentelemetryMetrics::new(&mut rng);
+            let _ot = OpentelemetryMetrics::default(&mut rng).expect("failed to create metrics");
         })
     });
 }
@@ -22,7 +22,7 @@ fn opentelemetry_metric_all(c: &mut Criterion) {
         group.bench_with_input(BenchmarkId::from_parameter(size), size, |b, &size| {
             b.iter(|| {
                 let mut rng = SmallRng::seed_from_u64(19690716);
-                let ot = OpentelemetryMetrics::new(&mut rng);
+                let ot = OpentelemetryMetrics::default(&mut rng).expect("failed to create metrics");
                 let mut writer = Vec::with_capacity(size);
 
                 ot.to_bytes(rng, size, &mut writer)
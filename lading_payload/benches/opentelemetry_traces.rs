@@ -8,7 +8,7 @@ fn opentelemetry_traces_setup(c: &mut Criterion) {
     c.bench_function("opentelemetry_traces_setup", |b| {
         b.iter(|| {
             let mut rng = SmallRng::seed_from_u64(19690716);
-            let _ot = OpentelemetryTraces::new(&mut rng);
+            let _ot = OpentelemetryTraces::default(&mut rng).expect("failed to create traces");
         })
     });
 }
@@ -22,7 +22,7 @@ fn opentelemetry_traces_all(c: &mut Criterion) {
         group.bench_with_input(BenchmarkId::from_parameter(size), size, |b, &size| {
             b.iter(|| {
                 let mut rng = SmallRng::seed_from_u64(19690716);
-                let ot = OpentelemetryTraces::new(&mut rng);
+                let ot = OpentelemetryTraces::default(&mut rng).expect("failed to create traces");
                 let mut writer = Vec::with_capacity(size);
 
                 ot.to_bytes(rng, size, &mut writer)
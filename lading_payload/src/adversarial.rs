@@ -0,0 +1,561 @@
+//! Adversarial payload.
+//!
+//! An [`Adversarial`] payload injects defects that parsers mishandle into the
+//! records of another payload: invalid UTF-8, NUL and control characters,
+//! extremely long lines, truncated records, deeply nested JSON and mismatched
+//! framing. A record is a line of a newline delimited payload and otherwise a
+//! whole block. Each record receives at most one defect, chosen per the
+//! configured rates. A line whose defect would overflow its block ends the
+//! block instead and the defect is carried to the first line of the next
+//! block, so that defects occur at their configured rates. A block record is
+//! generated small enough for its defect.
+//!
+//! The defects in each block are counted, see [`Counts`]. Generators add the
+//! counts of each block they send to the `payload_defects` counter, labeled by
+//! `defect` and the generator's labels, so that the error counters of a
+//! target can be cross-checked. Blocks read through
+//! [`crate::block::Cache::read_at`] are counted per
+//! [`crate::block::Cache::defects_at`].
+
+use std::{cell::Cell, io::Write};
+
+use metrics::counter;
+use rand::{seq::SliceRandom, Rng, RngCore};
+use serde::{Deserialize, Serialize as SerdeSerialize};
+
+use crate::{Error, Serialize};
+
+/// Bytes that are never valid in UTF-8 where they are inserted.
+const INVALID_UTF8: [u8; 4] = [0x80, 0xc0, 0xfe, 0xff];
+/// The most bytes of a record repeated after it when its framing is broken.
+const TRAILING_BYTES: usize = 16;
+
+fn default_long_line_bytes() -> u32 {
+    64 * 1024
+}
+
+fn default_nesting_depth() -> u16 {
+    1_024
+}
+
+/// A kind of defect
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Defect {
+    /// A byte that is not valid UTF-8 is inserted
+    InvalidUtf8,
+    /// A NUL byte is inserted
+    Nul,
+    /// A control character other than newline is inserted
+    ControlCharacter,
+    /// `long_line_bytes` bytes are appended
+    LongLine,
+    /// The record is cut short, its delimiter kept
+    Truncated,
+    /// The record is replaced by JSON arrays nested `nesting_depth` deep
+    DeepNesting,
+    /// The delimiter of a line is removed, merging it with the next. Bytes
+    /// past the end of a block record are added.
+    MismatchedFraming,
+}
+
+const DEFECTS: [Defect; 7] = [
+    Defect::InvalidUtf8,
+    Defect::Nul,
+    Defect::ControlCharacter,
+    Defect::LongLine,
+    Defect::Truncated,
+    Defect::DeepNesting,
+    Defect::MismatchedFraming,
+];
+
+impl Defect {
+    /// The name of this defect, the `defect` label of `payload_defects`.
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Defect::InvalidUtf8 => "invalid_utf8",
+            Defect::Nul => "nul",
+            Defect::ControlCharacter => "control_character",
+            Defect::LongLine => "long_line",
+            Defect::Truncated => "truncated",
+            Defect::DeepNesting => "deep_nesting",
+            Defect::MismatchedFraming => "mismatched_framing",
+        }
+    }
+}
+
+/// The probability that a record receives each defect
+#[derive(Debug, Default, Deserialize, SerdeSerialize, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields, default)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct Rates {
+    /// See [`Defect::InvalidUtf8`]
+    pub invalid_utf8: f32,
+    /// See [`Defect::Nul`]
+    pub nul: f32,
+    /// See [`Defect::ControlCharacter`]
+    pub control_character: f32,
+    /// See [`Defect::LongLine`]
+    pub long_line: f32,
+    /// See [`Defect::Truncated`]
+    pub truncated: f32,
+    /// See [`Defect::DeepNesting`]
+    pub deep_nesting: f32,
+    /// See [`Defect::MismatchedFraming`]
+    pub mismatched_framing: f32,
+}
+
+impl Rates {
+    /// The rates in the order of [`DEFECTS`].
+    fn as_array(self) -> [f32; 7] {
+        [
+            self.invalid_utf8,
+            self.nul,
+            self.control_character,
+            self.long_line,
+            self.truncated,
+            self.deep_nesting,
+            self.mismatched_framing,
+        ]
+    }
+}
+
+/// Configuration for [`Adversarial`]
+#[derive(Debug, Deserialize, SerdeSerialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct Config {
+    /// The payload into which defects are injected
+    pub payload: Box<crate::Config>,
+    /// The probability that a record receives each defect, default none
+    #[serde(default)]
+    pub rates: Rates,
+    /// The bytes appended to a record by a long line defect
+    #[serde(default = "default_long_line_bytes")]
+    pub long_line_bytes: u32,
+    /// The depth of the arrays replacing a record by a deep nesting defect
+    #[serde(default = "default_nesting_depth")]
+    pub nesting_depth: u16,
+}
+
+impl Config {
+    /// Determine whether the passed configuration obeys validation criteria
+    ///
+    /// # Errors
+    ///
+    /// Function will error if a rate is not within `[0, 1]`, if the rates sum
+    /// to more than 1, if the long line bytes or nesting depth are zero or if
+    /// the payload is itself adversarial.
+    pub fn valid(&self) -> Result<(), String> {
+        let rates = self.rates.as_array();
+        if rates.iter().any(|rate| !(0.0..=1.0).contains(rate)) {
+            return Err("Defect rates must be between 0 and 1".to_string());
+        }
+        if rates.iter().sum::<f32>() > 1.0 {
+            return Err("Defect rates must not sum to more than 1".to_string());
+        }
+        if self.long_line_bytes == 0 {
+            return Err("Long line bytes cannot be 0".to_string());
+        }
+        if self.nesting_depth == 0 {
+            return Err("Nesting depth cannot be 0".to_string());
+        }
+        if matches!(*self.payload, crate::Config::Adversarial(_)) {
+            return Err("Adversarial payloads cannot be nested".to_string());
+        }
+        Ok(())
+    }
+
+    /// Determine whether every defect with a non-zero rate fits in a block of
+    /// `maximum_block_bytes`
+    ///
+    /// # Errors
+    ///
+    /// Function will error if long lines or deeply nested records are enabled
+    /// and larger than `maximum_block_bytes`.
+    pub fn fits(&self, maximum_block_bytes: u32) -> Result<(), String> {
+        if self.rates.long_line > 0.0 && self.long_line_bytes >= maximum_block_bytes {
+            return Err(format!(
+                "Long line bytes {} must be less than the maximum block size {maximum_block_bytes}",
+                self.long_line_bytes
+            ));
+        }
+        if self.rates.deep_nesting > 0.0 && 2 * u32::from(self.nesting_depth) >= maximum_block_bytes
+        {
+            return Err(format!(
+                "Nesting depth {} must be less than half the maximum block size {maximum_block_bytes}",
+                self.nesting_depth
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The defects injected into a block, by kind
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Counts([u32; 7]);
+
+impl Counts {
+    /// The number of `defect` defects.
+    #[must_use]
+    pub fn get(&self, defect: Defect) -> u32 {
+        self.0[defect as usize]
+    }
+
+    /// The number of defects of each kind.
+    pub fn iter(&self) -> impl Iterator<Item = (Defect, u32)> + '_ {
+        DEFECTS.iter().map(|defect| (*defect, self.get(*defect)))
+    }
+
+    /// Add `other` to these counts.
+    pub fn add(&mut self, other: &Counts) {
+        for (count, other) in self.0.iter_mut().zip(other.0) {
+            *count += other;
+        }
+    }
+
+    /// Add these counts to the `payload_defects` counter, labeled by
+    /// `defect` and `labels`.
+    pub fn record(&self, labels: &[(String, String)]) {
+        for (defect, count) in self.iter().filter(|(_, count)| *count > 0) {
+            let mut labels = labels.to_vec();
+            labels.push(("defect".to_string(), defect.name().to_string()));
+            counter!("payload_defects", &labels).increment(u64::from(count));
+        }
+    }
+}
+
+/// A payload injecting defects into another, see the module documentation
+#[derive(Debug)]
+pub struct Adversarial {
+    payload: Box<crate::Payload>,
+    newline_delimited: bool,
+    rates: [f32; 7],
+    long_line_bytes: usize,
+    nesting_depth: usize,
+    /// The defects injected by the last call to `to_bytes`.
+    counts: Cell<Counts>,
+    /// The defect of a line that did not fit in the last block, injected into
+    /// the first line of the next.
+    carried: Cell<Option<Defect>>,
+}
+
+impl Adversarial {
+    /// Create a new instance of [`Adversarial`] injecting defects into
+    /// `payload`, constructed from `config.payload`.
+    pub(crate) fn new(config: &Config, payload: crate::Payload) -> Self {
+        Self {
+            payload: Box::new(payload),
+            newline_delimited: config.payload.newline_delimited(),
+            rates: config.rates.as_array(),
+            long_line_bytes: config.long_line_bytes as usize,
+            nesting_depth: usize::from(config.nesting_depth),
+            counts: Cell::default(),
+            carried: Cell::default(),
+        }
+    }
+
+    /// Take the defects injected by the last call to `to_bytes`.
+    pub(crate) fn take_counts(&self) -> Counts {
+        self.counts.take()
+    }
+
+    /// The most `defect` grows a record by.
+    fn growth(&self, defect: Defect) -> usize {
+        match defect {
+            Defect::InvalidUtf8 | Defect::Nul | Defect::ControlCharacter => 1,
+            Defect::LongLine => self.long_line_bytes,
+            Defect::Truncated => 0,
+            Defect::DeepNesting => 2 * self.nesting_depth,
+            Defect::MismatchedFraming => TRAILING_BYTES,
+        }
+    }
+
+    fn choose<R>(&self, rng: &mut R) -> Option<Defect>
+    where
+        R: Rng + ?Sized,
+    {
+        let chance: f32 = rng.gen();
+        let mut cumulative = 0.0;
+        for (defect, rate) in DEFECTS.iter().zip(self.rates) {
+            cumulative += rate;
+            if chance < cumulative {
+                return Some(*defect);
+            }
+        }
+        None
+    }
+
+    /// Write `record` with `defect` into `out`. A delimiter, if any, is the
+    /// last byte of `record`.
+    fn inject<R>(&self, rng: &mut R, defect: Defect, record: &[u8], out: &mut Vec<u8>)
+    where
+        R: Rng + ?Sized,
+    {
+        let (body, delimiter) = match record.split_last() {
+            Some((b'\n', body)) if self.newline_delimited => (body, Some(b'\n')),
+            _ => (record, None),
+        };
+        let position = rng.gen_range(0..=body.len());
+        match defect {
+            Defect::InvalidUtf8 | Defect::Nul | Defect::ControlCharacter => {
+                let byte = match defect {
+                    Defect::InvalidUtf8 => *INVALID_UTF8.choose(rng).expect("not empty"),
+                    Defect::Nul => 0,
+                    _ => *[0x01, 0x08, 0x0b, 0x0c, 0x0d, 0x1b, 0x7f]
+                        .choose(rng)
+                        .expect("not empty"),
+                };
+                out.extend_from_slice(&body[..position]);
+                out.push(byte);
+                out.extend_from_slice(&body[position..]);
+            }
+            Defect::LongLine => {
+                out.extend_from_slice(body);
+                let filler = if body.is_empty() { b"x" } else { body };
+                out.extend(filler.iter().cycle().take(self.long_line_bytes));
+            }
+            Defect::Truncated => out.extend_from_slice(&body[..position.min(body.len() - 1)]),
+            Defect::DeepNesting => {
+                out.extend(std::iter::repeat(b'[').take(self.nesting_depth));
+                out.extend(std::iter::repeat(b']').take(self.nesting_depth));
+            }
+            Defect::MismatchedFraming => {
+                out.extend_from_slice(body);
+                if delimiter.is_none() {
+                    out.extend_from_slice(&body[..body.len().min(TRAILING_BYTES)]);
+                }
+                return;
+            }
+        }
+        out.extend(delimiter);
+    }
+}
+
+impl Serialize for Adversarial {
+    fn to_bytes<W, R>(&self, mut rng: R, max_bytes: usize, writer: &mut W) -> Result<(), Error>
+    where
+        R: Rng + Sized,
+        W: Write,
+    {
+        // As in the mixed payload the inner payload is serialized with a trait
+        // object so that the rng type does not grow.
+        let rng: &mut dyn RngCore = &mut rng;
+        let mut counts = Counts::default();
+
+        if self.newline_delimited {
+            let mut buf = Vec::with_capacity(max_bytes);
+            self.payload.to_bytes(&mut *rng, max_bytes, &mut buf)?;
+
+            let mut bytes_remaining = max_bytes;
+            let mut record = Vec::new();
+            let mut carried = self.carried.take();
+            for original in buf.split_inclusive(|&b| b == b'\n') {
+                // A line without a body, an empty line, is left alone.
+                let defect = if original.len() > 1 {
+                    carried.take().or_else(|| self.choose(rng))
+                } else {
+                    None
+                };
+                let bytes = match defect {
+                    Some(defect) => {
+                        record.clear();
+                        self.inject(rng, defect, original, &mut record);
+                        record.as_slice()
+                    }
+                    None => original,
+                };
+                // The block ends at the first line that does not fit, its
+                // defect carried to the next block so that none is dropped.
+                let Some(remainder) = bytes_remaining.checked_sub(bytes.len()) else {
+                    carried = defect.or(carried);
+                    break;
+                };
+                writer.write_all(bytes)?;
+                bytes_remaining = remainder;
+                if let Some(defect) = defect {
+                    counts.0[defect as usize] += 1;
+                }
+            }
+            self.carried.set(carried);
+        } else {
+            // The block is a single record, room for its defect is left when
+            // it is generated.
+            let defect = self.choose(rng);
+            let growth = defect.map_or(0, |defect| self.growth(defect));
+            let mut buf = Vec::with_capacity(max_bytes);
+            self.payload
+                .to_bytes(&mut *rng, max_bytes.saturating_sub(growth), &mut buf)?;
+            match defect {
+                Some(defect) if !buf.is_empty() => {
+                    let mut record = Vec::with_capacity(max_bytes);
+                    self.inject(rng, defect, &buf, &mut record);
+                    writer.write_all(&record)?;
+                    counts.0[defect as usize] += 1;
+                }
+                _ => writer.write_all(&buf)?,
+            }
+        }
+        self.counts.set(counts);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroU32;
+
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use super::{Config, Counts, Defect, Rates};
+    use crate::block::Cache;
+
+    fn config(rates: Rates) -> crate::Config {
+        crate::Config::Adversarial(Config {
+            payload: Box::new(crate::Config::Json(crate::json::Config::default())),
+            rates,
+            long_line_bytes: 1024,
+            nesting_depth: 64,
+        })
+    }
+
+    fn cache(config: &crate::Config) -> Cache {
+        let mut rng = SmallRng::seed_from_u64(50);
+        Cache::fixed(
+            &mut rng,
+            NonZeroU32::new(256 * 1024).expect("non-zero"),
+            16 * 1024,
+            config,
+        )
+        .expect("failed to generate cache")
+    }
+
+    fn blocks(config: &crate::Config) -> Vec<crate::block::Block> {
+        let Cache::Fixed { blocks, .. } = cache(config) else {
+            unreachable!("fixed cache constructed");
+        };
+        blocks
+    }
+
+    // The counted defects are those found in the blocks.
+    #[test]
+    fn counts_match_defects() {
+        let config = config(Rates {
+            nul: 0.1,
+            deep_nesting: 0.1,
+            ..Rates::default()
+        });
+        let mut counts = Counts::default();
+        let (mut nul, mut nested, mut lines) = (0, 0, 0);
+        for block in blocks(&config) {
+            counts.add(&block.defects.expect("adversarial blocks count defects"));
+            for line in block.bytes.split(|&b| b == b'\n').filter(|l| !l.is_empty()) {
+                lines += 1;
+                if line.contains(&0) {
+                    nul += 1;
+                }
+                if line.starts_with(b"[[") {
+                    nested += 1;
+                }
+            }
+        }
+        assert_eq!(counts.get(Defect::Nul), nul);
+        assert_eq!(counts.get(Defect::DeepNesting), nested);
+        assert_eq!(counts.get(Defect::Truncated), 0);
+        // About a tenth of lines receive each defect.
+        for count in [nul, nested] {
+            assert!(
+                (lines / 20..lines / 5).contains(&count),
+                "{count} of {lines}"
+            );
+        }
+    }
+
+    // Long lines, each a large part of a block, are injected at their rate
+    // rather than dropped once a block has one.
+    #[test]
+    fn long_lines_occur_at_their_rate() {
+        let crate::Config::Adversarial(mut config) = config(Rates {
+            long_line: 0.1,
+            ..Rates::default()
+        }) else {
+            unreachable!("adversarial config");
+        };
+        config.long_line_bytes = 4096;
+        let config = crate::Config::Adversarial(config);
+
+        let (mut long_lines, mut lines) = (0, 0);
+        for block in blocks(&config) {
+            long_lines += block.defects.expect("counted").get(Defect::LongLine);
+            lines += block
+                .bytes
+                .split(|&b| b == b'\n')
+                .filter(|l| !l.is_empty())
+                .count();
+        }
+        let rate = f64::from(long_lines) / lines as f64;
+        assert!((0.05..0.2).contains(&rate), "{long_lines} of {lines}");
+    }
+
+    // Reading through the cache counts each block once per cycle.
+    #[test]
+    fn defects_at_counts_blocks_read_through() {
+        let cache = cache(&config(Rates {
+            nul: 0.1,
+            ..Rates::default()
+        }));
+        let Cache::Fixed {
+            ref blocks,
+            total_cycle_size,
+            ..
+        } = cache
+        else {
+            unreachable!("fixed cache constructed");
+        };
+        let mut total = Counts::default();
+        for block in blocks {
+            total.add(&block.defects.expect("counted"));
+        }
+        assert!(total.get(Defect::Nul) > 0);
+
+        let cycle = usize::try_from(total_cycle_size).expect("fits");
+        let mut read = Counts::default();
+        let mut offset = 0;
+        // Three cycles in reads that do not align with blocks.
+        while offset < 3 * cycle {
+            let size = 1000.min(3 * cycle - offset);
+            read.add(&cache.defects_at(offset as u64, size).expect("counted"));
+            offset += size;
+        }
+        assert_eq!(read.get(Defect::Nul), 3 * total.get(Defect::Nul));
+        assert_eq!(cache.defects_at(0, 0), Some(Counts::default()));
+    }
+
+    #[test]
+    fn blocks_are_well_formed_without_defects() {
+        for block in blocks(&config(Rates::default())) {
+            assert_eq!(block.defects, Some(Counts::default()));
+            crate::conformance::validate(
+                &crate::Config::Json(crate::json::Config::default()),
+                &block.bytes,
+            )
+            .expect("no defects were injected");
+        }
+    }
+
+    #[test]
+    fn rates_are_bounded() {
+        let crate::Config::Adversarial(mut config) = config(Rates {
+            truncated: 0.6,
+            long_line: 0.6,
+            ..Rates::default()
+        }) else {
+            unreachable!("adversarial config");
+        };
+        assert!(config.valid().is_err());
+        config.rates.long_line = 0.4;
+        assert!(config.valid().is_ok());
+        assert!(config.fits(2048).is_ok());
+        assert!(config.fits(1024).is_err());
+    }
+}
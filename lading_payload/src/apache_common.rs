@@ -198,7 +198,7 @@ impl fmt::Display for Timestamp {
 
         write!(
             f,
-            "{day:02}/{month}/{year}:{hour:02}:{minute:02}:{second:02} {timezone:04}"
+            "{day:02}/{month}/{year}:{hour:02}:{minute:02}:{second:02} +{timezone:02}00"
         )
     }
 }
@@ -368,8 +368,39 @@ mod test {
     use proptest::prelude::*;
     use rand::{rngs::SmallRng, SeedableRng};
 
+    use super::{Month, Timestamp};
     use crate::{ApacheCommon, Serialize};
 
+    // The timezone is a signed offset of whole hours, `+0500` rather than the
+    // unsigned `0005` once written.
+    #[test]
+    fn timestamp_timezone_is_signed_offset() {
+        let timestamp = Timestamp {
+            day: 0,
+            month: Month::March,
+            year: 0,
+            hour: 13,
+            minute: 5,
+            second: 9,
+            timezone: 5,
+        };
+        assert_eq!(timestamp.to_string(), "01/Mar/2022:13:05:09 +0500");
+    }
+
+    proptest! {
+        #[test]
+        fn timestamp_timezone_is_valid(seed: u64) {
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let timestamp: Timestamp = rand::Rng::gen(&mut rng);
+            let timestamp = timestamp.to_string();
+            let (_, zone) = timestamp.rsplit_once(' ').expect("no timezone");
+            prop_assert_eq!(zone.len(), 5);
+            prop_assert!(zone.starts_with('+'));
+            prop_assert!(zone[1..3].parse::<u8>().is_ok_and(|hours| hours < 24));
+            prop_assert_eq!(&zone[3..], "00");
+        }
+    }
+
     // We want to be sure that the serialized size of the payload does not
     // exceed `max_bytes`.
     proptest! {
@@ -3,15 +3,27 @@
 use std::io::Write;
 
 use rand::Rng;
+use serde::{Deserialize, Serialize as SerdeSerialize};
 
-use crate::{common::strings, Error};
+use crate::{common::strings, text, Error};
 
 const MAX_LENGTH: u16 = 6_144; // 6 KiB
 
+/// Configuration for [`Ascii`]
+#[derive(Debug, Default, Deserialize, SerdeSerialize, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields, default)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct Config {
+    /// Lines of compressible text rather than random characters
+    pub text: Option<text::Config>,
+}
+
 #[derive(Debug, Clone)]
 /// ASCII text payload
 pub struct Ascii {
     pool: strings::Pool,
+    text: Option<text::Text>,
 }
 
 impl Ascii {
@@ -24,6 +36,18 @@ impl Ascii {
             // SAFETY: Do not adjust this downward below MAX_LENGTH without also
             // adjusting the input to `self.pool.of_size` below.
             pool: strings::Pool::with_size(rng, usize::from(MAX_LENGTH * 4)),
+            text: None,
+        }
+    }
+
+    /// Construct a new instance of `Ascii` per `config`
+    pub fn with_config<R>(config: &Config, rng: &mut R) -> Self
+    where
+        R: rand::Rng + ?Sized,
+    {
+        Self {
+            text: config.text.map(|text| text::Text::new(text, rng)),
+            ..Self::new(rng)
         }
     }
 }
@@ -35,14 +59,20 @@ impl crate::Serialize for Ascii {
         W: Write,
     {
         let mut bytes_remaining = max_bytes;
+        let mut line = String::new();
         loop {
             let bytes = rng.gen_range(1..MAX_LENGTH);
-            // SAFETY: the maximum request is always less than the size of the
-            // pool, per our constructor.
-            let encoding: &str = self
-                .pool
-                .of_size(&mut rng, usize::from(bytes))
-                .ok_or(Error::StringGenerate)?;
+            let encoding: &str = if let Some(text) = &self.text {
+                line.clear();
+                text.fill(&mut rng, usize::from(bytes), &mut line);
+                &line
+            } else {
+                // SAFETY: the maximum request is always less than the size of
+                // the pool, per our constructor.
+                self.pool
+                    .of_size(&mut rng, usize::from(bytes))
+                    .ok_or(Error::StringGenerate)?
+            };
             let line_length = encoding.len() + 1; // add one for the newline
             match bytes_remaining.checked_sub(line_length) {
                 Some(remainder) => {
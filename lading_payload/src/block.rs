@@ -5,11 +5,18 @@
 //! from that, decoupling the create/send operations. This module is the
 //! mechanism by which 'blocks' -- that is, byte blobs of a predetermined size
 //! -- are created.
-use std::num::NonZeroU32;
+use std::{
+    num::NonZeroU32,
+    path::Path,
+    sync::{Mutex, PoisonError},
+};
 
 use byte_unit::{Byte, ByteUnit};
 use bytes::{buf::Writer, BufMut, Bytes, BytesMut};
-use rand::Rng;
+use rand::{
+    rngs::{SmallRng, StdRng},
+    Rng, SeedableRng,
+};
 use serde::{Deserialize, Serialize};
 use tokio::{
     sync::mpsc::{error::SendError, Sender},
@@ -17,6 +24,8 @@ use tokio::{
 };
 use tracing::{error, info, span, warn, Level};
 
+mod persist;
+
 /// Error for `Cache::spin`
 #[derive(Debug, thiserror::Error)]
 pub enum SpinError {
@@ -70,6 +79,9 @@ pub enum Error {
     /// See [`SpinError`]
     #[error(transparent)]
     Spin(#[from] SpinError),
+    /// A persisted cache could not be read or written
+    #[error("Persisted block cache error: {0}")]
+    Persist(#[from] std::io::Error),
 }
 
 /// Errors for the construction of chunks
@@ -90,6 +102,19 @@ pub struct Block {
     pub total_bytes: NonZeroU32,
     /// The bytes of this block.
     pub bytes: Bytes,
+    /// The defects injected into this block by an adversarial payload.
+    pub defects: Option<crate::adversarial::Counts>,
+}
+
+impl Block {
+    /// Add the defects injected into this block, if any, to the
+    /// `payload_defects` counter with `labels`. Generators call this once the
+    /// block is sent.
+    pub fn record_defects(&self, labels: &[(String, String)]) {
+        if let Some(defects) = &self.defects {
+            defects.record(labels);
+        }
+    }
 }
 
 /// Errors for the construction of the block cache
@@ -108,6 +133,7 @@ impl<'a> arbitrary::Arbitrary<'a> for Block {
         Ok(Self {
             total_bytes: NonZeroU32::new(total_bytes).expect("total_bytes must be non-zero"),
             bytes,
+            defects: None,
         })
     }
 }
@@ -118,6 +144,10 @@ impl<'a> arbitrary::Arbitrary<'a> for Block {
 pub enum CacheMethod {
     /// Create a single fixed size block cache and rotate through it
     Fixed,
+    /// Generate blocks continuously while running. Payloads that evolve over
+    /// time, such as `DogStatsD` with context churn, only do so with this
+    /// method. Generation is costlier than reading from a fixed cache.
+    Streaming,
 }
 
 /// The default cache method.
@@ -142,11 +172,9 @@ pub fn default_maximum_block_size() -> Byte {
 ///
 /// The `Cache` is a mechanism to allow generators to request 'blocks' without
 /// needing to be aware of the origin or generation mechanism of these
-/// blocks. We support a single mode of operation where all blocks are computed
-/// ahead-of-time and stored in the `Cache`. Callers are responsible for timing
-/// et al.
-///
-/// We expect to expand the different modes of `Cache` operation in the future.
+/// blocks. Blocks are either all computed ahead-of-time and stored in the
+/// `Cache` or generated as they are requested. Callers are responsible for
+/// timing et al.
 pub enum Cache {
     /// A fixed size cache of blocks. Blocks are looped over in a round-robin
     /// fashion.
@@ -158,6 +186,84 @@ pub enum Cache {
         /// The amount of data stored in one cycle, or all blocks
         total_cycle_size: u64,
     },
+    /// A source of blocks generated on demand, never repeating.
+    Streaming(Box<Stream>),
+}
+
+/// The block source of a [`Cache::Streaming`].
+#[derive(Debug)]
+pub struct Stream {
+    /// Payloads are not all `Sync`. Access is only through `&mut self` so the
+    /// lock is never taken, it exists to keep `Cache` shareable.
+    generator: Mutex<StreamGenerator>,
+    /// The block returned by the next call to `next_block`.
+    next: Block,
+    /// The block most recently returned by `next_block`.
+    current: Block,
+}
+
+/// The number of consecutive empty blocks a [`Stream`] tolerates before
+/// concluding the payload cannot fit in the maximum block size.
+const STREAM_MAXIMUM_EMPTY_BLOCKS: u32 = 128;
+
+#[derive(Debug)]
+struct StreamGenerator {
+    payload: crate::Payload,
+    rng: SmallRng,
+    maximum_block_bytes: u32,
+    minimum_block_bytes: u32,
+}
+
+impl StreamGenerator {
+    fn generate(&mut self) -> Result<Block, SpinError> {
+        for _ in 0..STREAM_MAXIMUM_EMPTY_BLOCKS {
+            let block_size = self
+                .rng
+                .gen_range(self.minimum_block_bytes..self.maximum_block_bytes);
+            match construct_block(&mut self.rng, &self.payload, block_size) {
+                Ok(block) => return Ok(block),
+                // As in the fixed cache, raise the floor of block sizes when a
+                // block cannot be filled.
+                Err(SpinError::EmptyBlock) => {
+                    self.minimum_block_bytes = (block_size / 4).max(self.minimum_block_bytes);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(SpinError::ConstructBlockCache(
+            ConstructBlockCacheError::InsufficientBlockSizes,
+        ))
+    }
+}
+
+impl Stream {
+    fn advance(&mut self) -> Result<&Block, SpinError> {
+        let next = self
+            .generator
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .generate()?;
+        self.current = std::mem::replace(&mut self.next, next);
+        Ok(&self.current)
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for Box<Stream> {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let mut rng = SmallRng::seed_from_u64(u64::arbitrary(u)?);
+        Cache::streaming(
+            &mut rng,
+            4096,
+            &crate::Config::Ascii(crate::ascii::Config::default()),
+        )
+        .ok()
+        .and_then(|cache| match cache {
+            Cache::Streaming(stream) => Some(stream),
+            Cache::Fixed { .. } => None,
+        })
+        .ok_or(arbitrary::Error::IncorrectFormat)
+    }
 }
 
 impl Cache {
@@ -165,15 +271,16 @@ impl Cache {
     ///
     /// This constructor makes an internal pool of `Block` instances up to
     /// `total_bytes`, each of which are no larger than `maximum_block_bytes`.
+    /// The blocks are generated from a seed drawn from `rng`, as
+    /// [`Self::fixed_persisted`] generates them.
     ///
     /// # Errors
     ///
     /// Function will return an error if `maximum_block_bytes` is greater than
-    /// `u32::MAX` or if it is larger than `total_bytes`.
-    #[allow(clippy::too_many_lines)]
-    #[allow(clippy::cast_possible_truncation)]
+    /// `u32::MAX` or if it is larger than `total_bytes`, or if `payload` stamps
+    /// messages with the time, which a fixed cache would replay stale.
     pub fn fixed<R>(
-        mut rng: &mut R,
+        rng: &mut R,
         total_bytes: NonZeroU32,
         maximum_block_bytes: u128,
         payload: &crate::Config,
@@ -181,6 +288,20 @@ impl Cache {
     where
         R: Rng + ?Sized,
     {
+        let mut seed = [0; 32];
+        rng.fill(&mut seed);
+        Self::fixed_seeded(seed, total_bytes, maximum_block_bytes, payload)
+    }
+
+    /// Construct a `Cache` of fixed size from `seed`, see [`Self::fixed`].
+    #[allow(clippy::cast_possible_truncation)]
+    fn fixed_seeded(
+        seed: [u8; 32],
+        total_bytes: NonZeroU32,
+        maximum_block_bytes: u128,
+        payload: &crate::Config,
+    ) -> Result<Self, Error> {
+        let mut rng = StdRng::from_seed(seed);
         let maximum_block_bytes = if (maximum_block_bytes > u32::MAX.into())
             || (maximum_block_bytes > total_bytes.get().into())
         {
@@ -189,142 +310,22 @@ impl Cache {
             maximum_block_bytes as u32
         };
 
-        let blocks = match payload {
-            crate::Config::TraceAgent(enc) => {
-                let ta = match enc {
-                    crate::Encoding::Json => crate::TraceAgent::json(&mut rng),
-                    crate::Encoding::MsgPack => crate::TraceAgent::msg_pack(&mut rng),
-                };
-
-                let span = span!(Level::INFO, "fixed", payload = "trace-agent");
-                let _guard = span.enter();
-
-                construct_block_cache_inner(&mut rng, &ta, maximum_block_bytes, total_bytes.get())?
-            }
-            crate::Config::Syslog5424 => {
-                let span = span!(Level::INFO, "fixed", payload = "syslog5424");
-                let _guard = span.enter();
-
-                construct_block_cache_inner(
-                    &mut rng,
-                    &crate::Syslog5424::default(),
-                    maximum_block_bytes,
-                    total_bytes.get(),
-                )?
+        fixed_timestamps(payload)?;
+        if let crate::Config::DogStatsD(conf) = payload {
+            if conf.context_churn.is_some() {
+                warn!("DogStatsD context churn has no effect in a fixed block cache, use the streaming cache method");
             }
-            crate::Config::DogStatsD(conf) => {
-                match conf.valid() {
-                    Ok(()) => (),
-                    Err(e) => {
-                        warn!("Invalid DogStatsD configuration: {}", e);
-                        return Err(Error::InvalidConfig(e));
-                    }
-                }
-                let serializer = crate::DogStatsD::new(*conf, &mut rng)?;
-
-                let span = span!(Level::INFO, "fixed", payload = "dogstatsd");
-                let _guard = span.enter();
+        }
+        let payload = construct_payload(&mut rng, payload, maximum_block_bytes)?;
 
-                construct_block_cache_inner(
-                    &mut rng,
-                    &serializer,
-                    maximum_block_bytes,
-                    total_bytes.get(),
-                )?
-            }
-            crate::Config::Fluent => {
-                let pyld = crate::Fluent::new(&mut rng);
-                let span = span!(Level::INFO, "fixed", payload = "fluent");
-                let _guard = span.enter();
-                construct_block_cache_inner(
-                    &mut rng,
-                    &pyld,
-                    maximum_block_bytes,
-                    total_bytes.get(),
-                )?
-            }
-            crate::Config::SplunkHec { encoding } => {
-                let span = span!(Level::INFO, "fixed", payload = "splunkHec");
-                let _guard = span.enter();
-                construct_block_cache_inner(
-                    &mut rng,
-                    &crate::SplunkHec::new(*encoding),
-                    maximum_block_bytes,
-                    total_bytes.get(),
-                )?
-            }
-            crate::Config::ApacheCommon => {
-                let pyld = crate::ApacheCommon::new(&mut rng);
-                let span = span!(Level::INFO, "fixed", payload = "apache-common");
-                let _guard = span.enter();
-                construct_block_cache_inner(
-                    &mut rng,
-                    &pyld,
-                    maximum_block_bytes,
-                    total_bytes.get(),
-                )?
-            }
-            crate::Config::Ascii => {
-                let pyld = crate::Ascii::new(&mut rng);
-                let span = span!(Level::INFO, "fixed", payload = "ascii");
-                let _guard = span.enter();
-                construct_block_cache_inner(
-                    &mut rng,
-                    &pyld,
-                    maximum_block_bytes,
-                    total_bytes.get(),
-                )?
-            }
-            crate::Config::DatadogLog => {
-                let serializer = crate::DatadogLog::new(&mut rng);
-                let span = span!(Level::INFO, "fixed", payload = "datadog-log");
-                let _guard = span.enter();
-                construct_block_cache_inner(
-                    &mut rng,
-                    &serializer,
-                    maximum_block_bytes,
-                    total_bytes.get(),
-                )?
-            }
-            crate::Config::Json => {
-                let span = span!(Level::INFO, "fixed", payload = "json");
-                let _guard = span.enter();
-                construct_block_cache_inner(
-                    &mut rng,
-                    &crate::Json,
-                    maximum_block_bytes,
-                    total_bytes.get(),
-                )?
-            }
-            crate::Config::Static { ref static_path } => {
-                let span = span!(Level::INFO, "fixed", payload = "static");
-                let _guard = span.enter();
-                construct_block_cache_inner(
-                    &mut rng,
-                    &crate::Static::new(static_path)?,
-                    maximum_block_bytes,
-                    total_bytes.get(),
-                )?
-            }
-            crate::Config::OpentelemetryTraces => {
-                let pyld = crate::OpentelemetryTraces::new(&mut rng);
-                let span = span!(Level::INFO, "fixed", payload = "otel-traces");
-                let _guard = span.enter();
-                construct_block_cache_inner(rng, &pyld, maximum_block_bytes, total_bytes.get())?
-            }
-            crate::Config::OpentelemetryLogs => {
-                let pyld = crate::OpentelemetryLogs::new(&mut rng);
-                let span = span!(Level::INFO, "fixed", payload = "otel-logs");
-                let _guard = span.enter();
-                construct_block_cache_inner(rng, &pyld, maximum_block_bytes, total_bytes.get())?
-            }
-            crate::Config::OpentelemetryMetrics => {
-                let pyld = crate::OpentelemetryMetrics::new(&mut rng);
-                let span = span!(Level::INFO, "fixed", payload = "otel-metrics");
-                let _guard = span.enter();
-                construct_block_cache_inner(rng, &pyld, maximum_block_bytes, total_bytes.get())?
-            }
-        };
+        let span = span!(Level::INFO, "fixed", payload = payload.name());
+        let _guard = span.enter();
+        let blocks = construct_block_cache_inner(
+            &mut rng,
+            &payload,
+            maximum_block_bytes,
+            total_bytes.get(),
+        )?;
 
         let total_cycle_size = blocks
             .iter()
@@ -338,6 +339,120 @@ impl Cache {
         })
     }
 
+    /// Construct a `Cache` of fixed size, persisted in `directory`.
+    ///
+    /// As [`Self::fixed`] except that the blocks are stored on disk, keyed by
+    /// `payload`, the sizes and a seed drawn from `rng`. A later call with the
+    /// same inputs and `rng` in the same state reloads the blocks rather than
+    /// generating them again. Either way the blocks, and the state `rng` is
+    /// left in, are those of [`Self::fixed`].
+    ///
+    /// Static payloads are keyed by their path, not the contents of the file.
+    /// Adversarial payloads are not persisted, their blocks carry defect
+    /// counts that the persisted format does not.
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error for the reasons [`Self::fixed`] does or
+    /// if the generated blocks cannot be written to `directory`.
+    pub fn fixed_persisted<R>(
+        rng: &mut R,
+        total_bytes: NonZeroU32,
+        maximum_block_bytes: u128,
+        payload: &crate::Config,
+        directory: &Path,
+    ) -> Result<Self, Error>
+    where
+        R: Rng + ?Sized,
+    {
+        fixed_timestamps(payload)?;
+        if let crate::Config::Adversarial(_) = payload {
+            warn!("Defect counts are not persisted, generating the adversarial block cache");
+            return Self::fixed(rng, total_bytes, maximum_block_bytes, payload);
+        }
+        let mut seed = [0; 32];
+        rng.fill(&mut seed);
+        let key = persist::Key::new(payload, seed, total_bytes, maximum_block_bytes)?;
+        let path = directory.join(key.file_name());
+
+        match persist::read(&path, &key) {
+            Ok(Some(blocks)) => {
+                info!(path = %path.display(), "Loaded persisted block cache");
+                let total_cycle_size = blocks
+                    .iter()
+                    .map(|block| u64::from(block.total_bytes.get()))
+                    .sum();
+                return Ok(Self::Fixed {
+                    idx: 0,
+                    blocks,
+                    total_cycle_size,
+                });
+            }
+            Ok(None) => {}
+            // A damaged cache is replaced.
+            Err(err) => {
+                warn!(path = %path.display(), "Failed to read persisted block cache: {err}");
+            }
+        }
+
+        let cache = Self::fixed_seeded(seed, total_bytes, maximum_block_bytes, payload)?;
+        if let Self::Fixed { ref blocks, .. } = cache {
+            persist::write(&path, &key, blocks)?;
+            info!(path = %path.display(), "Persisted block cache");
+        }
+        Ok(cache)
+    }
+
+    /// Construct a streaming `Cache`.
+    ///
+    /// This constructor makes no blocks ahead of time beyond the first,
+    /// instead generating each `Block` as it is requested. No block is larger
+    /// than `maximum_block_bytes`.
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if `maximum_block_bytes` is greater than
+    /// `u32::MAX` or if no block can be generated.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn streaming<R>(
+        mut rng: &mut R,
+        maximum_block_bytes: u128,
+        payload: &crate::Config,
+    ) -> Result<Self, Error>
+    where
+        R: Rng + ?Sized,
+    {
+        let maximum_block_bytes = if maximum_block_bytes > u32::MAX.into() {
+            return Err(Error::MaximumBlock);
+        } else {
+            maximum_block_bytes as u32
+        };
+        let payload = construct_payload(&mut rng, payload, maximum_block_bytes)?;
+        info!(
+            payload = payload.name(),
+            "Constructing streaming block cache"
+        );
+
+        let empty = Block {
+            total_bytes: NonZeroU32::MIN,
+            bytes: Bytes::new(),
+            defects: None,
+        };
+        let mut generator = StreamGenerator {
+            payload,
+            rng: SmallRng::from_rng(rng).map_err(|_| SpinError::EmptyRng)?,
+            maximum_block_bytes,
+            minimum_block_bytes: 0,
+        };
+        let next = generator.generate()?;
+
+        Ok(Self::Streaming(Box::new(Stream {
+            generator: Mutex::new(generator),
+            next,
+            current: empty,
+        })))
+    }
+
     /// Run `Cache` forward on the user-provided mpsc sender.
     ///
     /// This is a blocking function that pushes `Block` instances into the
@@ -357,6 +472,9 @@ impl Cache {
                 snd.blocking_send(blocks[idx].clone())?;
                 idx = (idx + 1) % blocks.len();
             },
+            Self::Streaming(mut stream) => loop {
+                snd.blocking_send(stream.advance()?.clone())?;
+            },
         }
     }
 
@@ -369,6 +487,7 @@ impl Cache {
     pub fn peek_next(&self) -> &Block {
         match self {
             Self::Fixed { idx, blocks, .. } => &blocks[*idx],
+            Self::Streaming(stream) => &stream.next,
         }
     }
 
@@ -376,7 +495,12 @@ impl Cache {
     ///
     /// This is a blocking function that returns a single `Block` instance as
     /// soon as one is ready, blocking the caller until one is available.
-    pub fn next_block(&mut self) -> &Block {
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if a streaming cache fails to generate a
+    /// block.
+    pub fn next_block(&mut self) -> Result<&Block, SpinError> {
         match self {
             Self::Fixed {
                 ref mut idx,
@@ -385,8 +509,9 @@ impl Cache {
             } => {
                 let block = &blocks[*idx];
                 *idx = (*idx + 1) % blocks.len();
-                block
+                Ok(block)
             }
+            Self::Streaming(stream) => stream.advance(),
         }
     }
 
@@ -394,7 +519,10 @@ impl Cache {
     ///
     /// # Panics
     ///
-    /// Function will panic if reads are larger than machine word bytes wide.
+    /// Function will panic if reads are larger than machine word bytes wide or
+    /// if called on a streaming cache, which has no stable offsets. Generators
+    /// reading at offsets reject the streaming cache method in their
+    /// configuration.
     pub fn read_at(&self, offset: u64, size: usize) -> Bytes {
         let mut data = BytesMut::with_capacity(size);
 
@@ -408,6 +536,7 @@ impl Cache {
                 usize::try_from(*total_cycle_size)
                     .expect("cycle size larger than machine word bytes"),
             ),
+            Cache::Streaming(_) => panic!("streaming caches do not support reads at an offset"),
         };
 
         let mut remaining = size;
@@ -450,6 +579,213 @@ impl Cache {
 
         data.freeze()
     }
+
+    /// The defects of the blocks whose final byte lies in the `size` bytes
+    /// from `offset`, as read by [`Self::read_at`]. Counting each block by its
+    /// final byte counts it once as the cache is read through.
+    ///
+    /// Returns `None` if the blocks carry no defect counts, as for every
+    /// payload other than adversarial, or for a streaming cache.
+    #[must_use]
+    pub fn defects_at(&self, offset: u64, size: usize) -> Option<crate::adversarial::Counts> {
+        let Cache::Fixed {
+            blocks,
+            total_cycle_size,
+            ..
+        } = self
+        else {
+            return None;
+        };
+        if blocks.first().map_or(true, |block| block.defects.is_none()) {
+            return None;
+        }
+
+        let end = offset.saturating_add(size as u64);
+        // The number of offsets below `bound` at which a block ending at
+        // `last` within the cycle is read to its end.
+        let ends_below = |last: u64, bound: u64| {
+            if bound <= last {
+                0
+            } else {
+                (bound - last - 1) / total_cycle_size + 1
+            }
+        };
+
+        let mut counts = crate::adversarial::Counts::default();
+        let mut block_end = 0;
+        for block in blocks {
+            block_end += u64::from(block.total_bytes.get());
+            let Some(defects) = &block.defects else {
+                continue;
+            };
+            let last = block_end - 1;
+            for _ in 0..ends_below(last, end) - ends_below(last, offset) {
+                counts.add(defects);
+            }
+        }
+        Some(counts)
+    }
+}
+
+/// Construct the payload described by `config`, validating it first.
+#[allow(clippy::too_many_lines)]
+fn construct_payload<R>(
+    mut rng: &mut R,
+    config: &crate::Config,
+    maximum_block_bytes: u32,
+) -> Result<crate::Payload, Error>
+where
+    R: Rng + ?Sized,
+{
+    let payload = match config {
+        crate::Config::TraceAgent(conf) => {
+            if let Err(e) = conf.valid() {
+                warn!("Invalid TraceAgent configuration: {}", e);
+                return Err(Error::InvalidConfig(e));
+            }
+            crate::Payload::TraceAgent(crate::TraceAgent::new(conf, &mut rng)?)
+        }
+        crate::Config::TraceAgentStats(conf) => {
+            if let Err(e) = conf.valid() {
+                warn!("Invalid TraceAgentStats configuration: {}", e);
+                return Err(Error::InvalidConfig(e));
+            }
+            crate::Payload::TraceAgentStats(crate::trace_agent::stats::TraceAgentStats::new(
+                *conf, &mut rng,
+            )?)
+        }
+        crate::Config::Syslog5424 => crate::Payload::Syslog5424(crate::Syslog5424::default()),
+        crate::Config::Syslog(conf) => {
+            match conf.valid() {
+                Ok(()) => (),
+                Err(e) => {
+                    warn!("Invalid Syslog configuration: {}", e);
+                    return Err(Error::InvalidConfig(e));
+                }
+            }
+            crate::Payload::Syslog(crate::Syslog::new(conf)?)
+        }
+        crate::Config::Gelf(conf) => {
+            if let Err(e) = conf.valid() {
+                warn!("Invalid GELF configuration: {}", e);
+                return Err(Error::InvalidConfig(e));
+            }
+            if let Some(chunking) = conf.chunking {
+                if u32::from(chunking.maximum_chunk_size) > maximum_block_bytes {
+                    return Err(Error::InvalidConfig(
+                        "GELF maximum chunk size exceeds maximum block size".to_string(),
+                    ));
+                }
+            }
+            crate::Payload::Gelf(crate::Gelf::new(conf, &mut rng))
+        }
+        crate::Config::DogStatsD(conf) => {
+            match conf.valid() {
+                Ok(()) => (),
+                Err(e) => {
+                    warn!("Invalid DogStatsD configuration: {}", e);
+                    return Err(Error::InvalidConfig(e));
+                }
+            }
+            crate::Payload::DogStatsdD(crate::DogStatsD::new(*conf, &mut rng)?)
+        }
+        crate::Config::Fluent => crate::Payload::Fluent(crate::Fluent::new(&mut rng)),
+        crate::Config::SplunkHec { encoding } => {
+            crate::Payload::SplunkHec(crate::SplunkHec::new(*encoding))
+        }
+        crate::Config::ApacheCommon => {
+            crate::Payload::ApacheCommon(crate::ApacheCommon::new(&mut rng))
+        }
+        crate::Config::Ascii(conf) => {
+            valid_text(conf.text)?;
+            crate::Payload::Ascii(crate::Ascii::with_config(conf, &mut rng))
+        }
+        crate::Config::DatadogLog(conf) => {
+            valid_text(conf.text)?;
+            crate::Payload::DatadogLog(crate::DatadogLog::with_config(conf, &mut rng))
+        }
+        crate::Config::Json(conf) => {
+            if let Err(e) = conf.valid() {
+                warn!("Invalid Json configuration: {}", e);
+                return Err(Error::InvalidConfig(e));
+            }
+            crate::Payload::Json(crate::Json::new(conf, &mut rng)?)
+        }
+        crate::Config::Static { ref static_path } => {
+            crate::Payload::Static(crate::Static::new(static_path)?)
+        }
+        crate::Config::OpentelemetryTraces(conf) => {
+            if let Err(e) = conf.valid() {
+                warn!("Invalid OpenTelemetry traces configuration: {}", e);
+                return Err(Error::InvalidConfig(e));
+            }
+            crate::Payload::OtelTraces(crate::OpentelemetryTraces::new(conf, &mut rng)?)
+        }
+        crate::Config::OpentelemetryLogs => {
+            crate::Payload::OtelLogs(crate::OpentelemetryLogs::new(&mut rng))
+        }
+        crate::Config::OpentelemetryMetrics(conf) => {
+            if let Err(e) = conf.valid() {
+                warn!("Invalid OpenTelemetry metrics configuration: {}", e);
+                return Err(Error::InvalidConfig(e));
+            }
+            crate::Payload::OtelMetrics(crate::OpentelemetryMetrics::new(*conf, &mut rng)?)
+        }
+        crate::Config::Mixed(conf) => {
+            if let Err(e) = conf.valid() {
+                warn!("Invalid Mixed configuration: {}", e);
+                return Err(Error::InvalidConfig(e));
+            }
+            let payloads = conf
+                .payloads
+                .iter()
+                .map(|weighted| {
+                    construct_payload(&mut *rng, &weighted.payload, maximum_block_bytes)
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            crate::Payload::Mixed(crate::Mixed::new(conf, payloads)?)
+        }
+        crate::Config::Adversarial(conf) => {
+            if let Err(e) = conf.valid().and_then(|()| conf.fits(maximum_block_bytes)) {
+                warn!("Invalid Adversarial configuration: {}", e);
+                return Err(Error::InvalidConfig(e));
+            }
+            let payload = construct_payload(&mut *rng, &conf.payload, maximum_block_bytes)?;
+            crate::Payload::Adversarial(crate::Adversarial::new(conf, payload))
+        }
+    };
+    Ok(payload)
+}
+
+/// Reject a payload that stamps messages with the time they are generated,
+/// `DogStatsD` client timestamps, for a fixed cache. The Agent drops
+/// timestamped metrics that are too far in the past, as a fixed cache's would
+/// soon be.
+fn fixed_timestamps(payload: &crate::Config) -> Result<(), Error> {
+    fn timestamped(payload: &crate::Config) -> bool {
+        match payload {
+            crate::Config::DogStatsD(conf) => conf.timestamp_probability > 0.0,
+            crate::Config::Adversarial(conf) => timestamped(&conf.payload),
+            crate::Config::Mixed(conf) => conf.payloads.iter().any(|w| timestamped(&w.payload)),
+            _ => false,
+        }
+    }
+
+    if timestamped(payload) {
+        return Err(Error::InvalidConfig(
+            "DogStatsD timestamp_probability requires the streaming cache method".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Validate the compressible text configuration of a payload, if any.
+fn valid_text(config: Option<crate::text::Config>) -> Result<(), Error> {
+    if let Some(Err(e)) = config.map(|text| text.valid()) {
+        warn!("Invalid text configuration: {}", e);
+        return Err(Error::InvalidConfig(e));
+    }
+    Ok(())
 }
 
 /// Construct a new block cache of form defined by `serializer`.
@@ -468,14 +804,13 @@ impl Cache {
 #[tracing::instrument(skip_all)]
 #[allow(clippy::cast_possible_truncation)]
 #[allow(clippy::cast_sign_loss)]
-fn construct_block_cache_inner<R, S>(
+fn construct_block_cache_inner<R>(
     mut rng: &mut R,
-    serializer: &S,
+    serializer: &crate::Payload,
     max_block_size: u32,
     total_bytes: u32,
 ) -> Result<Vec<Block>, SpinError>
 where
-    S: crate::Serialize,
     R: Rng + ?Sized,
 {
     let mut block_cache: Vec<Block> = Vec::with_capacity(128);
@@ -572,17 +907,16 @@ where
 /// Function will panic if the `serializer` signals an error. In the future we
 /// would like to propagate this error to the caller.
 #[inline]
-fn construct_block<R, S>(
+fn construct_block<R>(
     mut rng: &mut R,
-    serializer: &S,
+    serializer: &crate::Payload,
     chunk_size: u32,
 ) -> Result<Block, SpinError>
 where
-    S: crate::Serialize,
     R: Rng + ?Sized,
 {
     let mut block: Writer<BytesMut> = BytesMut::with_capacity(chunk_size as usize).writer();
-    serializer.to_bytes(&mut rng, chunk_size as usize, &mut block)?;
+    crate::Serialize::to_bytes(serializer, &mut rng, chunk_size as usize, &mut block)?;
     let bytes: Bytes = block.into_inner().freeze();
     if bytes.is_empty() {
         // Blocks should not be empty and if they are empty this is an
@@ -598,6 +932,39 @@ where
                 .expect("failed to get length of bytes"),
         )
         .ok_or(SpinError::Zero)?;
-        Ok(Block { total_bytes, bytes })
+        Ok(Block {
+            total_bytes,
+            bytes,
+            defects: serializer.take_defects(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use super::Cache;
+
+    // A streaming cache generates blocks without end, each within the maximum
+    // block size, the peeked block being the one next returned.
+    #[test]
+    fn streaming_blocks_are_sized() {
+        let mut rng = SmallRng::seed_from_u64(41);
+        let config = crate::Config::Ascii(crate::ascii::Config::default());
+        let mut cache = Cache::streaming(&mut rng, 4096, &config).expect("failed to create cache");
+
+        let mut sizes = Vec::new();
+        for _ in 0..64 {
+            let peeked = cache.peek_next().bytes.clone();
+            let block = cache.next_block().expect("failed to generate a block");
+            assert_eq!(block.bytes, peeked);
+            assert_eq!(block.bytes.len(), block.total_bytes.get() as usize);
+            assert!(block.bytes.len() <= 4096, "{} bytes", block.bytes.len());
+            sizes.push(block.bytes.len());
+        }
+        sizes.sort_unstable();
+        sizes.dedup();
+        assert!(sizes.len() > 1, "blocks are all {sizes:?} bytes");
     }
 }
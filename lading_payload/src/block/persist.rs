@@ -0,0 +1,210 @@
+//! On-disk storage of fixed block caches, see
+//! [`super::Cache::fixed_persisted`].
+//!
+//! A file holds a header, the key the blocks were generated from and then the
+//! blocks themselves. All integers are little-endian.
+//!
+//! ```text
+//! magic "LADINGBC" | format version: u32 | key length: u32 | key
+//! block count: u32 | (block length: u32 | block bytes)*
+//! ```
+//!
+//! The file name is a hash of the key. The key is stored in full and compared
+//! on load so a hash collision is treated as a miss.
+
+use std::{
+    fs,
+    hash::Hasher,
+    io::{self, BufWriter, Write},
+    num::NonZeroU32,
+    path::Path,
+};
+
+use bytes::{Buf, Bytes};
+use rustc_hash::FxHasher;
+use serde::Serialize;
+
+use super::Block;
+
+const MAGIC: &[u8; 8] = b"LADINGBC";
+const VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct KeyFields<'a> {
+    /// Payload generation may change between releases.
+    lading_payload: &'static str,
+    payload: &'a crate::Config,
+    seed: [u8; 32],
+    total_bytes: u32,
+    maximum_block_bytes: u128,
+}
+
+/// The inputs a persisted cache was generated from.
+#[derive(Debug, PartialEq)]
+pub(super) struct Key(Vec<u8>);
+
+impl Key {
+    pub(super) fn new(
+        payload: &crate::Config,
+        seed: [u8; 32],
+        total_bytes: NonZeroU32,
+        maximum_block_bytes: u128,
+    ) -> io::Result<Self> {
+        let fields = KeyFields {
+            lading_payload: env!("CARGO_PKG_VERSION"),
+            payload,
+            seed,
+            total_bytes: total_bytes.get(),
+            maximum_block_bytes,
+        };
+        Ok(Self(serde_json::to_vec(&fields)?))
+    }
+
+    /// The name of the file holding blocks generated from this key.
+    pub(super) fn file_name(&self) -> String {
+        let mut hasher = FxHasher::default();
+        hasher.write(&self.0);
+        format!("{:016x}.blocks", hasher.finish())
+    }
+}
+
+/// Read the blocks persisted at `path`, returning `None` if there are none or
+/// they were generated from a key other than `key`.
+pub(super) fn read(path: &Path, key: &Key) -> io::Result<Option<Vec<Block>>> {
+    let mut buf = match fs::read(path) {
+        Ok(contents) => Bytes::from(contents),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+
+    if take(&mut buf, MAGIC.len())? != MAGIC.as_slice() || take_u32(&mut buf)? != VERSION {
+        return Ok(None);
+    }
+    let key_len = take_u32(&mut buf)? as usize;
+    if take(&mut buf, key_len)? != key.0 {
+        return Ok(None);
+    }
+
+    let count = take_u32(&mut buf)?;
+    let mut blocks = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let total_bytes = NonZeroU32::new(take_u32(&mut buf)?)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "empty block"))?;
+        let bytes = take(&mut buf, total_bytes.get() as usize)?;
+        blocks.push(Block {
+            total_bytes,
+            bytes,
+            defects: None,
+        });
+    }
+    Ok(Some(blocks))
+}
+
+/// Persist `blocks` generated from `key` at `path`.
+///
+/// The blocks are written to a temporary file first and renamed into place,
+/// a concurrent reader never observes a partial file.
+pub(super) fn write(path: &Path, key: &Key, blocks: &[Block]) -> io::Result<()> {
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)?;
+    }
+    let tmp = path.with_extension(format!("{}.tmp", std::process::id()));
+
+    let mut file = BufWriter::new(fs::File::create(&tmp)?);
+    file.write_all(MAGIC)?;
+    file.write_all(&VERSION.to_le_bytes())?;
+    file.write_all(&len_u32(key.0.len())?.to_le_bytes())?;
+    file.write_all(&key.0)?;
+    file.write_all(&len_u32(blocks.len())?.to_le_bytes())?;
+    for block in blocks {
+        file.write_all(&block.total_bytes.get().to_le_bytes())?;
+        file.write_all(&block.bytes)?;
+    }
+    file.into_inner()
+        .map_err(io::IntoInnerError::into_error)?
+        .sync_all()?;
+
+    fs::rename(&tmp, path)
+}
+
+fn len_u32(len: usize) -> io::Result<u32> {
+    u32::try_from(len).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "too long"))
+}
+
+fn take(buf: &mut Bytes, len: usize) -> io::Result<Bytes> {
+    if buf.remaining() < len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(buf.split_to(len))
+}
+
+fn take_u32(buf: &mut Bytes) -> io::Result<u32> {
+    Ok(take(buf, 4)?.get_u32_le())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, num::NonZeroU32};
+
+    use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+    use super::Key;
+    use crate::block::Cache;
+
+    fn blocks(cache: Cache) -> Vec<bytes::Bytes> {
+        let Cache::Fixed { blocks, .. } = cache else {
+            panic!("persisted caches are fixed");
+        };
+        blocks.into_iter().map(|block| block.bytes).collect()
+    }
+
+    #[test]
+    fn persisted_cache_reloads() {
+        let directory = tempfile::tempdir().expect("failed to create directory");
+        let total_bytes = NonZeroU32::new(64 * 1024).expect("non-zero");
+        let payload = crate::Config::Ascii(crate::ascii::Config::default());
+
+        let mut rng = SmallRng::seed_from_u64(44);
+        let fixed =
+            Cache::fixed(&mut rng, total_bytes, 1024, &payload).expect("failed to generate cache");
+        let fixed_next: u64 = rng.gen();
+
+        let mut rng = SmallRng::seed_from_u64(44);
+        let generated =
+            Cache::fixed_persisted(&mut rng, total_bytes, 1024, &payload, directory.path())
+                .expect("failed to generate cache");
+        let generated_next: u64 = rng.gen();
+        assert_eq!(
+            fs::read_dir(directory.path())
+                .expect("no directory")
+                .count(),
+            1
+        );
+
+        let mut rng = SmallRng::seed_from_u64(44);
+        let loaded =
+            Cache::fixed_persisted(&mut rng, total_bytes, 1024, &payload, directory.path())
+                .expect("failed to load cache");
+        let loaded_next: u64 = rng.gen();
+
+        // Persisting neither changes the blocks nor how the caller's rng
+        // advances, whether blocks are generated or loaded.
+        let fixed = blocks(fixed);
+        assert_eq!(fixed, blocks(generated));
+        assert_eq!(fixed, blocks(loaded));
+        assert_eq!(fixed_next, generated_next);
+        assert_eq!(fixed_next, loaded_next);
+    }
+
+    #[test]
+    fn keys_differ_by_input() {
+        let total_bytes = NonZeroU32::new(1024).expect("non-zero");
+        let ascii = crate::Config::Ascii(crate::ascii::Config::default());
+        let key = Key::new(&ascii, [0; 32], total_bytes, 512).expect("key");
+        let other_seed = Key::new(&ascii, [1; 32], total_bytes, 512).expect("key");
+        let other_payload =
+            Key::new(&crate::Config::Fluent, [0; 32], total_bytes, 512).expect("key");
+        assert_ne!(key.file_name(), other_seed.file_name());
+        assert_ne!(key.file_name(), other_payload.file_name());
+    }
+}
@@ -0,0 +1,1146 @@
+//! Conformance of generated payloads to their formats.
+//!
+//! Each block a payload generates is decoded with a parser independent of the
+//! code that produced it: `serde_json` for JSON, `rmpv` for `MessagePack`,
+//! `prost` for OpenTelemetry protobuf and line grammars for the text
+//! protocols. A block conforms if it decodes in full, without trailing bytes,
+//! and every message obeys the grammar of its format. Static payloads make no
+//! claim about their format and adversarial payloads are malformed by design,
+//! both always conform.
+
+use std::{io::Read, net::Ipv4Addr, str};
+
+use flate2::read::{GzDecoder, ZlibDecoder};
+use opentelemetry_proto::tonic::collector::{
+    logs::v1::ExportLogsServiceRequest, metrics::v1::ExportMetricsServiceRequest,
+    trace::v1::ExportTraceServiceRequest,
+};
+use prost::Message;
+use rmpv::Value as MsgPack;
+use serde_json::Value as Json;
+
+use crate::{splunk_hec, syslog, trace_agent, Config, Encoding};
+
+/// The longest excerpt of a malformed message included in an error.
+const EXCERPT_BYTES: usize = 256;
+
+/// Errors produced by [`validate`]
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// A text format was not valid UTF-8
+    #[error("Invalid UTF-8: {0}")]
+    Utf8(#[from] str::Utf8Error),
+    /// A JSON document failed to decode
+    #[error("Invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    /// A `MessagePack` value failed to decode
+    #[error("Invalid MessagePack: {0}")]
+    MsgPack(#[from] rmpv::decode::Error),
+    /// A protobuf message failed to decode
+    #[error("Invalid protobuf: {0}")]
+    Protobuf(#[from] prost::DecodeError),
+    /// Compressed data failed to decompress
+    #[error("Invalid compressed data: {0}")]
+    Decompress(#[from] std::io::Error),
+    /// A message decoded but does not obey the grammar of its format
+    #[error("Malformed message, {reason}: {excerpt}")]
+    Malformed {
+        /// The rule the message breaks
+        reason: &'static str,
+        /// The start of the message
+        excerpt: String,
+    },
+}
+
+fn malformed(reason: &'static str, message: &[u8]) -> Error {
+    Error::Malformed {
+        reason,
+        excerpt: String::from_utf8_lossy(&message[..message.len().min(EXCERPT_BYTES)]).into_owned(),
+    }
+}
+
+/// Validate a block generated from `config`, returning the number of messages
+/// it holds.
+///
+/// What a message is depends on the format: a line, a span, a metric and so
+/// on. A chunk of a chunked GELF message holds none.
+///
+/// # Errors
+///
+/// Function will error if the block does not conform to the format of
+/// `config`.
+pub fn validate(config: &Config, block: &[u8]) -> Result<usize, Error> {
+    match config {
+        Config::Fluent => fluent(block),
+        Config::Syslog5424 => delimited(block, b'\n', rfc5424),
+        Config::Syslog(config) => syslog(config, block),
+        Config::Gelf(_) => gelf(block),
+        Config::SplunkHec { encoding } => match encoding {
+            splunk_hec::Encoding::Json => delimited(block, b'\n', |line| {
+                let event = json_object(line)?;
+                if event.contains_key("event") {
+                    Ok(())
+                } else {
+                    Err(malformed("no event field", line.as_bytes()))
+                }
+            }),
+            splunk_hec::Encoding::Text => delimited(block, b'\n', non_empty),
+        },
+        Config::DatadogLog(_) => datadog_log(block),
+        Config::Static { .. } | Config::Adversarial(_) => Ok(0),
+        Config::Ascii(_) => delimited(block, b'\n', |line| {
+            if !line.is_empty() && line.bytes().all(|b| b.is_ascii_graphic() || b == b' ') {
+                Ok(())
+            } else {
+                Err(malformed("not a line of printable ASCII", line.as_bytes()))
+            }
+        }),
+        Config::Json(_) => delimited(block, b'\n', |line| json_object(line).map(|_| ())),
+        Config::ApacheCommon => delimited(block, b'\n', apache_common),
+        Config::OpentelemetryTraces(_) => {
+            let request = ExportTraceServiceRequest::decode(block)?;
+            Ok(request
+                .resource_spans
+                .iter()
+                .flat_map(|resource| &resource.instrumentation_library_spans)
+                .map(|library| library.spans.len())
+                .sum())
+        }
+        Config::OpentelemetryLogs => {
+            let request = ExportLogsServiceRequest::decode(block)?;
+            Ok(request
+                .resource_logs
+                .iter()
+                .flat_map(|resource| &resource.instrumentation_library_logs)
+                .map(|library| library.log_records.len())
+                .sum())
+        }
+        Config::OpentelemetryMetrics(_) => {
+            let request = ExportMetricsServiceRequest::decode(block)?;
+            Ok(request
+                .resource_metrics
+                .iter()
+                .flat_map(|resource| &resource.instrumentation_library_metrics)
+                .map(|library| library.metrics.len())
+                .sum())
+        }
+        Config::DogStatsD(config) => dogstatsd(config.length_prefix_framed, block),
+        Config::TraceAgent(config) => trace_agent(config, block),
+        Config::TraceAgentStats(_) => trace_agent_stats(block),
+        Config::Mixed(config) => delimited(block, b'\n', |line| {
+            // Each line conforms to at least one of the mixed payloads.
+            let message = format!("{line}\n");
+            let mut result = Err(malformed("no mixed payloads", line.as_bytes()));
+            for weighted in &config.payloads {
+                result = validate(&weighted.payload, message.as_bytes());
+                if result.is_ok() {
+                    break;
+                }
+            }
+            result.map(|_| ())
+        }),
+    }
+}
+
+/// Validate each `delimiter` terminated message of a UTF-8 block.
+fn delimited<F>(block: &[u8], delimiter: u8, mut message: F) -> Result<usize, Error>
+where
+    F: FnMut(&str) -> Result<(), Error>,
+{
+    let text = str::from_utf8(block)?;
+    if text.is_empty() {
+        return Ok(0);
+    }
+    let Some(text) = text.strip_suffix(char::from(delimiter)) else {
+        return Err(malformed("unterminated message", block));
+    };
+    let mut messages = 0;
+    for line in text.split(char::from(delimiter)) {
+        message(line)?;
+        messages += 1;
+    }
+    Ok(messages)
+}
+
+fn non_empty(line: &str) -> Result<(), Error> {
+    if line.is_empty() {
+        Err(malformed("empty message", line.as_bytes()))
+    } else {
+        Ok(())
+    }
+}
+
+fn json_object(line: &str) -> Result<serde_json::Map<String, Json>, Error> {
+    match serde_json::from_str(line)? {
+        Json::Object(object) => Ok(object),
+        _ => Err(malformed("not a JSON object", line.as_bytes())),
+    }
+}
+
+fn datadog_log(block: &[u8]) -> Result<usize, Error> {
+    let Json::Array(logs) = serde_json::from_slice(block)? else {
+        return Err(malformed("not a JSON array", block));
+    };
+    for log in &logs {
+        if !log.get("message").is_some_and(Json::is_string) {
+            return Err(malformed(
+                "log without a message",
+                log.to_string().as_bytes(),
+            ));
+        }
+    }
+    Ok(logs.len())
+}
+
+/// Fluent forward protocol messages, one after another. See the
+/// [specification](https://github.com/fluent/fluentd/wiki/Forward-Protocol-Specification-v1).
+fn fluent(block: &[u8]) -> Result<usize, Error> {
+    let mut rest = block;
+    let mut messages = 0;
+    while !rest.is_empty() {
+        let value = rmpv::decode::read_value(&mut rest)?;
+        let MsgPack::Array(fields) = &value else {
+            return Err(malformed("message is not an array", block));
+        };
+        match fields.as_slice() {
+            // Message mode, [tag, time, record] with an optional option map.
+            [tag, time, record] | [tag, time, record, MsgPack::Map(_)]
+                if tag.is_str() && is_event_time(time) && record.is_map() =>
+            {
+                messages += 1;
+            }
+            // Forward mode, [tag, [[time, record], ...]] with an optional
+            // option map.
+            [tag, MsgPack::Array(entries)] | [tag, MsgPack::Array(entries), MsgPack::Map(_)]
+                if tag.is_str() =>
+            {
+                for entry in entries {
+                    match entry.as_array().map(Vec::as_slice) {
+                        Some([time, record]) if is_event_time(time) && record.is_map() => {}
+                        _ => return Err(malformed("malformed forward mode entry", block)),
+                    }
+                }
+                messages += entries.len();
+            }
+            _ => {
+                return Err(malformed(
+                    "neither a message nor forward mode message",
+                    block,
+                ))
+            }
+        }
+    }
+    Ok(messages)
+}
+
+/// Fluent time is integer seconds or the `EventTime` extension, type 0.
+fn is_event_time(time: &MsgPack) -> bool {
+    match time {
+        MsgPack::Integer(time) => time.is_u64(),
+        MsgPack::Ext(0, bytes) => bytes.len() == 8,
+        _ => false,
+    }
+}
+
+fn syslog(config: &syslog::Config, block: &[u8]) -> Result<usize, Error> {
+    let message = match config.format {
+        syslog::Format::Rfc5424 => rfc5424,
+        syslog::Format::Rfc3164 => rfc3164,
+    };
+    match config.framing {
+        syslog::Framing::NonTransparent => delimited(block, b'\n', message),
+        syslog::Framing::NonTransparentNul => delimited(block, b'\0', message),
+        syslog::Framing::OctetCounting => {
+            // RFC 6587: MSG-LEN SP SYSLOG-MSG, MSG-LEN a NONZERO-DIGIT
+            // followed by digits.
+            let mut rest = str::from_utf8(block)?;
+            let mut messages = 0;
+            while !rest.is_empty() {
+                let (len, tail) = rest
+                    .split_once(' ')
+                    .ok_or_else(|| malformed("no message length", rest.as_bytes()))?;
+                if len.starts_with('0') || !is_digits(len) {
+                    return Err(malformed("invalid message length", rest.as_bytes()));
+                }
+                let len: usize = len
+                    .parse()
+                    .map_err(|_| malformed("invalid message length", rest.as_bytes()))?;
+                let (msg, tail) = tail
+                    .split_at_checked(len)
+                    .ok_or_else(|| malformed("message shorter than its length", rest.as_bytes()))?;
+                message(msg)?;
+                messages += 1;
+                rest = tail;
+            }
+            Ok(messages)
+        }
+    }
+}
+
+fn is_digits(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Split the next space delimited field from `rest`.
+fn field<'a>(rest: &mut &'a str) -> Option<&'a str> {
+    let (field, tail) = rest.split_once(' ').unwrap_or((rest, ""));
+    *rest = tail;
+    Some(field).filter(|field| !field.is_empty())
+}
+
+/// `<PRI>`, PRI a facility and severity no greater than 191.
+fn priority(message: &str) -> Result<&str, Error> {
+    let (pri, rest) = message
+        .strip_prefix('<')
+        .and_then(|rest| rest.split_once('>'))
+        .ok_or_else(|| malformed("no priority", message.as_bytes()))?;
+    let valid = is_digits(pri)
+        && pri.len() <= 3
+        && (pri == "0" || !pri.starts_with('0'))
+        && pri.parse::<u8>().is_ok_and(|pri| pri <= 191);
+    if valid {
+        Ok(rest)
+    } else {
+        Err(malformed("invalid priority", message.as_bytes()))
+    }
+}
+
+/// Printable US-ASCII of at most `max` characters, or `-`.
+fn is_header_field(field: &str, max: usize) -> bool {
+    field.len() <= max && field.bytes().all(|b| (33..=126).contains(&b))
+}
+
+/// An RFC 5424 message.
+fn rfc5424(message: &str) -> Result<(), Error> {
+    let bad = |reason| malformed(reason, message.as_bytes());
+    let mut rest = priority(message)?;
+
+    let version = field(&mut rest).ok_or_else(|| bad("no version"))?;
+    if !is_digits(version) || version.len() > 3 || version.starts_with('0') {
+        return Err(bad("invalid version"));
+    }
+    let timestamp = field(&mut rest).ok_or_else(|| bad("no timestamp"))?;
+    if timestamp != "-" && !is_rfc5424_timestamp(timestamp) {
+        return Err(bad("invalid timestamp"));
+    }
+    for (max, reason) in [
+        (255, "invalid hostname"),
+        (48, "invalid app name"),
+        (128, "invalid procid"),
+        (32, "invalid msgid"),
+    ] {
+        if !field(&mut rest).is_some_and(|field| is_header_field(field, max)) {
+            return Err(bad(reason));
+        }
+    }
+
+    // STRUCTURED-DATA, NILVALUE or a run of [SD-ID *(SP PARAM-NAME="VALUE")]
+    // elements, is followed by an optional SP MSG.
+    if let Some(tail) = rest.strip_prefix('-') {
+        rest = tail;
+    } else {
+        if !rest.starts_with('[') {
+            return Err(bad("invalid structured data"));
+        }
+        while let Some(tail) = rest.strip_prefix('[') {
+            rest = structured_data_element(tail).ok_or_else(|| bad("invalid structured data"))?;
+        }
+    }
+    if rest.is_empty() || rest.starts_with(' ') {
+        Ok(())
+    } else {
+        Err(bad("structured data not followed by a space"))
+    }
+}
+
+/// Parse an SD-ELEMENT following its `[`, returning the rest of the message.
+fn structured_data_element(element: &str) -> Option<&str> {
+    let is_sd_name = |name: &str| {
+        !name.is_empty()
+            && name.len() <= 32
+            && name
+                .bytes()
+                .all(|b| (33..=126).contains(&b) && !matches!(b, b'=' | b']' | b'"'))
+    };
+    let end = element.find([' ', ']'])?;
+    if !is_sd_name(&element[..end]) {
+        return None;
+    }
+    let mut rest = &element[end..];
+    while let Some(param) = rest.strip_prefix(' ') {
+        let (name, value) = param.split_once("=\"")?;
+        if !is_sd_name(name) {
+            return None;
+        }
+        // The value runs to the first unescaped quote.
+        let mut escaped = false;
+        let close = value.char_indices().find_map(|(idx, c)| {
+            let close = c == '"' && !escaped;
+            escaped = c == '\\' && !escaped;
+            close.then_some(idx)
+        })?;
+        rest = &value[close + 1..];
+    }
+    rest.strip_prefix(']')
+}
+
+/// `FULL-DATE "T" FULL-TIME`, at most microsecond precision.
+fn is_rfc5424_timestamp(timestamp: &str) -> bool {
+    let is_shape = |s: &str, shape: &str| {
+        s.len() == shape.len()
+            && s.bytes().zip(shape.bytes()).all(|(b, shape)| match shape {
+                b'n' => b.is_ascii_digit(),
+                _ => b == shape,
+            })
+    };
+    let Some((date_time, offset)) = timestamp
+        .strip_suffix('Z')
+        .map(|date_time| (date_time, "Z"))
+        .or_else(|| {
+            timestamp
+                .rfind(['+', '-'])
+                .map(|idx| timestamp.split_at(idx))
+        })
+    else {
+        return false;
+    };
+    if offset != "Z" && !is_shape(offset, "+nn:nn") && !is_shape(offset, "-nn:nn") {
+        return false;
+    }
+    let (seconds, fraction) = date_time.split_once('.').unwrap_or((date_time, "1"));
+    is_shape(seconds, "nnnn-nn-nnTnn:nn:nn") && fraction.len() <= 6 && is_digits(fraction)
+}
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// An RFC 3164 message, `<PRI>Mmm dd hh:mm:ss HOSTNAME TAG[PID]: MSG`.
+fn rfc3164(message: &str) -> Result<(), Error> {
+    let bad = |reason| malformed(reason, message.as_bytes());
+    let rest = priority(message)?;
+
+    // The timestamp is fixed width, the day space padded.
+    let (timestamp, mut rest) = rest
+        .split_at_checked(15)
+        .ok_or_else(|| bad("no timestamp"))?;
+    let ts = timestamp.as_bytes();
+    let valid = MONTHS.contains(&&timestamp[..3])
+        && ts[3] == b' '
+        && (ts[4] == b' ' || ts[4].is_ascii_digit())
+        && ts[5].is_ascii_digit()
+        && ts[6] == b' '
+        && [7, 8, 10, 11, 13, 14]
+            .iter()
+            .all(|i| ts[*i].is_ascii_digit())
+        && ts[9] == b':'
+        && ts[12] == b':';
+    if !valid {
+        return Err(bad("invalid timestamp"));
+    }
+    rest = rest.strip_prefix(' ').ok_or_else(|| bad("no hostname"))?;
+    if !field(&mut rest).is_some_and(|hostname| is_header_field(hostname, 255)) {
+        return Err(bad("invalid hostname"));
+    }
+
+    let (tag, _msg) = rest.split_once(':').ok_or_else(|| bad("no tag"))?;
+    let tag = match tag.split_once('[') {
+        Some((tag, pid)) if pid.strip_suffix(']').is_some_and(is_digits) => tag,
+        Some(_) => return Err(bad("invalid pid")),
+        None => tag,
+    };
+    if tag.is_empty() || tag.len() > 32 || !tag.bytes().all(|b| b.is_ascii_alphanumeric()) {
+        return Err(bad("invalid tag"));
+    }
+    Ok(())
+}
+
+/// `host ident user [dd/Mmm/yyyy:hh:mm:ss +zzzz] "METHOD path PROTOCOL" status bytes`
+fn apache_common(line: &str) -> Result<(), Error> {
+    let bad = |reason| malformed(reason, line.as_bytes());
+    let mut rest = line;
+
+    if !field(&mut rest).is_some_and(|host| host.parse::<Ipv4Addr>().is_ok()) {
+        return Err(bad("invalid host"));
+    }
+    if field(&mut rest).is_none() || field(&mut rest).is_none() {
+        return Err(bad("no ident or user"));
+    }
+
+    let (timestamp, tail) = rest
+        .strip_prefix('[')
+        .and_then(|rest| rest.split_once("] "))
+        .ok_or_else(|| bad("no timestamp"))?;
+    let valid = timestamp.len() == 26 && {
+        let (date, zone) = timestamp.split_at(20);
+        let ts = date.as_bytes();
+        is_digits(&date[..2])
+            && ts[2] == b'/'
+            && MONTHS.contains(&&date[3..6])
+            && ts[6] == b'/'
+            && is_digits(&date[7..11])
+            && ts[11] == b':'
+            && is_digits(&date[12..14])
+            && ts[14] == b':'
+            && is_digits(&date[15..17])
+            && ts[17] == b':'
+            && is_digits(&date[18..20])
+            && (zone.starts_with(" +") || zone.starts_with(" -"))
+            && is_digits(&zone[2..])
+    };
+    if !valid {
+        return Err(bad("invalid timestamp"));
+    }
+
+    let (request, mut rest) = tail
+        .strip_prefix('"')
+        .and_then(|rest| rest.split_once("\" "))
+        .ok_or_else(|| bad("no request"))?;
+    let mut request = request.split(' ');
+    let valid = request
+        .next()
+        .is_some_and(|method| !method.is_empty() && method.bytes().all(|b| b.is_ascii_uppercase()))
+        && request.next().is_some_and(|path| path.starts_with('/'))
+        && request
+            .next()
+            .is_some_and(|protocol| protocol.starts_with("HTTP/"))
+        && request.next().is_none();
+    if !valid {
+        return Err(bad("invalid request"));
+    }
+
+    if !field(&mut rest).is_some_and(|status| status.len() == 3 && is_digits(status)) {
+        return Err(bad("invalid status"));
+    }
+    if !field(&mut rest).is_some_and(|bytes| bytes == "-" || is_digits(bytes)) || !rest.is_empty() {
+        return Err(bad("invalid size"));
+    }
+    Ok(())
+}
+
+/// GELF blocks are a single datagram, a message or a chunk of one. Messages
+/// are JSON, optionally compressed. See the
+/// [specification](https://go2docs.graylog.org/current/getting_in_log_data/gelf.html).
+fn gelf(block: &[u8]) -> Result<usize, Error> {
+    // Chunks can only be validated together, check the header alone.
+    if let Some(header) = block.strip_prefix(&[0x1e, 0x0f]) {
+        let valid = header.len() > 10 && {
+            let (sequence, count) = (header[8], header[9]);
+            (1..=128).contains(&count) && sequence < count
+        };
+        return if valid {
+            Ok(0)
+        } else {
+            Err(malformed("invalid chunk header", block))
+        };
+    }
+
+    let mut message = Vec::new();
+    match block {
+        [0x1f, 0x8b, ..] => {
+            GzDecoder::new(block).read_to_end(&mut message)?;
+        }
+        [0x78, ..] => {
+            ZlibDecoder::new(block).read_to_end(&mut message)?;
+        }
+        _ => message.extend_from_slice(block),
+    }
+
+    let Json::Object(fields) = serde_json::from_slice(&message)? else {
+        return Err(malformed("not a JSON object", &message));
+    };
+    let bad = |reason| malformed(reason, &message);
+    if fields.get("version").and_then(Json::as_str) != Some("1.1") {
+        return Err(bad("version is not 1.1"));
+    }
+    for required in ["host", "short_message"] {
+        if !fields
+            .get(required)
+            .and_then(Json::as_str)
+            .is_some_and(|value| !value.is_empty())
+        {
+            return Err(bad("missing host or short message"));
+        }
+    }
+    if fields
+        .get("level")
+        .is_some_and(|level| !level.as_u64().is_some_and(|level| level <= 7))
+    {
+        return Err(bad("invalid level"));
+    }
+    for (key, value) in &fields {
+        match key.strip_prefix('_') {
+            Some(name) => {
+                let valid_name = key != "_id"
+                    && name
+                        .bytes()
+                        .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'_' | b'.' | b'-'));
+                if !valid_name || !(value.is_string() || value.is_number()) {
+                    return Err(bad("invalid additional field"));
+                }
+            }
+            None if matches!(
+                key.as_str(),
+                "version"
+                    | "host"
+                    | "short_message"
+                    | "full_message"
+                    | "timestamp"
+                    | "level"
+                    | "facility"
+                    | "line"
+                    | "file"
+            ) => {}
+            None => return Err(bad("unknown field without an underscore prefix")),
+        }
+    }
+    Ok(1)
+}
+
+fn dogstatsd(length_prefix_framed: bool, block: &[u8]) -> Result<usize, Error> {
+    let lines = if length_prefix_framed {
+        let (length, lines) = block
+            .split_first_chunk::<4>()
+            .ok_or_else(|| malformed("no length prefix", block))?;
+        if u32::from_le_bytes(*length) as usize != lines.len() {
+            return Err(malformed("length prefix does not match contents", block));
+        }
+        lines
+    } else {
+        block
+    };
+    delimited(lines, b'\n', |line| {
+        let result = if let Some(rest) = line.strip_prefix("_e{") {
+            dogstatsd_event(rest)
+        } else if let Some(rest) = line.strip_prefix("_sc|") {
+            dogstatsd_service_check(rest)
+        } else {
+            dogstatsd_metric(line)
+        };
+        result.map_err(|reason| malformed(reason, line.as_bytes()))
+    })
+}
+
+/// `<NAME>:<VALUE>[:<VALUE>...]|<TYPE>[|@<RATE>][|#<TAGS>][|T<TIMESTAMP>]`
+/// followed by origin fields.
+fn dogstatsd_metric(line: &str) -> Result<(), &'static str> {
+    let mut fields = line.split('|');
+    let mut values = fields.next().unwrap_or_default().split(':');
+    if !values.next().is_some_and(is_metric_name) {
+        return Err("invalid metric name");
+    }
+    let values: Vec<&str> = values.collect();
+    let kind = fields.next().ok_or("no metric type")?;
+    let valid_values = !values.is_empty()
+        && values.iter().all(|value| match kind {
+            "s" => !value.is_empty(),
+            _ => value.parse::<f64>().is_ok_and(f64::is_finite),
+        });
+    if !["c", "g", "ms", "h", "s", "d"].contains(&kind) {
+        return Err("invalid metric type");
+    }
+    if !valid_values {
+        return Err("invalid metric value");
+    }
+    for field in fields {
+        if let Some(rate) = field.strip_prefix('@') {
+            if !rate
+                .parse::<f64>()
+                .is_ok_and(|rate| (0.0..=1.0).contains(&rate))
+            {
+                return Err("invalid sample rate");
+            }
+        } else if let Some(timestamp) = field.strip_prefix('T') {
+            if !is_digits(timestamp) {
+                return Err("invalid timestamp");
+            }
+        } else {
+            dogstatsd_common_field(field)?;
+        }
+    }
+    Ok(())
+}
+
+/// The protocol only requires a name, the Datadog Agent accepts any that
+/// parses.
+fn is_metric_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_graphic() && !matches!(b, b'|' | b'#' | b'@'))
+}
+
+/// `_e{<TITLE_LENGTH>,<TEXT_LENGTH>}:<TITLE>|<TEXT>` followed by optional
+/// fields, following the `_e{`.
+fn dogstatsd_event(rest: &str) -> Result<(), &'static str> {
+    let (lengths, rest) = rest.split_once("}:").ok_or("no event lengths")?;
+    let (title_length, text_length) = lengths.split_once(',').ok_or("no event lengths")?;
+    let title_length: usize = title_length.parse().map_err(|_| "invalid title length")?;
+    let text_length: usize = text_length.parse().map_err(|_| "invalid text length")?;
+
+    let (title, rest) = rest
+        .split_at_checked(title_length)
+        .ok_or("title shorter than its length")?;
+    let rest = rest
+        .strip_prefix('|')
+        .ok_or("title longer than its length")?;
+    let (text, rest) = rest
+        .split_at_checked(text_length)
+        .ok_or("text shorter than its length")?;
+    if title.is_empty() || text.is_empty() {
+        return Err("empty title or text");
+    }
+    if !rest.is_empty() && !rest.starts_with('|') {
+        return Err("text longer than its length");
+    }
+
+    for field in rest.split('|').skip(1) {
+        let valid = match field.split_once(':') {
+            Some(("d", timestamp)) => is_digits(timestamp),
+            Some(("p", priority)) => matches!(priority, "normal" | "low"),
+            Some(("t", alert_type)) => {
+                matches!(alert_type, "error" | "warning" | "info" | "success")
+            }
+            Some(("h" | "k" | "s", value)) => !value.is_empty(),
+            _ => {
+                dogstatsd_common_field(field)?;
+                true
+            }
+        };
+        if !valid {
+            return Err("invalid event field");
+        }
+    }
+    Ok(())
+}
+
+/// `_sc|<NAME>|<STATUS>` followed by optional fields, the message last,
+/// following the `_sc|`.
+fn dogstatsd_service_check(rest: &str) -> Result<(), &'static str> {
+    // The message runs to the end of the line.
+    let rest = match rest.split_once("|m:") {
+        Some((rest, _message)) => rest,
+        None => rest,
+    };
+    let mut fields = rest.split('|');
+    if !fields.next().is_some_and(|name| !name.is_empty()) {
+        return Err("no service check name");
+    }
+    if !matches!(fields.next(), Some("0" | "1" | "2" | "3")) {
+        return Err("invalid service check status");
+    }
+    for field in fields {
+        let valid = match field.split_once(':') {
+            Some(("d", timestamp)) => is_digits(timestamp),
+            Some(("h", hostname)) => !hostname.is_empty(),
+            _ => {
+                dogstatsd_common_field(field)?;
+                true
+            }
+        };
+        if !valid {
+            return Err("invalid service check field");
+        }
+    }
+    Ok(())
+}
+
+/// Tags and origin fields, common to every message type.
+fn dogstatsd_common_field(field: &str) -> Result<(), &'static str> {
+    if let Some(tags) = field.strip_prefix('#') {
+        return if tags.split(',').all(|tag| !tag.is_empty()) {
+            Ok(())
+        } else {
+            Err("empty tag")
+        };
+    }
+    match field.split_once(':') {
+        Some(("c" | "e", value)) if !value.is_empty() => Ok(()),
+        Some(("card", "none" | "low" | "orchestrator" | "high")) => Ok(()),
+        _ => Err("unknown field"),
+    }
+}
+
+fn trace_agent(config: &trace_agent::Config, block: &[u8]) -> Result<usize, Error> {
+    match (config.version, config.encoding) {
+        (trace_agent::Version::V04, Encoding::Json) => {
+            trace_agent_v04(&serde_json::from_slice(block)?, block)
+        }
+        (trace_agent::Version::V04, Encoding::MsgPack) => {
+            trace_agent_v04(&msgpack_to_json(&msgpack(block)?, block)?, block)
+        }
+        (trace_agent::Version::V05, _) => trace_agent_v05(&msgpack(block)?, block),
+    }
+}
+
+/// Decode a single `MessagePack` value spanning all of `block`.
+fn msgpack(block: &[u8]) -> Result<MsgPack, Error> {
+    let mut rest = block;
+    let value = rmpv::decode::read_value(&mut rest)?;
+    if rest.is_empty() {
+        Ok(value)
+    } else {
+        Err(malformed("trailing bytes", block))
+    }
+}
+
+/// Convert a `MessagePack` value to JSON, binary becoming an array of bytes.
+/// Maps must have string keys.
+fn msgpack_to_json(value: &MsgPack, block: &[u8]) -> Result<Json, Error> {
+    Ok(match value {
+        MsgPack::Nil => Json::Null,
+        MsgPack::Boolean(b) => Json::Bool(*b),
+        MsgPack::Integer(i) => match (i.as_u64(), i.as_i64()) {
+            (Some(u), _) => Json::from(u),
+            (None, Some(i)) => Json::from(i),
+            (None, None) => return Err(malformed("integer out of range", block)),
+        },
+        MsgPack::F32(f) => Json::from(f64::from(*f)),
+        MsgPack::F64(f) => Json::from(*f),
+        MsgPack::String(s) => Json::from(
+            s.as_str()
+                .ok_or_else(|| malformed("string is not UTF-8", block))?,
+        ),
+        MsgPack::Binary(bytes) => Json::from(bytes.clone()),
+        MsgPack::Array(values) => Json::Array(
+            values
+                .iter()
+                .map(|value| msgpack_to_json(value, block))
+                .collect::<Result<_, _>>()?,
+        ),
+        MsgPack::Map(entries) => Json::Object(
+            entries
+                .iter()
+                .map(|(key, value)| {
+                    let key = key
+                        .as_str()
+                        .ok_or_else(|| malformed("map key is not a string", block))?;
+                    Ok((key.to_string(), msgpack_to_json(value, block)?))
+                })
+                .collect::<Result<_, Error>>()?,
+        ),
+        MsgPack::Ext(..) => return Err(malformed("unexpected extension type", block)),
+    })
+}
+
+/// An array of traces, each an array of span maps. See the trace-agent's
+/// [span definition](https://github.com/DataDog/datadog-agent/blob/main/pkg/proto/datadog/trace/span.proto).
+fn trace_agent_v04(traces: &Json, block: &[u8]) -> Result<usize, Error> {
+    let bad = |reason| malformed(reason, block);
+    let traces = traces
+        .as_array()
+        .ok_or_else(|| bad("payload is not an array of traces"))?;
+    let mut spans = 0;
+    for trace in traces {
+        for span in trace
+            .as_array()
+            .ok_or_else(|| bad("trace is not an array of spans"))?
+        {
+            let span = span.as_object().ok_or_else(|| bad("span is not a map"))?;
+            let has = |key: &str, valid: fn(&Json) -> bool| span.get(key).is_some_and(valid);
+            let valid = ["service", "name", "resource"]
+                .iter()
+                .all(|key| has(key, Json::is_string))
+                && ["trace_id", "span_id"]
+                    .iter()
+                    .all(|key| has(key, Json::is_u64))
+                && ["start", "duration"]
+                    .iter()
+                    .all(|key| has(key, Json::is_i64))
+                && span.get("meta").map_or(true, |meta| {
+                    meta.as_object()
+                        .is_some_and(|meta| meta.values().all(Json::is_string))
+                })
+                && span.get("metrics").map_or(true, |metrics| {
+                    metrics
+                        .as_object()
+                        .is_some_and(|metrics| metrics.values().all(Json::is_number))
+                });
+            if !valid {
+                return Err(bad("span is missing or mistypes a field"));
+            }
+            spans += 1;
+        }
+    }
+    Ok(spans)
+}
+
+/// A string table and an array of traces, each an array of twelve element
+/// span arrays whose strings index into the table.
+fn trace_agent_v05(payload: &MsgPack, block: &[u8]) -> Result<usize, Error> {
+    let bad = |reason| malformed(reason, block);
+    let Some([MsgPack::Array(strings), MsgPack::Array(traces)]) =
+        payload.as_array().map(Vec::as_slice)
+    else {
+        return Err(bad("payload is not a string table and traces"));
+    };
+    if !strings.iter().all(MsgPack::is_str) {
+        return Err(bad("string table holds a non-string"));
+    }
+    let is_string_index = |value: &MsgPack| {
+        value
+            .as_u64()
+            .is_some_and(|idx| usize::try_from(idx).is_ok_and(|idx| idx < strings.len()))
+    };
+
+    let mut spans = 0;
+    for trace in traces {
+        for span in trace
+            .as_array()
+            .ok_or_else(|| bad("trace is not an array of spans"))?
+        {
+            let Some(
+                [service, name, resource, trace_id, span_id, parent_id, start, duration, error, meta, metrics, kind],
+            ) = span.as_array().map(Vec::as_slice)
+            else {
+                return Err(bad("span is not a twelve element array"));
+            };
+            let valid = [service, name, resource, kind]
+                .into_iter()
+                .all(is_string_index)
+                && [trace_id, span_id, parent_id]
+                    .into_iter()
+                    .all(MsgPack::is_u64)
+                && [start, duration, error].into_iter().all(MsgPack::is_i64)
+                && meta.as_map().is_some_and(|meta| {
+                    meta.iter()
+                        .all(|(k, v)| is_string_index(k) && is_string_index(v))
+                })
+                && metrics.as_map().is_some_and(|metrics| {
+                    metrics
+                        .iter()
+                        .all(|(k, v)| is_string_index(k) && v.is_number())
+                });
+            if !valid {
+                return Err(bad("span mistypes a field"));
+            }
+            spans += 1;
+        }
+    }
+    Ok(spans)
+}
+
+/// A map holding buckets of grouped stats, each group's latency summaries
+/// encoded sketches. Returns the number of groups.
+fn trace_agent_stats(block: &[u8]) -> Result<usize, Error> {
+    let bad = |reason| malformed(reason, block);
+    let get = |value: &MsgPack, key: &str| -> Option<MsgPack> {
+        value
+            .as_map()?
+            .iter()
+            .find(|(k, _)| k.as_str() == Some(key))
+            .map(|(_, v)| v.clone())
+    };
+
+    let payload = msgpack(block)?;
+    if !payload.is_map() {
+        return Err(bad("payload is not a map"));
+    }
+    let buckets = get(&payload, "Stats").ok_or_else(|| bad("payload has no stats"))?;
+    let mut groups = 0;
+    for bucket in buckets
+        .as_array()
+        .ok_or_else(|| bad("stats is not an array of buckets"))?
+    {
+        let valid = ["Start", "Duration"]
+            .iter()
+            .all(|key| get(bucket, key).is_some_and(|value| value.is_u64()));
+        if !valid {
+            return Err(bad("bucket has no start or duration"));
+        }
+        let bucket_groups = get(bucket, "Stats").ok_or_else(|| bad("bucket has no stats"))?;
+        for group in bucket_groups
+            .as_array()
+            .ok_or_else(|| bad("bucket stats is not an array"))?
+        {
+            let count = |key: &str| get(group, key).and_then(|value| value.as_u64());
+            let valid = matches!((count("Hits"), count("Errors")), (Some(hits), Some(errors)) if errors <= hits)
+                && ["OkSummary", "ErrorSummary"].iter().all(|key| {
+                    matches!(get(group, key), Some(MsgPack::Binary(sketch)) if !sketch.is_empty())
+                });
+            if !valid {
+                return Err(bad("grouped stats missing counts or summaries"));
+            }
+            groups += 1;
+        }
+    }
+    Ok(groups)
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroU32;
+
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use super::{validate, Error};
+    use crate::{ascii, block::Cache, datadog_logs, dogstatsd, gelf, json};
+    use crate::{opentelemetry_metric, opentelemetry_trace, text};
+    use crate::{splunk_hec, syslog, trace_agent, trace_topology, Config, Encoding};
+
+    /// Assert that every block of fixed caches generated from `config` conforms.
+    fn assert_conforms(config: &Config) {
+        assert_conforms_sized(config, 64 * 1024, 8 * 1024);
+    }
+
+    fn assert_conforms_sized(config: &Config, total_bytes: u32, maximum_block_bytes: u128) {
+        let total_bytes = NonZeroU32::new(total_bytes).expect("non-zero");
+        let mut messages = 0;
+        for seed in 0..2 {
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let Cache::Fixed { blocks, .. } =
+                Cache::fixed(&mut rng, total_bytes, maximum_block_bytes, config)
+                    .expect("failed to generate cache")
+            else {
+                unreachable!("fixed cache constructed");
+            };
+            for block in &blocks {
+                messages += validate(config, &block.bytes).unwrap_or_else(|err| {
+                    panic!("{config:?} generated a non-conforming block: {err}")
+                });
+            }
+        }
+        assert!(messages > 0, "{config:?} generated no messages");
+    }
+
+    #[test]
+    fn fluent_payloads_conform() {
+        // Forward mode messages are large, smaller blocks are often empty.
+        assert_conforms_sized(&Config::Fluent, 256 * 1024, 128 * 1024);
+    }
+
+    #[test]
+    fn text_payloads_conform() {
+        let text = Some(text::Config {
+            compression_ratio: 4.0,
+        });
+        for config in [
+            Config::Ascii(ascii::Config::default()),
+            Config::Ascii(ascii::Config { text }),
+            Config::Json(json::Config::default()),
+            Config::Json(json::Config {
+                text,
+                structure: None,
+            }),
+            Config::Json(json::Config {
+                text,
+                structure: Some(json::Structure::default()),
+            }),
+            Config::ApacheCommon,
+            Config::DatadogLog(datadog_logs::Config::default()),
+            Config::DatadogLog(datadog_logs::Config { text }),
+            Config::SplunkHec {
+                encoding: splunk_hec::Encoding::Json,
+            },
+            Config::SplunkHec {
+                encoding: splunk_hec::Encoding::Text,
+            },
+        ] {
+            assert_conforms(&config);
+        }
+    }
+
+    #[test]
+    fn syslog_payloads_conform() {
+        assert_conforms(&Config::Syslog5424);
+        for format in [syslog::Format::Rfc5424, syslog::Format::Rfc3164] {
+            for framing in [
+                syslog::Framing::OctetCounting,
+                syslog::Framing::NonTransparent,
+                syslog::Framing::NonTransparentNul,
+            ] {
+                assert_conforms(&Config::Syslog(syslog::Config {
+                    format,
+                    framing,
+                    ..syslog::Config::default()
+                }));
+            }
+        }
+    }
+
+    #[test]
+    fn gelf_payloads_conform() {
+        for compression in [
+            gelf::Compression::None,
+            gelf::Compression::Gzip,
+            gelf::Compression::Zlib,
+        ] {
+            assert_conforms(&Config::Gelf(gelf::Config {
+                compression,
+                ..gelf::Config::default()
+            }));
+        }
+    }
+
+    #[test]
+    fn dogstatsd_payloads_conform() {
+        assert_conforms(&Config::DogStatsD(dogstatsd::Config::default()));
+        assert_conforms(&Config::DogStatsD(dogstatsd::Config {
+            length_prefix_framed: true,
+            ..dogstatsd::Config::default()
+        }));
+    }
+
+    #[test]
+    fn binary_payloads_conform() {
+        for config in [
+            Config::OpentelemetryTraces(opentelemetry_trace::Config::default()),
+            Config::OpentelemetryLogs,
+            Config::OpentelemetryMetrics(opentelemetry_metric::Config::default()),
+            Config::TraceAgentStats(trace_agent::stats::Config::default()),
+        ] {
+            assert_conforms(&config);
+        }
+        for (encoding, version) in [
+            (Encoding::Json, trace_agent::Version::V04),
+            (Encoding::MsgPack, trace_agent::Version::V04),
+            (Encoding::MsgPack, trace_agent::Version::V05),
+        ] {
+            assert_conforms(&Config::TraceAgent(trace_agent::Config {
+                encoding,
+                version,
+                topology: Some(trace_topology::Config::default()),
+            }));
+        }
+    }
+
+    #[test]
+    fn malformed_blocks_are_rejected() {
+        let dogstatsd = Config::DogStatsD(dogstatsd::Config::default());
+        assert!(validate(&dogstatsd, b"metric:1|c\n").is_ok());
+        for block in [
+            &b"metric:1|c"[..],
+            b"metric:one|c\n",
+            b"metric:1|x\n",
+            b"_e{5,4}:title|tex\n",
+        ] {
+            assert!(matches!(
+                validate(&dogstatsd, block),
+                Err(Error::Malformed { .. })
+            ));
+        }
+
+        let syslog = Config::Syslog5424;
+        assert!(validate(
+            &syslog,
+            b"<34>1 2003-10-11T22:14:15.003Z mymachine su - ID47 - msg\n"
+        )
+        .is_ok());
+        assert!(validate(
+            &syslog,
+            b"<192>1 2003-10-11T22:14:15.003Z mymachine su - ID47 - msg\n"
+        )
+        .is_err());
+        assert!(validate(
+            &syslog,
+            b"<34>1 2003-10-11T22:14:15.0000003Z mymachine su - ID47 - msg\n"
+        )
+        .is_err());
+
+        let traces = Config::OpentelemetryTraces(opentelemetry_trace::Config::default());
+        assert!(matches!(
+            validate(&traces, &[0x0a, 0x10, 0x01]),
+            Err(Error::Protobuf(_))
+        ));
+    }
+}
@@ -1,10 +1,11 @@
 //! Datadog Logs payload.
 
-use std::io::Write;
+use std::{borrow::Cow, io::Write};
 
 use rand::{distributions::Standard, prelude::Distribution, seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize as SerdeSerialize};
 
-use crate::{common::strings, Error, Generator};
+use crate::{common::strings, text, Error, Generator};
 
 const STATUSES: [&str; 3] = ["notice", "info", "warning"];
 const HOSTNAMES: [&str; 4] = ["alpha", "beta", "gamma", "localhost"];
@@ -45,20 +46,27 @@ impl Distribution<Structured> for Standard {
 #[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
 #[serde(untagged)]
 pub(crate) enum Message<'a> {
-    Unstructured(&'a str),
+    Unstructured(Cow<'a, str>),
     Structured(String),
 }
 
-fn message<'a, R>(rng: &mut R, str_pool: &'a strings::Pool) -> Message<'a>
+fn message<'a, R>(
+    rng: &mut R,
+    str_pool: &'a strings::Pool,
+    text: Option<&text::Text>,
+) -> Message<'a>
 where
     R: rand::Rng + ?Sized,
 {
     match rng.gen_range(0..2) {
-        0 => Message::Unstructured(
-            str_pool
-                .of_size_range(rng, 1_u8..16)
-                .expect("failed to generate string"),
-        ),
+        0 => Message::Unstructured(match text {
+            Some(text) => Cow::Owned(text.message(rng)),
+            None => Cow::Borrowed(
+                str_pool
+                    .of_size_range(rng, 1_u8..16)
+                    .expect("failed to generate string"),
+            ),
+        }),
         1 => Message::Structured(
             serde_json::to_string(&rng.gen::<Structured>()).expect("failed to generate string"),
         ),
@@ -85,10 +93,22 @@ pub struct Member<'a> {
     pub(crate) ddtags: &'a str,
 }
 
+/// Configuration for [`DatadogLog`]
+#[derive(Debug, Default, Deserialize, SerdeSerialize, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields, default)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct Config {
+    /// Unstructured messages of compressible text rather than short random
+    /// strings
+    pub text: Option<text::Config>,
+}
+
 #[derive(Debug)]
 /// Datadog log format payload
 pub struct DatadogLog {
     str_pool: strings::Pool,
+    text: Option<text::Text>,
 }
 
 impl DatadogLog {
@@ -99,6 +119,18 @@ impl DatadogLog {
     {
         Self {
             str_pool: strings::Pool::with_size(rng, 1_000_000),
+            text: None,
+        }
+    }
+
+    /// Create a new instance of `DatadogLog` per `config`
+    pub fn with_config<R>(config: &Config, rng: &mut R) -> Self
+    where
+        R: rand::Rng + ?Sized,
+    {
+        Self {
+            text: config.text.map(|text| text::Text::new(text, rng)),
+            ..Self::new(rng)
         }
     }
 }
@@ -112,7 +144,7 @@ impl<'a> Generator<'a> for DatadogLog {
         R: rand::Rng + ?Sized,
     {
         Ok(Member {
-            message: message(&mut rng, &self.str_pool),
+            message: message(&mut rng, &self.str_pool, self.text.as_ref()),
             status: STATUSES.choose(rng).expect("failed to generate status"),
             timestamp: rng.gen(),
             hostname: HOSTNAMES.choose(rng).expect("failed to generate hostnames"),
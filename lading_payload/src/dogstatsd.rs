@@ -1,6 +1,6 @@
 //! `DogStatsD` payload.
 
-use std::{cmp, fmt, io::Write, ops::Range, rc::Rc};
+use std::{cell::RefCell, cmp, fmt, io::Write, ops::Range, sync::Arc, time::Instant};
 
 use rand::{
     distributions::{uniform::SampleUniform, WeightedIndex},
@@ -14,12 +14,15 @@ use tracing::{debug, warn};
 use crate::{common::strings, Serialize};
 
 use self::{
-    common::tags, event::EventGenerator, metric::MetricGenerator,
+    common::{tags, OriginGenerator},
+    event::EventGenerator,
+    metric::{MetricGenerator, TemplateGenerator},
     service_check::ServiceCheckGenerator,
 };
 
 use super::Generator;
 
+mod churn;
 mod common;
 pub mod event;
 pub mod metric;
@@ -144,6 +147,123 @@ impl Default for ValueConf {
         }
     }
 }
+/// Configuration of the origin fields that may appear in all messages.
+///
+/// These drive the Agent's origin detection. Container IDs and their external
+/// data are drawn from a fixed set, bounding the number of origins a target
+/// sees.
+#[derive(Debug, Deserialize, SerdeSerialize, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields, default)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct OriginConf {
+    /// The range of unique container IDs.
+    pub container_ids: ConfRange<u16>,
+    /// Probability between 0 and 1 that a given msg carries a container ID,
+    /// `|c:<id>`.
+    pub container_id_probability: f32,
+    /// Probability between 0 and 1 that a given msg carries external data,
+    /// `|e:<data>`.
+    pub external_data_probability: f32,
+    /// Probability between 0 and 1 that a given msg carries a tag
+    /// cardinality, `|card:<cardinality>`.
+    pub cardinality_probability: f32,
+}
+
+impl OriginConf {
+    fn valid(&self) -> (bool, &'static str) {
+        let (valid, reason) = self.container_ids.valid();
+        if !valid {
+            return (valid, reason);
+        }
+        for prob in [
+            self.container_id_probability,
+            self.external_data_probability,
+            self.cardinality_probability,
+        ] {
+            if !(0.0..=1.0).contains(&prob) {
+                return (false, "probabilities must be between 0 and 1");
+            }
+        }
+        (true, "")
+    }
+}
+
+impl Default for OriginConf {
+    fn default() -> Self {
+        Self {
+            container_ids: ConfRange::Inclusive { min: 16, max: 1024 },
+            container_id_probability: 0.5,
+            external_data_probability: 0.0,
+            cardinality_probability: 0.0,
+        }
+    }
+}
+
+/// Configuration of metric context churn.
+///
+/// Every interval new contexts are introduced and every context retires once
+/// its lifetime elapses, so the set of active contexts turns over. In steady
+/// state roughly `new_contexts_per_interval * lifetime_seconds /
+/// interval_seconds` contexts are active. Time is wall-clock time while
+/// blocks are generated, so contexts only evolve during a run with
+/// [`crate::block::CacheMethod::Streaming`].
+#[derive(Debug, Deserialize, SerdeSerialize, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields, default)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct ContextChurn {
+    /// The length of an interval, in seconds.
+    pub interval_seconds: u32,
+    /// The number of new contexts introduced every interval.
+    pub new_contexts_per_interval: ConfRange<u32>,
+    /// The lifetime of a context in seconds, drawn uniformly from this range.
+    /// No lifetime may be shorter than an interval.
+    pub lifetime_seconds: ConfRange<u32>,
+}
+
+impl ContextChurn {
+    fn valid(&self) -> (bool, &'static str) {
+        if self.interval_seconds == 0 {
+            return (false, "interval_seconds cannot be 0");
+        }
+        let (valid, reason) = self.new_contexts_per_interval.valid();
+        if !valid {
+            return (valid, reason);
+        }
+        if self.new_contexts_per_interval.start() == 0 {
+            return (false, "new_contexts_per_interval start value cannot be 0");
+        }
+        if self.new_contexts_per_interval.end() > MAX_CONTEXTS {
+            return (
+                false,
+                "new_contexts_per_interval end value is greater than the maximum contexts",
+            );
+        }
+        let (valid, reason) = self.lifetime_seconds.valid();
+        if !valid {
+            return (valid, reason);
+        }
+        if self.lifetime_seconds.start() < self.interval_seconds {
+            return (
+                false,
+                "lifetime_seconds cannot be shorter than interval_seconds",
+            );
+        }
+        (true, "")
+    }
+}
+
+impl Default for ContextChurn {
+    fn default() -> Self {
+        Self {
+            interval_seconds: 10,
+            new_contexts_per_interval: ConfRange::Inclusive { min: 10, max: 100 },
+            lifetime_seconds: ConfRange::Inclusive { min: 60, max: 600 },
+        }
+    }
+}
+
 /// Range expression for configuration
 #[derive(Debug, Deserialize, SerdeSerialize, Clone, PartialEq, Copy)]
 #[serde(deny_unknown_fields)]
@@ -171,21 +291,21 @@ where
 {
     /// Returns true if the range provided by the user is valid, false
     /// otherwise.
-    fn valid(&self) -> (bool, &'static str) {
+    pub(crate) fn valid(&self) -> (bool, &'static str) {
         match self {
             Self::Constant(_) => (true, ""),
             Self::Inclusive { min, max } => (min < max, "min must be less than max"),
         }
     }
 
-    fn start(&self) -> T {
+    pub(crate) fn start(&self) -> T {
         match self {
             ConfRange::Constant(c) => *c,
             ConfRange::Inclusive { min, .. } => *min,
         }
     }
 
-    fn end(&self) -> T {
+    pub(crate) fn end(&self) -> T {
         match self {
             ConfRange::Constant(c) => *c,
             ConfRange::Inclusive { max, .. } => *max,
@@ -197,7 +317,7 @@ impl<T> ConfRange<T>
 where
     T: PartialEq + cmp::PartialOrd + Clone + Copy + SampleUniform,
 {
-    fn sample<R>(&self, rng: &mut R) -> T
+    pub(crate) fn sample<R>(&self, rng: &mut R) -> T
     where
         R: rand::Rng + ?Sized,
     {
@@ -272,6 +392,19 @@ pub struct Config {
     /// If true, a fixed `smp.` (4 bytes) prefix will be prepended
     /// to each metric name.
     pub prefix_metric_names: bool,
+
+    /// Probability between 0 and 1 that a given count or gauge carries a
+    /// client timestamp, `|T<unix>`. The Agent does not aggregate timestamped
+    /// metrics. Timestamps are taken as blocks are generated, so a non-zero
+    /// probability requires the streaming cache method.
+    pub timestamp_probability: f32,
+
+    /// The configuration of origin fields that appear in all messages.
+    pub origin: OriginConf,
+
+    /// If set, metric contexts are retired and introduced over time, starting
+    /// from the contexts chosen by `contexts`.
+    pub context_churn: Option<ContextChurn>,
 }
 
 impl Default for Config {
@@ -300,6 +433,9 @@ impl Default for Config {
             length_prefix_framed: false,
             unique_tag_ratio: 0.11,
             prefix_metric_names: false,
+            timestamp_probability: 0.0,
+            origin: OriginConf::default(),
+            context_churn: None,
         }
     }
 }
@@ -355,6 +491,19 @@ impl Config {
         if !value_valid {
             return Result::Err(format!("Value configuration is invalid: {reason}"));
         }
+        if !(0.0..=1.0).contains(&self.timestamp_probability) {
+            return Result::Err("Timestamp probability must be between 0 and 1".to_string());
+        }
+        let (origin_valid, reason) = self.origin.valid();
+        if !origin_valid {
+            return Result::Err(format!("Origin configuration is invalid: {reason}"));
+        }
+        if let Some(churn) = self.context_churn {
+            let (churn_valid, reason) = churn.valid();
+            if !churn_valid {
+                return Result::Err(format!("Context churn configuration is invalid: {reason}"));
+            }
+        }
         Ok(())
     }
 }
@@ -452,6 +601,8 @@ impl MemberGenerator {
         value_conf: ValueConf,
         unique_tag_ratio: f32,
         metric_name_prefix: &'static str,
+        timestamp_probability: f32,
+        origin_conf: OriginConf,
         mut rng: &mut R,
     ) -> Result<Self, crate::Error>
     where
@@ -459,16 +610,16 @@ impl MemberGenerator {
     {
         // TODO only create the Generators if they're needed per the kind weights
 
-        let pool = Rc::new(strings::Pool::with_size(&mut rng, 8_000_000));
+        let pool = Arc::new(strings::Pool::with_size(&mut rng, 8_000_000));
 
         let num_contexts = contexts.sample(rng);
 
-        let mut tags_generator = match tags::Generator::new(
+        let tags_generator = match tags::Generator::new(
             rng.gen(),
             tags_per_msg,
             tag_length,
             num_contexts as usize,
-            Rc::clone(&pool),
+            Arc::clone(&pool),
             unique_tag_ratio,
         ) {
             Ok(tg) => tg,
@@ -488,10 +639,11 @@ impl MemberGenerator {
 
         let texts_or_messages = random_strings_with_length(pool.as_ref(), 4..128, 1024, &mut rng);
         let small_strings = random_strings_with_length(pool.as_ref(), 16..1024, 8, &mut rng);
+        let origin_generator = OriginGenerator::new(origin_conf, pool.as_ref(), &mut rng)?;
 
         // NOTE the ordering here of `metric_choices` is very important! If you
-        // change it here you MUST also change it in `Generator<Metric> for
-        // MetricGenerator`.
+        // change it here you MUST also change it in `Generator<Template> for
+        // TemplateGenerator`.
         let metric_choices = [
             u16::from(metric_weights.count),
             u16::from(metric_weights.gauge),
@@ -502,33 +654,39 @@ impl MemberGenerator {
         ];
 
         let event_generator = EventGenerator {
-            str_pool: Rc::clone(&pool),
+            str_pool: Arc::clone(&pool),
             title_length: name_length,
             texts_or_messages_length_range: 1..1024,
             small_strings_length_range: 1..8,
             tags_generator: tags_generator.clone(),
+            origin_generator: origin_generator.clone(),
         };
 
         let service_check_generator = ServiceCheckGenerator {
             names: service_event_titles.clone(),
-            small_strings: small_strings.clone(),
+            small_strings,
             texts_or_messages,
             tags_generator: tags_generator.clone(),
+            origin_generator: origin_generator.clone(),
         };
 
+        let template_generator = TemplateGenerator {
+            name_length,
+            metric_weights: WeightedIndex::new(metric_choices)?,
+            tags_generator,
+            str_pool: Arc::clone(&pool),
+            metric_name_prefix,
+        };
         let metric_generator = MetricGenerator::new(
             num_contexts as usize,
-            name_length,
             multivalue_count,
             multivalue_pack_probability,
             sampling,
             sampling_probability,
-            &WeightedIndex::new(metric_choices)?,
-            small_strings,
-            &mut tags_generator,
-            pool.as_ref(),
+            template_generator,
+            origin_generator,
+            timestamp_probability,
             value_conf,
-            metric_name_prefix,
             &mut rng,
         );
 
@@ -550,12 +708,20 @@ impl MemberGenerator {
 }
 
 impl MemberGenerator {
-    fn generate<'a, R>(&'a self, rng: &mut R) -> Result<Member<'a>, crate::Error>
+    /// Generate a `Member`, its metric context drawn from `contexts` if set.
+    fn generate<'a, R>(
+        &'a self,
+        rng: &mut R,
+        contexts: Option<&'a churn::Contexts>,
+    ) -> Result<Member<'a>, crate::Error>
     where
         R: rand::Rng + ?Sized,
     {
         match self.kind_weights.sample(rng) {
-            0 => Ok(Member::Metric(self.metric_generator.generate(rng)?)),
+            0 => match contexts.and_then(|contexts| contexts.choose(rng)) {
+                Some(template) => Ok(Member::Metric(self.metric_generator.metric(template, rng)?)),
+                None => Ok(Member::Metric(self.metric_generator.generate(rng)?)),
+            },
             1 => {
                 let event = self.event_generator.generate(rng)?;
                 Ok(Member::Event(event))
@@ -595,6 +761,7 @@ impl<'a> fmt::Display for Member<'a> {
 /// A generator for `DogStatsD` payloads
 pub struct DogStatsD {
     member_generator: MemberGenerator,
+    contexts: Option<RefCell<churn::Contexts>>,
     length_prefix_framed: bool,
 }
 
@@ -614,7 +781,8 @@ impl DogStatsD {
 
     /// Generate a single `Member`.
     /// Prefer using the Serialize implementation for `DogStatsD` which
-    /// generates a stream of `Member`s according to some constraints.
+    /// generates a stream of `Member`s according to some constraints. Context
+    /// churn is not applied, metrics are drawn from the initial contexts.
     ///
     /// # Errors
     ///
@@ -623,7 +791,7 @@ impl DogStatsD {
     where
         R: rand::Rng + ?Sized,
     {
-        self.member_generator.generate(rng)
+        self.member_generator.generate(rng, None)
     }
 
     /// Create a new instance of `DogStatsD`.
@@ -656,11 +824,25 @@ impl DogStatsD {
             config.value,
             config.unique_tag_ratio,
             prefix,
+            config.timestamp_probability,
+            config.origin,
             rng,
         )?;
 
+        let contexts = config.context_churn.map(|churn| {
+            let metric_generator = &member_generator.metric_generator;
+            RefCell::new(churn::Contexts::new(
+                churn,
+                &metric_generator.templates,
+                metric_generator.template_generator.clone(),
+                Instant::now(),
+                rng,
+            ))
+        });
+
         Ok(Self {
             member_generator,
+            contexts,
             length_prefix_framed: config.length_prefix_framed,
         })
     }
@@ -690,11 +872,17 @@ impl DogStatsD {
         R: Rng + Sized,
         W: Write,
     {
+        let mut contexts = self.contexts.as_ref().map(RefCell::borrow_mut);
+        if let Some(contexts) = contexts.as_mut() {
+            contexts.advance(Instant::now(), &mut rng)?;
+        }
+        let contexts = contexts.as_deref();
+
         let mut bytes_remaining = max_bytes;
         let mut members = Vec::new();
         // generate as many messages as we can fit
         loop {
-            let member: Member = self.member_generator.generate(&mut rng)?;
+            let member: Member = self.member_generator.generate(&mut rng, contexts)?;
             let encoding = format!("{member}");
             let line_length = encoding.len() + 1; // add one for the newline
             match bytes_remaining.checked_sub(line_length) {
@@ -747,9 +935,15 @@ impl DogStatsD {
         R: Rng + Sized,
         W: Write,
     {
+        let mut contexts = self.contexts.as_ref().map(RefCell::borrow_mut);
+        if let Some(contexts) = contexts.as_mut() {
+            contexts.advance(Instant::now(), &mut rng)?;
+        }
+        let contexts = contexts.as_deref();
+
         let mut bytes_remaining = max_bytes;
         loop {
-            let member: Member = self.member_generator.generate(&mut rng)?;
+            let member: Member = self.member_generator.generate(&mut rng, contexts)?;
             let encoding = format!("{member}");
             let line_length = encoding.len() + 1; // add one for the newline
             match bytes_remaining.checked_sub(line_length) {
@@ -775,10 +969,14 @@ impl DogStatsD {
 
 #[cfg(test)]
 mod test {
-    use super::Config;
+    use std::{collections::HashSet, num::NonZeroU32};
+
+    use super::{ConfRange, Config, OriginConf};
     use proptest::prelude::*;
     use rand::{rngs::SmallRng, SeedableRng};
 
+    use crate::block::{self, Cache};
+
     use crate::DogStatsD;
 
     // We want to be sure that the serialized size of the payload does not
@@ -822,4 +1020,60 @@ mod test {
             );
         }
     }
+
+    // We want to know that the protocol extensions appear when always enabled
+    // and that container IDs stay within the configured cardinality.
+    proptest! {
+        #[test]
+        fn extensions_always_present(seed: u64, container_ids in 1_u16..64) {
+            let mut rng = SmallRng::seed_from_u64(seed);
+
+            let dogstatsd_config = Config {
+                timestamp_probability: 1.0,
+                origin: OriginConf {
+                    container_ids: ConfRange::Constant(container_ids),
+                    container_id_probability: 1.0,
+                    external_data_probability: 1.0,
+                    cardinality_probability: 1.0,
+                },
+                ..Default::default()
+            };
+            let dogstatsd = DogStatsD::new(dogstatsd_config, &mut rng).expect("failed to create DogStatsD");
+
+            let mut bytes = Vec::with_capacity(16_384);
+            dogstatsd.to_bytes(rng, 16_384, &mut bytes).expect("failed to convert to bytes");
+            let payload = std::str::from_utf8(&bytes).expect("failed to convert from utf-8 to str");
+
+            let mut seen = HashSet::new();
+            for line in payload.lines() {
+                let fields: Vec<&str> = line.split('|').collect();
+                let container_id = fields.iter().find_map(|f| f.strip_prefix("c:"));
+                prop_assert!(container_id.is_some(), "{}", line);
+                prop_assert!(fields.iter().any(|f| f.starts_with("e:it-")), "{}", line);
+                prop_assert!(fields.iter().any(|f| f.starts_with("card:")), "{}", line);
+                if fields.get(1).is_some_and(|kind| *kind == "c" || *kind == "g") {
+                    prop_assert!(fields.iter().any(|f| f.starts_with('T')), "{}", line);
+                }
+                seen.insert(container_id);
+            }
+            prop_assert!(seen.len() <= usize::from(container_ids));
+        }
+    }
+
+    // A fixed cache would replay client timestamps long after they were taken.
+    #[test]
+    fn timestamps_require_streaming_cache() {
+        let config = crate::Config::DogStatsD(Config {
+            timestamp_probability: 0.5,
+            ..Default::default()
+        });
+        let mut rng = SmallRng::seed_from_u64(40);
+        let total_bytes = NonZeroU32::new(64 * 1024).expect("non-zero");
+
+        assert!(matches!(
+            Cache::fixed(&mut rng, total_bytes, 8 * 1024, &config),
+            Err(block::Error::InvalidConfig(_))
+        ));
+        assert!(Cache::streaming(&mut rng, 8 * 1024, &config).is_ok());
+    }
 }
@@ -0,0 +1,174 @@
+//! `DogStatsD` metric context churn.
+//!
+//! The set of active contexts evolves with wall-clock time: every interval new
+//! contexts are born and every context retires once its lifetime elapses. See
+//! [`super::ContextChurn`].
+use std::time::{Duration, Instant};
+
+use rand::{seq::SliceRandom, Rng};
+
+use crate::{Error, Generator};
+
+use super::{
+    metric::{template::Template, TemplateGenerator},
+    ContextChurn, MAX_CONTEXTS,
+};
+
+#[derive(Debug, Clone)]
+pub(crate) struct Contexts {
+    config: ContextChurn,
+    template_generator: TemplateGenerator,
+    /// Active contexts and the instant each retires.
+    active: Vec<(Template, Instant)>,
+    /// The instant at which the next contexts are born.
+    next_birth: Instant,
+}
+
+impl Contexts {
+    /// Create a set of contexts beginning with `templates` at `now`.
+    ///
+    /// The initial contexts are placed part way through their lifetimes so
+    /// that they retire gradually rather than all at once.
+    pub(crate) fn new<R>(
+        config: ContextChurn,
+        templates: &[Template],
+        template_generator: TemplateGenerator,
+        now: Instant,
+        rng: &mut R,
+    ) -> Self
+    where
+        R: Rng + ?Sized,
+    {
+        let active = templates
+            .iter()
+            .map(|template| {
+                let lifetime = lifetime(&config, rng).mul_f64(rng.gen());
+                (template.clone(), now + lifetime)
+            })
+            .collect();
+
+        Self {
+            config,
+            template_generator,
+            active,
+            next_birth: now,
+        }
+    }
+
+    /// Retire the contexts whose lifetimes have elapsed by `now` and introduce
+    /// those born since the last call.
+    pub(crate) fn advance<R>(&mut self, now: Instant, rng: &mut R) -> Result<(), Error>
+    where
+        R: Rng + ?Sized,
+    {
+        self.active.retain(|(_, retires)| *retires > now);
+
+        let interval = Duration::from_secs(u64::from(self.config.interval_seconds));
+        // Contexts born longer ago than the longest lifetime have already
+        // retired, skip their intervals.
+        let horizon = Duration::from_secs(u64::from(self.config.lifetime_seconds.end()));
+        if let Some(earliest) = now.checked_sub(horizon) {
+            if self.next_birth < earliest {
+                let skipped = (earliest - self.next_birth).as_secs() / interval.as_secs();
+                self.next_birth +=
+                    interval.saturating_mul(u32::try_from(skipped).unwrap_or(u32::MAX));
+            }
+        }
+
+        while self.next_birth <= now {
+            let born = self.next_birth;
+            self.next_birth += interval;
+            for _ in 0..self.config.new_contexts_per_interval.sample(rng) {
+                let retires = born + lifetime(&self.config, rng);
+                if retires <= now || self.active.len() >= MAX_CONTEXTS as usize {
+                    continue;
+                }
+                self.active
+                    .push((self.template_generator.generate(rng)?, retires));
+            }
+        }
+        Ok(())
+    }
+
+    /// Choose an active context, if any.
+    pub(crate) fn choose<R>(&self, rng: &mut R) -> Option<&Template>
+    where
+        R: Rng + ?Sized,
+    {
+        self.active.choose(rng).map(|(template, _)| template)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.active.len()
+    }
+}
+
+fn lifetime<R>(config: &ContextChurn, rng: &mut R) -> Duration
+where
+    R: Rng + ?Sized,
+{
+    Duration::from_secs(u64::from(config.lifetime_seconds.sample(rng)))
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use super::Contexts;
+    use crate::{
+        dogstatsd::{ConfRange, Config, ContextChurn},
+        DogStatsD,
+    };
+
+    // With constant births and lifetimes the active population is exact: the
+    // initial contexts have retired and only those born within the last
+    // lifetime remain, even after a long pause.
+    #[test]
+    fn population_turns_over() {
+        let mut rng = SmallRng::seed_from_u64(19);
+        let churn = ContextChurn {
+            interval_seconds: 10,
+            new_contexts_per_interval: ConfRange::Constant(5),
+            lifetime_seconds: ConfRange::Constant(30),
+        };
+        let config = Config {
+            contexts: ConfRange::Constant(20),
+            context_churn: Some(churn),
+            ..Default::default()
+        };
+        let dogstatsd = DogStatsD::new(config, &mut rng).expect("failed to create DogStatsD");
+        let metric_generator = &dogstatsd.member_generator.metric_generator;
+
+        let start = Instant::now();
+        let mut contexts = Contexts::new(
+            churn,
+            &metric_generator.templates,
+            metric_generator.template_generator.clone(),
+            start,
+            &mut rng,
+        );
+        assert_eq!(contexts.len(), 20);
+
+        // Births at 0s, 10s and 20s are alive at 25s alongside some of the
+        // initial contexts.
+        contexts
+            .advance(start + Duration::from_secs(25), &mut rng)
+            .expect("failed to advance");
+        assert!(contexts.len() >= 15);
+
+        // Births at 80s, 90s and 100s are alive at 100s, the one at 70s
+        // retires at 100s.
+        contexts
+            .advance(start + Duration::from_secs(100), &mut rng)
+            .expect("failed to advance");
+        assert_eq!(contexts.len(), 15);
+
+        contexts
+            .advance(start + Duration::from_secs(100_000), &mut rng)
+            .expect("failed to advance");
+        assert_eq!(contexts.len(), 15);
+    }
+}
@@ -6,9 +6,9 @@ use rand::{
     Rng,
 };
 
-use crate::{Error, Generator};
+use crate::{common::strings, Error, Generator};
 
-use super::{ConfRange, ValueConf};
+use super::{ConfRange, OriginConf, ValueConf};
 
 pub(crate) mod tags;
 
@@ -150,3 +150,152 @@ impl fmt::Display for ZeroToOne {
         }
     }
 }
+
+/// Generates the origin fields shared by all message kinds.
+///
+/// Every container ID is paired with the external data of the same container
+/// so that a message carrying both describes a single origin.
+#[derive(Clone, Debug)]
+pub(crate) struct OriginGenerator {
+    container_ids: Vec<String>,
+    external_data: Vec<String>,
+    container_id_probability: f32,
+    external_data_probability: f32,
+    cardinality_probability: f32,
+}
+
+impl OriginGenerator {
+    pub(crate) fn new<R>(
+        conf: OriginConf,
+        str_pool: &strings::Pool,
+        rng: &mut R,
+    ) -> Result<Self, Error>
+    where
+        R: Rng + ?Sized,
+    {
+        let total = usize::from(conf.container_ids.sample(rng));
+        let mut container_ids = Vec::with_capacity(total);
+        let mut external_data = Vec::with_capacity(total);
+        for _ in 0..total {
+            container_ids.push(format!(
+                "{:032x}{:032x}",
+                rng.gen::<u128>(),
+                rng.gen::<u128>()
+            ));
+            let uid = rng.gen::<u128>();
+            let container_name = str_pool
+                .of_size_range(rng, 1_u8..16)
+                .ok_or(Error::StringGenerate)?;
+            external_data.push(format!(
+                "it-{init},cn-{container_name},pu-{a:08x}-{b:04x}-{c:04x}-{d:04x}-{e:012x}",
+                init = rng.gen_bool(0.1),
+                a = uid >> 96,
+                b = (uid >> 80) & 0xffff,
+                c = (uid >> 64) & 0xffff,
+                d = (uid >> 48) & 0xffff,
+                e = uid & 0xffff_ffff_ffff,
+            ));
+        }
+
+        Ok(Self {
+            container_ids,
+            external_data,
+            container_id_probability: conf.container_id_probability,
+            external_data_probability: conf.external_data_probability,
+            cardinality_probability: conf.cardinality_probability,
+        })
+    }
+}
+
+impl<'a> Generator<'a> for OriginGenerator {
+    type Output = Origin<'a>;
+    type Error = Error;
+
+    fn generate<R>(&'a self, rng: &mut R) -> Result<Self::Output, Error>
+    where
+        R: rand::Rng + ?Sized,
+    {
+        let mut origin = Origin::default();
+        if !self.container_ids.is_empty() {
+            let idx = rng.gen_range(0..self.container_ids.len());
+            let prob: f32 = OpenClosed01.sample(rng);
+            if prob < self.container_id_probability {
+                origin.container_id = Some(&self.container_ids[idx]);
+            }
+            let prob: f32 = OpenClosed01.sample(rng);
+            if prob < self.external_data_probability {
+                origin.external_data = Some(&self.external_data[idx]);
+            }
+        }
+        let prob: f32 = OpenClosed01.sample(rng);
+        if prob < self.cardinality_probability {
+            origin.cardinality = Some(rng.gen());
+        }
+        Ok(origin)
+    }
+}
+
+/// Origin fields of a message, used by the Agent's origin detection.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Origin<'a> {
+    /// Container ID of the sender, `|c:`.
+    pub container_id: Option<&'a str>,
+    /// External data injected by the Agent's admission controller, `|e:`.
+    pub external_data: Option<&'a str>,
+    /// Tag cardinality requested for the message, `|card:`.
+    pub cardinality: Option<Cardinality>,
+}
+
+impl<'a> fmt::Display for Origin<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(container_id) = self.container_id {
+            write!(f, "|c:{container_id}")?;
+        }
+        if let Some(external_data) = self.external_data {
+            write!(f, "|e:{external_data}")?;
+        }
+        if let Some(cardinality) = self.cardinality {
+            write!(f, "|card:{cardinality}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Tag cardinality of a message.
+#[derive(Clone, Copy, Debug)]
+pub enum Cardinality {
+    /// No origin tags.
+    None,
+    /// Low cardinality origin tags.
+    Low,
+    /// Orchestrator cardinality origin tags.
+    Orchestrator,
+    /// High cardinality origin tags.
+    High,
+}
+
+impl Distribution<Cardinality> for Standard {
+    fn sample<R>(&self, rng: &mut R) -> Cardinality
+    where
+        R: Rng + ?Sized,
+    {
+        match rng.gen_range(0..4) {
+            0 => Cardinality::None,
+            1 => Cardinality::Low,
+            2 => Cardinality::Orchestrator,
+            3 => Cardinality::High,
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl fmt::Display for Cardinality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => write!(f, "none"),
+            Self::Low => write!(f, "low"),
+            Self::Orchestrator => write!(f, "orchestrator"),
+            Self::High => write!(f, "high"),
+        }
+    }
+}
@@ -1,6 +1,6 @@
 //! Tag generation for dogstatsd payloads
 use std::cell::{Cell, RefCell};
-use std::rc::Rc;
+use std::sync::Arc;
 
 use rand::distributions::Distribution;
 use rand::Rng;
@@ -54,7 +54,7 @@ pub(crate) struct Generator {
     num_tagsets: usize,
     tags_per_msg: ConfRange<u8>,
     tag_length: ConfRange<u16>,
-    str_pool: Rc<Pool>,
+    str_pool: Arc<Pool>,
     unique_tag_probability: f32,
     unique_tags: RefCell<Vec<(Handle, Handle)>>,
 }
@@ -79,7 +79,7 @@ impl Generator {
         tags_per_msg: ConfRange<u8>,
         tag_length: ConfRange<u16>,
         num_tagsets: usize,
-        str_pool: Rc<Pool>,
+        str_pool: Arc<Pool>,
         unique_tag_probability: f32,
     ) -> Result<Self, Error> {
         let (tag_length_valid, tag_length_valid_msg) = tag_length.valid();
@@ -161,6 +161,10 @@ impl<'a> crate::Generator<'a> for Generator {
             self.internal_rng
                 .replace(SmallRng::seed_from_u64(self.seed.get()));
             self.tagsets_produced.set(0);
+            // Forget the tags of the previous loop, both so that the loop
+            // repeats exactly and so that a generator used indefinitely does
+            // not grow without bound.
+            self.unique_tags.borrow_mut().clear();
         }
 
         // a tagset is a list of tags that will be put on a single dogstatsd message.
@@ -212,7 +216,7 @@ mod test {
     use std::collections::{hash_map::RandomState, BTreeSet, HashMap};
     use std::hash::BuildHasher;
     use std::hash::Hasher;
-    use std::rc::Rc;
+    use std::sync::Arc;
 
     use proptest::prelude::*;
     use rand::{rngs::SmallRng, SeedableRng};
@@ -271,7 +275,7 @@ mod test {
         fn tagsets_repeat_after_reaching_tagset_max(seed: u64, num_tagsets in 1..100_000_usize) {
             let mut rng = SmallRng::seed_from_u64(seed);
 
-            let str_pool = Rc::new(Pool::with_size(&mut rng, 8_000_000));
+            let str_pool = Arc::new(Pool::with_size(&mut rng, 8_000_000));
             let tags_per_msg_range = ConfRange::Inclusive { min: 0, max: 25 };
             let tag_size_range = ConfRange::Inclusive { min: 3, max: 128 };
             let generator =
@@ -308,7 +312,7 @@ mod test {
         fn generator_yields_valid_tagsets(seed: u64, num_tagsets in 1..100_000_usize, tags_per_msg_max in 1..u8::MAX) {
             let mut rng = SmallRng::seed_from_u64(seed);
 
-            let str_pool = Rc::new(Pool::with_size(&mut rng, 8_000_000));
+            let str_pool = Arc::new(Pool::with_size(&mut rng, 8_000_000));
             let tags_per_msg_range = ConfRange::Inclusive{min: 0, max: tags_per_msg_max};
             let tag_size_range = ConfRange::Inclusive{min: 3, max: 128};
             let generator = tags::Generator::new(
@@ -343,7 +347,7 @@ mod test {
             let tag_size_range = ConfRange::Inclusive { min: 3, max: 128 };
             let mut rng = SmallRng::seed_from_u64(seed);
 
-            let str_pool = Rc::new(Pool::with_size(&mut rng, 8_000_000));
+            let str_pool = Arc::new(Pool::with_size(&mut rng, 8_000_000));
             let generator = tags::Generator::new(
                 seed,
                 tags_per_msg_range,
@@ -381,7 +385,7 @@ mod test {
             let tag_size_range = ConfRange::Inclusive { min: 3, max: 128 };
             let mut rng = SmallRng::seed_from_u64(seed);
 
-            let str_pool = Rc::new(Pool::with_size(&mut rng, 8_000_000));
+            let str_pool = Arc::new(Pool::with_size(&mut rng, 8_000_000));
             let generator = tags::Generator::new(
                 seed,
                 tags_per_msg_range,
@@ -1,5 +1,5 @@
 //! `DogStatsD` event.
-use std::{fmt, ops::Range, rc::Rc};
+use std::{fmt, ops::Range, sync::Arc};
 
 use rand::{distributions::Standard, prelude::Distribution, Rng};
 
@@ -12,8 +12,9 @@ pub(crate) struct EventGenerator {
     pub(crate) title_length: ConfRange<u16>,
     pub(crate) texts_or_messages_length_range: Range<u16>,
     pub(crate) small_strings_length_range: Range<u16>,
-    pub(crate) str_pool: Rc<strings::Pool>,
+    pub(crate) str_pool: Arc<strings::Pool>,
     pub(crate) tags_generator: common::tags::Generator,
+    pub(crate) origin_generator: common::OriginGenerator,
 }
 
 impl<'a> Generator<'a> for EventGenerator {
@@ -60,6 +61,7 @@ impl<'a> Generator<'a> for EventGenerator {
             }),
             alert_type: rng.gen(),
             tags,
+            origin: self.origin_generator.generate(&mut rng)?,
         })
     }
 }
@@ -89,6 +91,8 @@ pub struct Event<'a> {
     pub alert_type: Option<Alert>,
     /// Tags of the event.
     pub tags: Option<common::tags::Tagset>,
+    /// Origin of the event.
+    pub origin: common::Origin<'a>,
 }
 
 impl<'a> fmt::Display for Event<'a> {
@@ -133,6 +137,7 @@ impl<'a> fmt::Display for Event<'a> {
                 }
             }
         }
+        write!(f, "{origin}", origin = self.origin)?;
         Ok(())
     }
 }
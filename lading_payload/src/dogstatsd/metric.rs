@@ -1,5 +1,9 @@
 //! `DogStatsD` metric.
-use std::fmt;
+use std::{
+    fmt,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use rand::{
     distributions::{OpenClosed01, WeightedIndex},
@@ -11,16 +15,62 @@ use crate::{common::strings, dogstatsd::metric::template::Template, Error, Gener
 use tracing::debug;
 
 use super::{
-    choose_or_not_ref,
     common::{self, NumValueGenerator},
     ConfRange, ValueConf,
 };
 
-mod template;
+pub(crate) mod template;
+
+/// Generates metric templates, the name, type and tags of a context.
+#[derive(Clone, Debug)]
+pub(crate) struct TemplateGenerator {
+    pub(crate) name_length: ConfRange<u16>,
+    pub(crate) metric_weights: WeightedIndex<u16>,
+    pub(crate) tags_generator: common::tags::Generator,
+    pub(crate) str_pool: Arc<strings::Pool>,
+    pub(crate) metric_name_prefix: &'static str,
+}
+
+impl<'a> Generator<'a> for TemplateGenerator {
+    type Output = Template;
+    type Error = Error;
+
+    fn generate<R>(&'a self, mut rng: &mut R) -> Result<Self::Output, Self::Error>
+    where
+        R: rand::Rng + ?Sized,
+    {
+        let tags = self.tags_generator.generate(&mut rng);
+        let name_sz = self.name_length.sample(&mut rng) as usize;
+        let strpool_name = String::from(
+            self.str_pool
+                .of_size(&mut rng, name_sz)
+                .ok_or(Error::StringGenerate)?,
+        );
+        let name = if self.metric_name_prefix.is_empty() {
+            strpool_name
+        } else {
+            format!("{prefix}{strpool_name}", prefix = self.metric_name_prefix)
+        };
+
+        // NOTE the ordering here is very important! It MUST match the
+        // ordering of `metric_choices` in `MemberGenerator::new`.
+        Ok(match self.metric_weights.sample(rng) {
+            0 => Template::Count(template::Count { name, tags: tags? }),
+            1 => Template::Gauge(template::Gauge { name, tags: tags? }),
+            2 => Template::Timer(template::Timer { name, tags: tags? }),
+            3 => Template::Distribution(template::Dist { name, tags: tags? }),
+            4 => Template::Set(template::Set { name, tags: tags? }),
+            5 => Template::Histogram(template::Histogram { name, tags: tags? }),
+            _ => unreachable!(),
+        })
+    }
+}
 
 #[derive(Clone, Debug)]
 pub(crate) struct MetricGenerator {
-    pub(crate) container_ids: Vec<String>,
+    pub(crate) origin_generator: common::OriginGenerator,
+    pub(crate) timestamp_probability: f32,
+    pub(crate) template_generator: TemplateGenerator,
     pub(crate) templates: Vec<template::Template>,
     pub(crate) multivalue_count: ConfRange<u16>,
     pub(crate) multivalue_pack_probability: f32,
@@ -33,17 +83,14 @@ impl MetricGenerator {
     #[allow(clippy::too_many_arguments)]
     pub(crate) fn new<R>(
         num_contexts: usize,
-        name_length: ConfRange<u16>,
         multivalue_count: ConfRange<u16>,
         multivalue_pack_probability: f32,
         sampling: ConfRange<f32>,
         sampling_probability: f32,
-        metric_weights: &WeightedIndex<u16>,
-        container_ids: Vec<String>,
-        tags_generator: &mut common::tags::Generator,
-        str_pool: &strings::Pool,
+        template_generator: TemplateGenerator,
+        origin_generator: common::OriginGenerator,
+        timestamp_probability: f32,
         value_conf: ValueConf,
-        metric_name_prefix: &'static str,
         mut rng: &mut R,
     ) -> Result<Self, Error>
     where
@@ -53,33 +100,13 @@ impl MetricGenerator {
 
         debug!("Generating metric templates for {} contexts.", num_contexts);
         for _ in 0..num_contexts {
-            let tags = tags_generator.generate(&mut rng);
-            let name_sz = name_length.sample(&mut rng) as usize;
-            let strpool_name = String::from(
-                str_pool
-                    .of_size(&mut rng, name_sz)
-                    .ok_or(Error::StringGenerate)?,
-            );
-            let name = if metric_name_prefix.is_empty() {
-                strpool_name
-            } else {
-                format!("{metric_name_prefix}{strpool_name}")
-            };
-
-            let res = match metric_weights.sample(rng) {
-                0 => Template::Count(template::Count { name, tags: tags? }),
-                1 => Template::Gauge(template::Gauge { name, tags: tags? }),
-                2 => Template::Timer(template::Timer { name, tags: tags? }),
-                3 => Template::Distribution(template::Dist { name, tags: tags? }),
-                4 => Template::Set(template::Set { name, tags: tags? }),
-                5 => Template::Histogram(template::Histogram { name, tags: tags? }),
-                _ => unreachable!(),
-            };
-            templates.push(res);
+            templates.push(template_generator.generate(&mut rng)?);
         }
 
         Ok(MetricGenerator {
-            container_ids,
+            origin_generator,
+            timestamp_probability,
+            template_generator,
             templates,
             multivalue_count,
             multivalue_pack_probability,
@@ -88,24 +115,28 @@ impl MetricGenerator {
             num_value_generator: NumValueGenerator::new(value_conf),
         })
     }
-}
-
-impl<'a> Generator<'a> for MetricGenerator {
-    type Output = Metric<'a>;
-    type Error = Error;
 
-    fn generate<R>(&'a self, mut rng: &mut R) -> Result<Self::Output, Self::Error>
+    /// Generate a metric of the context described by `template`.
+    pub(crate) fn metric<'a, R>(
+        &'a self,
+        template: &'a Template,
+        mut rng: &mut R,
+    ) -> Result<Metric<'a>, Error>
     where
         R: rand::Rng + ?Sized,
     {
-        // SAFETY: If `self.templates` is ever empty this is a serious logic bug
-        // and the program should crash prior to this point.
-        let template: &Template = self
-            .templates
-            .choose(&mut rng)
-            .expect("failed to choose templates");
-
-        let container_id = choose_or_not_ref(&mut rng, &self.container_ids).map(String::as_str);
+        let origin = self.origin_generator.generate(&mut rng)?;
+        let prob: f32 = OpenClosed01.sample(&mut rng);
+        let timestamp = if prob < self.timestamp_probability {
+            // Timestamped metrics skip aggregation in the Agent, which
+            // rejects them if too far in the past.
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| u32::try_from(d.as_secs()).unwrap_or(u32::MAX));
+            Some(now.saturating_sub(rng.gen_range(0..60)))
+        } else {
+            None
+        };
         // https://docs.datadoghq.com/metrics/custom_metrics/dogstatsd_metrics_submission/#sample-rates
         let prob: f32 = OpenClosed01.sample(&mut rng);
         let sample_rate = if prob < self.sampling_probability {
@@ -135,45 +166,65 @@ impl<'a> Generator<'a> for MetricGenerator {
                 values,
                 sample_rate,
                 tags: &count.tags,
-                container_id,
+                timestamp,
+                origin,
             })),
             Template::Gauge(ref gauge) => Ok(Metric::Gauge(Gauge {
                 name: &gauge.name,
                 values,
                 tags: &gauge.tags,
-                container_id,
+                timestamp,
+                origin,
             })),
             Template::Distribution(ref dist) => Ok(Metric::Distribution(Dist {
                 name: &dist.name,
                 values,
                 sample_rate,
                 tags: &dist.tags,
-                container_id,
+                origin,
             })),
             Template::Histogram(ref hist) => Ok(Metric::Histogram(Histogram {
                 name: &hist.name,
                 values,
                 sample_rate,
                 tags: &hist.tags,
-                container_id,
+                origin,
             })),
             Template::Timer(ref timer) => Ok(Metric::Timer(Timer {
                 name: &timer.name,
                 values,
                 sample_rate,
                 tags: &timer.tags,
-                container_id,
+                origin,
             })),
             Template::Set(ref set) => Ok(Metric::Set(Set {
                 name: &set.name,
                 value: values.pop().expect("failed to pop value from Vec"),
                 tags: &set.tags,
-                container_id,
+                origin,
             })),
         }
     }
 }
 
+impl<'a> Generator<'a> for MetricGenerator {
+    type Output = Metric<'a>;
+    type Error = Error;
+
+    fn generate<R>(&'a self, mut rng: &mut R) -> Result<Self::Output, Self::Error>
+    where
+        R: rand::Rng + ?Sized,
+    {
+        // SAFETY: If `self.templates` is ever empty this is a serious logic bug
+        // and the program should crash prior to this point.
+        let template: &Template = self
+            .templates
+            .choose(&mut rng)
+            .expect("failed to choose templates");
+        self.metric(template, rng)
+    }
+}
+
 /// Representation of a dogstatsd Metric
 #[derive(Clone)]
 pub enum Metric<'a> {
@@ -228,8 +279,10 @@ pub struct Count<'a> {
     pub sample_rate: Option<common::ZeroToOne>,
     /// Tags of the metric.
     pub tags: &'a common::tags::Tagset,
-    /// Container ID of the metric.
-    pub container_id: Option<&'a str>,
+    /// Client timestamp of the metric, in seconds since the unix epoch.
+    pub timestamp: Option<u32>,
+    /// Origin of the metric.
+    pub origin: common::Origin<'a>,
 }
 
 impl<'a> fmt::Display for Count<'a> {
@@ -255,9 +308,10 @@ impl<'a> fmt::Display for Count<'a> {
                 }
             }
         }
-        if let Some(container_id) = self.container_id {
-            write!(f, "|c:{container_id}")?;
+        if let Some(timestamp) = self.timestamp {
+            write!(f, "|T{timestamp}")?;
         }
+        write!(f, "{origin}", origin = self.origin)?;
 
         Ok(())
     }
@@ -272,8 +326,10 @@ pub struct Gauge<'a> {
     pub values: Vec<common::NumValue>,
     /// Tags of the metric.
     pub tags: &'a common::tags::Tagset,
-    /// Container ID of the metric.
-    pub container_id: Option<&'a str>,
+    /// Client timestamp of the metric, in seconds since the unix epoch.
+    pub timestamp: Option<u32>,
+    /// Origin of the metric.
+    pub origin: common::Origin<'a>,
 }
 
 impl<'a> fmt::Display for Gauge<'a> {
@@ -296,9 +352,10 @@ impl<'a> fmt::Display for Gauge<'a> {
                 }
             }
         }
-        if let Some(container_id) = self.container_id {
-            write!(f, "|c:{container_id}")?;
+        if let Some(timestamp) = self.timestamp {
+            write!(f, "|T{timestamp}")?;
         }
+        write!(f, "{origin}", origin = self.origin)?;
 
         Ok(())
     }
@@ -315,8 +372,8 @@ pub struct Timer<'a> {
     pub sample_rate: Option<common::ZeroToOne>,
     /// Tags of the metric.
     pub tags: &'a common::tags::Tagset,
-    /// Container ID of the metric.
-    pub container_id: Option<&'a str>,
+    /// Origin of the metric.
+    pub origin: common::Origin<'a>,
 }
 
 impl<'a> fmt::Display for Timer<'a> {
@@ -342,9 +399,7 @@ impl<'a> fmt::Display for Timer<'a> {
                 }
             }
         }
-        if let Some(container_id) = self.container_id {
-            write!(f, "|c:{container_id}")?;
-        }
+        write!(f, "{origin}", origin = self.origin)?;
 
         Ok(())
     }
@@ -361,8 +416,8 @@ pub struct Dist<'a> {
     pub sample_rate: Option<common::ZeroToOne>,
     /// Tags of the metric.
     pub tags: &'a common::tags::Tagset,
-    /// Container ID of the metric.
-    pub container_id: Option<&'a str>,
+    /// Origin of the metric.
+    pub origin: common::Origin<'a>,
 }
 
 impl<'a> fmt::Display for Dist<'a> {
@@ -388,9 +443,7 @@ impl<'a> fmt::Display for Dist<'a> {
                 }
             }
         }
-        if let Some(container_id) = self.container_id {
-            write!(f, "|c:{container_id}")?;
-        }
+        write!(f, "{origin}", origin = self.origin)?;
 
         Ok(())
     }
@@ -405,8 +458,8 @@ pub struct Set<'a> {
     pub value: common::NumValue,
     /// Tags of the metric.
     pub tags: &'a common::tags::Tagset,
-    /// Container ID of the metric.
-    pub container_id: Option<&'a str>,
+    /// Origin of the metric.
+    pub origin: common::Origin<'a>,
 }
 
 impl<'a> fmt::Display for Set<'a> {
@@ -425,9 +478,7 @@ impl<'a> fmt::Display for Set<'a> {
                 }
             }
         }
-        if let Some(container_id) = self.container_id {
-            write!(f, "|c:{container_id}")?;
-        }
+        write!(f, "{origin}", origin = self.origin)?;
 
         Ok(())
     }
@@ -444,8 +495,8 @@ pub struct Histogram<'a> {
     pub sample_rate: Option<common::ZeroToOne>,
     /// Tags of the metric.
     pub tags: &'a common::tags::Tagset,
-    /// Container ID of the metric.
-    pub container_id: Option<&'a str>,
+    /// Origin of the metric.
+    pub origin: common::Origin<'a>,
 }
 
 impl<'a> fmt::Display for Histogram<'a> {
@@ -471,9 +522,7 @@ impl<'a> fmt::Display for Histogram<'a> {
                 }
             }
         }
-        if let Some(container_id) = self.container_id {
-            write!(f, "|c:{container_id}")?;
-        }
+        write!(f, "{origin}", origin = self.origin)?;
 
         Ok(())
     }
@@ -16,6 +16,7 @@ pub(crate) struct ServiceCheckGenerator {
     pub(crate) small_strings: Vec<String>,
     pub(crate) texts_or_messages: Vec<String>,
     pub(crate) tags_generator: common::tags::Generator,
+    pub(crate) origin_generator: common::OriginGenerator,
 }
 
 impl<'a> Generator<'a> for ServiceCheckGenerator {
@@ -42,6 +43,7 @@ impl<'a> Generator<'a> for ServiceCheckGenerator {
             hostname,
             tags,
             message,
+            origin: self.origin_generator.generate(&mut rng)?,
         })
     }
 }
@@ -61,6 +63,8 @@ pub struct ServiceCheck<'a> {
     pub tags: Option<Tagset>,
     /// Message of the service check.
     pub message: Option<&'a str>,
+    /// Origin of the service check.
+    pub origin: common::Origin<'a>,
 }
 
 impl<'a> fmt::Display for ServiceCheck<'a> {
@@ -91,6 +95,8 @@ impl<'a> fmt::Display for ServiceCheck<'a> {
                 }
             }
         }
+        // The message must be the last field.
+        write!(f, "{origin}", origin = self.origin)?;
         if let Some(msg) = self.message {
             write!(f, "|m:{msg}")?;
         }
@@ -0,0 +1,446 @@
+//! Graylog Extended Log Format payload.
+//!
+//! Implements [GELF 1.1](https://go2docs.graylog.org/current/getting_in_log_data/gelf.html).
+//! Each call to [`crate::Serialize::to_bytes`] writes exactly one datagram: a
+//! whole message, optionally compressed, or when chunking is enabled a single
+//! chunk of a message too large for one datagram. The chunks of a message are
+//! written by consecutive calls, in order, so that a block cache built from
+//! this payload and sent one block per datagram exercises reassembly. A chunked
+//! message cut off at the end of a block cache is never completed.
+
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, VecDeque},
+    io::Write,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use flate2::{
+    write::{GzEncoder, ZlibEncoder},
+    Compression as Level,
+};
+use rand::Rng;
+use serde::{Deserialize, Serialize as SerdeSerialize};
+use serde_json::{Map, Value};
+
+use crate::{common::strings, dogstatsd::ConfRange, Error};
+
+/// The GELF chunk magic bytes.
+const CHUNK_MAGIC: [u8; 2] = [0x1e, 0x0f];
+/// Magic bytes, message ID, sequence number and sequence count.
+pub const CHUNK_HEADER_SIZE: u16 = 12;
+/// The most chunks a GELF message may be split into.
+const MAXIMUM_CHUNKS: usize = 128;
+/// An upper bound on the serialized size of a message's standard fields, their
+/// names and the JSON punctuation around them.
+const MAXIMUM_STANDARD_FIELDS_SIZE: usize = 4608;
+/// An upper bound on the serialized size of a generated additional field.
+const MAXIMUM_ADDITIONAL_FIELD_SIZE: usize = 96;
+
+fn default_additional_fields() -> ConfRange<u8> {
+    ConfRange::Inclusive { min: 0, max: 8 }
+}
+
+fn default_maximum_chunk_size() -> u16 {
+    1420
+}
+
+/// Compression applied to each message
+#[derive(Debug, Default, Deserialize, SerdeSerialize, Clone, Copy, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(test, derive(proptest_derive::Arbitrary))]
+pub enum Compression {
+    /// Uncompressed JSON
+    #[default]
+    None,
+    /// Gzip compressed JSON
+    Gzip,
+    /// Zlib compressed JSON
+    Zlib,
+}
+
+/// Chunking of messages too large for a single datagram
+#[derive(Debug, Deserialize, SerdeSerialize, Clone, Copy, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct Chunking {
+    /// The largest datagram to write, including the chunk header. Messages
+    /// larger than this are chunked.
+    #[serde(default = "default_maximum_chunk_size")]
+    pub maximum_chunk_size: u16,
+}
+
+/// Configuration for [`Gelf`]
+#[derive(Debug, Deserialize, SerdeSerialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct Config {
+    /// The number of randomly named additional fields in each message
+    #[serde(default = "default_additional_fields")]
+    pub additional_fields: ConfRange<u8>,
+    /// Additional fields included in every message, named without the leading
+    /// underscore
+    #[serde(default)]
+    pub static_fields: BTreeMap<String, String>,
+    /// Compression applied to each message, default none
+    #[serde(default)]
+    pub compression: Compression,
+    /// Chunk messages too large for a single datagram, default off
+    #[serde(default)]
+    pub chunking: Option<Chunking>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            additional_fields: default_additional_fields(),
+            static_fields: BTreeMap::default(),
+            compression: Compression::default(),
+            chunking: None,
+        }
+    }
+}
+
+impl Config {
+    /// Determine whether the passed configuration obeys validation criteria
+    ///
+    /// # Errors
+    ///
+    /// Function will error if the additional field range is invalid, if a
+    /// static field is named `id` or if the chunk size leaves no room for
+    /// data or is too small for the largest message to fit in 128 chunks.
+    pub fn valid(&self) -> Result<(), String> {
+        let (valid, reason) = self.additional_fields.valid();
+        if !valid {
+            return Err(format!("Additional fields are invalid: {reason}"));
+        }
+        if self.static_fields.contains_key("id") {
+            return Err("Static field `id` is reserved".to_string());
+        }
+        if let Some(chunking) = self.chunking {
+            if chunking.maximum_chunk_size <= CHUNK_HEADER_SIZE {
+                return Err(format!(
+                    "Maximum chunk size must exceed the {CHUNK_HEADER_SIZE} byte chunk header"
+                ));
+            }
+            let data_size = usize::from(chunking.maximum_chunk_size - CHUNK_HEADER_SIZE);
+            let maximum_message_size = self.maximum_message_size();
+            if maximum_message_size > MAXIMUM_CHUNKS * data_size {
+                return Err(format!(
+                    "Maximum chunk size {size} is too small, messages of up to \
+                     {maximum_message_size} bytes need at most {MAXIMUM_CHUNKS} chunks",
+                    size = chunking.maximum_chunk_size
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// An upper bound on the size of a message, compressed or not.
+    fn maximum_message_size(&self) -> usize {
+        // Static fields are escaped, at worst six bytes per byte, and quoted.
+        let static_fields: usize = self
+            .static_fields
+            .iter()
+            .map(|(k, v)| 6 * (k.len() + v.len()) + 8)
+            .sum();
+        let json = MAXIMUM_STANDARD_FIELDS_SIZE
+            + usize::from(self.additional_fields.end()) * MAXIMUM_ADDITIONAL_FIELD_SIZE
+            + static_fields;
+        // Deflate grows incompressible input by a few bytes per block, the
+        // gzip and zlib framing by a few more.
+        match self.compression {
+            Compression::None => json,
+            Compression::Gzip | Compression::Zlib => json + json / 1024 + 64,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// GELF payload
+pub struct Gelf {
+    str_pool: strings::Pool,
+    additional_fields: ConfRange<u8>,
+    static_fields: Map<String, Value>,
+    compression: Compression,
+    chunking: Option<Chunking>,
+    /// Datagrams not yet written: the remaining chunks of the current message,
+    /// all carrying its message ID, or a whole message waiting for a block
+    /// large enough to hold it. Serialization is otherwise stateless but a
+    /// chunked message spans several calls, so `to_bytes` takes the next
+    /// datagram from here and only generates a message when it is empty. A
+    /// clone carries the same pending chunks, and so the same message ID.
+    pending: RefCell<VecDeque<Vec<u8>>>,
+}
+
+impl Gelf {
+    /// Construct a new instance of `Gelf`
+    pub fn new<R>(config: &Config, rng: &mut R) -> Self
+    where
+        R: rand::Rng + ?Sized,
+    {
+        let static_fields = config
+            .static_fields
+            .iter()
+            .map(|(k, v)| (format!("_{k}"), Value::from(v.as_str())))
+            .collect();
+        Self {
+            str_pool: strings::Pool::with_size(rng, 1_000_000),
+            additional_fields: config.additional_fields,
+            static_fields,
+            compression: config.compression,
+            chunking: config.chunking,
+            pending: RefCell::new(VecDeque::new()),
+        }
+    }
+
+    fn message<R>(&self, rng: &mut R) -> Result<Vec<u8>, Error>
+    where
+        R: rand::Rng + ?Sized,
+    {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("clock before epoch")
+            .as_millis() as f64
+            / 1000.0;
+
+        let mut message = Map::new();
+        message.insert("version".to_string(), Value::from("1.1"));
+        message.insert(
+            "host".to_string(),
+            Value::from(
+                self.str_pool
+                    .of_size_range(rng, 4_u8..32)
+                    .ok_or(Error::StringGenerate)?,
+            ),
+        );
+        message.insert(
+            "short_message".to_string(),
+            Value::from(
+                self.str_pool
+                    .of_size_range(rng, 16_u16..256)
+                    .ok_or(Error::StringGenerate)?,
+            ),
+        );
+        if rng.gen_bool(0.25) {
+            message.insert(
+                "full_message".to_string(),
+                Value::from(
+                    self.str_pool
+                        .of_size_range(rng, 256_u16..4096)
+                        .ok_or(Error::StringGenerate)?,
+                ),
+            );
+        }
+        message.insert("timestamp".to_string(), Value::from(timestamp));
+        message.insert("level".to_string(), Value::from(rng.gen_range(0_u8..=7)));
+        for _ in 0..self.additional_fields.sample(rng) {
+            let name = self
+                .str_pool
+                .of_size_range(rng, 1_u8..16)
+                .ok_or(Error::StringGenerate)?;
+            let value = if rng.gen() {
+                Value::from(rng.gen::<u32>())
+            } else {
+                Value::from(
+                    self.str_pool
+                        .of_size_range(rng, 1_u8..64)
+                        .ok_or(Error::StringGenerate)?,
+                )
+            };
+            message.insert(format!("_{name}"), value);
+        }
+        for (k, v) in &self.static_fields {
+            message.insert(k.clone(), v.clone());
+        }
+
+        let json = serde_json::to_vec(&Value::Object(message))?;
+        let encoded = match self.compression {
+            Compression::None => json,
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Level::default());
+                encoder.write_all(&json)?;
+                encoder.finish()?
+            }
+            Compression::Zlib => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Level::default());
+                encoder.write_all(&json)?;
+                encoder.finish()?
+            }
+        };
+        Ok(encoded)
+    }
+
+    /// Split `message` into chunks of at most `maximum_chunk_size` bytes,
+    /// header included. Returns `None` if more than the permitted number of
+    /// chunks would be needed, which [`Config::valid`] rules out.
+    fn chunk<R>(rng: &mut R, message: &[u8], maximum_chunk_size: u16) -> Option<Vec<Vec<u8>>>
+    where
+        R: rand::Rng + ?Sized,
+    {
+        let data_size = usize::from(maximum_chunk_size - CHUNK_HEADER_SIZE);
+        let count = message.len().div_ceil(data_size);
+        if count > MAXIMUM_CHUNKS {
+            return None;
+        }
+        let id: [u8; 8] = rng.gen();
+        let chunks = message
+            .chunks(data_size)
+            .enumerate()
+            .map(|(seq, data)| {
+                let mut chunk = Vec::with_capacity(usize::from(CHUNK_HEADER_SIZE) + data.len());
+                chunk.extend_from_slice(&CHUNK_MAGIC);
+                chunk.extend_from_slice(&id);
+                chunk.push(u8::try_from(seq).expect("sequence bounded by MAXIMUM_CHUNKS"));
+                chunk.push(u8::try_from(count).expect("count bounded by MAXIMUM_CHUNKS"));
+                chunk.extend_from_slice(data);
+                chunk
+            })
+            .collect();
+        Some(chunks)
+    }
+}
+
+impl crate::Serialize for Gelf {
+    fn to_bytes<W, R>(&self, mut rng: R, max_bytes: usize, writer: &mut W) -> Result<(), Error>
+    where
+        R: Rng + Sized,
+        W: Write,
+    {
+        let mut pending = self.pending.borrow_mut();
+        if pending.is_empty() {
+            let message = self.message(&mut rng)?;
+            match self.chunking {
+                Some(chunking) if message.len() > usize::from(chunking.maximum_chunk_size) => {
+                    let chunks = Self::chunk(&mut rng, &message, chunking.maximum_chunk_size)
+                        .ok_or(Error::Serialize)?;
+                    pending.extend(chunks);
+                }
+                _ => pending.push_back(message),
+            }
+        }
+
+        // A datagram that does not fit is held for a later, larger block. An
+        // unchunked message that will never fit is dropped.
+        if let Some(datagram) = pending.front() {
+            if datagram.len() <= max_bytes {
+                writer.write_all(datagram)?;
+                pending.pop_front();
+            } else if self.chunking.is_none() {
+                pending.pop_front();
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+    use proptest::prelude::*;
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use super::{Chunking, Compression, Config, Gelf, CHUNK_HEADER_SIZE};
+    use crate::{dogstatsd::ConfRange, Serialize};
+
+    // We want to be sure that the serialized size of the payload does not
+    // exceed `max_bytes`.
+    proptest! {
+        #[test]
+        fn payload_not_exceed_max_bytes(seed: u64, max_bytes: u16, compression: Compression) {
+            let max_bytes = max_bytes as usize;
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let config = Config { compression, ..Config::default() };
+            let gelf = Gelf::new(&config, &mut rng);
+
+            let mut bytes = Vec::with_capacity(max_bytes);
+            gelf.to_bytes(rng, max_bytes, &mut bytes).expect("failed to convert to bytes");
+            prop_assert!(bytes.len() <= max_bytes);
+        }
+    }
+
+    // Chunks written by consecutive calls must reassemble into the original
+    // message.
+    proptest! {
+        #[test]
+        fn chunks_reassemble(seed: u64, maximum_chunk_size in 64_u16..512) {
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let config = Config {
+                compression: Compression::Gzip,
+                chunking: Some(Chunking { maximum_chunk_size }),
+                static_fields: [("service".to_string(), "lading".to_string())].into(),
+                ..Config::default()
+            };
+            let gelf = Gelf::new(&config, &mut rng);
+
+            let mut datagrams = Vec::new();
+            loop {
+                let mut datagram = Vec::new();
+                gelf.to_bytes(&mut rng, usize::from(maximum_chunk_size), &mut datagram)
+                    .expect("failed to convert to bytes");
+                prop_assert!(!datagram.is_empty());
+                prop_assert!(datagram.len() <= usize::from(maximum_chunk_size));
+                let done = datagram[..2] != [0x1e, 0x0f] || datagram[10] + 1 == datagram[11];
+                datagrams.push(datagram);
+                if done {
+                    break;
+                }
+            }
+
+            let compressed: Vec<u8> = if datagrams.len() == 1 && datagrams[0][..2] != [0x1e, 0x0f] {
+                datagrams.remove(0)
+            } else {
+                prop_assert_eq!(usize::from(datagrams[0][11]), datagrams.len());
+                datagrams
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(seq, d)| {
+                        assert_eq!(usize::from(d[10]), seq);
+                        assert_eq!(d[2..10], datagrams[0][2..10]);
+                        d[usize::from(CHUNK_HEADER_SIZE)..].to_vec()
+                    })
+                    .collect()
+            };
+            let mut json = String::new();
+            GzDecoder::new(&compressed[..]).read_to_string(&mut json).expect("not gzip");
+            let value: serde_json::Value = serde_json::from_str(&json).expect("not json");
+            prop_assert_eq!(&value["version"], "1.1");
+            prop_assert_eq!(&value["_service"], "lading");
+        }
+    }
+
+    // Messages must not exceed the bound that chunk sizes are validated
+    // against, lest one need more chunks than GELF permits.
+    proptest! {
+        #[test]
+        fn messages_within_maximum_size(seed: u64, max_fields in 1_u8..64, compression: Compression) {
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let config = Config {
+                additional_fields: ConfRange::Inclusive { min: 0, max: max_fields },
+                static_fields: [("service".to_string(), "\"lading\"\n".to_string())].into(),
+                compression,
+                ..Config::default()
+            };
+            let gelf = Gelf::new(&config, &mut rng);
+            for _ in 0..16 {
+                let message = gelf.message(&mut rng).expect("failed to generate message");
+                prop_assert!(message.len() <= config.maximum_message_size());
+            }
+        }
+    }
+
+    #[test]
+    fn small_chunks_are_invalid() {
+        let config = |maximum_chunk_size| Config {
+            chunking: Some(Chunking { maximum_chunk_size }),
+            ..Config::default()
+        };
+        assert!(config(super::default_maximum_chunk_size()).valid().is_ok());
+        assert!(config(48).valid().is_err());
+        assert!(config(CHUNK_HEADER_SIZE).valid().is_err());
+    }
+}
@@ -1,10 +1,21 @@
 //! JSON payload.
+//!
+//! By default each line is a [`Member`], an object of fixed shape. With a
+//! [`Structure`] each line is instead an object whose nesting, keys, arrays
+//! and values are drawn from the configured distributions.
 
 use std::io::Write;
 
-use rand::{distributions::Standard, prelude::Distribution, seq::SliceRandom, Rng};
+use rand::{
+    distributions::{Standard, WeightedIndex},
+    prelude::Distribution,
+    seq::SliceRandom,
+    Rng,
+};
+use serde::{Deserialize, Serialize as SerdeSerialize};
+use serde_json::{Map, Value};
 
-use crate::Error;
+use crate::{common::strings, dogstatsd::ConfRange, text, Error};
 
 use super::Generator;
 
@@ -21,6 +32,10 @@ pub struct Member {
     pub(crate) seed: u16,
     /// A variable length array of bytes. Its name has no meaning.
     pub(crate) byte_parade: Vec<u8>,
+    /// A message of compressible text, if configured. The message then
+    /// takes the place of the byte parade as the bulk of the member.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) message: Option<String>,
 }
 
 impl Distribution<Member> for Standard {
@@ -35,14 +50,357 @@ impl Distribution<Member> for Standard {
             name: rng.gen(),
             seed: rng.gen(),
             byte_parade: rng.sample_iter(Standard).take(*max).collect(),
+            message: None,
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, Default)]
-#[cfg_attr(test, derive(proptest_derive::Arbitrary))]
+/// Weights for the types of JSON values
+///
+/// Defines the relative probability of each type of value in an object or
+/// array. Objects and arrays are not chosen beyond the maximum depth.
+#[derive(Debug, Deserialize, SerdeSerialize, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct ValueWeights {
+    string: u8,
+    number: u8,
+    boolean: u8,
+    null: u8,
+    object: u8,
+    array: u8,
+}
+
+impl ValueWeights {
+    /// Create a new instance of `ValueWeights` according to the args
+    #[must_use]
+    pub fn new(string: u8, number: u8, boolean: u8, null: u8, object: u8, array: u8) -> Self {
+        Self {
+            string,
+            number,
+            boolean,
+            null,
+            object,
+            array,
+        }
+    }
+}
+
+impl Default for ValueWeights {
+    fn default() -> Self {
+        ValueWeights {
+            string: 40,  // 40%
+            number: 30,  // 30%
+            boolean: 10, // 10%
+            null: 5,     // 5%
+            object: 10,  // 10%
+            array: 5,    // 5%
+        }
+    }
+}
+
+/// Configure the structure of JSON documents.
+#[derive(Debug, Deserialize, SerdeSerialize, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields, default)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct Structure {
+    /// The maximum nesting depth of each document, the top-level object being
+    /// depth 1
+    pub depth: ConfRange<u8>,
+    /// The number of keys in each object, fewer if keys repeat
+    pub keys_per_object: ConfRange<u8>,
+    /// The number of values in each array
+    pub array_length: ConfRange<u8>,
+    /// Defines the relative probability of each type of value
+    pub value_weights: ValueWeights,
+    /// The number of distinct keys across all documents
+    pub key_cardinality: u16,
+    /// Length of each key
+    pub key_length: ConfRange<u8>,
+    /// Length of each string value
+    pub string_length: ConfRange<u16>,
+    /// Length of the `message` string in each top-level object, compressible
+    /// text if `text` is configured
+    pub message_length: ConfRange<u16>,
+}
+
+impl Default for Structure {
+    fn default() -> Self {
+        Self {
+            depth: ConfRange::Inclusive { min: 1, max: 4 },
+            keys_per_object: ConfRange::Inclusive { min: 1, max: 16 },
+            array_length: ConfRange::Inclusive { min: 0, max: 8 },
+            value_weights: ValueWeights::default(),
+            key_cardinality: 256,
+            key_length: ConfRange::Inclusive { min: 1, max: 32 },
+            string_length: ConfRange::Inclusive { min: 0, max: 64 },
+            message_length: ConfRange::Inclusive { min: 16, max: 512 },
+        }
+    }
+}
+
+impl Structure {
+    /// Determine whether the passed configuration obeys validation criteria
+    ///
+    /// # Errors
+    ///
+    /// Function will error if a range is invalid, if documents could have no
+    /// depth, keys could be empty or there are none or if only objects and
+    /// arrays are weighted.
+    pub fn valid(&self) -> Result<(), String> {
+        for (name, (valid, reason)) in [
+            ("Depth", self.depth.valid()),
+            ("Keys per object", self.keys_per_object.valid()),
+            ("Array length", self.array_length.valid()),
+            ("Key length", self.key_length.valid()),
+            ("String length", self.string_length.valid()),
+            ("Message length", self.message_length.valid()),
+        ] {
+            if !valid {
+                return Err(format!("{name} value is invalid: {reason}"));
+            }
+        }
+        if self.depth.start() == 0 {
+            return Err("Depth start value cannot be 0".to_string());
+        }
+        if self.key_length.start() == 0 {
+            return Err("Key length start value cannot be 0".to_string());
+        }
+        if self.key_cardinality == 0 {
+            return Err("Key cardinality cannot be 0".to_string());
+        }
+        let weights = self.value_weights;
+        if [
+            weights.string,
+            weights.number,
+            weights.boolean,
+            weights.null,
+        ]
+        .iter()
+        .all(|w| *w == 0)
+        {
+            return Err("Scalar value weights must not all be zero".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Configuration for [`Json`]
+#[derive(Debug, Default, Deserialize, SerdeSerialize, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields, default)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct Config {
+    /// Generate compressible text as the `message` of each document or, in
+    /// place of the byte parade, as the bulk of each member
+    pub text: Option<text::Config>,
+    /// Generate documents of the configured structure rather than members,
+    /// default off
+    pub structure: Option<Structure>,
+}
+
+impl Config {
+    /// Determine whether the passed configuration obeys validation criteria
+    ///
+    /// # Errors
+    ///
+    /// Function will error if the text or structure configuration is invalid.
+    pub fn valid(&self) -> Result<(), String> {
+        if let Some(text) = self.text {
+            text.valid()?;
+        }
+        if let Some(structure) = self.structure {
+            structure.valid()?;
+        }
+        Ok(())
+    }
+}
+
+/// The kinds of JSON values, in the order of [`ValueWeights`].
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    String,
+    Number,
+    Boolean,
+    Null,
+    Object,
+    Array,
+}
+
+const KINDS: [Kind; 6] = [
+    Kind::String,
+    Kind::Number,
+    Kind::Boolean,
+    Kind::Null,
+    Kind::Object,
+    Kind::Array,
+];
+
+/// Generator of documents per a [`Structure`]
+#[derive(Debug, Clone)]
+struct Documents {
+    config: Structure,
+    str_pool: strings::Pool,
+    keys: Vec<String>,
+    /// Choice of any kind of value.
+    kinds: WeightedIndex<u8>,
+    /// Choice of scalar values, at the maximum depth.
+    scalars: WeightedIndex<u8>,
+}
+
+impl Documents {
+    fn new<R>(config: Structure, rng: &mut R) -> Result<Self, Error>
+    where
+        R: rand::Rng + ?Sized,
+    {
+        let longest = config
+            .string_length
+            .end()
+            .max(config.message_length.end())
+            .max(u16::from(config.key_length.end()));
+        let str_pool = strings::Pool::with_size(rng, usize::from(longest) * 4 + 1);
+        let keys = (0..config.key_cardinality)
+            .map(|_| {
+                let length = usize::from(config.key_length.sample(rng));
+                str_pool
+                    .of_size(rng, length)
+                    .map(String::from)
+                    .ok_or(Error::StringGenerate)
+            })
+            .collect::<Result<_, _>>()?;
+
+        let weights = config.value_weights;
+        let scalar_weights = [
+            weights.string,
+            weights.number,
+            weights.boolean,
+            weights.null,
+        ];
+        Ok(Self {
+            config,
+            str_pool,
+            keys,
+            kinds: WeightedIndex::new(
+                scalar_weights
+                    .into_iter()
+                    .chain([weights.object, weights.array]),
+            )?,
+            scalars: WeightedIndex::new(scalar_weights)?,
+        })
+    }
+
+    fn document<R>(&self, rng: &mut R, text: Option<&text::Text>) -> Result<Value, Error>
+    where
+        R: rand::Rng + ?Sized,
+    {
+        let depth = self.config.depth.sample(rng);
+        let mut document = self.object(rng, 1, depth)?;
+
+        let length = usize::from(self.config.message_length.sample(rng));
+        let message = match text {
+            Some(text) => {
+                let mut message = String::with_capacity(length);
+                text.fill(rng, length, &mut message);
+                message
+            }
+            None => self.string(rng, length)?,
+        };
+        document.insert("message".to_string(), Value::String(message));
+        Ok(Value::Object(document))
+    }
+
+    fn object<R>(
+        &self,
+        rng: &mut R,
+        depth: u8,
+        maximum_depth: u8,
+    ) -> Result<Map<String, Value>, Error>
+    where
+        R: rand::Rng + ?Sized,
+    {
+        let mut object = Map::new();
+        for _ in 0..self.config.keys_per_object.sample(rng) {
+            let key = self.keys.choose(rng).expect("keys are not empty").clone();
+            object.insert(key, self.value(rng, depth, maximum_depth)?);
+        }
+        Ok(object)
+    }
+
+    /// Generate a value held by a container at `depth`.
+    fn value<R>(&self, rng: &mut R, depth: u8, maximum_depth: u8) -> Result<Value, Error>
+    where
+        R: rand::Rng + ?Sized,
+    {
+        let kind = if depth < maximum_depth {
+            KINDS[self.kinds.sample(rng)]
+        } else {
+            KINDS[self.scalars.sample(rng)]
+        };
+        let value = match kind {
+            Kind::String => {
+                let length = usize::from(self.config.string_length.sample(rng));
+                Value::String(self.string(rng, length)?)
+            }
+            Kind::Number => {
+                if rng.gen() {
+                    Value::from(rng.gen::<i64>())
+                } else {
+                    Value::from(rng.gen::<f64>())
+                }
+            }
+            Kind::Boolean => Value::Bool(rng.gen()),
+            Kind::Null => Value::Null,
+            Kind::Object => Value::Object(self.object(rng, depth + 1, maximum_depth)?),
+            Kind::Array => {
+                let length = self.config.array_length.sample(rng);
+                Value::Array(
+                    (0..length)
+                        .map(|_| self.value(rng, depth + 1, maximum_depth))
+                        .collect::<Result<_, _>>()?,
+                )
+            }
+        };
+        Ok(value)
+    }
+
+    fn string<R>(&self, rng: &mut R, length: usize) -> Result<String, Error>
+    where
+        R: rand::Rng + ?Sized,
+    {
+        self.str_pool
+            .of_size(rng, length)
+            .map(String::from)
+            .ok_or(Error::StringGenerate)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
 /// A JSON payload
-pub struct Json;
+pub struct Json {
+    text: Option<text::Text>,
+    documents: Option<Documents>,
+}
+
+impl Json {
+    /// Construct a new instance of `Json` per `config`
+    ///
+    /// # Errors
+    ///
+    /// Function will error if the value weights in `config` are invalid.
+    pub fn new<R>(config: &Config, rng: &mut R) -> Result<Self, Error>
+    where
+        R: rand::Rng + ?Sized,
+    {
+        Ok(Self {
+            text: config.text.map(|text| text::Text::new(text, rng)),
+            documents: config
+                .structure
+                .map(|structure| Documents::new(structure, rng))
+                .transpose()?,
+        })
+    }
+}
 
 impl<'a> Generator<'a> for Json {
     type Output = Member;
@@ -52,7 +410,17 @@ impl<'a> Generator<'a> for Json {
     where
         R: rand::Rng + ?Sized,
     {
-        Ok(rng.gen())
+        let mut member: Member = rng.gen();
+        if let Some(text) = &self.text {
+            // The random byte parade would dominate the member and hold it far
+            // below the configured compression ratio, text replaces it. A byte
+            // serializes to about four characters.
+            let mut message = String::new();
+            text.fill(rng, 4 * member.byte_parade.len().max(16), &mut message);
+            member.byte_parade.clear();
+            member.message = Some(message);
+        }
+        Ok(member)
     }
 }
 
@@ -65,8 +433,12 @@ impl crate::Serialize for Json {
         let mut bytes_remaining = max_bytes;
 
         loop {
-            let member = self.generate(&mut rng);
-            let encoding = serde_json::to_string(&member?)?;
+            let encoding = match &self.documents {
+                Some(documents) => {
+                    serde_json::to_string(&documents.document(&mut rng, self.text.as_ref())?)?
+                }
+                None => serde_json::to_string(&self.generate(&mut rng)?)?,
+            };
             let line_length = encoding.len() + 1; // add one for the newline
 
             match bytes_remaining.checked_sub(line_length) {
@@ -86,8 +458,8 @@ mod test {
     use proptest::prelude::*;
     use rand::{rngs::SmallRng, SeedableRng};
 
-    use super::Member;
-    use crate::{Json, Serialize};
+    use super::{Config, Member, Structure};
+    use crate::{dogstatsd::ConfRange, Json, Serialize};
 
     // We want to be sure that the serialized size of the payload does not
     // exceed `max_bytes`.
@@ -96,7 +468,7 @@ mod test {
         fn payload_not_exceed_max_bytes(seed: u64, max_bytes: u16) {
             let max_bytes = max_bytes as usize;
             let rng = SmallRng::seed_from_u64(seed);
-            let json = Json;
+            let json = Json::default();
 
             let mut bytes = Vec::with_capacity(max_bytes);
             json.to_bytes(rng, max_bytes, &mut bytes).expect("failed to convert to bytes");
@@ -111,7 +483,7 @@ mod test {
         fn every_payload_deserializes(seed: u64, max_bytes: u16) {
             let max_bytes = max_bytes as usize;
             let rng = SmallRng::seed_from_u64(seed);
-            let json = Json;
+            let json = Json::default();
 
             let mut bytes: Vec<u8> = Vec::with_capacity(max_bytes);
             json.to_bytes(rng, max_bytes, &mut bytes).expect("failed to convert to bytes");
@@ -122,4 +494,78 @@ mod test {
             }
         }
     }
+
+    fn depth(value: &serde_json::Value) -> u8 {
+        match value {
+            serde_json::Value::Object(map) => 1 + map.values().map(depth).max().unwrap_or(0),
+            serde_json::Value::Array(values) => 1 + values.iter().map(depth).max().unwrap_or(0),
+            _ => 0,
+        }
+    }
+
+    // Every structured document deserializes and stays within the configured
+    // depth, keys and message length.
+    proptest! {
+        #[test]
+        fn structured_documents_obey_config(seed: u64, max_bytes: u16, max_depth in 1_u8..6, key_cardinality in 1_u16..64) {
+            let max_bytes = max_bytes as usize;
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let structure = Structure {
+                depth: ConfRange::Inclusive { min: 1, max: max_depth },
+                keys_per_object: ConfRange::Inclusive { min: 0, max: 8 },
+                array_length: ConfRange::Inclusive { min: 0, max: 4 },
+                key_cardinality,
+                message_length: ConfRange::Constant(32),
+                ..Structure::default()
+            };
+            let config = Config { text: None, structure: Some(structure) };
+            let json = Json::new(&config, &mut rng).expect("failed to create Json");
+
+            let mut bytes: Vec<u8> = Vec::with_capacity(max_bytes);
+            json.to_bytes(rng, max_bytes, &mut bytes).expect("failed to convert to bytes");
+            prop_assert!(bytes.len() <= max_bytes);
+
+            let payload = std::str::from_utf8(&bytes).expect("failed to convert from utf-8 to str");
+            let mut keys = std::collections::HashSet::new();
+            for msg in payload.lines() {
+                let document: serde_json::Value = serde_json::from_str(msg).expect("failed to deserialize from str");
+                prop_assert!(depth(&document) <= max_depth);
+                let object = document.as_object().expect("document is an object");
+                prop_assert_eq!(object["message"].as_str().map(str::len), Some(32));
+                keys.extend(object.keys().filter(|key| *key != "message").cloned());
+            }
+            prop_assert!(keys.len() <= usize::from(key_cardinality));
+        }
+    }
+
+    // Members with text compress at about the configured ratio, the text
+    // being the bulk of each.
+    #[test]
+    fn text_members_compress_at_target_ratio() {
+        use std::io::Write;
+
+        use flate2::{write::DeflateEncoder, Compression};
+
+        let mut rng = SmallRng::seed_from_u64(48);
+        for compression_ratio in [4.0, 8.0] {
+            let config = Config {
+                text: Some(crate::text::Config { compression_ratio }),
+                structure: None,
+            };
+            let json = Json::new(&config, &mut rng).expect("failed to create Json");
+            let mut bytes = Vec::new();
+            json.to_bytes(&mut rng, 256 * 1024, &mut bytes)
+                .expect("failed to convert to bytes");
+
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&bytes).expect("failed to compress");
+            let compressed = encoder.finish().expect("failed to compress");
+            let ratio = bytes.len() as f64 / compressed.len() as f64;
+            let error = (ratio - f64::from(compression_ratio)).abs() / f64::from(compression_ratio);
+            assert!(
+                error < 0.25,
+                "target {compression_ratio}, compressed at {ratio}"
+            );
+        }
+    }
 }
@@ -21,36 +21,52 @@
 #![allow(clippy::multiple_crate_versions)]
 
 use std::{
+    fmt,
     io::{self, Write},
+    iter,
     path::PathBuf,
 };
 
 use rand::{distributions::WeightedError, Rng};
-use serde::{Deserialize, Serialize as SerdeSerialize};
+use serde::{
+    de::{
+        self,
+        value::{EnumAccessDeserializer, MapAccessDeserializer, MapDeserializer},
+        EnumAccess, IntoDeserializer, MapAccess, Visitor,
+    },
+    Deserialize, Deserializer, Serialize as SerdeSerialize, Serializer,
+};
 
 pub mod block;
 
+pub use adversarial::Adversarial;
 pub use apache_common::ApacheCommon;
 pub use ascii::Ascii;
 pub use datadog_logs::DatadogLog;
 pub use dogstatsd::DogStatsD;
 pub use fluent::Fluent;
+pub use gelf::Gelf;
 pub use json::Json;
+pub use mixed::Mixed;
 pub use opentelemetry_log::OpentelemetryLogs;
 pub use opentelemetry_metric::OpentelemetryMetrics;
 pub use opentelemetry_trace::OpentelemetryTraces;
 pub use splunk_hec::SplunkHec;
 pub use statik::Static;
-pub use syslog::Syslog5424;
+pub use syslog::{Syslog, Syslog5424};
 pub use trace_agent::TraceAgent;
 
+pub mod adversarial;
 pub mod apache_common;
 pub mod ascii;
 pub(crate) mod common;
+pub mod conformance;
 pub mod datadog_logs;
 pub mod dogstatsd;
 pub mod fluent;
+pub mod gelf;
 pub mod json;
+pub mod mixed;
 pub mod opentelemetry_log;
 pub mod opentelemetry_metric;
 pub mod opentelemetry_trace;
@@ -58,7 +74,9 @@ pub mod procfs;
 pub mod splunk_hec;
 pub mod statik;
 pub mod syslog;
+pub mod text;
 pub mod trace_agent;
+pub mod trace_topology;
 
 /// Errors related to serialization
 #[derive(thiserror::Error, Debug)]
@@ -115,18 +133,26 @@ pub enum Encoding {
 #[serde(rename_all = "snake_case")]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[serde(deny_unknown_fields)]
+// The derived implementations are wrapped by those below, which also accept
+// a variant by its bare name.
+#[serde(remote = "Self")]
 pub enum Config {
     /// Generates Fluent messages
     Fluent,
     /// Generates syslog5424 messages
     Syslog5424,
+    /// Generates syslog messages in a configurable format and framing
+    Syslog(crate::syslog::Config),
+    /// Generates Graylog Extended Log Format messages, one per block
+    Gelf(crate::gelf::Config),
     /// Generates Splunk HEC messages
     SplunkHec {
         /// Defines the encoding to use for the Splunk HEC messages.
         encoding: splunk_hec::Encoding,
     },
     /// Generates Datadog Logs JSON messages
-    DatadogLog,
+    #[serde(deserialize_with = "default_if_unit")]
+    DatadogLog(crate::datadog_logs::Config),
     /// Generates a static, user supplied data
     Static {
         /// Defines the file path to read static variant data from. Content is
@@ -134,26 +160,157 @@ pub enum Config {
         static_path: PathBuf,
     },
     /// Generates a line of printable ascii characters
-    Ascii,
+    #[serde(deserialize_with = "default_if_unit")]
+    Ascii(crate::ascii::Config),
     /// Generates a json encoded line
-    Json,
+    #[serde(deserialize_with = "default_if_unit")]
+    Json(crate::json::Config),
     /// Generates a Apache Common log lines
     ApacheCommon,
     /// Generates OpenTelemetry traces
-    OpentelemetryTraces,
+    #[serde(deserialize_with = "default_if_unit")]
+    OpentelemetryTraces(crate::opentelemetry_trace::Config),
     /// Generates OpenTelemetry logs
     OpentelemetryLogs,
     /// Generates OpenTelemetry metrics
-    OpentelemetryMetrics,
+    #[serde(deserialize_with = "default_if_unit")]
+    OpentelemetryMetrics(crate::opentelemetry_metric::Config),
     /// Generates `DogStatsD`
     #[serde(rename = "dogstatsd")]
     DogStatsD(crate::dogstatsd::Config),
-    /// Generates `TraceAgent` payloads in JSON format
-    TraceAgent(Encoding),
+    /// Generates `TraceAgent` payloads in JSON or `MsgPack` format
+    #[serde(deserialize_with = "encoding_or_config")]
+    TraceAgent(crate::trace_agent::Config),
+    /// Generates `TraceAgent` client stats payloads in `MsgPack` format
+    TraceAgentStats(crate::trace_agent::stats::Config),
+    /// Interleaves the lines of weighted newline delimited payloads
+    Mixed(crate::mixed::Config),
+    /// Injects defects into the records of another payload
+    Adversarial(crate::adversarial::Config),
+}
+
+impl<'de> Deserialize<'de> for Config {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(ConfigVisitor)
+    }
+}
+
+/// Hands a variant to the derived implementation of [`Config`], be it given
+/// as a single key map, a tagged enum or a bare name.
+struct ConfigVisitor;
+
+impl<'de> Visitor<'de> for ConfigVisitor {
+    type Value = Config;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a payload variant")
+    }
+
+    fn visit_str<E>(self, name: &str) -> Result<Config, E>
+    where
+        E: de::Error,
+    {
+        // A bare name is a variant without content. Variants that once took
+        // no configuration accept this as their default configuration, see
+        // `default_if_unit`.
+        let map = MapDeserializer::new(iter::once((name, ())));
+        Config::deserialize(MapAccessDeserializer::new(map))
+    }
+
+    fn visit_map<A>(self, map: A) -> Result<Config, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        Config::deserialize(MapAccessDeserializer::new(map))
+    }
+
+    fn visit_enum<A>(self, data: A) -> Result<Config, A::Error>
+    where
+        A: EnumAccess<'de>,
+    {
+        Config::deserialize(EnumAccessDeserializer::new(data))
+    }
+}
+
+/// Deserialize the configuration of a variant, or its default configuration
+/// if the variant is given without one.
+fn default_if_unit<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + Default,
+{
+    Option::<T>::deserialize(deserializer).map(Option::unwrap_or_default)
+}
+
+/// Deserialize the configuration of `trace_agent`, or a bare encoding as the
+/// variant once took.
+fn encoding_or_config<'de, D>(deserializer: D) -> Result<trace_agent::Config, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct EncodingOrConfig;
+
+    impl<'de> Visitor<'de> for EncodingOrConfig {
+        type Value = trace_agent::Config;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("an encoding or a trace_agent configuration")
+        }
+
+        fn visit_str<E>(self, encoding: &str) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            Ok(trace_agent::Config {
+                encoding: Encoding::deserialize(encoding.into_deserializer())?,
+                version: trace_agent::Version::default(),
+                topology: None,
+            })
+        }
+
+        fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error>
+        where
+            A: MapAccess<'de>,
+        {
+            trace_agent::Config::deserialize(MapAccessDeserializer::new(map))
+        }
+    }
+
+    deserializer.deserialize_any(EncodingOrConfig)
+}
+
+impl SerdeSerialize for Config {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        Config::serialize(self, serializer)
+    }
+}
+
+impl Config {
+    /// Whether each message of this payload is a line of its own.
+    pub(crate) fn newline_delimited(&self) -> bool {
+        match self {
+            Config::Ascii(_)
+            | Config::Json(_)
+            | Config::ApacheCommon
+            | Config::Syslog5424
+            | Config::SplunkHec { .. }
+            | Config::Static { .. }
+            | Config::Mixed(_) => true,
+            Config::Syslog(config) => config.framing == syslog::Framing::NonTransparent,
+            Config::DogStatsD(config) => !config.length_prefix_framed,
+            _ => false,
+        }
+    }
 }
 
 #[derive(Debug)]
-#[allow(dead_code, clippy::large_enum_variant)]
+#[allow(clippy::large_enum_variant)]
 pub(crate) enum Payload {
     ApacheCommon(ApacheCommon),
     Ascii(Ascii),
@@ -162,12 +319,52 @@ pub(crate) enum Payload {
     Json(Json),
     SplunkHec(splunk_hec::SplunkHec),
     Static(Static),
-    Syslog(Syslog5424),
+    Syslog5424(Syslog5424),
+    Syslog(Syslog),
+    Gelf(Gelf),
     OtelTraces(OpentelemetryTraces),
     OtelLogs(OpentelemetryLogs),
     OtelMetrics(OpentelemetryMetrics),
     DogStatsdD(DogStatsD),
     TraceAgent(TraceAgent),
+    TraceAgentStats(trace_agent::stats::TraceAgentStats),
+    Mixed(Mixed),
+    Adversarial(Adversarial),
+}
+
+impl Payload {
+    /// The name of this payload, for diagnostics.
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Payload::ApacheCommon(_) => "apache-common",
+            Payload::Ascii(_) => "ascii",
+            Payload::DatadogLog(_) => "datadog-log",
+            Payload::Fluent(_) => "fluent",
+            Payload::Json(_) => "json",
+            Payload::SplunkHec(_) => "splunkHec",
+            Payload::Static(_) => "static",
+            Payload::Syslog5424(_) => "syslog5424",
+            Payload::Syslog(_) => "syslog",
+            Payload::Gelf(_) => "gelf",
+            Payload::OtelTraces(_) => "otel-traces",
+            Payload::OtelLogs(_) => "otel-logs",
+            Payload::OtelMetrics(_) => "otel-metrics",
+            Payload::DogStatsdD(_) => "dogstatsd",
+            Payload::TraceAgent(_) => "trace-agent",
+            Payload::TraceAgentStats(_) => "trace-agent-stats",
+            Payload::Mixed(_) => "mixed",
+            Payload::Adversarial(_) => "adversarial",
+        }
+    }
+
+    /// The defects injected by the last call to `to_bytes`, if this payload
+    /// injects defects.
+    pub(crate) fn take_defects(&self) -> Option<adversarial::Counts> {
+        match self {
+            Payload::Adversarial(adversarial) => Some(adversarial.take_counts()),
+            _ => None,
+        }
+    }
 }
 
 impl Serialize for Payload {
@@ -184,12 +381,17 @@ impl Serialize for Payload {
             Payload::Json(ser) => ser.to_bytes(rng, max_bytes, writer),
             Payload::SplunkHec(ser) => ser.to_bytes(rng, max_bytes, writer),
             Payload::Static(ser) => ser.to_bytes(rng, max_bytes, writer),
+            Payload::Syslog5424(ser) => ser.to_bytes(rng, max_bytes, writer),
             Payload::Syslog(ser) => ser.to_bytes(rng, max_bytes, writer),
+            Payload::Gelf(ser) => ser.to_bytes(rng, max_bytes, writer),
             Payload::OtelTraces(ser) => ser.to_bytes(rng, max_bytes, writer),
             Payload::OtelLogs(ser) => ser.to_bytes(rng, max_bytes, writer),
             Payload::OtelMetrics(ser) => ser.to_bytes(rng, max_bytes, writer),
             Payload::DogStatsdD(ser) => ser.to_bytes(rng, max_bytes, writer),
             Payload::TraceAgent(ser) => ser.to_bytes(rng, max_bytes, writer),
+            Payload::TraceAgentStats(ser) => ser.to_bytes(rng, max_bytes, writer),
+            Payload::Mixed(ser) => ser.to_bytes(rng, max_bytes, writer),
+            Payload::Adversarial(ser) => ser.to_bytes(rng, max_bytes, writer),
         }
     }
 }
@@ -203,3 +405,70 @@ pub(crate) trait Generator<'a> {
     where
         R: rand::Rng + ?Sized;
 }
+
+#[cfg(test)]
+mod test {
+    use super::Config;
+
+    #[derive(Debug, PartialEq, serde::Deserialize)]
+    struct Variant {
+        variant: Config,
+    }
+
+    fn assert_same(old: &str, new: &str) {
+        let old: Variant = serde_yaml::from_str(old).expect("failed to parse old spelling");
+        let new: Variant = serde_yaml::from_str(new).expect("failed to parse new spelling");
+        assert_eq!(old, new);
+        let serialized = serde_yaml::to_string(&old.variant).expect("failed to serialize");
+        let reparsed: Config = serde_yaml::from_str(&serialized).expect("failed to reparse");
+        assert_eq!(old.variant, reparsed);
+    }
+
+    // Variants that once took no configuration, or a bare encoding,
+    // deserialize from their old spelling as they do from a configuration
+    // map.
+    #[test]
+    fn bare_variants_deserialize() {
+        assert_same(
+            "variant: opentelemetry_metrics",
+            "variant: { opentelemetry_metrics: {} }",
+        );
+        for bare in ["ascii", "json", "datadog_log"] {
+            assert_same(
+                &format!("variant: {bare}"),
+                &format!("variant: {{ {bare}: {{}} }}"),
+            );
+        }
+        assert_same(
+            "variant: opentelemetry_traces",
+            "variant: { opentelemetry_traces: {} }",
+        );
+        assert_same(
+            "variant: { trace_agent: json }",
+            "variant: { trace_agent: { encoding: json } }",
+        );
+        assert_same(
+            "variant: !trace_agent msgpack",
+            "variant: { trace_agent: { encoding: msg_pack } }",
+        );
+        for encoding in ["msgpack", "msg_pack"] {
+            assert_same(
+                &format!("variant:\n  trace_agent: {encoding}"),
+                "variant: { trace_agent: { encoding: msg_pack } }",
+            );
+        }
+    }
+
+    // The old spellings are not particular to YAML.
+    #[test]
+    fn bare_variants_deserialize_from_json() {
+        let bare: Config = serde_json::from_str(r#""ascii""#).expect("failed to parse bare ascii");
+        assert_eq!(bare, Config::Ascii(crate::ascii::Config::default()));
+        let encoding: Config =
+            serde_json::from_str(r#"{"trace_agent": "json"}"#).expect("failed to parse encoding");
+        let config: Config = serde_json::from_str(r#"{"trace_agent": {"encoding": "json"}}"#)
+            .expect("failed to parse configuration");
+        assert_eq!(encoding, config);
+        assert!(serde_json::from_str::<Config>(r#""splunk_hec""#).is_err());
+    }
+}
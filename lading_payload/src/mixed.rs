@@ -0,0 +1,236 @@
+//! Mixed payload.
+//!
+//! A [`Mixed`] payload interleaves the messages of several newline delimited
+//! payloads within each block, choosing the payload of each message per its
+//! weight. A host whose log stream holds JSON, Apache and syslog lines is then
+//! one generator rather than several.
+
+use std::{cell::RefCell, io::Write};
+
+use rand::{distributions::WeightedIndex, prelude::Distribution, Rng, RngCore};
+use serde::{Deserialize, Serialize as SerdeSerialize};
+
+use crate::{Error, Serialize};
+
+/// A payload and its relative probability of being chosen
+#[derive(Debug, Deserialize, SerdeSerialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct Weighted {
+    /// The payload
+    pub payload: crate::Config,
+    /// The relative weight of this payload
+    pub weight: u16,
+}
+
+/// Configuration for [`Mixed`]
+#[derive(Debug, Deserialize, SerdeSerialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct Config {
+    /// Weighted payloads, each newline delimited
+    pub payloads: Vec<Weighted>,
+}
+
+impl Config {
+    /// Determine whether the passed configuration obeys validation criteria
+    ///
+    /// # Errors
+    ///
+    /// Function will error if there are no payloads, if their weights are all
+    /// zero or if a payload is not newline delimited.
+    pub fn valid(&self) -> Result<(), String> {
+        if self.payloads.iter().all(|w| w.weight == 0) {
+            return Err("Mixed payloads must not be empty or all zero weight".to_string());
+        }
+        if let Some(weighted) = self
+            .payloads
+            .iter()
+            .find(|w| !w.payload.newline_delimited())
+        {
+            return Err(format!(
+                "Mixed payloads must be newline delimited, {:?} is not",
+                weighted.payload
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Lines generated by a payload but not yet written.
+#[derive(Debug, Default)]
+struct Pending {
+    bytes: Vec<u8>,
+    offset: usize,
+}
+
+impl Pending {
+    /// The next line of `payload`, including its newline, generating more if
+    /// none remain. Lines are generated no longer than `max_bytes`. The line
+    /// stays pending until [`Self::consume`] is called.
+    fn peek_line(
+        &mut self,
+        rng: &mut dyn RngCore,
+        payload: &crate::Payload,
+        max_bytes: usize,
+    ) -> Result<Option<&[u8]>, Error> {
+        if self.offset >= self.bytes.len() {
+            self.bytes.clear();
+            self.offset = 0;
+            payload.to_bytes(rng, max_bytes, &mut self.bytes)?;
+            // A trailing partial line, possible for static payloads, is
+            // discarded.
+            let complete = self
+                .bytes
+                .iter()
+                .rposition(|&b| b == b'\n')
+                .map_or(0, |newline| newline + 1);
+            self.bytes.truncate(complete);
+            if self.bytes.is_empty() {
+                return Ok(None);
+            }
+        }
+        let rest = &self.bytes[self.offset..];
+        let len = rest
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(rest.len(), |newline| newline + 1);
+        Ok(Some(&rest[..len]))
+    }
+
+    /// Remove the `len` byte line last returned by [`Self::peek_line`].
+    fn consume(&mut self, len: usize) {
+        self.offset += len;
+    }
+}
+
+/// A payload interleaving the lines of weighted payloads
+#[derive(Debug)]
+pub struct Mixed {
+    payloads: Vec<crate::Payload>,
+    index: WeightedIndex<u16>,
+    /// The lines of each payload not yet written. Each payload generates a
+    /// block's worth of lines at a time, kept across calls to `to_bytes` so
+    /// that those not written to one block are written to the next.
+    pending: RefCell<Vec<Pending>>,
+}
+
+impl Mixed {
+    /// Create a new instance of [`Mixed`] choosing from `payloads`, which are
+    /// constructed from and in the order of `config.payloads`.
+    ///
+    /// # Errors
+    ///
+    /// Function will error if the weights in `config` are invalid.
+    pub(crate) fn new(config: &Config, payloads: Vec<crate::Payload>) -> Result<Self, Error> {
+        Ok(Self {
+            pending: RefCell::new(payloads.iter().map(|_| Pending::default()).collect()),
+            payloads,
+            index: WeightedIndex::new(config.payloads.iter().map(|w| w.weight))?,
+        })
+    }
+}
+
+impl Serialize for Mixed {
+    fn to_bytes<W, R>(&self, mut rng: R, max_bytes: usize, writer: &mut W) -> Result<(), Error>
+    where
+        R: Rng + Sized,
+        W: Write,
+    {
+        // Payloads are serialized with a trait object so that the rng type
+        // does not grow with each level of payload.
+        let rng: &mut dyn RngCore = &mut rng;
+        let mut pending = self.pending.borrow_mut();
+
+        let mut bytes_remaining = max_bytes;
+        loop {
+            let choice = self.index.sample(rng);
+            let pending = &mut pending[choice];
+            let Some(line) = pending.peek_line(rng, &self.payloads[choice], bytes_remaining)?
+            else {
+                break;
+            };
+            let len = line.len();
+            let Some(remainder) = bytes_remaining.checked_sub(len) else {
+                // The line is left for the next block unless it would not fit
+                // even an empty one.
+                if bytes_remaining == max_bytes {
+                    pending.consume(len);
+                }
+                break;
+            };
+            writer.write_all(line)?;
+            pending.consume(len);
+            bytes_remaining = remainder;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroU32;
+
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use super::{Config, Weighted};
+    use crate::{block::Cache, conformance, syslog};
+
+    // Lines are interleaved at about their configured ratio and each conforms
+    // to one of the mixed payloads.
+    #[test]
+    fn lines_follow_weights() {
+        let config = crate::Config::Mixed(Config {
+            payloads: vec![
+                Weighted {
+                    payload: crate::Config::Json(crate::json::Config::default()),
+                    weight: 3,
+                },
+                Weighted {
+                    payload: crate::Config::ApacheCommon,
+                    weight: 1,
+                },
+            ],
+        });
+        let mut rng = SmallRng::seed_from_u64(47);
+        let Cache::Fixed { blocks, .. } = Cache::fixed(
+            &mut rng,
+            NonZeroU32::new(256 * 1024).expect("non-zero"),
+            8 * 1024,
+            &config,
+        )
+        .expect("failed to generate cache") else {
+            unreachable!("fixed cache constructed");
+        };
+
+        let (mut json, mut apache) = (0_u32, 0_u32);
+        for block in &blocks {
+            conformance::validate(&config, &block.bytes).expect("non-conforming block");
+            for line in block.bytes.split(|&b| b == b'\n').filter(|l| !l.is_empty()) {
+                if line.starts_with(b"{") {
+                    json += 1;
+                } else {
+                    apache += 1;
+                }
+            }
+        }
+        let ratio = f64::from(json) / f64::from(apache);
+        assert!((2.5..3.5).contains(&ratio), "json:apache ratio {ratio}");
+    }
+
+    #[test]
+    fn framed_payloads_are_invalid() {
+        let config = Config {
+            payloads: vec![Weighted {
+                payload: crate::Config::Syslog(syslog::Config {
+                    framing: syslog::Framing::OctetCounting,
+                    ..syslog::Config::default()
+                }),
+                weight: 1,
+            }],
+        };
+        assert!(config.valid().is_err());
+        assert!(Config { payloads: vec![] }.valid().is_err());
+    }
+}
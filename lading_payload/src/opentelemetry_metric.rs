@@ -6,16 +6,205 @@
 //! This format is valid for OTLP/gRPC and binary OTLP/HTTP messages. The
 //! experimental JSON OTLP/HTTP format can also be supported but is not
 //! currently implemented.
+//!
+//! Metrics are drawn from a fixed set of contexts built when the payload is
+//! constructed. A context is a metric name, type and attribute set, so the
+//! number of contexts bounds the number of unique timeseries a target sees.
 
 use std::io::Write;
 
-use crate::{common::strings, Error};
-use opentelemetry_proto::tonic::metrics::v1;
+use crate::{common::strings, dogstatsd::ConfRange, Error};
+use opentelemetry_proto::tonic::{
+    common::v1::{any_value, AnyValue, KeyValue},
+    metrics::v1,
+};
 use prost::Message;
-use rand::{distributions::Standard, prelude::Distribution, seq::SliceRandom, Rng};
+use rand::{distributions::WeightedIndex, prelude::Distribution, seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize as SerdeSerialize};
 
 use super::Generator;
 
+const MAX_CONTEXTS: u32 = 1_000_000;
+/// Quantiles reported by every summary data point.
+const QUANTILES: [f64; 5] = [0.0, 0.5, 0.9, 0.99, 1.0];
+
+/// Weights for OTLP metric types: gauges, sums, etc
+///
+/// Defines the relative probability of each type of metric among the
+/// generated contexts.
+#[derive(Debug, Deserialize, SerdeSerialize, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct MetricWeights {
+    gauge: u8,
+    sum: u8,
+    histogram: u8,
+    exponential_histogram: u8,
+    summary: u8,
+}
+
+impl MetricWeights {
+    /// Create a new instance of `MetricWeights` according to the args
+    #[must_use]
+    pub fn new(gauge: u8, sum: u8, histogram: u8, exponential_histogram: u8, summary: u8) -> Self {
+        Self {
+            gauge,
+            sum,
+            histogram,
+            exponential_histogram,
+            summary,
+        }
+    }
+}
+
+impl Default for MetricWeights {
+    fn default() -> Self {
+        MetricWeights {
+            gauge: 30,                 // 30%
+            sum: 30,                   // 30%
+            histogram: 20,             // 20%
+            exponential_histogram: 10, // 10%
+            summary: 10,               // 10%
+        }
+    }
+}
+
+/// Weights for the aggregation temporality of sums and histograms
+#[derive(Debug, Deserialize, SerdeSerialize, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct TemporalityWeights {
+    delta: u8,
+    cumulative: u8,
+}
+
+impl TemporalityWeights {
+    /// Create a new instance of `TemporalityWeights` according to the args
+    #[must_use]
+    pub fn new(delta: u8, cumulative: u8) -> Self {
+        Self { delta, cumulative }
+    }
+}
+
+impl Default for TemporalityWeights {
+    fn default() -> Self {
+        TemporalityWeights {
+            delta: 50,      // 50%
+            cumulative: 50, // 50%
+        }
+    }
+}
+
+/// Configure the OpenTelemetry metrics payload.
+#[derive(Debug, Deserialize, SerdeSerialize, Clone, PartialEq, Copy)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[serde(deny_unknown_fields, default)]
+pub struct Config {
+    /// The unique metric contexts to generate. A context is a metric name,
+    /// type and set of attributes.
+    pub contexts: ConfRange<u32>,
+
+    /// Number of attributes attached to each context.
+    pub attributes_per_metric: ConfRange<u8>,
+
+    /// Number of data points in each metric.
+    pub data_points_per_metric: ConfRange<u8>,
+
+    /// Number of metrics in each export request. If not set requests are
+    /// filled up to the maximum block size.
+    pub metrics_per_request: Option<ConfRange<u16>>,
+
+    /// Number of buckets in histogram and exponential histogram data points.
+    pub histogram_buckets: ConfRange<u8>,
+
+    /// Defines the relative probability of each type of metric.
+    pub metric_weights: MetricWeights,
+
+    /// Defines the relative probability of each aggregation temporality for
+    /// sums, histograms and exponential histograms.
+    pub temporality_weights: TemporalityWeights,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            contexts: ConfRange::Inclusive {
+                min: 1_000,
+                max: 5_000,
+            },
+            attributes_per_metric: ConfRange::Inclusive { min: 0, max: 8 },
+            data_points_per_metric: ConfRange::Inclusive { min: 1, max: 32 },
+            metrics_per_request: None,
+            histogram_buckets: ConfRange::Inclusive { min: 1, max: 16 },
+            metric_weights: MetricWeights::default(),
+            temporality_weights: TemporalityWeights::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Determine whether the passed configuration obeys validation criteria
+    /// # Errors
+    /// Function will error if the configuration is invalid
+    pub fn valid(&self) -> Result<(), String> {
+        let (contexts_valid, reason) = self.contexts.valid();
+        if !contexts_valid {
+            return Err(format!("Contexts value is invalid: {reason}"));
+        }
+        if self.contexts.start() == 0 {
+            return Err("Contexts start value cannot be 0".to_string());
+        }
+        if self.contexts.end() > MAX_CONTEXTS {
+            return Err(format!(
+                "Contexts end value is greater than the maximum allowed value of {MAX_CONTEXTS}"
+            ));
+        }
+        let (attributes_valid, reason) = self.attributes_per_metric.valid();
+        if !attributes_valid {
+            return Err(format!("Attributes per metric value is invalid: {reason}"));
+        }
+        let (data_points_valid, reason) = self.data_points_per_metric.valid();
+        if !data_points_valid {
+            return Err(format!("Data points per metric value is invalid: {reason}"));
+        }
+        if self.data_points_per_metric.start() == 0 {
+            return Err("Data points per metric start value cannot be 0".to_string());
+        }
+        if let Some(metrics_per_request) = self.metrics_per_request {
+            let (valid, reason) = metrics_per_request.valid();
+            if !valid {
+                return Err(format!("Metrics per request value is invalid: {reason}"));
+            }
+            if metrics_per_request.start() == 0 {
+                return Err("Metrics per request start value cannot be 0".to_string());
+            }
+        }
+        let (buckets_valid, reason) = self.histogram_buckets.valid();
+        if !buckets_valid {
+            return Err(format!("Histogram buckets value is invalid: {reason}"));
+        }
+        let weights = self.metric_weights;
+        if [
+            weights.gauge,
+            weights.sum,
+            weights.histogram,
+            weights.exponential_histogram,
+            weights.summary,
+        ]
+        .iter()
+        .all(|w| *w == 0)
+        {
+            return Err("Metric weights cannot all be 0".to_string());
+        }
+        if self.temporality_weights.delta == 0 && self.temporality_weights.cumulative == 0 {
+            return Err("Temporality weights cannot all be 0".to_string());
+        }
+        Ok(())
+    }
+}
+
 /// Wrapper to generate arbitrary OpenTelemetry [`ExportMetricsServiceRequests`](opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest)
 struct ExportMetricsServiceRequest(Vec<Metric>);
 
@@ -41,89 +230,261 @@ impl ExportMetricsServiceRequest {
 #[derive(Debug)]
 pub struct Metric(v1::Metric);
 
-struct NumberDataPoint(v1::NumberDataPoint);
-struct Gauge(v1::Gauge);
-struct Sum(v1::Sum);
+/// The type of a context and its fixed properties.
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Gauge,
+    Sum {
+        aggregation_temporality: i32,
+        is_monotonic: bool,
+    },
+    Histogram {
+        aggregation_temporality: i32,
+    },
+    ExponentialHistogram {
+        aggregation_temporality: i32,
+    },
+    Summary,
+}
 
-impl Distribution<NumberDataPoint> for Standard {
-    fn sample<R>(&self, rng: &mut R) -> NumberDataPoint
-    where
-        R: Rng + ?Sized,
-    {
-        let value = match rng.gen_range(0..=1) {
-            0 => v1::number_data_point::Value::AsDouble(rng.gen()),
-            1 => v1::number_data_point::Value::AsInt(rng.gen()),
-            _ => unreachable!(),
-        };
+/// A unique metric name, type and attribute set.
+#[derive(Debug, Clone)]
+struct Context {
+    name: String,
+    description: String,
+    unit: String,
+    kind: Kind,
+    attributes: Vec<KeyValue>,
+}
 
-        NumberDataPoint(v1::NumberDataPoint {
-            attributes: Vec::new(),
-            start_time_unix_nano: rng.gen(),
-            time_unix_nano: rng.gen(),
-            exemplars: Vec::new(),
-            flags: 0,
-            value: Some(value),
-        })
-    }
+#[derive(Debug, Clone)]
+/// OTLP metric payload
+pub struct OpentelemetryMetrics {
+    contexts: Vec<Context>,
+    data_points_per_metric: ConfRange<u8>,
+    metrics_per_request: Option<ConfRange<u16>>,
+    histogram_buckets: ConfRange<u8>,
 }
 
-impl Distribution<Gauge> for Standard {
-    fn sample<R>(&self, rng: &mut R) -> Gauge
+impl OpentelemetryMetrics {
+    /// Create a new default instance of `OpentelemetryMetrics` with reasonable
+    /// settings.
+    ///
+    /// # Errors
+    /// Function will error if the payload could not be created
+    pub fn default<R>(rng: &mut R) -> Result<Self, Error>
     where
-        R: Rng + ?Sized,
+        R: rand::Rng + ?Sized,
     {
-        let total = rng.gen_range(0..64);
-        let data_points = Standard
-            .sample_iter(rng)
-            .map(|ndp: NumberDataPoint| ndp.0)
-            .take(total)
-            .collect();
-        Gauge(v1::Gauge { data_points })
+        Self::new(Config::default(), rng)
     }
-}
 
-impl Distribution<Sum> for Standard {
-    fn sample<R>(&self, rng: &mut R) -> Sum
+    /// Construct a new instance of `OpentelemetryMetrics`
+    ///
+    /// # Errors
+    ///
+    /// Function will error if the configured weights are invalid or strings
+    /// cannot be generated.
+    pub fn new<R>(config: Config, rng: &mut R) -> Result<Self, Error>
     where
-        R: Rng + ?Sized,
+        R: rand::Rng + ?Sized,
     {
-        // 0: Unspecified AggregationTemporality, MUST not be used
-        // 1: Delta
-        // 2: Cumulative
-        let aggregation_temporality = *[1, 2]
-            .choose(rng)
-            .expect("failed to choose aggregation temporality");
-        let is_monotonic = rng.gen();
-        let total = rng.gen_range(0..64);
-        let data_points = Standard
-            .sample_iter(rng)
-            .map(|ndp: NumberDataPoint| ndp.0)
-            .take(total)
-            .collect();
-
-        Sum(v1::Sum {
-            data_points,
-            aggregation_temporality,
-            is_monotonic,
+        let str_pool = strings::Pool::with_size(rng, 1_000_000);
+
+        let weights = config.metric_weights;
+        let kind_idx = WeightedIndex::new([
+            u16::from(weights.gauge),
+            u16::from(weights.sum),
+            u16::from(weights.histogram),
+            u16::from(weights.exponential_histogram),
+            u16::from(weights.summary),
+        ])?;
+        let temporality_idx = WeightedIndex::new([
+            u16::from(config.temporality_weights.delta),
+            u16::from(config.temporality_weights.cumulative),
+        ])?;
+
+        let total_contexts = config.contexts.sample(rng) as usize;
+        let mut contexts = Vec::with_capacity(total_contexts);
+        for _ in 0..total_contexts {
+            let string = |rng: &mut R, range: std::ops::Range<u8>| {
+                str_pool
+                    .of_size_range(rng, range)
+                    .map(String::from)
+                    .ok_or(Error::StringGenerate)
+            };
+
+            let name = string(rng, 1_u8..16)?;
+            let description = string(rng, 1_u8..16)?;
+            let unit = string(rng, 1_u8..16)?;
+
+            // 0: Unspecified AggregationTemporality, MUST not be used
+            // 1: Delta
+            // 2: Cumulative
+            let aggregation_temporality = match temporality_idx.sample(rng) {
+                0 => 1,
+                _ => 2,
+            };
+            let kind = match kind_idx.sample(rng) {
+                0 => Kind::Gauge,
+                1 => Kind::Sum {
+                    aggregation_temporality,
+                    is_monotonic: rng.gen(),
+                },
+                2 => Kind::Histogram {
+                    aggregation_temporality,
+                },
+                3 => Kind::ExponentialHistogram {
+                    aggregation_temporality,
+                },
+                4 => Kind::Summary,
+                _ => unreachable!(),
+            };
+
+            let total_attributes = config.attributes_per_metric.sample(rng);
+            let mut attributes = Vec::with_capacity(total_attributes.into());
+            for _ in 0..total_attributes {
+                let key = string(rng, 1_u8..16)?;
+                let value = string(rng, 1_u8..32)?;
+                attributes.push(KeyValue {
+                    key,
+                    value: Some(AnyValue {
+                        value: Some(any_value::Value::StringValue(value)),
+                    }),
+                });
+            }
+
+            contexts.push(Context {
+                name,
+                description,
+                unit,
+                kind,
+                attributes,
+            });
+        }
+
+        Ok(Self {
+            contexts,
+            data_points_per_metric: config.data_points_per_metric,
+            metrics_per_request: config.metrics_per_request,
+            histogram_buckets: config.histogram_buckets,
         })
     }
 }
 
-#[derive(Debug, Clone)]
-/// OTLP metric payload
-pub struct OpentelemetryMetrics {
-    str_pool: strings::Pool,
+fn number_data_point<R>(rng: &mut R, attributes: &[KeyValue]) -> v1::NumberDataPoint
+where
+    R: Rng + ?Sized,
+{
+    let value = match rng.gen_range(0..=1) {
+        0 => v1::number_data_point::Value::AsDouble(rng.gen()),
+        1 => v1::number_data_point::Value::AsInt(rng.gen()),
+        _ => unreachable!(),
+    };
+
+    v1::NumberDataPoint {
+        attributes: attributes.to_vec(),
+        start_time_unix_nano: rng.gen(),
+        time_unix_nano: rng.gen(),
+        exemplars: Vec::new(),
+        flags: 0,
+        value: Some(value),
+    }
 }
 
-impl OpentelemetryMetrics {
-    /// Construct a new instance of `OpentelemetryMetrics`
-    pub fn new<R>(rng: &mut R) -> Self
-    where
-        R: rand::Rng + ?Sized,
-    {
-        Self {
-            str_pool: strings::Pool::with_size(rng, 1_000_000),
+fn histogram_data_point<R>(
+    rng: &mut R,
+    attributes: &[KeyValue],
+    buckets: u8,
+) -> v1::HistogramDataPoint
+where
+    R: Rng + ?Sized,
+{
+    // Bounds must be strictly increasing and there is always one more bucket
+    // than there are bounds.
+    let mut bound: f64 = rng.gen_range(-1_000.0..1_000.0);
+    let explicit_bounds: Vec<f64> = (0..buckets)
+        .map(|_| {
+            bound += rng.gen_range(0.1..100.0);
+            bound
+        })
+        .collect();
+    let bucket_counts: Vec<u64> = (0..=buckets).map(|_| rng.gen_range(0..1_000)).collect();
+
+    v1::HistogramDataPoint {
+        attributes: attributes.to_vec(),
+        start_time_unix_nano: rng.gen(),
+        time_unix_nano: rng.gen(),
+        count: bucket_counts.iter().sum(),
+        sum: rng.gen(),
+        bucket_counts,
+        explicit_bounds,
+        exemplars: Vec::new(),
+        flags: 0,
+    }
+}
+
+fn exponential_histogram_data_point<R>(
+    rng: &mut R,
+    attributes: &[KeyValue],
+    buckets: u8,
+) -> v1::ExponentialHistogramDataPoint
+where
+    R: Rng + ?Sized,
+{
+    let mut count = 0;
+    let mut buckets = |rng: &mut R| {
+        let bucket_counts: Vec<u64> = (0..buckets).map(|_| rng.gen_range(0..1_000)).collect();
+        count += bucket_counts.iter().sum::<u64>();
+        v1::exponential_histogram_data_point::Buckets {
+            offset: rng.gen_range(-64..64),
+            bucket_counts,
         }
+    };
+    let positive = Some(buckets(rng));
+    let negative = if rng.gen() { Some(buckets(rng)) } else { None };
+    let zero_count = rng.gen_range(0..100);
+
+    v1::ExponentialHistogramDataPoint {
+        attributes: attributes.to_vec(),
+        start_time_unix_nano: rng.gen(),
+        time_unix_nano: rng.gen(),
+        count: count + zero_count,
+        sum: rng.gen(),
+        scale: rng.gen_range(-4..=8),
+        zero_count,
+        positive,
+        negative,
+        flags: 0,
+        exemplars: Vec::new(),
+    }
+}
+
+fn summary_data_point<R>(rng: &mut R, attributes: &[KeyValue]) -> v1::SummaryDataPoint
+where
+    R: Rng + ?Sized,
+{
+    // Quantile values must be non-decreasing as the quantile increases.
+    let mut value: f64 = rng.gen_range(-1_000.0..1_000.0);
+    let quantile_values = QUANTILES
+        .iter()
+        .map(|quantile| {
+            value += rng.gen_range(0.0..100.0);
+            v1::summary_data_point::ValueAtQuantile {
+                quantile: *quantile,
+                value,
+            }
+        })
+        .collect();
+
+    v1::SummaryDataPoint {
+        attributes: attributes.to_vec(),
+        start_time_unix_nano: rng.gen(),
+        time_unix_nano: rng.gen(),
+        count: rng.gen_range(1..10_000),
+        sum: rng.gen(),
+        quantile_values,
+        flags: 0,
     }
 }
 
@@ -131,36 +492,67 @@ impl<'a> Generator<'a> for OpentelemetryMetrics {
     type Output = Metric;
     type Error = Error;
 
-    fn generate<R>(&'a self, mut rng: &mut R) -> Result<Self::Output, Error>
+    fn generate<R>(&'a self, rng: &mut R) -> Result<Self::Output, Error>
     where
         R: rand::Rng + ?Sized,
     {
-        let data = match rng.gen_range(0..=1) {
-            0 => v1::metric::Data::Gauge(rng.gen::<Gauge>().0),
-            1 => v1::metric::Data::Sum(rng.gen::<Sum>().0),
-            // Currently unsupported: Histogram, ExponentialHistogram, Summary
-            _ => unreachable!(),
+        let context = self
+            .contexts
+            .choose(rng)
+            .expect("there is always at least one context");
+        let total = usize::from(self.data_points_per_metric.sample(rng));
+        let attributes = &context.attributes;
+
+        let data = match context.kind {
+            Kind::Gauge => v1::metric::Data::Gauge(v1::Gauge {
+                data_points: (0..total)
+                    .map(|_| number_data_point(rng, attributes))
+                    .collect(),
+            }),
+            Kind::Sum {
+                aggregation_temporality,
+                is_monotonic,
+            } => v1::metric::Data::Sum(v1::Sum {
+                data_points: (0..total)
+                    .map(|_| number_data_point(rng, attributes))
+                    .collect(),
+                aggregation_temporality,
+                is_monotonic,
+            }),
+            Kind::Histogram {
+                aggregation_temporality,
+            } => v1::metric::Data::Histogram(v1::Histogram {
+                data_points: (0..total)
+                    .map(|_| {
+                        let buckets = self.histogram_buckets.sample(rng);
+                        histogram_data_point(rng, attributes, buckets)
+                    })
+                    .collect(),
+                aggregation_temporality,
+            }),
+            Kind::ExponentialHistogram {
+                aggregation_temporality,
+            } => v1::metric::Data::ExponentialHistogram(v1::ExponentialHistogram {
+                data_points: (0..total)
+                    .map(|_| {
+                        let buckets = self.histogram_buckets.sample(rng);
+                        exponential_histogram_data_point(rng, attributes, buckets)
+                    })
+                    .collect(),
+                aggregation_temporality,
+            }),
+            Kind::Summary => v1::metric::Data::Summary(v1::Summary {
+                data_points: (0..total)
+                    .map(|_| summary_data_point(rng, attributes))
+                    .collect(),
+            }),
         };
-        let data = Some(data);
-
-        let name = self
-            .str_pool
-            .of_size_range(&mut rng, 1_u8..16)
-            .ok_or(Error::StringGenerate)?;
-        let description = self
-            .str_pool
-            .of_size_range(&mut rng, 1_u8..16)
-            .ok_or(Error::StringGenerate)?;
-        let unit = self
-            .str_pool
-            .of_size_range(&mut rng, 1_u8..16)
-            .ok_or(Error::StringGenerate)?;
 
         Ok(Metric(v1::Metric {
-            name: String::from(name),
-            description: String::from(description),
-            unit: String::from(unit),
-            data,
+            name: context.name.clone(),
+            description: context.description.clone(),
+            unit: context.unit.clone(),
+            data: Some(data),
         }))
     }
 }
@@ -178,9 +570,12 @@ impl crate::Serialize for OpentelemetryMetrics {
         let Some(mut bytes_remaining) = bytes_remaining else {
             return Ok(());
         };
+        let maximum_metrics = self
+            .metrics_per_request
+            .map_or(usize::MAX, |range| usize::from(range.sample(&mut rng)));
 
         let mut acc = ExportMetricsServiceRequest(Vec::new());
-        loop {
+        while acc.0.len() < maximum_metrics {
             let member: Metric = self.generate(&mut rng)?;
             let len = member.0.encoded_len() + 2;
             match bytes_remaining.checked_sub(len) {
@@ -199,11 +594,13 @@ impl crate::Serialize for OpentelemetryMetrics {
 
 #[cfg(test)]
 mod test {
-    use super::OpentelemetryMetrics;
-    use crate::Serialize;
+    use super::{Config, OpentelemetryMetrics};
+    use crate::{dogstatsd::ConfRange, Serialize};
+    use opentelemetry_proto::tonic::metrics::v1::metric::Data;
     use proptest::prelude::*;
     use prost::Message;
     use rand::{rngs::SmallRng, SeedableRng};
+    use std::collections::HashSet;
 
     // We want to be sure that the serialized size of the payload does not
     // exceed `max_bytes`.
@@ -212,10 +609,10 @@ mod test {
         fn payload_not_exceed_max_bytes(seed: u64, max_bytes: u16) {
             let max_bytes = max_bytes as usize;
             let mut rng = SmallRng::seed_from_u64(seed);
-            let logs = OpentelemetryMetrics::new(&mut rng);
+            let metrics = OpentelemetryMetrics::default(&mut rng).expect("failed to create metrics");
 
             let mut bytes = Vec::with_capacity(max_bytes);
-            logs.to_bytes(rng, max_bytes, &mut bytes).expect("failed to convert to bytes");
+            metrics.to_bytes(rng, max_bytes, &mut bytes).expect("failed to convert to bytes");
             assert!(bytes.len() <= max_bytes, "max len: {max_bytes}, actual: {}", bytes.len());
         }
     }
@@ -226,10 +623,10 @@ mod test {
         fn payload_is_at_least_half_of_max_bytes(seed: u64, max_bytes in 16u16..u16::MAX) {
             let max_bytes = max_bytes as usize;
             let mut rng = SmallRng::seed_from_u64(seed);
-            let logs = OpentelemetryMetrics::new(&mut rng);
+            let metrics = OpentelemetryMetrics::default(&mut rng).expect("failed to create metrics");
 
             let mut bytes = Vec::with_capacity(max_bytes);
-            logs.to_bytes(rng, max_bytes, &mut bytes).expect("failed to convert to bytes");
+            metrics.to_bytes(rng, max_bytes, &mut bytes).expect("failed to convert to bytes");
 
             assert!(!bytes.is_empty());
         }
@@ -242,12 +639,45 @@ mod test {
         fn payload_deserializes(seed: u64, max_bytes: u16)  {
             let max_bytes = max_bytes as usize;
             let mut rng = SmallRng::seed_from_u64(seed);
-            let logs = OpentelemetryMetrics::new(&mut rng);
+            let metrics = OpentelemetryMetrics::default(&mut rng).expect("failed to create metrics");
 
             let mut bytes: Vec<u8> = Vec::with_capacity(max_bytes);
-            logs.to_bytes(rng, max_bytes, &mut bytes).expect("failed to convert to bytes");
+            metrics.to_bytes(rng, max_bytes, &mut bytes).expect("failed to convert to bytes");
 
             opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest::decode(bytes.as_slice()).expect("failed to decode the message from the buffer");
         }
     }
+
+    // We want to know that the configuration is respected: metric types
+    // follow their weights, the number of unique names is bounded by the
+    // contexts and requests hold no more than the configured metrics.
+    proptest! {
+        #[test]
+        fn payload_respects_config(seed: u64, contexts in 1u32..64, per_request in 1u16..32) {
+            let config = Config {
+                contexts: ConfRange::Constant(contexts),
+                metrics_per_request: Some(ConfRange::Constant(per_request)),
+                metric_weights: super::MetricWeights::new(0, 0, 1, 0, 1),
+                ..Config::default()
+            };
+            config.valid().expect("config must be valid");
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let metrics = OpentelemetryMetrics::new(config, &mut rng).expect("failed to create metrics");
+
+            let mut names = HashSet::new();
+            for _ in 0..8 {
+                let mut bytes: Vec<u8> = Vec::new();
+                metrics.to_bytes(&mut rng, 1_000_000, &mut bytes).expect("failed to convert to bytes");
+                let request = opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest::decode(bytes.as_slice()).expect("failed to decode the message from the buffer");
+                let metrics = &request.resource_metrics[0].instrumentation_library_metrics[0].metrics;
+                prop_assert_eq!(metrics.len(), usize::from(per_request));
+                for metric in metrics {
+                    names.insert(metric.name.clone());
+                    let is_histogram_or_summary = matches!(metric.data, Some(Data::Histogram(_) | Data::Summary(_)));
+                    prop_assert!(is_histogram_or_summary);
+                }
+            }
+            prop_assert!(names.len() <= contexts as usize);
+        }
+    }
 }
@@ -5,15 +5,56 @@
 //! This format is valid for OTLP/gRPC and binary OTLP/HTTP messages. The
 //! experimental JSON OTLP/HTTP format can also be supported but is not
 //! currently implemented.
+//!
+//! By default spans are generated independently of one another. If a
+//! [topology](crate::trace_topology) is configured spans are instead generated
+//! as complete traces, grouped into one resource per service.
 
-use crate::{common::strings, Error};
-use opentelemetry_proto::tonic::trace::v1;
+use crate::{
+    common::strings,
+    trace_topology::{self, Topology},
+    Error,
+};
+use opentelemetry_proto::tonic::{
+    common::v1::{any_value, AnyValue, KeyValue},
+    resource::v1::Resource,
+    trace::v1,
+};
 use prost::Message;
 use rand::{distributions::Standard, prelude::Distribution, Rng};
+use rustc_hash::{FxHashMap, FxHashSet};
+use serde::{Deserialize, Serialize as SerdeSerialize};
 use std::io::Write;
 
 use super::Generator;
 
+/// `SPAN_KIND_SERVER`, every topology span is the handling of a call.
+const SPAN_KIND_SERVER: i32 = 2;
+/// `STATUS_CODE_ERROR`
+const STATUS_CODE_ERROR: i32 = 2;
+
+/// Configure the OpenTelemetry traces payload.
+#[derive(Debug, Deserialize, SerdeSerialize, Clone, PartialEq, Copy, Default)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[serde(deny_unknown_fields, default)]
+pub struct Config {
+    /// If set spans are generated as complete traces through a service
+    /// topology, otherwise every span is independent.
+    pub topology: Option<trace_topology::Config>,
+}
+
+impl Config {
+    /// Determine whether the passed configuration obeys validation criteria
+    /// # Errors
+    /// Function will error if the configuration is invalid
+    pub fn valid(&self) -> Result<(), String> {
+        match self.topology {
+            Some(topology) => topology.valid(),
+            None => Ok(()),
+        }
+    }
+}
+
 /// Wrapper to generate arbitrary OpenTelemetry [`ExportTraceServiceRequest`](opentelemetry_proto::tonic::collector::trace::v1::ExportTraceServiceRequest)
 struct ExportTraceServiceRequest(Vec<Span>);
 
@@ -45,20 +86,160 @@ pub struct Span(v1::Span);
 /// OTLP trace payload
 pub struct OpentelemetryTraces {
     str_pool: strings::Pool,
+    topology: Option<Topology>,
 }
 
 impl OpentelemetryTraces {
+    /// Create a new default instance of `OpentelemetryTraces`, generating
+    /// independent spans.
+    ///
+    /// # Errors
+    /// Function will error if the payload could not be created
+    pub fn default<R>(rng: &mut R) -> Result<Self, Error>
+    where
+        R: rand::Rng + ?Sized,
+    {
+        Self::new(&Config::default(), rng)
+    }
+
     /// Construct a new instance of `OpentelemetryTraces`
-    pub fn new<R>(rng: &mut R) -> Self
+    ///
+    /// # Errors
+    ///
+    /// Function will error if the topology could not be built.
+    pub fn new<R>(config: &Config, rng: &mut R) -> Result<Self, Error>
     where
         R: rand::Rng + ?Sized,
     {
-        Self {
+        let topology = match config.topology {
+            Some(topology) => Some(Topology::new(&topology, rng)?),
+            None => None,
+        };
+        Ok(Self {
             str_pool: strings::Pool::with_size(rng, 1_000_000),
+            topology,
+        })
+    }
+
+    /// Fill a request with complete traces from `topology`, one resource per
+    /// service.
+    fn to_bytes_topology<W, R>(
+        topology: &Topology,
+        mut rng: R,
+        max_bytes: usize,
+        writer: &mut W,
+    ) -> Result<(), Error>
+    where
+        R: Rng + Sized,
+        W: Write,
+    {
+        let Some(mut bytes_remaining) = max_bytes.checked_sub(5 + max_bytes.div_ceil(0x7F)) else {
+            return Ok(());
+        };
+
+        let mut traces: Vec<Vec<(&str, v1::Span)>> = Vec::new();
+        let mut services: FxHashSet<&str> = FxHashSet::default();
+        loop {
+            let trace: Vec<(&str, v1::Span)> = topology
+                .trace(&mut rng)
+                .iter()
+                .map(|span| (span.service.name.as_str(), otlp_span(span)))
+                .collect();
+            // Each span is length-delimited and each newly seen service adds a
+            // resource holding its name.
+            let mut len = 0;
+            for (service, span) in &trace {
+                len += span.encoded_len() + 4;
+                if !services.contains(service) {
+                    len += service.len() + 32;
+                }
+            }
+            match bytes_remaining.checked_sub(len) {
+                Some(remainder) => {
+                    for (service, _) in &trace {
+                        services.insert(service);
+                    }
+                    traces.push(trace);
+                    bytes_remaining = remainder;
+                }
+                None => break,
+            }
+        }
+
+        // The estimate above is conservative but we check the real encoding
+        // regardless, dropping traces until the request fits.
+        loop {
+            let buf = encode_traces(&traces);
+            if buf.len() <= max_bytes || traces.is_empty() {
+                writer.write_all(&buf)?;
+                return Ok(());
+            }
+            traces.pop();
         }
     }
 }
 
+/// Convert a topology span into its OTLP representation.
+fn otlp_span(span: &trace_topology::Span<'_>) -> v1::Span {
+    v1::Span {
+        trace_id: span.trace_id.to_be_bytes().to_vec(),
+        span_id: span.span_id.to_be_bytes().to_vec(),
+        trace_state: String::new(),
+        parent_span_id: if span.parent_id == 0 {
+            Vec::new()
+        } else {
+            span.parent_id.to_be_bytes().to_vec()
+        },
+        name: span.operation.name.clone(),
+        kind: SPAN_KIND_SERVER,
+        start_time_unix_nano: span.start,
+        end_time_unix_nano: span.start.saturating_add(span.duration),
+        attributes: Vec::new(),
+        dropped_attributes_count: 0,
+        events: Vec::new(),
+        dropped_events_count: 0,
+        links: Vec::new(),
+        dropped_links_count: 0,
+        status: span.error.then(|| v1::Status {
+            message: String::new(),
+            code: STATUS_CODE_ERROR,
+        }),
+    }
+}
+
+/// Encode `traces` as a request with one resource per service.
+fn encode_traces(traces: &[Vec<(&str, v1::Span)>]) -> Vec<u8> {
+    let mut resource_spans: Vec<v1::ResourceSpans> = Vec::new();
+    let mut index: FxHashMap<&str, usize> = FxHashMap::default();
+    for (service, span) in traces.iter().flatten() {
+        let idx = *index.entry(service).or_insert_with(|| {
+            resource_spans.push(v1::ResourceSpans {
+                resource: Some(Resource {
+                    attributes: vec![KeyValue {
+                        key: String::from("service.name"),
+                        value: Some(AnyValue {
+                            value: Some(any_value::Value::StringValue(String::from(*service))),
+                        }),
+                    }],
+                    dropped_attributes_count: 0,
+                }),
+                instrumentation_library_spans: vec![v1::InstrumentationLibrarySpans {
+                    instrumentation_library: None,
+                    spans: Vec::new(),
+                    schema_url: String::new(),
+                }],
+                schema_url: String::new(),
+            });
+            resource_spans.len() - 1
+        });
+        resource_spans[idx].instrumentation_library_spans[0]
+            .spans
+            .push(span.clone());
+    }
+    opentelemetry_proto::tonic::collector::trace::v1::ExportTraceServiceRequest { resource_spans }
+        .encode_to_vec()
+}
+
 impl<'a> Generator<'a> for OpentelemetryTraces {
     type Output = Span;
     type Error = Error;
@@ -109,6 +290,10 @@ impl crate::Serialize for OpentelemetryTraces {
         R: Rng + Sized,
         W: Write,
     {
+        if let Some(topology) = &self.topology {
+            return Self::to_bytes_topology(topology, rng, max_bytes, writer);
+        }
+
         // An Export*ServiceRequest message has 5 bytes of fixed values plus
         // a varint-encoded message length field. The worst case for the message
         // length field is the max message size divided by 0x7F.
@@ -138,11 +323,12 @@ impl crate::Serialize for OpentelemetryTraces {
 
 #[cfg(test)]
 mod test {
-    use super::OpentelemetryTraces;
-    use crate::Serialize;
+    use super::{Config, OpentelemetryTraces};
+    use crate::{trace_topology, Serialize};
     use proptest::prelude::*;
     use prost::Message;
     use rand::{rngs::SmallRng, SeedableRng};
+    use std::collections::HashSet;
 
     // We want to be sure that the serialized size of the payload does not
     // exceed `max_bytes`.
@@ -151,7 +337,7 @@ mod test {
         fn payload_not_exceed_max_bytes(seed: u64, max_bytes: u16) {
             let max_bytes = max_bytes as usize;
             let mut rng = SmallRng::seed_from_u64(seed);
-            let traces = OpentelemetryTraces::new(&mut rng);
+            let traces = OpentelemetryTraces::default(&mut rng).expect("failed to create traces");
 
             let mut bytes = Vec::with_capacity(max_bytes);
             traces.to_bytes(rng, max_bytes, &mut bytes).expect("failed to convert to bytes");
@@ -165,7 +351,7 @@ mod test {
         fn payload_is_at_least_half_of_max_bytes(seed: u64, max_bytes in 16u16..u16::MAX) {
             let max_bytes = max_bytes as usize;
             let mut rng = SmallRng::seed_from_u64(seed);
-            let traces = OpentelemetryTraces::new(&mut rng);
+            let traces = OpentelemetryTraces::default(&mut rng).expect("failed to create traces");
 
             let mut bytes = Vec::with_capacity(max_bytes);
             traces.to_bytes(rng, max_bytes, &mut bytes).expect("failed to convert to bytes");
@@ -181,7 +367,7 @@ mod test {
         fn payload_deserializes(seed: u64, max_bytes: u16)  {
             let max_bytes = max_bytes as usize;
             let mut rng = SmallRng::seed_from_u64(seed);
-            let traces = OpentelemetryTraces::new(&mut rng);
+            let traces = OpentelemetryTraces::default(&mut rng).expect("failed to create traces");
 
             let mut bytes: Vec<u8> = Vec::with_capacity(max_bytes);
             traces.to_bytes(rng, max_bytes, &mut bytes).expect("failed to convert to bytes");
@@ -189,4 +375,31 @@ mod test {
             opentelemetry_proto::tonic::collector::trace::v1::ExportTraceServiceRequest::decode(bytes.as_slice()).expect("failed to decode the message from the buffer");
         }
     }
+
+    // We want to know that topology payloads respect `max_bytes`, decode and
+    // hold only complete traces.
+    proptest! {
+        #[test]
+        fn topology_payload_holds_complete_traces(seed: u64, max_bytes: u16)  {
+            let max_bytes = max_bytes as usize;
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let config = Config { topology: Some(trace_topology::Config::default()) };
+            let traces = OpentelemetryTraces::new(&config, &mut rng).expect("failed to create traces");
+
+            let mut bytes: Vec<u8> = Vec::with_capacity(max_bytes);
+            traces.to_bytes(rng, max_bytes, &mut bytes).expect("failed to convert to bytes");
+            prop_assert!(bytes.len() <= max_bytes);
+
+            let request = opentelemetry_proto::tonic::collector::trace::v1::ExportTraceServiceRequest::decode(bytes.as_slice()).expect("failed to decode the message from the buffer");
+            let spans: Vec<_> = request
+                .resource_spans
+                .iter()
+                .flat_map(|rs| rs.instrumentation_library_spans.iter().flat_map(|ils| ils.spans.iter()))
+                .collect();
+            let ids: HashSet<&[u8]> = spans.iter().map(|s| s.span_id.as_slice()).collect();
+            for span in &spans {
+                prop_assert!(span.parent_span_id.is_empty() || ids.contains(span.parent_span_id.as_slice()));
+            }
+        }
+    }
 }
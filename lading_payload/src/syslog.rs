@@ -1,12 +1,227 @@
 //! Syslog payload.
+//!
+//! [`Syslog5424`] emits newline delimited RFC 5424 messages. [`Syslog`] is
+//! configurable, emitting RFC 5424 or RFC 3164 (BSD) messages with RFC 6587
+//! octet-counting or non-transparent framing and weighted facilities,
+//! severities and app names.
 
 use std::{io::Write, time::SystemTime};
 
-use rand::{distributions::Standard, prelude::Distribution, seq::SliceRandom, Rng};
+use rand::{
+    distributions::{Standard, WeightedIndex},
+    prelude::Distribution,
+    seq::SliceRandom,
+    Rng,
+};
+use serde::{Deserialize, Serialize as SerdeSerialize};
 use time::{format_description::well_known::Rfc3339, OffsetDateTime};
 
 use crate::Error;
 
+const MAX_FACILITY: u8 = 23;
+const MAX_SEVERITY: u8 = 7;
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// The syslog message format
+#[derive(Debug, Default, Deserialize, SerdeSerialize, Clone, Copy, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(test, derive(proptest_derive::Arbitrary))]
+pub enum Format {
+    /// RFC 5424 messages
+    #[default]
+    Rfc5424,
+    /// RFC 3164, or BSD, messages
+    Rfc3164,
+}
+
+/// The RFC 6587 framing of messages
+#[derive(Debug, Default, Deserialize, SerdeSerialize, Clone, Copy, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(test, derive(proptest_derive::Arbitrary))]
+pub enum Framing {
+    /// Each message is prefixed by its length in bytes and a space
+    OctetCounting,
+    /// Each message is followed by a newline
+    #[default]
+    NonTransparent,
+    /// Each message is followed by a NUL byte
+    NonTransparentNul,
+}
+
+/// A value and its relative probability of being chosen
+#[derive(Debug, Deserialize, SerdeSerialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct Weighted<T> {
+    /// The value
+    pub value: T,
+    /// The relative weight of this value
+    pub weight: u16,
+}
+
+/// Configuration for [`Syslog`]
+#[derive(Debug, Default, Deserialize, SerdeSerialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct Config {
+    /// The message format, default RFC 5424
+    #[serde(default)]
+    pub format: Format,
+    /// The message framing, default newline delimited
+    #[serde(default)]
+    pub framing: Framing,
+    /// Weighted facilities, each within `[0, 23]`. Default uniform over all
+    /// facilities.
+    #[serde(default)]
+    pub facilities: Vec<Weighted<u8>>,
+    /// Weighted severities, each within `[0, 7]`. Default uniform over all
+    /// severities.
+    #[serde(default)]
+    pub severities: Vec<Weighted<u8>>,
+    /// Weighted app names. Default uniform over a small built-in set.
+    #[serde(default)]
+    pub app_names: Vec<Weighted<String>>,
+}
+
+impl Config {
+    /// Determine whether the passed configuration obeys validation criteria
+    ///
+    /// # Errors
+    ///
+    /// Function will error if a facility or severity is out of range, or if
+    /// any weights are all zero.
+    pub fn valid(&self) -> Result<(), String> {
+        if let Some(facility) = self.facilities.iter().find(|f| f.value > MAX_FACILITY) {
+            return Err(format!(
+                "Facility {} exceeds maximum of {MAX_FACILITY}",
+                facility.value
+            ));
+        }
+        if let Some(severity) = self.severities.iter().find(|s| s.value > MAX_SEVERITY) {
+            return Err(format!(
+                "Severity {} exceeds maximum of {MAX_SEVERITY}",
+                severity.value
+            ));
+        }
+        let all_zero = |weights: &mut dyn Iterator<Item = u16>| {
+            let mut weights = weights.peekable();
+            weights.peek().is_some() && weights.all(|w| w == 0)
+        };
+        if all_zero(&mut self.facilities.iter().map(|f| f.weight))
+            || all_zero(&mut self.severities.iter().map(|s| s.weight))
+            || all_zero(&mut self.app_names.iter().map(|a| a.weight))
+        {
+            return Err("Weights must not all be zero".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Choose from `values` per their weights, or uniformly from `default` if no
+/// values are given.
+#[derive(Debug, Clone)]
+struct Choice<T> {
+    values: Vec<T>,
+    index: WeightedIndex<u16>,
+}
+
+impl<T: Clone> Choice<T> {
+    fn new(weighted: &[Weighted<T>], default: impl Iterator<Item = T>) -> Result<Self, Error> {
+        let (values, weights): (Vec<T>, Vec<u16>) = if weighted.is_empty() {
+            default.map(|v| (v, 1)).unzip()
+        } else {
+            weighted.iter().map(|w| (w.value.clone(), w.weight)).unzip()
+        };
+        Ok(Self {
+            values,
+            index: WeightedIndex::new(weights)?,
+        })
+    }
+
+    fn choose<R: Rng + ?Sized>(&self, rng: &mut R) -> &T {
+        &self.values[self.index.sample(rng)]
+    }
+}
+
+/// Configurable syslog payload
+#[derive(Debug, Clone)]
+pub struct Syslog {
+    format: Format,
+    framing: Framing,
+    facilities: Choice<u8>,
+    severities: Choice<u8>,
+    app_names: Choice<String>,
+}
+
+impl Syslog {
+    /// Create a new instance of [`Syslog`]
+    ///
+    /// # Errors
+    ///
+    /// Function will error if the weights in `config` are invalid.
+    pub fn new(config: &Config) -> Result<Self, Error> {
+        Ok(Self {
+            format: config.format,
+            framing: config.framing,
+            facilities: Choice::new(&config.facilities, 0..=MAX_FACILITY)?,
+            severities: Choice::new(&config.severities, 0..=MAX_SEVERITY)?,
+            app_names: Choice::new(&config.app_names, APP_NAMES.iter().map(ToString::to_string))?,
+        })
+    }
+
+    fn message<R: Rng + ?Sized>(&self, rng: &mut R) -> String {
+        let priority =
+            u16::from(*self.facilities.choose(rng)) * 8 + u16::from(*self.severities.choose(rng));
+        let hostname = HOSTNAMES.choose(rng).expect("failed to choose hostname");
+        let app_name = self.app_names.choose(rng);
+        let procid: u16 = rng.gen_range(100..=9999);
+        let message = serde_json::to_string(&rng.gen::<Message>()).expect("failed to serialize");
+        match self.format {
+            Format::Rfc5424 => {
+                let msgid: u16 = rng.gen_range(1..=999);
+                let timestamp = to_rfc3339(SystemTime::now());
+                format!("<{priority}>1 {timestamp} {hostname} {app_name} {procid} ID{msgid} - {message}")
+            }
+            Format::Rfc3164 => {
+                let timestamp = to_rfc3164(SystemTime::now());
+                format!("<{priority}>{timestamp} {hostname} {app_name}[{procid}]: {message}")
+            }
+        }
+    }
+}
+
+impl crate::Serialize for Syslog {
+    fn to_bytes<W, R>(&self, mut rng: R, max_bytes: usize, writer: &mut W) -> Result<(), Error>
+    where
+        R: Rng + Sized,
+        W: Write,
+    {
+        let mut written_bytes = 0;
+        loop {
+            let message = self.message(&mut rng);
+            let framed = match self.framing {
+                Framing::OctetCounting => format!("{} {message}", message.len()),
+                Framing::NonTransparent => format!("{message}\n"),
+                Framing::NonTransparentNul => format!("{message}\0"),
+            };
+            if framed.len() + written_bytes > max_bytes {
+                break;
+            }
+            writer.write_all(framed.as_bytes())?;
+            written_bytes += framed.len();
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Debug, Default, Clone, Copy)]
 #[allow(clippy::module_name_repetitions)]
 #[cfg_attr(test, derive(proptest_derive::Arbitrary))]
@@ -80,7 +295,27 @@ fn to_rfc3339<T>(dt: T) -> String
 where
     T: Into<OffsetDateTime>,
 {
-    dt.into().format(&Rfc3339).expect("failed to format")
+    // RFC 5424 allows at most microsecond precision.
+    let dt = dt.into();
+    dt.replace_microsecond(dt.microsecond())
+        .expect("valid microsecond")
+        .format(&Rfc3339)
+        .expect("failed to format")
+}
+
+fn to_rfc3164<T>(dt: T) -> String
+where
+    T: Into<OffsetDateTime>,
+{
+    let dt = dt.into();
+    format!(
+        "{} {:>2} {:02}:{:02}:{:02}",
+        MONTHS[usize::from(u8::from(dt.month())) - 1],
+        dt.day(),
+        dt.hour(),
+        dt.minute(),
+        dt.second()
+    )
 }
 
 impl Member {
@@ -133,8 +368,22 @@ mod test {
     use proptest::prelude::*;
     use rand::{rngs::SmallRng, SeedableRng};
 
+    use time::OffsetDateTime;
+
+    use super::{to_rfc3339, Config, Format, Framing, Syslog};
     use crate::{Serialize, Syslog5424};
 
+    // RFC 5424 TIME-SECFRAC is at most six digits, nanoseconds are truncated
+    // to microseconds.
+    #[test]
+    fn rfc3339_timestamps_are_microsecond_precision() {
+        let dt = OffsetDateTime::from_unix_timestamp_nanos(1_704_067_200_123_456_789)
+            .expect("valid timestamp");
+        assert_eq!(to_rfc3339(dt), "2024-01-01T00:00:00.123456Z");
+        let dt = OffsetDateTime::from_unix_timestamp(1_704_067_200).expect("valid timestamp");
+        assert_eq!(to_rfc3339(dt), "2024-01-01T00:00:00Z");
+    }
+
     // We want to be sure that the serialized size of the payload does not
     // exceed `max_bytes`.
     proptest! {
@@ -153,4 +402,40 @@ mod test {
             );
         }
     }
+
+    proptest! {
+        #[test]
+        fn configured_payload_not_exceed_max_bytes(seed: u64, max_bytes: u16, format: Format, framing: Framing) {
+            let max_bytes = max_bytes as usize;
+            let rng = SmallRng::seed_from_u64(seed);
+            let config = Config { format, framing, ..Config::default() };
+            let syslog = Syslog::new(&config).expect("failed to create syslog");
+
+            let mut bytes = Vec::with_capacity(max_bytes);
+            syslog.to_bytes(rng, max_bytes, &mut bytes).expect("failed to convert to bytes");
+            prop_assert!(bytes.len() <= max_bytes);
+        }
+    }
+
+    // Every octet-counted frame's length prefix must match the message that
+    // follows it.
+    proptest! {
+        #[test]
+        fn octet_counting_frames_are_exact(seed: u64, max_bytes: u16, format: Format) {
+            let rng = SmallRng::seed_from_u64(seed);
+            let config = Config { format, framing: Framing::OctetCounting, ..Config::default() };
+            let syslog = Syslog::new(&config).expect("failed to create syslog");
+
+            let mut bytes = Vec::with_capacity(max_bytes as usize);
+            syslog.to_bytes(rng, max_bytes as usize, &mut bytes).expect("failed to convert to bytes");
+            let mut rest = std::str::from_utf8(&bytes).expect("failed to convert from utf-8 to str");
+            while !rest.is_empty() {
+                let (len, tail) = rest.split_once(' ').expect("missing length prefix");
+                let len: usize = len.parse().expect("invalid length prefix");
+                prop_assert!(tail.len() >= len);
+                prop_assert!(tail.starts_with('<'));
+                rest = &tail[len..];
+            }
+        }
+    }
 }
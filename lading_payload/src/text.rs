@@ -0,0 +1,329 @@
+//! Compressible text for message bodies.
+//!
+//! Text is a walk over a dictionary of words. Each word has a successor and
+//! the walk follows it unless a uniformly chosen word or, more random still, a
+//! random alphanumeric token is emitted in its place. How often the walk
+//! departs from its successors is calibrated when the text is constructed so
+//! that the text compresses at about the configured ratio.
+
+use std::io::Write;
+
+use flate2::{write::DeflateEncoder, Compression};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize as SerdeSerialize};
+
+const ALPHANUM: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+const WORDS: [&str; 128] = [
+    "accepted",
+    "access",
+    "account",
+    "active",
+    "address",
+    "agent",
+    "allocated",
+    "api",
+    "attempt",
+    "auth",
+    "backend",
+    "batch",
+    "buffer",
+    "cache",
+    "called",
+    "channel",
+    "check",
+    "client",
+    "closed",
+    "cluster",
+    "completed",
+    "config",
+    "connection",
+    "consumer",
+    "container",
+    "context",
+    "created",
+    "database",
+    "deadline",
+    "debug",
+    "default",
+    "deleted",
+    "disk",
+    "dropped",
+    "duration",
+    "endpoint",
+    "error",
+    "event",
+    "exceeded",
+    "expired",
+    "failed",
+    "file",
+    "finished",
+    "flush",
+    "for",
+    "from",
+    "handler",
+    "health",
+    "host",
+    "id",
+    "in",
+    "index",
+    "info",
+    "job",
+    "key",
+    "latency",
+    "limit",
+    "listener",
+    "loaded",
+    "lock",
+    "memory",
+    "message",
+    "metric",
+    "missing",
+    "module",
+    "node",
+    "not",
+    "of",
+    "offset",
+    "on",
+    "opened",
+    "partition",
+    "path",
+    "peer",
+    "pending",
+    "pool",
+    "process",
+    "producer",
+    "query",
+    "queue",
+    "read",
+    "received",
+    "record",
+    "refused",
+    "region",
+    "replica",
+    "request",
+    "reset",
+    "response",
+    "retry",
+    "returned",
+    "route",
+    "scheduled",
+    "sent",
+    "server",
+    "service",
+    "session",
+    "shard",
+    "shutdown",
+    "size",
+    "skipped",
+    "socket",
+    "started",
+    "status",
+    "stopped",
+    "stream",
+    "success",
+    "sync",
+    "task",
+    "the",
+    "thread",
+    "timeout",
+    "to",
+    "token",
+    "transaction",
+    "update",
+    "upstream",
+    "user",
+    "valid",
+    "value",
+    "version",
+    "waiting",
+    "warning",
+    "with",
+    "worker",
+    "write",
+    "written",
+    "zone",
+];
+
+/// The randomness at which every word is a random token, the least
+/// compressible text.
+const MAX_RANDOMNESS: f64 = 2.0;
+const CALIBRATION_BYTES: usize = 32 * 1024;
+const CALIBRATION_ROUNDS: u32 = 12;
+
+/// Configuration for compressible text
+#[derive(Debug, Deserialize, SerdeSerialize, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct Config {
+    /// The ratio of the size of the text to its size compressed with DEFLATE
+    /// at the default level. Ratios beyond what the text can achieve, roughly
+    /// `[1.3, 200]`, are clamped.
+    pub compression_ratio: f32,
+}
+
+impl Config {
+    /// Determine whether the passed configuration obeys validation criteria
+    ///
+    /// # Errors
+    ///
+    /// Function will error if the compression ratio is less than 1.
+    pub fn valid(&self) -> Result<(), String> {
+        if self.compression_ratio.is_finite() && self.compression_ratio >= 1.0 {
+            Ok(())
+        } else {
+            Err(format!(
+                "Compression ratio {} must be at least 1",
+                self.compression_ratio
+            ))
+        }
+    }
+}
+
+/// Text compressible at a configured ratio, see the module documentation.
+#[derive(Debug, Clone)]
+pub(crate) struct Text {
+    /// The index of the word following each word.
+    successors: Vec<usize>,
+    /// Within `[0, 1]` the probability of a uniformly chosen word rather than
+    /// the successor, within `(1, 2]` one more than the probability of a
+    /// random token rather than a uniformly chosen word.
+    randomness: f64,
+}
+
+impl Text {
+    /// Create a new instance of [`Text`] calibrated to `config`.
+    pub(crate) fn new<R>(config: Config, rng: &mut R) -> Self
+    where
+        R: Rng + ?Sized,
+    {
+        let successors = (0..WORDS.len())
+            .map(|_| rng.gen_range(0..WORDS.len()))
+            .collect();
+        let seed: u64 = rng.gen();
+
+        // Text grows less compressible with randomness, bisect for the
+        // randomness that achieves the target ratio.
+        let mut text = Self {
+            successors,
+            randomness: 0.0,
+        };
+        let target = f64::from(config.compression_ratio);
+        let (mut low, mut high) = (0.0, MAX_RANDOMNESS);
+        for _ in 0..CALIBRATION_ROUNDS {
+            text.randomness = (low + high) / 2.0;
+            if text.compression_ratio(&mut SmallRng::seed_from_u64(seed)) > target {
+                low = text.randomness;
+            } else {
+                high = text.randomness;
+            }
+        }
+        text.randomness = (low + high) / 2.0;
+        text
+    }
+
+    /// Append exactly `bytes` bytes of text to `out`.
+    pub(crate) fn fill<R>(&self, rng: &mut R, bytes: usize, out: &mut String)
+    where
+        R: Rng + ?Sized,
+    {
+        let start = out.len();
+        let mut word = rng.gen_range(0..WORDS.len());
+        while out.len() < start + bytes {
+            if out.len() > start {
+                out.push(' ');
+            }
+            self.next_word(rng, &mut word, out);
+        }
+        out.truncate(start + bytes);
+    }
+
+    /// Generate a message of a few words.
+    pub(crate) fn message<R>(&self, rng: &mut R) -> String
+    where
+        R: Rng + ?Sized,
+    {
+        let mut message = String::new();
+        let mut word = rng.gen_range(0..WORDS.len());
+        for _ in 0..rng.gen_range(4..32) {
+            if !message.is_empty() {
+                message.push(' ');
+            }
+            self.next_word(rng, &mut word, &mut message);
+        }
+        message
+    }
+
+    /// Append the word following `word` to `out`, updating `word` unless a
+    /// random token is appended.
+    fn next_word<R>(&self, rng: &mut R, word: &mut usize, out: &mut String)
+    where
+        R: Rng + ?Sized,
+    {
+        let chance: f64 = rng.gen();
+        if chance < self.randomness - 1.0 {
+            for _ in 0..rng.gen_range(4..=12) {
+                out.push(char::from(ALPHANUM[rng.gen_range(0..ALPHANUM.len())]));
+            }
+            return;
+        }
+        *word = if chance < self.randomness {
+            rng.gen_range(0..WORDS.len())
+        } else {
+            self.successors[*word]
+        };
+        out.push_str(WORDS[*word]);
+    }
+
+    fn compression_ratio<R>(&self, rng: &mut R) -> f64
+    where
+        R: Rng + ?Sized,
+    {
+        let mut sample = String::with_capacity(CALIBRATION_BYTES);
+        self.fill(rng, CALIBRATION_BYTES, &mut sample);
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(sample.as_bytes())
+            .expect("failed to compress to memory");
+        let compressed = encoder.finish().expect("failed to compress to memory");
+        sample.len() as f64 / compressed.len() as f64
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use super::{Config, Text};
+
+    // Text generated after calibration compresses at about the target ratio.
+    #[test]
+    fn compresses_at_target_ratio() {
+        let mut rng = SmallRng::seed_from_u64(48);
+        for compression_ratio in [2.0, 4.0, 8.0, 16.0] {
+            let text = Text::new(Config { compression_ratio }, &mut rng);
+            let ratio = text.compression_ratio(&mut rng);
+            let error = (ratio - f64::from(compression_ratio)).abs() / f64::from(compression_ratio);
+            assert!(
+                error < 0.15,
+                "target {compression_ratio}, compressed at {ratio}"
+            );
+        }
+    }
+
+    #[test]
+    fn fill_is_exact() {
+        let mut rng = SmallRng::seed_from_u64(48);
+        let text = Text::new(
+            Config {
+                compression_ratio: 3.0,
+            },
+            &mut rng,
+        );
+        for bytes in [0, 1, 7, 1024] {
+            let mut out = String::new();
+            text.fill(&mut rng, bytes, &mut out);
+            assert_eq!(out.len(), bytes);
+        }
+    }
+}
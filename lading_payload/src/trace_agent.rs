@@ -1,4 +1,15 @@
 //! Trace-agent payload.
+//!
+//! By default spans are generated independently of one another. If a
+//! [topology](crate::trace_topology) is configured each payload instead holds
+//! complete traces whose parent IDs and timings are consistent.
+//!
+//! Payloads are encoded for the `/v0.4/traces` endpoint unless the `v0.5`
+//! [version](Version) is configured, in which case all strings are hoisted into
+//! a string table shared by the spans of the payload. Client computed stats for
+//! the `/v0.6/stats` endpoint are generated by [`stats`].
+
+pub mod stats;
 
 use std::io::Write;
 
@@ -6,8 +17,13 @@ use rand::{seq::SliceRandom, Rng};
 use rmp_serde::Serializer;
 use rustc_hash::FxHashMap;
 
-use crate::{common::strings, Error, Generator};
-use serde::Serialize;
+use crate::{
+    common::strings,
+    trace_topology::{self, Topology},
+    Error, Generator,
+};
+use serde::{Deserialize, Serialize};
+use serde_tuple::Serialize_tuple;
 
 const SERVICES: [&str; 7] = [
     "tablet",
@@ -115,15 +131,89 @@ pub enum Encoding {
     MsgPack,
 }
 
+/// The trace-agent API version payloads are encoded for.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub enum Version {
+    /// Encode for `/v0.4/traces`, an array of traces each an array of span
+    /// maps.
+    #[default]
+    #[serde(rename = "v0.4")]
+    V04,
+    /// Encode for `/v0.5/traces`, a string table and an array of traces each
+    /// an array of span arrays whose strings index into the table. Only
+    /// supported with `msg_pack` encoding.
+    #[serde(rename = "v0.5")]
+    V05,
+}
+
+/// Configure the trace-agent payload.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// The encoding of payloads.
+    pub encoding: crate::Encoding,
+    /// The trace-agent API version to encode for.
+    #[serde(default)]
+    pub version: Version,
+    /// If set spans are generated as complete traces through a service
+    /// topology, otherwise every span is independent.
+    #[serde(default)]
+    pub topology: Option<trace_topology::Config>,
+}
+
+impl Config {
+    /// Determine whether the passed configuration obeys validation criteria
+    /// # Errors
+    /// Function will error if the configuration is invalid
+    pub fn valid(&self) -> Result<(), String> {
+        if self.version == Version::V05 && self.encoding == crate::Encoding::Json {
+            return Err("version v0.5 requires msg_pack encoding".to_string());
+        }
+        match self.topology {
+            Some(topology) => topology.valid(),
+            None => Ok(()),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 #[allow(clippy::module_name_repetitions)]
 /// Trace Agent payload
 pub struct TraceAgent {
     encoding: Encoding,
+    version: Version,
     str_pool: strings::Pool,
+    topology: Option<Topology>,
 }
 
 impl TraceAgent {
+    /// Construct a new instance of `TraceAgent`
+    ///
+    /// # Errors
+    ///
+    /// Function will error if the topology could not be built.
+    pub fn new<R>(config: &Config, rng: &mut R) -> Result<Self, Error>
+    where
+        R: rand::Rng + ?Sized,
+    {
+        let encoding = match config.encoding {
+            crate::Encoding::Json => Encoding::Json,
+            crate::Encoding::MsgPack => Encoding::MsgPack,
+        };
+        let topology = match config.topology {
+            Some(topology) => Some(Topology::new(&topology, rng)?),
+            None => None,
+        };
+        Ok(Self {
+            encoding,
+            version: config.version,
+            str_pool: strings::Pool::with_size(rng, 1_000_000),
+            topology,
+        })
+    }
+
     /// JSON encoding
     #[must_use]
     pub fn json<R>(rng: &mut R) -> Self
@@ -132,7 +222,9 @@ impl TraceAgent {
     {
         Self {
             encoding: Encoding::Json,
+            version: Version::V04,
             str_pool: strings::Pool::with_size(rng, 1_000_000),
+            topology: None,
         }
     }
 
@@ -144,7 +236,9 @@ impl TraceAgent {
     {
         Self {
             encoding: Encoding::MsgPack,
+            version: Version::V04,
             str_pool: strings::Pool::with_size(rng, 1_000_000),
+            topology: None,
         }
     }
 }
@@ -193,12 +287,183 @@ impl<'a> Generator<'a> for TraceAgent {
     }
 }
 
+impl<'a> Span<'a> {
+    #[allow(clippy::cast_possible_truncation)]
+    fn from_topology(span: &trace_topology::Span<'a>) -> Self {
+        Span {
+            service: &span.service.name,
+            name: &span.operation.name,
+            resource: &span.operation.resource,
+            // The trace-agent carries the low 64 bits of the trace ID.
+            trace_id: span.trace_id as u64,
+            span_id: span.span_id,
+            parent_id: span.parent_id,
+            start: i64::try_from(span.start).unwrap_or(i64::MAX),
+            duration: i64::try_from(span.duration).unwrap_or(i64::MAX),
+            error: i32::from(span.error),
+            meta: FxHashMap::default(),
+            metrics: FxHashMap::default(),
+            kind: span.service.kind,
+            meta_struct: FxHashMap::default(),
+        }
+    }
+}
+
+impl TraceAgent {
+    fn encode(&self, traces: &[Vec<Span>], max_bytes: usize) -> Result<Vec<u8>, Error> {
+        if self.version == Version::V05 {
+            return encode_v05(traces, max_bytes);
+        }
+        Ok(match self.encoding {
+            Encoding::Json => serde_json::to_vec(traces)?,
+            Encoding::MsgPack => {
+                // The trace-agent decodes v0.4 spans as maps.
+                let mut buf = Vec::with_capacity(max_bytes);
+                traces.serialize(&mut Serializer::new(&mut buf).with_struct_map())?;
+                buf
+            }
+        })
+    }
+
+    /// Fill a payload with complete traces from `topology`.
+    fn to_bytes_topology<W, R>(
+        &self,
+        topology: &Topology,
+        mut rng: R,
+        max_bytes: usize,
+        writer: &mut W,
+    ) -> Result<(), Error>
+    where
+        R: Rng + Sized,
+        W: Write,
+    {
+        // The outer array costs at most 5 bytes in either encoding, and each
+        // trace at most one separator beyond its own encoding.
+        let mut traces: Vec<Vec<Span>> = Vec::new();
+        let mut total = 5;
+        loop {
+            let trace: Vec<Span> = topology
+                .trace(&mut rng)
+                .iter()
+                .map(Span::from_topology)
+                .collect();
+            total += self.encode(std::slice::from_ref(&trace), max_bytes)?.len() + 1;
+            if total > max_bytes {
+                break;
+            }
+            traces.push(trace);
+        }
+
+        loop {
+            let encoding = self.encode(&traces[..], max_bytes)?;
+            if encoding.len() <= max_bytes {
+                writer.write_all(&encoding)?;
+                return Ok(());
+            }
+            if traces.pop().is_none() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+// The v0.5 span is a fixed-order array of twelve fields, every string being
+// an index into the payload's string table. See the trace-agent's
+// [decoder](https://github.com/DataDog/datadog-agent/blob/main/pkg/proto/pbgo/trace/decoder_v05.go).
+// `meta_struct` has no v0.5 equivalent.
+#[derive(Serialize_tuple)]
+#[allow(clippy::struct_field_names)]
+struct SpanV05 {
+    service: u32,
+    name: u32,
+    resource: u32,
+    trace_id: u64,
+    span_id: u64,
+    parent_id: u64,
+    start: i64,
+    duration: i64,
+    error: i32,
+    meta: FxHashMap<u32, u32>,
+    metrics: FxHashMap<u32, f64>,
+    kind: u32,
+}
+
+/// Strings of a v0.5 payload, the empty string always at index 0.
+struct StringTable<'a> {
+    strings: Vec<&'a str>,
+    index: FxHashMap<&'a str, u32>,
+}
+
+impl<'a> StringTable<'a> {
+    fn new() -> Self {
+        let mut table = Self {
+            strings: Vec::new(),
+            index: FxHashMap::default(),
+        };
+        table.intern("");
+        table
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn intern(&mut self, s: &'a str) -> u32 {
+        if let Some(idx) = self.index.get(s) {
+            return *idx;
+        }
+        let idx = self.strings.len() as u32;
+        self.strings.push(s);
+        self.index.insert(s, idx);
+        idx
+    }
+}
+
+fn encode_v05(traces: &[Vec<Span>], max_bytes: usize) -> Result<Vec<u8>, Error> {
+    let mut table = StringTable::new();
+    let traces: Vec<Vec<SpanV05>> = traces
+        .iter()
+        .map(|trace| {
+            trace
+                .iter()
+                .map(|span| SpanV05 {
+                    service: table.intern(span.service),
+                    name: table.intern(span.name),
+                    resource: table.intern(span.resource),
+                    trace_id: span.trace_id,
+                    span_id: span.span_id,
+                    parent_id: span.parent_id,
+                    start: span.start,
+                    duration: span.duration,
+                    error: span.error,
+                    meta: span
+                        .meta
+                        .iter()
+                        .map(|(k, v)| (table.intern(k), table.intern(v)))
+                        .collect(),
+                    metrics: span
+                        .metrics
+                        .iter()
+                        .map(|(k, v)| (table.intern(k), *v))
+                        .collect(),
+                    kind: table.intern(span.kind),
+                })
+                .collect()
+        })
+        .collect();
+
+    let mut buf = Vec::with_capacity(max_bytes);
+    (&table.strings, &traces).serialize(&mut Serializer::new(&mut buf))?;
+    Ok(buf)
+}
+
 impl crate::Serialize for TraceAgent {
     fn to_bytes<W, R>(&self, mut rng: R, max_bytes: usize, writer: &mut W) -> Result<(), Error>
     where
         R: Rng + Sized,
         W: Write,
     {
+        if let Some(topology) = &self.topology {
+            return self.to_bytes_topology(topology, rng, max_bytes, writer);
+        }
+
         // We will arbitrarily generate Member instances and then serialize. If
         // this is below `max_bytes` we'll add more until we're over. Once we
         // are we'll start removing instances until we're back below the limit.
@@ -220,14 +485,7 @@ impl crate::Serialize for TraceAgent {
 
         // Search for too many Member instances.
         loop {
-            let encoding = match self.encoding {
-                Encoding::Json => serde_json::to_vec(&members[0..])?,
-                Encoding::MsgPack => {
-                    let mut buf = Vec::with_capacity(max_bytes);
-                    members[0..].serialize(&mut Serializer::new(&mut buf))?;
-                    buf
-                }
-            };
+            let encoding = self.encode(&members[0..], max_bytes)?;
             if encoding.len() > max_bytes {
                 break;
             }
@@ -242,14 +500,7 @@ impl crate::Serialize for TraceAgent {
         // Search for an encoding that's just right.
         let mut high = members.len();
         loop {
-            let encoding = match self.encoding {
-                Encoding::Json => serde_json::to_vec(&members[0..high])?,
-                Encoding::MsgPack => {
-                    let mut buf = Vec::with_capacity(max_bytes);
-                    members[0..high].serialize(&mut Serializer::new(&mut buf))?;
-                    buf
-                }
-            };
+            let encoding = self.encode(&members[0..high], max_bytes)?;
             // NOTE because the type of Vec<Vec<Span>> this shrink isn't as
             // efficient as it could be. We want to shrink the tree present
             // here. This algorithm _does_ work perfectly if the tree is a
@@ -270,7 +521,10 @@ mod test {
     use proptest::prelude::*;
     use rand::{rngs::SmallRng, SeedableRng};
 
-    use crate::{Serialize, TraceAgent};
+    use rustc_hash::FxHashMap;
+
+    use super::{Config, Version};
+    use crate::{trace_topology, Encoding, Serialize, TraceAgent};
 
     // We want to be sure that the serialized size of the payload does not
     // exceed `max_bytes`.
@@ -307,4 +561,123 @@ mod test {
             );
         }
     }
+
+    // We want to know that topology payloads respect `max_bytes` and that
+    // every trace in them is complete: one root and every parent present.
+    proptest! {
+        #[test]
+        fn topology_payload_holds_complete_traces(seed: u64, max_bytes: u16) {
+            let max_bytes = max_bytes as usize;
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let config = Config {
+                encoding: Encoding::Json,
+                version: Version::V04,
+                topology: Some(trace_topology::Config::default()),
+            };
+            let trace_agent = TraceAgent::new(&config, &mut rng)?;
+
+            let mut bytes = Vec::with_capacity(max_bytes);
+            trace_agent.to_bytes(rng, max_bytes, &mut bytes)?;
+            prop_assert!(bytes.len() <= max_bytes);
+            if bytes.is_empty() {
+                return Ok(());
+            }
+
+            let traces: Vec<Vec<serde_json::Value>> = serde_json::from_slice(&bytes)?;
+            for trace in traces {
+                let ids: Vec<&serde_json::Value> = trace.iter().map(|span| &span["span_id"]).collect();
+                let roots = trace.iter().filter(|span| span["parent_id"] == 0).count();
+                prop_assert_eq!(roots, 1);
+                for span in &trace {
+                    prop_assert_eq!(&span["trace_id"], &trace[0]["trace_id"]);
+                    prop_assert!(span["parent_id"] == 0 || ids.contains(&&span["parent_id"]));
+                }
+            }
+        }
+    }
+
+    // The trace-agent decodes v0.4 `msg_pack` spans as maps keyed by field
+    // name, as in JSON, not as arrays of fields.
+    #[test]
+    fn v04_msg_pack_spans_are_maps() {
+        let config = Config {
+            encoding: Encoding::MsgPack,
+            version: Version::V04,
+            topology: Some(trace_topology::Config::default()),
+        };
+        for seed in 0..8 {
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let trace_agent = TraceAgent::new(&config, &mut rng).expect("invalid config");
+            let mut bytes = Vec::new();
+            trace_agent
+                .to_bytes(rng, 4_096, &mut bytes)
+                .expect("failed to convert to bytes");
+
+            let traces = rmpv::decode::read_value(&mut &bytes[..]).expect("failed to decode");
+            let spans: Vec<&rmpv::Value> = traces
+                .as_array()
+                .expect("traces are not an array")
+                .iter()
+                .flat_map(|trace| trace.as_array().expect("trace is not an array"))
+                .collect();
+            assert!(!spans.is_empty());
+            for span in spans {
+                let span = span.as_map().expect("span is not a map");
+                for key in ["service", "name", "resource", "trace_id", "span_id"] {
+                    assert!(
+                        span.iter().any(|(k, _)| k.as_str() == Some(key)),
+                        "no {key}"
+                    );
+                }
+            }
+        }
+    }
+
+    type SpanV05 = (
+        u32,
+        u32,
+        u32,
+        u64,
+        u64,
+        u64,
+        i64,
+        i64,
+        i32,
+        FxHashMap<u32, u32>,
+        FxHashMap<u32, f64>,
+        u32,
+    );
+
+    // We want to know that v0.5 payloads respect `max_bytes` and that every
+    // string reference of every span lands in the string table.
+    proptest! {
+        #[test]
+        fn v05_payload_references_string_table(seed: u64, max_bytes: u16) {
+            let max_bytes = max_bytes as usize;
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let config = Config {
+                encoding: Encoding::MsgPack,
+                version: Version::V05,
+                topology: Some(trace_topology::Config::default()),
+            };
+            let trace_agent = TraceAgent::new(&config, &mut rng)?;
+
+            let mut bytes = Vec::with_capacity(max_bytes);
+            trace_agent.to_bytes(rng, max_bytes, &mut bytes)?;
+            prop_assert!(bytes.len() <= max_bytes);
+            if bytes.is_empty() {
+                return Ok(());
+            }
+
+            let (strings, traces): (Vec<String>, Vec<Vec<SpanV05>>) =
+                rmp_serde::from_slice(&bytes).expect("failed to decode v0.5 payload");
+            prop_assert_eq!(strings[0].as_str(), "");
+            let len = u32::try_from(strings.len()).expect("string table too large");
+            for span in traces.iter().flatten() {
+                prop_assert!(span.0 < len && span.1 < len && span.2 < len && span.11 < len);
+                prop_assert!(span.9.iter().all(|(k, v)| *k < len && *v < len));
+                prop_assert!(span.10.keys().all(|k| *k < len));
+            }
+        }
+    }
 }
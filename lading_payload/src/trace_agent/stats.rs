@@ -0,0 +1,557 @@
+//! Trace-agent client stats payload.
+//!
+//! Tracers that compute trace stats themselves send them to the trace-agent's
+//! `/v0.6/stats` endpoint as a `MsgPack` encoded `ClientStatsPayload`, see
+//! [stats.proto](https://github.com/DataDog/datadog-agent/blob/main/pkg/proto/datadog/trace/stats.proto).
+//! Each payload holds one or more ten second buckets of stats grouped by
+//! service, operation, resource, HTTP status code and span type. The latency
+//! distribution of every group is a protobuf encoded `DDSketch`.
+//!
+//! Groups are drawn from a fixed set of contexts built when the payload is
+//! constructed, bounding the number of unique groups a target sees.
+
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    io::Write,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use prost::Message;
+use rand::{seq::SliceRandom, Rng};
+use rmp_serde::Serializer;
+use serde::{Deserialize, Serialize as SerdeSerialize};
+
+use crate::{common::strings, dogstatsd::ConfRange, Error};
+
+const MAX_CONTEXTS: u16 = 10_000;
+/// Width of a stats bucket, in nanoseconds.
+const BUCKET_DURATION: u64 = 10_000_000_000;
+/// Relative accuracy of generated sketches.
+const SKETCH_ACCURACY: f64 = 0.01;
+
+const ENVS: [&str; 3] = ["prod", "staging", "dev"];
+const LANGS: [&str; 7] = ["go", "python", "java", "ruby", "dotnet", "php", "nodejs"];
+const SPAN_TYPES: [&str; 5] = ["web", "http", "db", "cache", "custom"];
+const DB_TYPES: [&str; 4] = ["postgresql", "mysql", "redis", "mongodb"];
+const SPAN_KINDS: [&str; 4] = ["server", "client", "internal", "consumer"];
+const HTTP_STATUS_CODES: [u32; 8] = [200, 201, 204, 301, 400, 404, 500, 503];
+
+/// Configure the trace-agent client stats payload.
+#[derive(Debug, Deserialize, SerdeSerialize, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[serde(deny_unknown_fields, default)]
+pub struct Config {
+    /// The number of unique stats groups to generate. A group is a service,
+    /// operation name, resource, HTTP status code and span type.
+    pub contexts: ConfRange<u16>,
+    /// The number of ten second buckets in each payload.
+    pub buckets_per_payload: ConfRange<u8>,
+    /// The number of groups in each bucket.
+    pub groups_per_bucket: ConfRange<u16>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            contexts: ConfRange::Inclusive { min: 10, max: 1000 },
+            buckets_per_payload: ConfRange::Inclusive { min: 1, max: 3 },
+            groups_per_bucket: ConfRange::Inclusive { min: 1, max: 100 },
+        }
+    }
+}
+
+impl Config {
+    /// Determine whether the passed configuration obeys validation criteria
+    /// # Errors
+    /// Function will error if the configuration is invalid
+    pub fn valid(&self) -> Result<(), String> {
+        let (valid, reason) = self.contexts.valid();
+        if !valid {
+            return Err(format!("Contexts value is invalid: {reason}"));
+        }
+        if self.contexts.start() == 0 {
+            return Err("Contexts start value cannot be 0".to_string());
+        }
+        if self.contexts.end() > MAX_CONTEXTS {
+            return Err(format!(
+                "Contexts end value is greater than the maximum allowed value of {MAX_CONTEXTS}"
+            ));
+        }
+        let (valid, reason) = self.buckets_per_payload.valid();
+        if !valid {
+            return Err(format!("Buckets per payload value is invalid: {reason}"));
+        }
+        if self.buckets_per_payload.start() == 0 {
+            return Err("Buckets per payload start value cannot be 0".to_string());
+        }
+        let (valid, reason) = self.groups_per_bucket.valid();
+        if !valid {
+            return Err(format!("Groups per bucket value is invalid: {reason}"));
+        }
+        if self.groups_per_bucket.start() == 0 {
+            return Err("Groups per bucket start value cannot be 0".to_string());
+        }
+        Ok(())
+    }
+}
+
+// Manual implementation of the `DDSketch` protobuf, see
+// [ddsketch.proto](https://github.com/DataDog/sketches-go/blob/master/ddsketch/pb/ddsketch.proto).
+// Only the fields the trace-agent reads are present.
+#[derive(Clone, PartialEq, prost::Message)]
+struct DdSketch {
+    #[prost(message, optional, tag = "1")]
+    mapping: Option<IndexMapping>,
+    #[prost(message, optional, tag = "2")]
+    positive_values: Option<Store>,
+    #[prost(message, optional, tag = "3")]
+    negative_values: Option<Store>,
+    #[prost(double, tag = "4")]
+    zero_count: f64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct IndexMapping {
+    #[prost(double, tag = "1")]
+    gamma: f64,
+    #[prost(double, tag = "2")]
+    index_offset: f64,
+    /// `NONE`, the mapping is logarithmic.
+    #[prost(int32, tag = "3")]
+    interpolation: i32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct Store {
+    #[prost(map = "sint32, double", tag = "1")]
+    bin_counts: HashMap<i32, f64>,
+    #[prost(double, repeated, tag = "2")]
+    contiguous_bin_counts: Vec<f64>,
+    #[prost(sint32, tag = "3")]
+    contiguous_bin_index_offset: i32,
+}
+
+/// Bytes that encode as a `MsgPack` bin rather than an array of integers.
+#[derive(Debug, Clone)]
+struct Bin(Vec<u8>);
+
+impl SerdeSerialize for Bin {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+#[derive(SerdeSerialize)]
+#[serde(rename_all = "PascalCase")]
+struct ClientStatsPayload<'a> {
+    hostname: &'a str,
+    env: &'a str,
+    version: &'a str,
+    stats: Vec<ClientStatsBucket<'a>>,
+    lang: &'a str,
+    tracer_version: &'a str,
+    #[serde(rename = "RuntimeID")]
+    runtime_id: &'a str,
+    sequence: u64,
+    service: &'a str,
+}
+
+#[derive(SerdeSerialize)]
+#[serde(rename_all = "PascalCase")]
+struct ClientStatsBucket<'a> {
+    start: u64,
+    duration: u64,
+    stats: &'a [ClientGroupedStats<'a>],
+    agent_time_shift: i64,
+}
+
+#[derive(SerdeSerialize)]
+#[serde(rename_all = "PascalCase")]
+struct ClientGroupedStats<'a> {
+    service: &'a str,
+    name: &'a str,
+    resource: &'a str,
+    #[serde(rename = "HTTPStatusCode")]
+    http_status_code: u32,
+    #[serde(rename = "Type")]
+    kind: &'a str,
+    #[serde(rename = "DBType")]
+    db_type: &'a str,
+    hits: u64,
+    errors: u64,
+    duration: u64,
+    ok_summary: Bin,
+    error_summary: Bin,
+    synthetics: bool,
+    top_level_hits: u64,
+    span_kind: &'a str,
+}
+
+/// A unique stats group and its typical latency.
+#[derive(Debug, Clone)]
+struct Group {
+    service: String,
+    name: String,
+    resource: String,
+    http_status_code: u32,
+    kind: &'static str,
+    db_type: &'static str,
+    span_kind: &'static str,
+    /// Mean span duration, nanoseconds.
+    latency: f64,
+}
+
+#[derive(Debug, Clone)]
+#[allow(clippy::module_name_repetitions)]
+/// Trace-agent client stats payload
+pub struct TraceAgentStats {
+    config: Config,
+    groups: Vec<Group>,
+    hostname: String,
+    env: &'static str,
+    version: String,
+    lang: &'static str,
+    tracer_version: String,
+    runtime_id: String,
+    service: String,
+    sequence: Cell<u64>,
+}
+
+impl TraceAgentStats {
+    /// Construct a new instance of `TraceAgentStats`
+    ///
+    /// # Errors
+    ///
+    /// Function will error if string generation fails.
+    ///
+    /// # Panics
+    ///
+    /// Function will only panic if there is a serious programming mistake.
+    pub fn new<R>(config: Config, rng: &mut R) -> Result<Self, Error>
+    where
+        R: rand::Rng + ?Sized,
+    {
+        let str_pool = strings::Pool::with_size(rng, 1_000_000);
+        let string = |rng: &mut R, max: u8| {
+            str_pool
+                .of_size_range(rng, 1_u8..max)
+                .map(String::from)
+                .ok_or(Error::StringGenerate)
+        };
+
+        let services: Vec<String> = (0..rng.gen_range(1..=16))
+            .map(|_| string(rng, 16))
+            .collect::<Result<_, _>>()?;
+        let total_contexts = config.contexts.sample(rng);
+        let mut groups = Vec::with_capacity(usize::from(total_contexts));
+        for _ in 0..total_contexts {
+            let kind = *SPAN_TYPES.choose(rng).expect("span types are not empty");
+            groups.push(Group {
+                service: services
+                    .choose(rng)
+                    .expect("services are not empty")
+                    .clone(),
+                name: string(rng, 32)?,
+                resource: string(rng, 64)?,
+                http_status_code: if kind == "web" || kind == "http" {
+                    *HTTP_STATUS_CODES
+                        .choose(rng)
+                        .expect("status codes are not empty")
+                } else {
+                    0
+                },
+                kind,
+                db_type: if kind == "db" {
+                    DB_TYPES.choose(rng).expect("db types are not empty")
+                } else {
+                    ""
+                },
+                span_kind: SPAN_KINDS.choose(rng).expect("span kinds are not empty"),
+                latency: f64::from(rng.gen_range(10_u32..1_000_000)) * 1_000.0,
+            });
+        }
+
+        Ok(Self {
+            config,
+            groups,
+            hostname: string(rng, 32)?,
+            env: ENVS.choose(rng).expect("envs are not empty"),
+            version: format!(
+                "{}.{}.{}",
+                rng.gen_range(0..4),
+                rng.gen_range(0..32),
+                rng.gen_range(0..16)
+            ),
+            lang: LANGS.choose(rng).expect("langs are not empty"),
+            tracer_version: format!("{}.{}.0", rng.gen_range(0..3), rng.gen_range(0..64)),
+            runtime_id: format!("{:032x}", rng.gen::<u128>()),
+            service: services
+                .choose(rng)
+                .expect("services are not empty")
+                .clone(),
+            sequence: Cell::new(0),
+        })
+    }
+
+    /// Construct a new instance of `TraceAgentStats` with default configuration
+    ///
+    /// # Errors
+    ///
+    /// Function will error if string generation fails.
+    pub fn default<R>(rng: &mut R) -> Result<Self, Error>
+    where
+        R: rand::Rng + ?Sized,
+    {
+        Self::new(Config::default(), rng)
+    }
+}
+
+/// Build the stats of `group` for one bucket.
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_precision_loss,
+    clippy::cast_sign_loss
+)]
+fn grouped_stats<'a, R>(group: &'a Group, rng: &mut R) -> ClientGroupedStats<'a>
+where
+    R: rand::Rng + ?Sized,
+{
+    let hits: u64 = rng.gen_range(1..=10_000);
+    let errors = rng.gen_range(0..=hits / 10);
+    ClientGroupedStats {
+        service: &group.service,
+        name: &group.name,
+        resource: &group.resource,
+        http_status_code: group.http_status_code,
+        kind: group.kind,
+        db_type: group.db_type,
+        hits,
+        errors,
+        duration: (hits as f64 * group.latency) as u64,
+        ok_summary: sketch(rng, hits - errors, group.latency),
+        error_summary: sketch(rng, errors, group.latency),
+        synthetics: rng.gen_ratio(1, 100),
+        top_level_hits: rng.gen_range(0..=hits),
+        span_kind: group.span_kind,
+    }
+}
+
+/// Build an encoded sketch of `count` values clustered around `latency`.
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_precision_loss,
+    clippy::cast_sign_loss
+)]
+fn sketch<R>(rng: &mut R, count: u64, latency: f64) -> Bin
+where
+    R: rand::Rng + ?Sized,
+{
+    let gamma = (1.0 + SKETCH_ACCURACY) / (1.0 - SKETCH_ACCURACY);
+    let mut sketch = DdSketch {
+        mapping: Some(IndexMapping {
+            gamma,
+            index_offset: 0.0,
+            interpolation: 0,
+        }),
+        positive_values: None,
+        negative_values: None,
+        zero_count: 0.0,
+    };
+
+    if count > 0 {
+        // Spread `count` over contiguous bins either side of the bin holding
+        // `latency`, each receiving a random share.
+        let center = (latency.ln() / gamma.ln()).ceil() as i32;
+        let bins = rng
+            .gen_range(1..=32_u8)
+            .min(u8::try_from(count).unwrap_or(u8::MAX));
+        let offset = center - i32::from(bins / 2);
+        let bins = usize::from(bins);
+        let mut counts = vec![0.0; bins];
+        let mut remaining = count;
+        for slot in counts.iter_mut().take(bins - 1) {
+            let share = rng.gen_range(0..=remaining / 2);
+            *slot = share as f64;
+            remaining -= share;
+        }
+        counts[bins - 1] = remaining as f64;
+        counts.shuffle(rng);
+        sketch.positive_values = Some(Store {
+            bin_counts: HashMap::new(),
+            contiguous_bin_counts: counts,
+            contiguous_bin_index_offset: offset,
+        });
+    }
+
+    Bin(sketch.encode_to_vec())
+}
+
+impl crate::Serialize for TraceAgentStats {
+    fn to_bytes<W, R>(&self, mut rng: R, max_bytes: usize, writer: &mut W) -> Result<(), Error>
+    where
+        R: Rng + Sized,
+        W: Write,
+    {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let now = u64::try_from(now).unwrap_or(u64::MAX);
+        let latest = now - now % BUCKET_DURATION;
+
+        let total_buckets = self.config.buckets_per_payload.sample(&mut rng);
+        let buckets: Vec<(u64, Vec<ClientGroupedStats>)> = (0..u64::from(total_buckets))
+            .map(|i| {
+                let total_groups = usize::from(self.config.groups_per_bucket.sample(&mut rng));
+                let groups = self
+                    .groups
+                    .choose_multiple(&mut rng, total_groups)
+                    .collect::<Vec<_>>()
+                    .into_iter()
+                    .map(|group| grouped_stats(group, &mut rng))
+                    .collect();
+                (latest.saturating_sub(i * BUCKET_DURATION), groups)
+            })
+            .collect();
+
+        let sequence = self.sequence.get();
+        self.sequence.set(sequence + 1);
+
+        // Halve the number of groups in the payload until it fits, dropping
+        // groups from the oldest buckets first.
+        let mut keep: usize = buckets.iter().map(|(_, groups)| groups.len()).sum();
+        loop {
+            let mut remaining = keep;
+            let stats = buckets
+                .iter()
+                .map_while(|(start, groups)| {
+                    if remaining == 0 {
+                        return None;
+                    }
+                    let total = groups.len().min(remaining);
+                    remaining -= total;
+                    Some(ClientStatsBucket {
+                        start: *start,
+                        duration: BUCKET_DURATION,
+                        stats: &groups[..total],
+                        agent_time_shift: 0,
+                    })
+                })
+                .collect();
+            let payload = ClientStatsPayload {
+                hostname: &self.hostname,
+                env: self.env,
+                version: &self.version,
+                stats,
+                lang: self.lang,
+                tracer_version: &self.tracer_version,
+                runtime_id: &self.runtime_id,
+                sequence,
+                service: &self.service,
+            };
+
+            let mut buf = Vec::with_capacity(max_bytes);
+            payload.serialize(&mut Serializer::new(&mut buf).with_struct_map())?;
+            if buf.len() <= max_bytes {
+                writer.write_all(&buf)?;
+                return Ok(());
+            }
+            if keep == 0 {
+                return Ok(());
+            }
+            keep /= 2;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fmt;
+
+    use proptest::prelude::*;
+    use prost::Message;
+    use rand::{rngs::SmallRng, SeedableRng};
+    use serde::Deserialize;
+
+    use super::{DdSketch, TraceAgentStats};
+    use crate::Serialize;
+
+    /// Decodes a `MsgPack` bin.
+    struct Bin(Vec<u8>);
+
+    impl<'de> Deserialize<'de> for Bin {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: serde::Deserializer<'de>,
+        {
+            struct Visitor;
+            impl<'de> serde::de::Visitor<'de> for Visitor {
+                type Value = Bin;
+                fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    f.write_str("bytes")
+                }
+                fn visit_bytes<E>(self, v: &[u8]) -> Result<Bin, E> {
+                    Ok(Bin(v.to_vec()))
+                }
+            }
+            deserializer.deserialize_bytes(Visitor)
+        }
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct Payload {
+        stats: Vec<Bucket>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct Bucket {
+        stats: Vec<Group>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct Group {
+        hits: u64,
+        errors: u64,
+        ok_summary: Bin,
+        error_summary: Bin,
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn total(summary: &Bin) -> u64 {
+        let sketch = DdSketch::decode(&summary.0[..]).expect("failed to decode sketch");
+        sketch
+            .positive_values
+            .map_or(0.0, |store| store.contiguous_bin_counts.iter().sum::<f64>()) as u64
+    }
+
+    // We want to know that payloads respect `max_bytes` and that the sketches
+    // of every group account for its hits and errors.
+    proptest! {
+        #[test]
+        fn payload_not_exceed_max_bytes(seed: u64, max_bytes: u16) {
+            let max_bytes = max_bytes as usize;
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let stats = TraceAgentStats::default(&mut rng)?;
+
+            let mut bytes = Vec::with_capacity(max_bytes);
+            stats.to_bytes(rng, max_bytes, &mut bytes)?;
+            prop_assert!(bytes.len() <= max_bytes);
+            if bytes.is_empty() {
+                return Ok(());
+            }
+
+            let payload: Payload = rmp_serde::from_slice(&bytes).expect("failed to decode payload");
+            for group in payload.stats.iter().flat_map(|bucket| &bucket.stats) {
+                prop_assert_eq!(total(&group.ok_summary), group.hits - group.errors);
+                prop_assert_eq!(total(&group.error_summary), group.errors);
+            }
+        }
+    }
+}
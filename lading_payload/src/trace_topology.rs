@@ -0,0 +1,401 @@
+//! Service topology for trace payloads.
+//!
+//! Rather than generating spans independently this module builds a random
+//! service graph once, at construction, and walks it to produce complete
+//! traces. Services are arranged in layers, the first of which holds the
+//! entry points. Each operation of a service calls a number of operations in
+//! deeper layers, so the graph is acyclic and the depth of a trace is bounded
+//! by the number of layers.
+//!
+//! Every edge of the graph, including the entry edge into a root operation,
+//! carries its own error rate and duration distribution. Durations are
+//! log-normally distributed around a per-edge median. A parent span always
+//! encloses its children, which run one after another, and every span of a
+//! trace shares the trace's ID.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize as SerdeSerialize};
+
+use crate::{common::strings, dogstatsd::ConfRange, Error};
+
+const SERVICE_KINDS: [&str; 4] = ["web", "rpc", "cache", "db"];
+
+/// Configure the service topology of generated traces.
+#[derive(Debug, Deserialize, SerdeSerialize, Clone, PartialEq, Copy)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[serde(deny_unknown_fields, default)]
+pub struct Config {
+    /// The number of services in the topology.
+    pub services: ConfRange<u16>,
+    /// The number of operations each service exposes.
+    pub operations_per_service: ConfRange<u8>,
+    /// The number of downstream calls each operation makes.
+    pub fan_out: ConfRange<u8>,
+    /// The number of service layers, bounding the depth of traces.
+    pub maximum_depth: u8,
+    /// The range from which each edge's error rate is chosen, between 0 and 1.
+    pub error_rate: ConfRange<f32>,
+    /// The range from which each edge's median duration is chosen, in
+    /// microseconds.
+    pub duration_micros: ConfRange<u32>,
+    /// The standard deviation of the log-normal duration distribution. Zero
+    /// makes every span of an edge take exactly the median duration.
+    pub duration_spread: f32,
+    /// Traces stop growing once they hold this many spans.
+    pub maximum_spans_per_trace: u16,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            services: ConfRange::Inclusive { min: 5, max: 20 },
+            operations_per_service: ConfRange::Inclusive { min: 1, max: 5 },
+            fan_out: ConfRange::Inclusive { min: 0, max: 3 },
+            maximum_depth: 5,
+            error_rate: ConfRange::Inclusive {
+                min: 0.0,
+                max: 0.05,
+            },
+            duration_micros: ConfRange::Inclusive {
+                min: 100,
+                max: 100_000,
+            },
+            duration_spread: 0.5,
+            maximum_spans_per_trace: 1_000,
+        }
+    }
+}
+
+impl Config {
+    /// Determine whether the passed configuration obeys validation criteria
+    /// # Errors
+    /// Function will error if the configuration is invalid
+    pub fn valid(&self) -> Result<(), String> {
+        let (services_valid, reason) = self.services.valid();
+        if !services_valid {
+            return Err(format!("Services value is invalid: {reason}"));
+        }
+        if self.services.start() == 0 {
+            return Err("Services start value cannot be 0".to_string());
+        }
+        let (operations_valid, reason) = self.operations_per_service.valid();
+        if !operations_valid {
+            return Err(format!("Operations per service value is invalid: {reason}"));
+        }
+        if self.operations_per_service.start() == 0 {
+            return Err("Operations per service start value cannot be 0".to_string());
+        }
+        let (fan_out_valid, reason) = self.fan_out.valid();
+        if !fan_out_valid {
+            return Err(format!("Fan out value is invalid: {reason}"));
+        }
+        if self.maximum_depth == 0 {
+            return Err("Maximum depth cannot be 0".to_string());
+        }
+        let (error_rate_valid, reason) = self.error_rate.valid();
+        if !error_rate_valid {
+            return Err(format!("Error rate value is invalid: {reason}"));
+        }
+        if !(0.0..=1.0).contains(&self.error_rate.start())
+            || !(0.0..=1.0).contains(&self.error_rate.end())
+        {
+            return Err("Error rate must be between 0 and 1".to_string());
+        }
+        let (duration_valid, reason) = self.duration_micros.valid();
+        if !duration_valid {
+            return Err(format!("Duration value is invalid: {reason}"));
+        }
+        if self.duration_micros.start() == 0 {
+            return Err("Duration start value cannot be 0".to_string());
+        }
+        if !self.duration_spread.is_finite() || self.duration_spread < 0.0 {
+            return Err("Duration spread must be a non-negative number".to_string());
+        }
+        if self.maximum_spans_per_trace == 0 {
+            return Err("Maximum spans per trace cannot be 0".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// A call into an operation, with the error rate and duration distribution
+/// of spans made through it.
+#[derive(Debug, Clone, Copy)]
+struct Edge {
+    service: usize,
+    operation: usize,
+    error_rate: f32,
+    median_nanos: f64,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Operation {
+    pub(crate) name: String,
+    pub(crate) resource: String,
+    calls: Vec<Edge>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Service {
+    pub(crate) name: String,
+    /// The type of the service, e.g. web or db.
+    pub(crate) kind: &'static str,
+    layer: u8,
+    operations: Vec<Operation>,
+}
+
+/// A span of a generated trace.
+#[derive(Debug, Clone, Copy)]
+#[allow(clippy::struct_field_names)]
+pub(crate) struct Span<'a> {
+    pub(crate) trace_id: u128,
+    pub(crate) span_id: u64,
+    /// Zero for the root span of a trace.
+    pub(crate) parent_id: u64,
+    pub(crate) service: &'a Service,
+    pub(crate) operation: &'a Operation,
+    /// Nanoseconds since the Unix epoch.
+    pub(crate) start: u64,
+    /// Nanoseconds.
+    pub(crate) duration: u64,
+    pub(crate) error: bool,
+}
+
+/// A service graph from which complete traces are generated.
+#[derive(Debug, Clone)]
+pub(crate) struct Topology {
+    services: Vec<Service>,
+    roots: Vec<Edge>,
+    duration_spread: f64,
+    maximum_spans_per_trace: usize,
+}
+
+impl Topology {
+    /// Build a new random topology from `config`.
+    ///
+    /// # Errors
+    ///
+    /// Function will error if names cannot be generated.
+    pub(crate) fn new<R>(config: &Config, rng: &mut R) -> Result<Self, Error>
+    where
+        R: Rng + ?Sized,
+    {
+        let str_pool = strings::Pool::with_size(rng, 100_000);
+        let string = |rng: &mut R| {
+            str_pool
+                .of_size_range(rng, 3_u8..16)
+                .map(String::from)
+                .ok_or(Error::StringGenerate)
+        };
+        let edge = |rng: &mut R, service: usize, operation: usize| Edge {
+            service,
+            operation,
+            error_rate: config.error_rate.sample(rng),
+            median_nanos: f64::from(config.duration_micros.sample(rng)) * 1_000.0,
+        };
+
+        // Every layer up to the configured depth holds at least one service
+        // if there are enough services to go around, the rest are spread
+        // randomly.
+        let total_services = usize::from(config.services.sample(rng));
+        let mut services = Vec::with_capacity(total_services);
+        for idx in 0..total_services {
+            let layer = match u8::try_from(idx) {
+                Ok(layer) if layer < config.maximum_depth => layer,
+                _ => rng.gen_range(0..config.maximum_depth),
+            };
+            let total_operations = config.operations_per_service.sample(rng);
+            let mut operations = Vec::with_capacity(total_operations.into());
+            for _ in 0..total_operations {
+                operations.push(Operation {
+                    name: string(rng)?,
+                    resource: string(rng)?,
+                    calls: Vec::new(),
+                });
+            }
+            services.push(Service {
+                name: string(rng)?,
+                kind: SERVICE_KINDS
+                    [usize::from(layer) * SERVICE_KINDS.len() / usize::from(config.maximum_depth)],
+                layer,
+                operations,
+            });
+        }
+
+        // Operations call into the next populated layer below their own.
+        for idx in 0..services.len() {
+            let layer = services[idx].layer;
+            let Some(next) = services
+                .iter()
+                .map(|svc| svc.layer)
+                .filter(|l| *l > layer)
+                .min()
+            else {
+                continue;
+            };
+            let downstream: Vec<usize> = (0..services.len())
+                .filter(|i| services[*i].layer == next)
+                .collect();
+            for op in 0..services[idx].operations.len() {
+                let total_calls = config.fan_out.sample(rng);
+                for _ in 0..total_calls {
+                    let service = *downstream
+                        .choose(rng)
+                        .expect("downstream layer is never empty");
+                    let operation = rng.gen_range(0..services[service].operations.len());
+                    let call = edge(rng, service, operation);
+                    services[idx].operations[op].calls.push(call);
+                }
+            }
+        }
+
+        let mut roots = Vec::new();
+        for (service, svc) in services.iter().enumerate() {
+            if svc.layer == 0 {
+                for operation in 0..svc.operations.len() {
+                    roots.push(edge(rng, service, operation));
+                }
+            }
+        }
+
+        Ok(Self {
+            services,
+            roots,
+            duration_spread: f64::from(config.duration_spread),
+            maximum_spans_per_trace: usize::from(config.maximum_spans_per_trace),
+        })
+    }
+
+    /// Generate a complete trace, the root span first.
+    pub(crate) fn trace<R>(&self, rng: &mut R) -> Vec<Span<'_>>
+    where
+        R: Rng + ?Sized,
+    {
+        let root = self.roots.choose(rng).expect("there is always a root");
+        let trace_id = rng.gen_range(1..=u128::MAX);
+        let start = u64::try_from(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos(),
+        )
+        .unwrap_or(u64::MAX / 2);
+
+        let mut spans = Vec::new();
+        self.span(rng, root, trace_id, 0, start, &mut spans);
+        spans
+    }
+
+    /// Push the span made through `edge` and all of its descendants, returning
+    /// the span's end time.
+    fn span<'a, R>(
+        &'a self,
+        rng: &mut R,
+        edge: &Edge,
+        trace_id: u128,
+        parent_id: u64,
+        start: u64,
+        spans: &mut Vec<Span<'a>>,
+    ) -> u64
+    where
+        R: Rng + ?Sized,
+    {
+        let service = &self.services[edge.service];
+        let operation = &service.operations[edge.operation];
+        let span_id = rng.gen_range(1..=u64::MAX);
+        let own = self.duration(rng, edge.median_nanos);
+
+        let idx = spans.len();
+        spans.push(Span {
+            trace_id,
+            span_id,
+            parent_id,
+            service,
+            operation,
+            start,
+            duration: own,
+            error: rng.gen::<f32>() < edge.error_rate,
+        });
+
+        // Some work happens before, between and after calls downstream.
+        let gap = own / 10;
+        let mut cursor = start.saturating_add(gap);
+        for call in &operation.calls {
+            if spans.len() >= self.maximum_spans_per_trace {
+                break;
+            }
+            let end = self.span(rng, call, trace_id, span_id, cursor, spans);
+            cursor = end.saturating_add(gap);
+        }
+
+        let end = cursor.max(start.saturating_add(own));
+        spans[idx].duration = end - start;
+        end
+    }
+
+    /// Sample a log-normal duration in nanoseconds around `median`.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn duration<R>(&self, rng: &mut R, median: f64) -> u64
+    where
+        R: Rng + ?Sized,
+    {
+        // Box-Muller transform of two uniform samples into a standard normal.
+        let u1: f64 = 1.0 - rng.gen::<f64>();
+        let u2: f64 = rng.gen();
+        let z = (-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos();
+        let nanos = median * (self.duration_spread * z).exp();
+        (nanos as u64).max(1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Config, Topology};
+    use proptest::prelude::*;
+    use rand::{rngs::SmallRng, SeedableRng};
+    use rustc_hash::FxHashMap;
+
+    // We want to know that every trace is a tree rooted at its first span
+    // whose children fit within their parents and which obeys the configured
+    // depth and size.
+    proptest! {
+        #[test]
+        fn traces_are_consistent(seed: u64, maximum_depth in 1u8..8, maximum_spans in 1u16..256) {
+            let config = Config {
+                maximum_depth,
+                maximum_spans_per_trace: maximum_spans,
+                ..Config::default()
+            };
+            config.valid().expect("config must be valid");
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let topology = Topology::new(&config, &mut rng).expect("failed to build topology");
+
+            for _ in 0..16 {
+                let trace = topology.trace(&mut rng);
+                prop_assert!(!trace.is_empty());
+                prop_assert!(trace.len() <= usize::from(maximum_spans));
+                prop_assert_eq!(trace[0].parent_id, 0);
+
+                let mut depths = FxHashMap::default();
+                depths.insert(trace[0].span_id, 1u8);
+                for span in &trace {
+                    prop_assert_eq!(span.trace_id, trace[0].trace_id);
+                    if span.parent_id == 0 {
+                        continue;
+                    }
+                    let parent = trace
+                        .iter()
+                        .find(|s| s.span_id == span.parent_id)
+                        .expect("parent must be in the trace");
+                    prop_assert!(span.start >= parent.start);
+                    prop_assert!(span.start + span.duration <= parent.start + parent.duration);
+                    let depth = depths[&parent.span_id] + 1;
+                    prop_assert!(depth <= maximum_depth);
+                    depths.insert(span.span_id, depth);
+                }
+            }
+        }
+    }
+}